@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::ops::Add;
 
 use chrono::DateTime;
@@ -7,7 +8,7 @@ use chrono::SecondsFormat;
 use chrono::Utc;
 use cid::Cid;
 use libipld::cbor::DagCborCodec;
-use libipld::ipld;
+use libipld::codec::{Decode, Encode};
 use libipld::prelude::Codec;
 use libipld::DagCbor;
 use libipld::Ipld;
@@ -21,6 +22,16 @@ use serde::{Deserialize, Serialize};
 
 mod generate;
 
+/// Largest size of an encoded record accepted by implementations, as set by the IPNS
+/// specification.
+pub const MAX_RECORD_SIZE: usize = 10 * 1024;
+
+/// Prefix of the data signed by the V2 signature.
+const SIGNATURE_V2_PREFIX: &[u8] = b"ipns-signature:";
+
+/// Fields of the DAG-CBOR data defined by the specification, which extension fields cannot use.
+const DATA_FIELDS: [&str; 5] = ["Value", "ValidityType", "Validity", "Sequence", "TTL"];
+
 #[derive(
     Clone,
     Copy,
@@ -91,10 +102,19 @@ pub struct Record {
 
     signature_v1: Vec<u8>,
     signature_v2: Vec<u8>,
+
+    /// Whether the legacy fields are set in the protobuf, as needed by V1 implementations.
+    v1_fields: bool,
+
+    /// Size of the encoding the record was decoded from, which may carry unknown fields dropped
+    /// when encoding it again.
+    decoded_size: Option<usize>,
 }
 
 impl From<generate::ipns_pb::IpnsEntry<'_>> for Record {
     fn from(entry: generate::ipns_pb::IpnsEntry<'_>) -> Self {
+        let v1_fields =
+            !entry.value.is_empty() || !entry.validity.is_empty() || !entry.signatureV1.is_empty();
         Record {
             data: entry.data.into(),
             value: entry.value.into(),
@@ -105,12 +125,23 @@ impl From<generate::ipns_pb::IpnsEntry<'_>> for Record {
             public_key: entry.pubKey.into(),
             signature_v1: entry.signatureV1.into(),
             signature_v2: entry.signatureV2.into(),
+            v1_fields,
+            decoded_size: None,
         }
     }
 }
 
 impl From<&Record> for generate::ipns_pb::IpnsEntry<'_> {
     fn from(record: &Record) -> Self {
+        if !record.v1_fields {
+            return generate::ipns_pb::IpnsEntry {
+                pubKey: record.public_key.clone().into(),
+                signatureV2: record.signature_v2.clone().into(),
+                data: record.data.clone().into(),
+                ..Default::default()
+            };
+        }
+
         generate::ipns_pb::IpnsEntry {
             validity: record.validity.clone().into(),
             validityType: generate::ipns_pb::mod_IpnsEntry::ValidityType::EOL,
@@ -125,22 +156,14 @@ impl From<&Record> for generate::ipns_pb::IpnsEntry<'_> {
     }
 }
 
-#[derive(Debug, Clone, DagCbor)]
+#[derive(Debug, Clone)]
 pub struct Data {
-    #[ipld(rename = "Valid")]
     value: Vec<u8>,
-
-    #[ipld(rename = "ValidityType")]
     validity_type: ValidityType,
-
-    #[ipld(rename = "Validity")]
     validity: Vec<u8>,
-
-    #[ipld(rename = "Sequence")]
     sequence: u64,
-
-    #[ipld(rename = "TTL")]
     ttl: u64,
+    extensions: BTreeMap<String, Ipld>,
 }
 
 impl Data {
@@ -163,94 +186,326 @@ impl Data {
     pub fn ttl(&self) -> u64 {
         self.ttl
     }
+
+    /// Fields of the data beyond those defined by the specification.
+    pub fn extensions(&self) -> &BTreeMap<String, Ipld> {
+        &self.extensions
+    }
+
+    pub fn extension(&self, name: &str) -> Option<&Ipld> {
+        self.extensions.get(name)
+    }
 }
 
-impl Record {
-    #[cfg(feature = "libp2p")]
-    pub fn new(
-        keypair: &Keypair,
-        value: impl AsRef<[u8]>,
-        duration: Duration,
-        seq: u64,
-        ttl: u64,
-    ) -> std::io::Result<Self> {
-        let value = value.as_ref().to_vec();
+// Implemented by hand rather than derived, as the extension fields are flattened into the map
+// of the data.
+impl Encode<DagCborCodec> for Data {
+    fn encode<W: std::io::Write>(&self, c: DagCborCodec, w: &mut W) -> libipld::Result<()> {
+        let mut document = self.extensions.clone();
+        document.insert("Value".into(), Ipld::Bytes(self.value.clone()));
+        document.insert(
+            "ValidityType".into(),
+            Ipld::Integer(i32::from(self.validity_type).into()),
+        );
+        document.insert("Validity".into(), Ipld::Bytes(self.validity.clone()));
+        document.insert("Sequence".into(), Ipld::Integer(self.sequence.into()));
+        document.insert("TTL".into(), Ipld::Integer(self.ttl.into()));
+
+        Ipld::Map(document).encode(c, w)
+    }
+}
 
-        let validity = Utc::now()
-            .add(duration)
-            .to_rfc3339_opts(SecondsFormat::Nanos, false)
-            .into_bytes();
+impl Decode<DagCborCodec> for Data {
+    fn decode<R: std::io::Read + std::io::Seek>(
+        c: DagCborCodec,
+        r: &mut R,
+    ) -> libipld::Result<Self> {
+        //Note: Because of the cbor4ii crate giving errors without exact reason, we will convert it into an ipld document instead
+        //      and map it to the struct manually for the time being.
+        //TODO: Investigate cbor crate and why it would not deserialize the data directly
+        let document = Ipld::decode(c, r)?;
 
-        let validity_type = ValidityType::EOL;
+        let ipld_value = match document.get("Value") {
+            Ok(Ipld::Bytes(bytes)) => bytes.clone(),
+            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into()),
+        };
 
-        let signature_v1_construct = {
-            let mut data = Vec::with_capacity(value.len() + validity.len() + 3);
+        let ipld_validity_type = match document.get("ValidityType") {
+            Ok(Ipld::Integer(0)) => ValidityType::EOL,
+            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into()),
+        };
 
-            data.extend(value.iter());
-            data.extend(validity.iter());
-            data.extend(validity_type.to_string().as_bytes());
+        let ipld_validity = match document.get("Validity") {
+            Ok(Ipld::Bytes(bytes)) => bytes.clone(),
+            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into()),
+        };
 
-            data
+        let ipld_ttl = match document.get("TTL") {
+            Ok(Ipld::Integer(int)) => std::cmp::min(*int, i64::MAX as i128) as u64,
+            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into()),
         };
 
-        let signature_v1 = keypair
-            .sign(&signature_v1_construct)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+        let ipld_sequence = match document.get("Sequence") {
+            Ok(Ipld::Integer(int)) => std::cmp::min(*int, i64::MAX as i128) as u64,
+            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData).into()),
+        };
 
-        let document = ipld!({
-            "Value": value.clone(),
-            "ValidityType": i32::from(validity_type),
-            "Validity": validity.clone(),
-            "Sequence": seq,
-            "TTL": ttl
-        });
+        let extensions = match document {
+            Ipld::Map(map) => map
+                .into_iter()
+                .filter(|(name, _)| !DATA_FIELDS.contains(&name.as_str()))
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+
+        Ok(Data {
+            value: ipld_value,
+            validity_type: ipld_validity_type,
+            validity: ipld_validity,
+            sequence: ipld_sequence,
+            ttl: ipld_ttl,
+            extensions,
+        })
+    }
+}
+
+/// Builds and signs a [`Record`].
+///
+/// By default records are signed with both the V1 and V2 signatures so that they can be
+/// resolved by legacy implementations. [`RecordBuilder::v2_only`] leaves out the V1 signature
+/// and the legacy protobuf fields, as newer implementations do.
+#[cfg(feature = "libp2p")]
+#[derive(Clone, Debug)]
+pub struct RecordBuilder {
+    value: Vec<u8>,
+    eol: Option<DateTime<Utc>>,
+    lifetime: Duration,
+    sequence: u64,
+    ttl: u64,
+    v1_compatible: bool,
+    embed_public_key: bool,
+    extensions: BTreeMap<String, Ipld>,
+}
+
+#[cfg(feature = "libp2p")]
+impl RecordBuilder {
+    /// Record pointing to `value`, valid for 48 hours with a TTL of one minute.
+    pub fn new(value: impl AsRef<[u8]>) -> Self {
+        Self {
+            value: value.as_ref().to_vec(),
+            eol: None,
+            lifetime: Duration::hours(48),
+            sequence: 0,
+            ttl: 60_000_000_000,
+            v1_compatible: true,
+            embed_public_key: false,
+            extensions: BTreeMap::new(),
+        }
+    }
+
+    /// Validity of the record counted from the time it is signed.
+    pub fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self.eol = None;
+        self
+    }
+
+    /// End of the validity of the record, overriding the lifetime.
+    pub fn eol(mut self, eol: DateTime<Utc>) -> Self {
+        self.eol = Some(eol);
+        self
+    }
+
+    pub fn sequence(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Duration in nanoseconds for which the record may be cached.
+    pub fn ttl(mut self, ttl: u64) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Only sign the record with the V2 signature, leaving out the legacy fields.
+    pub fn v2_only(mut self) -> Self {
+        self.v1_compatible = false;
+        self
+    }
+
+    /// Embed the public key even if it can be inlined in the peer id. RSA keys are always embedded.
+    pub fn embed_public_key(mut self, embed: bool) -> Self {
+        self.embed_public_key = embed;
+        self
+    }
+
+    /// Adds a field to the DAG-CBOR data. The fields defined by the specification cannot be used.
+    pub fn extension(mut self, name: impl Into<String>, value: impl Into<Ipld>) -> Self {
+        self.extensions.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn sign(self, keypair: &Keypair) -> std::io::Result<Record> {
+        if let Some(name) = self
+            .extensions
+            .keys()
+            .find(|name| DATA_FIELDS.contains(&name.as_str()))
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("extension field {name} is reserved"),
+            ));
+        }
+
+        let value = self.value;
+
+        let validity = self
+            .eol
+            .unwrap_or_else(|| Utc::now().add(self.lifetime))
+            .to_rfc3339_opts(SecondsFormat::Nanos, true)
+            .into_bytes();
+
+        let validity_type = ValidityType::EOL;
+
+        let signature_v1 = match self.v1_compatible {
+            true => keypair
+                .sign(&signature_v1_data(&value, &validity, validity_type))
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?,
+            false => vec![],
+        };
+
+        let document = Data {
+            value: value.clone(),
+            validity_type,
+            validity: validity.clone(),
+            sequence: self.sequence,
+            ttl: self.ttl,
+            extensions: self.extensions,
+        };
 
         let data = DagCborCodec
             .encode(&document)
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
 
-        let mut signature_v2_construct = vec![
-            0x69, 0x70, 0x6e, 0x73, 0x2d, 0x73, 0x69, 0x67, 0x6e, 0x61, 0x74, 0x75, 0x72, 0x65,
-            0x3a,
-        ];
-
-        signature_v2_construct.extend(data.iter());
-
         let signature_v2 = keypair
-            .sign(&signature_v2_construct)
+            .sign(&[SIGNATURE_V2_PREFIX, &data].concat())
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-        let public_key = match keypair.key_type().into() {
-            KeyType::RSA => keypair
-                .to_protobuf_encoding()
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?,
+        // keys too large to be inlined in the peer id have to be embedded in the record
+        let public_key = match (keypair.key_type().into(), self.embed_public_key) {
+            (KeyType::RSA, _) | (_, true) => keypair.public().encode_protobuf(),
             _ => vec![],
         };
 
-        Ok(Record {
+        let record = Record {
             data,
             value,
             validity_type,
             validity,
-            sequence: seq,
-            ttl,
+            sequence: self.sequence,
+            ttl: self.ttl,
             public_key,
             signature_v1,
             signature_v2,
-        })
+            v1_fields: self.v1_compatible,
+            decoded_size: None,
+        };
+
+        if record.encode()?.len() > MAX_RECORD_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "record exceeds the maximum size",
+            ));
+        }
+
+        Ok(record)
     }
+}
 
-    pub fn decode(data: impl AsRef<[u8]>) -> std::io::Result<Self> {
-        let data = data.as_ref();
+/// Checks applied by [`Record::verify_with`] in addition to the V2 signature, which is always
+/// required.
+#[derive(Clone, Copy, Debug)]
+pub struct ValidationOptions {
+    /// Largest size of the encoded record.
+    pub max_size: usize,
+    /// Reject records carrying the legacy V1 fields.
+    pub v2_only: bool,
+    /// Verify the V1 signature too when the record carries one.
+    pub verify_v1: bool,
+    /// Reject records whose validity has ended.
+    pub reject_expired: bool,
+}
 
-        if data.len() > 10 * 1024 {
-            return Err(std::io::Error::from(std::io::ErrorKind::InvalidData));
+impl Default for ValidationOptions {
+    fn default() -> Self {
+        Self {
+            max_size: MAX_RECORD_SIZE,
+            v2_only: false,
+            verify_v1: false,
+            reject_expired: false,
         }
+    }
+}
+
+impl ValidationOptions {
+    /// Verifies the V1 signature of records carrying one and rejects expired records.
+    pub fn strict() -> Self {
+        Self {
+            verify_v1: true,
+            reject_expired: true,
+            ..Default::default()
+        }
+    }
+}
+
+fn signature_v1_data(value: &[u8], validity: &[u8], validity_type: ValidityType) -> Vec<u8> {
+    let mut data = Vec::with_capacity(value.len() + validity.len() + 3);
+
+    data.extend(value.iter());
+    data.extend(validity.iter());
+    data.extend(validity_type.to_string().as_bytes());
+
+    data
+}
+
+impl Record {
+    #[cfg(feature = "libp2p")]
+    pub fn new(
+        keypair: &Keypair,
+        value: impl AsRef<[u8]>,
+        duration: Duration,
+        seq: u64,
+        ttl: u64,
+    ) -> std::io::Result<Self> {
+        RecordBuilder::new(value)
+            .lifetime(duration)
+            .sequence(seq)
+            .ttl(ttl)
+            .sign(keypair)
+    }
+
+    /// Decodes a record of any size, which is checked against [`ValidationOptions::max_size`]
+    /// when verifying it.
+    pub fn decode(data: impl AsRef<[u8]>) -> std::io::Result<Self> {
+        let data = data.as_ref();
 
         let mut reader = BytesReader::from_bytes(data);
         let entry = generate::ipns_pb::IpnsEntry::from_reader(&mut reader, data)
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-        let record = entry.into();
+        let mut record: Record = entry.into();
+        record.decoded_size = Some(data.len());
+
+        // V2 only records carry their fields in the data alone
+        if !record.v1_fields {
+            if let Ok(data) = parse_data(&record.data) {
+                record.value = data.value;
+                record.validity_type = data.validity_type;
+                record.validity = data.validity;
+                record.sequence = data.sequence;
+                record.ttl = data.ttl;
+            }
+        }
+
         Ok(record)
     }
 
@@ -287,6 +542,12 @@ impl Record {
         self.ttl
     }
 
+    /// Embeds the public key of the signer in the record.
+    #[cfg(feature = "libp2p")]
+    pub fn embed_public_key(&mut self, public_key: &PublicKey) {
+        self.public_key = public_key.encode_protobuf();
+    }
+
     pub fn signature_v1(&self) -> bool {
         !self.signature_v1.is_empty()
     }
@@ -295,46 +556,13 @@ impl Record {
         !self.signature_v2.is_empty()
     }
 
-    pub fn data(&self) -> std::io::Result<Data> {
-        //Note: Because of the cbor4ii crate giving errors without exact reason, we will convert it into an ipld document instead
-        //      and map it to the struct manually for the time being.
-        //TODO: Investigate cbor crate and why it would not deserialize the data directly
-        let document: Ipld = DagCborCodec
-            .decode(&self.data)
-            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
-
-        let ipld_value = match document.get("Value") {
-            Ok(Ipld::Bytes(bytes)) => bytes.clone(),
-            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData)),
-        };
-
-        let ipld_validity_type = match document.get("ValidityType") {
-            Ok(Ipld::Integer(0)) => ValidityType::EOL,
-            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData)),
-        };
-
-        let ipld_validity = match document.get("Validity") {
-            Ok(Ipld::Bytes(bytes)) => bytes.clone(),
-            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData)),
-        };
-
-        let ipld_ttl = match document.get("TTL") {
-            Ok(Ipld::Integer(int)) => std::cmp::min(*int, i64::MAX as i128) as u64,
-            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData)),
-        };
-
-        let ipld_sequence = match document.get("Sequence") {
-            Ok(Ipld::Integer(int)) => std::cmp::min(*int, i64::MAX as i128) as u64,
-            _ => return Err(std::io::Error::from(std::io::ErrorKind::InvalidData)),
-        };
+    /// Whether the record carries the legacy fields needed by V1 implementations.
+    pub fn is_v1_compatible(&self) -> bool {
+        self.v1_fields
+    }
 
-        let data = Data {
-            value: ipld_value,
-            validity_type: ipld_validity_type,
-            validity: ipld_validity,
-            sequence: ipld_sequence,
-            ttl: ipld_ttl,
-        };
+    pub fn data(&self) -> std::io::Result<Data> {
+        let data = parse_data(&self.data)?;
 
         if data.value != self.value
             || data.validity != self.validity
@@ -356,7 +584,14 @@ impl Record {
 
     #[cfg(feature = "libp2p")]
     pub fn verify(&self, peer_id: PeerId) -> std::io::Result<()> {
-        if self.signature_v2.is_empty() && self.signature_v1.is_empty() {
+        self.verify_with(peer_id, &ValidationOptions::default())
+    }
+
+    /// Verifies the record of `peer_id` following the validation rules of the IPNS
+    /// specification, with the additional checks of `options`.
+    #[cfg(feature = "libp2p")]
+    pub fn verify_with(&self, peer_id: PeerId, options: &ValidationOptions) -> std::io::Result<()> {
+        if self.signature_v2.is_empty() {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 "Empty signature field",
@@ -370,6 +605,25 @@ impl Record {
             ));
         }
 
+        let size = match self.decoded_size {
+            Some(size) => size,
+            None => self.encode()?.len(),
+        };
+
+        if size > options.max_size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Record exceeds the maximum size",
+            ));
+        }
+
+        if options.v2_only && self.v1_fields {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Record carries legacy V1 fields",
+            ));
+        }
+
         let key = peer_id.to_bytes();
 
         let mh = libipld::Multihash::from_bytes(&key).expect("msg");
@@ -377,13 +631,19 @@ impl Record {
 
         let public_key = match self.public_key.is_empty() {
             true => cid.hash().digest(),
-            //TODO: Validate internal public key against the multhash publickey
             false => self.public_key.as_ref(),
         };
 
         let pk = PublicKey::try_decode_protobuf(public_key)
             .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
 
+        if pk.to_peer_id() != peer_id {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Public key does not match the peer id",
+            ));
+        }
+
         //TODO: Implement support for RSA
         if matches!(pk.key_type().into(), KeyType::RSA) {
             return Err(std::io::Error::new(
@@ -392,22 +652,291 @@ impl Record {
             ));
         }
 
-        let mut signature_v2 = vec![
-            0x69, 0x70, 0x6e, 0x73, 0x2d, 0x73, 0x69, 0x67, 0x6e, 0x61, 0x74, 0x75, 0x72, 0x65,
-            0x3a,
-        ];
-
-        signature_v2.extend(self.data.iter());
-
+        // also checks that the legacy fields, if any, match the data
         self.data()?;
 
-        if !pk.verify(&signature_v2, &self.signature_v2) {
+        if !pk.verify(
+            &[SIGNATURE_V2_PREFIX, &self.data].concat(),
+            &self.signature_v2,
+        ) {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 "Signature is invalid",
             ));
         }
 
+        if options.verify_v1 && !self.signature_v1.is_empty() {
+            let data = signature_v1_data(&self.value, &self.validity, self.validity_type);
+            if !pk.verify(&data, &self.signature_v1) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "V1 signature is invalid",
+                ));
+            }
+        }
+
+        if options.reject_expired && self.validity()? <= Utc::now() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Record has expired",
+            ));
+        }
+
         Ok(())
     }
 }
+
+fn parse_data(data: &[u8]) -> std::io::Result<Data> {
+    DagCborCodec
+        .decode(data)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cases mirror the test vectors of the IPNS record specification: V1 only records are
+    // rejected, V1+V2 and V2 only records are accepted, a broken V1 signature is ignored unless
+    // requested and a broken V2 signature is always rejected. The records are generated here as
+    // the `.ipns-record` fixtures of the specification are not vendored in this repository.
+
+    fn keypair() -> Keypair {
+        Keypair::ed25519_from_bytes([1; 32]).unwrap()
+    }
+
+    fn eol() -> DateTime<Utc> {
+        DateTime::from_timestamp(2_000_000_000, 0).unwrap()
+    }
+
+    fn builder() -> RecordBuilder {
+        RecordBuilder::new("/ipfs/bafkqaaa").eol(eol()).sequence(1)
+    }
+
+    fn roundtrip(record: &Record) -> Record {
+        Record::decode(record.encode().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn data_encoding() {
+        let record = builder().sign(&keypair()).unwrap();
+
+        // keys sorted by length then bytes, as required by DAG-CBOR
+        let mut expected = vec![0xa5];
+        expected.extend(b"\x63TTL\x1b\x00\x00\x00\x0d\xf8\x47\x58\x00");
+        expected.extend(b"\x65Value\x4e/ipfs/bafkqaaa");
+        expected.extend(b"\x68Sequence\x01");
+        expected.extend(b"\x68Validity\x58\x1e2033-05-18T03:33:20.000000000Z");
+        expected.extend(b"\x6cValidityType\x00");
+
+        assert_eq!(record.data, expected);
+
+        let data: Data = DagCborCodec.decode(&expected).unwrap();
+        assert_eq!(DagCborCodec.encode(&data).unwrap(), expected);
+    }
+
+    #[test]
+    fn v1_only_record_is_rejected() {
+        let keypair = keypair();
+        let mut record = builder().sign(&keypair).unwrap();
+        record.signature_v2.clear();
+        record.data.clear();
+
+        let record = roundtrip(&record);
+        assert!(record.signature_v1());
+        assert!(record.verify(keypair.public().to_peer_id()).is_err());
+    }
+
+    #[test]
+    fn v1_and_v2_record() {
+        let keypair = keypair();
+        let peer_id = keypair.public().to_peer_id();
+        let record = roundtrip(&builder().sign(&keypair).unwrap());
+
+        assert!(record.signature_v1() && record.signature_v2());
+        assert!(record.is_v1_compatible());
+        record.verify(peer_id).unwrap();
+        record
+            .verify_with(
+                peer_id,
+                &ValidationOptions {
+                    verify_v1: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert!(record
+            .verify_with(
+                peer_id,
+                &ValidationOptions {
+                    v2_only: true,
+                    ..Default::default()
+                }
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn v2_only_record() {
+        let keypair = keypair();
+        let peer_id = keypair.public().to_peer_id();
+        let record = builder().v2_only().sign(&keypair).unwrap();
+
+        // only the signature, the public key and the data are encoded
+        let entry: generate::ipns_pb::IpnsEntry = (&record).into();
+        assert!(entry.value.is_empty() && entry.validity.is_empty());
+        assert!(entry.signatureV1.is_empty());
+        assert_eq!((entry.sequence, entry.ttl), (0, 0));
+
+        let decoded = roundtrip(&record);
+        assert!(!decoded.signature_v1() && !decoded.is_v1_compatible());
+        assert_eq!(decoded.sequence(), 1);
+        assert_eq!(decoded.ttl(), 60_000_000_000);
+        assert_eq!(decoded.validity().unwrap(), eol());
+        assert_eq!(decoded.data().unwrap().value(), b"/ipfs/bafkqaaa");
+        assert_eq!(decoded.encode().unwrap(), record.encode().unwrap());
+
+        decoded.verify(peer_id).unwrap();
+        decoded
+            .verify_with(
+                peer_id,
+                &ValidationOptions {
+                    v2_only: true,
+                    ..ValidationOptions::strict()
+                },
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn broken_v1_signature() {
+        let keypair = keypair();
+        let peer_id = keypair.public().to_peer_id();
+        let mut record = builder().sign(&keypair).unwrap();
+        record.signature_v1[0] ^= 0xff;
+
+        let record = roundtrip(&record);
+        record.verify(peer_id).unwrap();
+        assert!(record
+            .verify_with(peer_id, &ValidationOptions::strict())
+            .is_err());
+    }
+
+    #[test]
+    fn broken_v2_signature() {
+        let keypair = keypair();
+        let mut record = builder().v2_only().sign(&keypair).unwrap();
+        record.signature_v2[0] ^= 0xff;
+
+        let record = roundtrip(&record);
+        assert!(record.verify(keypair.public().to_peer_id()).is_err());
+    }
+
+    #[test]
+    fn legacy_fields_must_match_data() {
+        let keypair = keypair();
+        let mut record = builder().sign(&keypair).unwrap();
+        record.value = b"/ipfs/bafkqaaa/other".to_vec();
+        record.signature_v1 = keypair
+            .sign(&signature_v1_data(
+                &record.value,
+                &record.validity,
+                record.validity_type,
+            ))
+            .unwrap();
+
+        let record = roundtrip(&record);
+        assert!(record.verify(keypair.public().to_peer_id()).is_err());
+    }
+
+    #[test]
+    fn size_limit() {
+        let keypair = keypair();
+        let large = Ipld::Bytes(vec![0; MAX_RECORD_SIZE]);
+        assert!(builder().extension("Large", large).sign(&keypair).is_err());
+
+        let record = builder()
+            .extension("Medium", Ipld::Bytes(vec![0; 1024]))
+            .sign(&keypair)
+            .unwrap();
+        assert!(record
+            .verify_with(
+                keypair.public().to_peer_id(),
+                &ValidationOptions {
+                    max_size: 1024,
+                    ..Default::default()
+                }
+            )
+            .is_err());
+
+        // decoding accepts any size, the limit is applied when verifying
+        let mut record = builder().sign(&keypair).unwrap();
+        record.public_key = vec![0; MAX_RECORD_SIZE];
+        let encoded = record.encode().unwrap();
+        let mut record = Record::decode(&encoded).unwrap();
+        record.public_key.clear();
+        let peer_id = keypair.public().to_peer_id();
+        assert!(record.verify(peer_id).is_err());
+        record
+            .verify_with(
+                peer_id,
+                &ValidationOptions {
+                    max_size: encoded.len(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn expired_record() {
+        let keypair = keypair();
+        let peer_id = keypair.public().to_peer_id();
+        let record = builder()
+            .eol(DateTime::from_timestamp(1_000_000_000, 0).unwrap())
+            .sign(&keypair)
+            .unwrap();
+
+        record.verify(peer_id).unwrap();
+        assert!(record
+            .verify_with(peer_id, &ValidationOptions::strict())
+            .is_err());
+    }
+
+    #[test]
+    fn extension_fields() {
+        let keypair = keypair();
+        let record = builder()
+            .extension("Custom", "value")
+            .extension("Count", 3)
+            .sign(&keypair)
+            .unwrap();
+
+        let record = roundtrip(&record);
+        record.verify(keypair.public().to_peer_id()).unwrap();
+
+        let data = record.data().unwrap();
+        assert_eq!(data.extensions().len(), 2);
+        assert_eq!(
+            data.extension("Custom"),
+            Some(&Ipld::String("value".into()))
+        );
+        assert_eq!(data.extension("Count"), Some(&Ipld::Integer(3)));
+        assert_eq!(DagCborCodec.encode(&data).unwrap(), record.data);
+
+        assert!(builder().extension("TTL", 0).sign(&keypair).is_err());
+    }
+
+    #[test]
+    fn embedded_public_key() {
+        let keypair = keypair();
+        let other = Keypair::generate_ed25519();
+
+        let mut record = builder().embed_public_key(true).sign(&keypair).unwrap();
+        assert_eq!(record.public_key, keypair.public().encode_protobuf());
+        record.verify(keypair.public().to_peer_id()).unwrap();
+
+        record.embed_public_key(&other.public());
+        assert!(record.verify(keypair.public().to_peer_id()).is_err());
+    }
+}
@@ -0,0 +1,630 @@
+//! Importing and exporting DAGs as [CAR] (Content Addressable aRchive) files, compatible with
+//! `ipfs dag export` and `ipfs dag import`.
+//!
+//! Exports are CARv1 by default, or CARv2 with a `MultihashIndexSorted` index like go-car writes
+//! them. Imports accept both.
+//!
+//! [CAR]: https://ipld.io/specs/transport/car/
+
+use std::collections::BTreeMap;
+use std::task::{Context, Poll};
+
+use anyhow::{anyhow, Error};
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::stream::{BoxStream, Stream};
+use futures::{FutureExt, StreamExt, TryStreamExt};
+use libipld::cbor::DagCborCodec;
+use libipld::codec::Codec;
+use libipld::{Cid, Ipld, IpldCodec};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tracing::{Instrument, Span};
+
+use crate::refs::{Edge, IpldRefs};
+use crate::repo::Repo;
+use crate::Block;
+
+/// The bytes starting a CARv2, which read as a CARv1 header of version 2.
+const V2_PRAGMA: [u8; 11] = [
+    0x0a, 0xa1, 0x67, 0x76, 0x65, 0x72, 0x73, 0x69, 0x6f, 0x6e, 0x02,
+];
+
+/// Length of the CARv2 header following the pragma.
+const V2_HEADER_LEN: u64 = 40;
+
+/// Multicodec of the index go-car writes into CARv2 files.
+const MULTIHASH_INDEX_SORTED: u64 = 0x0401;
+
+/// Largest accepted header, the default of go-car.
+const MAX_HEADER_SIZE: u64 = 32 * 1024 * 1024;
+
+/// Largest accepted block, the largest one bitswap transfers.
+const MAX_BLOCK_SIZE: u64 = 2 * 1024 * 1024;
+
+/// Room left in a section for the Cid of the block.
+const MAX_CID_SIZE: u64 = 256;
+
+/// Which blocks of the DAG under the root are exported.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CarSelector {
+    /// The whole DAG, like `ipfs dag export`.
+    #[default]
+    All,
+    /// The root and the blocks at most the given number of links away from it.
+    Depth(u64),
+}
+
+/// Format of an exported CAR.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CarVersion {
+    /// Plain CARv1: a header listing the roots followed by the blocks.
+    #[default]
+    V1,
+    /// CARv1 data wrapped with a header and followed by an index of the blocks.
+    V2,
+}
+
+/// Result of [`import`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CarImported {
+    /// The roots listed in the header of the CAR.
+    pub roots: Vec<Cid>,
+    /// Number of blocks read from the CAR.
+    pub blocks: usize,
+}
+
+/// Exports the DAG under `root` as a CAR, see [`crate::Ipfs::export_car`]. Missing blocks are
+/// fetched from the network.
+pub fn export(repo: Repo, root: Cid, selector: CarSelector) -> CarExport {
+    CarExport {
+        repo,
+        root,
+        selector,
+        version: CarVersion::V1,
+        span: None,
+        stream: None,
+    }
+}
+
+/// Stream of the bytes of a CAR, created with [`export`].
+///
+/// The blocks are visited breadth-first from the root, each of them once. Writing a CARv2 visits
+/// the DAG twice: first to size the data for the header, then to write it.
+pub struct CarExport {
+    repo: Repo,
+    root: Cid,
+    selector: CarSelector,
+    version: CarVersion,
+    span: Option<Span>,
+    stream: Option<BoxStream<'static, Result<Bytes, Error>>>,
+}
+
+impl CarExport {
+    /// Writes a CARv2 with an index instead of a CARv1.
+    pub fn v2(mut self) -> Self {
+        self.version = CarVersion::V2;
+        self
+    }
+
+    pub fn span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    fn start(&mut self) -> BoxStream<'static, Result<Bytes, Error>> {
+        let (repo, root, selector) = (self.repo.clone(), self.root, self.selector);
+
+        match self.version {
+            CarVersion::V1 => export_v1(repo, root, selector).boxed(),
+            CarVersion::V2 => export_v2(repo, root, selector).boxed(),
+        }
+    }
+}
+
+impl std::fmt::Debug for CarExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CarExport")
+            .field("root", &self.root)
+            .field("selector", &self.selector)
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+impl Stream for CarExport {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        if self.stream.is_none() {
+            let stream = self.start();
+            self.stream = Some(stream);
+        }
+
+        self.stream
+            .as_mut()
+            .expect("stream was just started")
+            .poll_next_unpin(cx)
+    }
+}
+
+impl std::future::IntoFuture for CarExport {
+    type Output = Result<Bytes, Error>;
+
+    type IntoFuture = futures::future::BoxFuture<'static, Self::Output>;
+
+    fn into_future(mut self) -> Self::IntoFuture {
+        let span = self.span.clone().unwrap_or(Span::current());
+        let mut stream = self.start();
+        async move {
+            let mut data = BytesMut::new();
+            while let Some(bytes) = stream.try_next().await? {
+                data.extend_from_slice(&bytes);
+            }
+            Ok(data.freeze())
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+/// Visits the blocks selected by `selector`, starting from the root.
+fn blocks(
+    repo: Repo,
+    root: Cid,
+    selector: CarSelector,
+) -> impl Stream<Item = Result<Block, Error>> + Send + 'static {
+    async_stream::try_stream! {
+        let block = repo.get_block(&root, &[], false).await?;
+        let ipld = block.decode::<IpldCodec, Ipld>()?;
+        yield block;
+
+        let mut refs = IpldRefs::default().with_only_unique().with_exit_on_error();
+        if let CarSelector::Depth(depth) = selector {
+            refs = refs.with_max_depth(depth);
+        }
+
+        let mut links = refs
+            .refs_of_resolved(repo.clone(), vec![(root, ipld)])
+            .boxed();
+
+        while let Some(Edge { destination, .. }) = links.try_next().await? {
+            // the walk has loaded the block already
+            yield repo.get_block(&destination, &[], false).await?;
+        }
+    }
+}
+
+fn export_v1(
+    repo: Repo,
+    root: Cid,
+    selector: CarSelector,
+) -> impl Stream<Item = Result<Bytes, Error>> + Send + 'static {
+    async_stream::try_stream! {
+        yield header(root)?;
+
+        let mut blocks = blocks(repo, root, selector).boxed();
+        while let Some(block) = blocks.try_next().await? {
+            yield section(&block);
+        }
+    }
+}
+
+fn export_v2(
+    repo: Repo,
+    root: Cid,
+    selector: CarSelector,
+) -> impl Stream<Item = Result<Bytes, Error>> + Send + 'static {
+    async_stream::try_stream! {
+        let header = header(root)?;
+
+        // the offsets of the sections from the start of the data, by multihash code
+        let mut offsets: BTreeMap<u64, Vec<(Vec<u8>, u64)>> = BTreeMap::new();
+        let mut data_size = header.len() as u64;
+
+        let mut sizing = blocks(repo.clone(), root, selector).boxed();
+        while let Some(block) = sizing.try_next().await? {
+            let hash = block.cid().hash();
+            offsets
+                .entry(hash.code())
+                .or_default()
+                .push((hash.digest().to_vec(), data_size));
+            data_size += section_len(&block) as u64;
+        }
+
+        let data_offset = V2_PRAGMA.len() as u64 + V2_HEADER_LEN;
+
+        let mut v2_header = BytesMut::with_capacity(V2_PRAGMA.len() + V2_HEADER_LEN as usize);
+        v2_header.put_slice(&V2_PRAGMA);
+        // no characteristics, like go-car
+        v2_header.put_slice(&[0; 16]);
+        v2_header.put_u64_le(data_offset);
+        v2_header.put_u64_le(data_size);
+        v2_header.put_u64_le(data_offset + data_size);
+        yield v2_header.freeze();
+
+        yield header;
+
+        let mut blocks = blocks(repo, root, selector).boxed();
+        while let Some(block) = blocks.try_next().await? {
+            yield section(&block);
+        }
+
+        yield index(offsets);
+    }
+}
+
+/// Encodes the CARv1 header with the single `root`.
+fn header(root: Cid) -> Result<Bytes, Error> {
+    let header = Ipld::Map(BTreeMap::from([
+        ("roots".to_owned(), Ipld::List(vec![Ipld::Link(root)])),
+        ("version".to_owned(), Ipld::Integer(1)),
+    ]));
+    let header = DagCborCodec.encode(&header)?;
+
+    let mut bytes = BytesMut::with_capacity(header.len() + 10);
+    put_varint(&mut bytes, header.len() as u64);
+    bytes.put_slice(&header);
+    Ok(bytes.freeze())
+}
+
+fn section_len(block: &Block) -> usize {
+    let len = block.cid().encoded_len() + block.data().len();
+    unsigned_varint::encode::u64(len as u64, &mut unsigned_varint::encode::u64_buffer()).len() + len
+}
+
+fn section(block: &Block) -> Bytes {
+    let cid = block.cid().to_bytes();
+    let mut bytes = BytesMut::with_capacity(section_len(block));
+    put_varint(&mut bytes, (cid.len() + block.data().len()) as u64);
+    bytes.put_slice(&cid);
+    bytes.put_slice(block.data());
+    bytes.freeze()
+}
+
+/// Encodes the `MultihashIndexSorted` index: for each multihash code, buckets of entries of the
+/// same digest length, each entry being the digest followed by the offset of the section.
+fn index(offsets: BTreeMap<u64, Vec<(Vec<u8>, u64)>>) -> Bytes {
+    let mut bytes = BytesMut::new();
+    put_varint(&mut bytes, MULTIHASH_INDEX_SORTED);
+    bytes.put_i32_le(offsets.len() as i32);
+
+    for (code, entries) in offsets {
+        let mut widths: BTreeMap<usize, Vec<(Vec<u8>, u64)>> = BTreeMap::new();
+        for (digest, offset) in entries {
+            widths
+                .entry(digest.len())
+                .or_default()
+                .push((digest, offset));
+        }
+
+        bytes.put_u64_le(code);
+        bytes.put_i32_le(widths.len() as i32);
+
+        for (len, mut entries) in widths {
+            entries.sort_unstable();
+
+            let width = len + 8;
+            bytes.put_u32_le(width as u32);
+            bytes.put_i64_le((width * entries.len()) as i64);
+            for (digest, offset) in entries {
+                bytes.put_slice(&digest);
+                bytes.put_u64_le(offset);
+            }
+        }
+    }
+
+    bytes.freeze()
+}
+
+fn put_varint(bytes: &mut BytesMut, value: u64) {
+    bytes.put_slice(unsigned_varint::encode::u64(
+        value,
+        &mut unsigned_varint::encode::u64_buffer(),
+    ));
+}
+
+/// Imports the blocks of a CARv1 or CARv2 into the `repo`, verifying each of them against its
+/// Cid, see [`crate::Ipfs::import_car`]. The roots are pinned recursively with `pin_roots`, which
+/// requires the CAR to contain the whole DAGs.
+pub async fn import<R>(repo: &Repo, reader: R, pin_roots: bool) -> Result<CarImported, Error>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let mut reader = CountingReader { reader, read: 0 };
+
+    let (version, mut roots) = read_header(&mut reader).await?;
+
+    let blocks = match version {
+        1 => import_blocks(repo, &mut reader).await?,
+        2 => {
+            let mut header = [0u8; V2_HEADER_LEN as usize];
+            reader.read_exact(&mut header).await?;
+
+            let field =
+                |at: usize| u64::from_le_bytes(header[at..at + 8].try_into().expect("eight bytes"));
+            let (data_offset, data_size) = (field(16), field(24));
+
+            let skip = data_offset
+                .checked_sub(reader.read)
+                .ok_or_else(|| anyhow!("invalid CARv2 data offset {data_offset}"))?;
+            tokio::io::copy(&mut (&mut reader).take(skip), &mut tokio::io::sink()).await?;
+
+            let mut data = CountingReader {
+                reader: (&mut reader).take(data_size),
+                read: 0,
+            };
+            let (version, inner_roots) = read_header(&mut data).await?;
+            if version != 1 {
+                return Err(anyhow!("unsupported CARv2 data version {version}"));
+            }
+            roots = inner_roots;
+
+            import_blocks(repo, &mut data).await?
+        }
+        version => return Err(anyhow!("unsupported CAR version {version}")),
+    };
+
+    if pin_roots {
+        for root in &roots {
+            if !repo.is_pinned(root).await? {
+                repo.insert_pin(root, true, true).await?;
+            }
+        }
+    }
+
+    Ok(CarImported { roots, blocks })
+}
+
+/// Reads the CARv1 header, or the CARv2 pragma, returning the version and the roots.
+async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(u64, Vec<Cid>), Error> {
+    let len = read_varint(reader)
+        .await?
+        .ok_or_else(|| anyhow!("missing CAR header"))?;
+    let header = read_bounded(reader, len, MAX_HEADER_SIZE, "header").await?;
+
+    let Ipld::Map(mut header) = DagCborCodec.decode::<Ipld>(&header)? else {
+        return Err(anyhow!("CAR header is not a map"));
+    };
+
+    let version = match header.remove("version") {
+        Some(Ipld::Integer(version)) => u64::try_from(version)?,
+        _ => return Err(anyhow!("CAR header has no version")),
+    };
+
+    let roots = match header.remove("roots") {
+        Some(Ipld::List(roots)) => roots
+            .into_iter()
+            .map(|root| match root {
+                Ipld::Link(cid) => Ok(cid),
+                _ => Err(anyhow!("CAR root is not a link")),
+            })
+            .collect::<Result<_, _>>()?,
+        // the CARv2 pragma has no roots
+        None if version == 2 => Vec::new(),
+        _ => return Err(anyhow!("CAR header has no roots")),
+    };
+
+    Ok((version, roots))
+}
+
+async fn import_blocks<R: AsyncRead + Unpin>(repo: &Repo, reader: &mut R) -> Result<usize, Error> {
+    let mut count = 0;
+
+    // sections of zero length are treated as the end of the data, like go-car optionally does
+    while let Some(len) = read_varint(reader).await?.filter(|len| *len != 0) {
+        let mut section =
+            read_bounded(reader, len, MAX_BLOCK_SIZE + MAX_CID_SIZE, "section").await?;
+
+        let mut cursor = std::io::Cursor::new(&section);
+        let cid = Cid::read_bytes(&mut cursor)?;
+        let data = section.split_off(cursor.position() as usize);
+
+        let block = if cid.hash().code() == rust_unixfs::IDENTITY_HASH_CODE {
+            if cid.hash().digest() != data {
+                return Err(anyhow!("block {cid} does not match its inlined data"));
+            }
+            Block::new_unchecked(cid, data)
+        } else {
+            Block::new(cid, data).map_err(|e| anyhow!("invalid block {cid}: {e}"))?
+        };
+
+        repo.put_block(block).await?;
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Reads `len` bytes declared by the CAR, refusing lengths over `max` before allocating.
+async fn read_bounded<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    len: u64,
+    max: u64,
+    what: &str,
+) -> Result<Vec<u8>, Error> {
+    if len > max {
+        return Err(anyhow!(
+            "CAR {what} of {len} bytes exceeds the limit of {max}"
+        ));
+    }
+    let mut bytes = vec![0; usize::try_from(len)?];
+    reader.read_exact(&mut bytes).await?;
+    Ok(bytes)
+}
+
+/// Reads an unsigned varint, or returns `None` at the end of the input.
+async fn read_varint<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<u64>, Error> {
+    let mut buffer = unsigned_varint::encode::u64_buffer();
+
+    for i in 0..buffer.len() {
+        match reader.read_u8().await {
+            Ok(byte) => buffer[i] = byte,
+            Err(e) if i == 0 && e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+
+        if unsigned_varint::decode::is_last(buffer[i]) {
+            let (value, _) = unsigned_varint::decode::u64(&buffer[..=i])?;
+            return Ok(Some(value));
+        }
+    }
+
+    Err(anyhow!("varint is too long"))
+}
+
+/// Keeps count of the bytes read, to find the data of a CARv2.
+struct CountingReader<R> {
+    reader: R,
+    read: u64,
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for CountingReader<R> {
+    fn poll_read(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let this = &mut *self;
+        futures::ready!(std::pin::Pin::new(&mut this.reader).poll_read(cx, buf))?;
+        this.read += (buf.filled().len() - before) as u64;
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CarSelector, V2_PRAGMA};
+    use crate::unixfs::{AddOpt, AddOption};
+    use futures::StreamExt;
+    use rust_unixfs::file::adder::Chunker;
+
+    async fn add_file(ipfs: &crate::Ipfs, content: Vec<u8>) -> libipld::Cid {
+        let stream = futures::stream::once(async move { Ok(bytes::Bytes::from(content)) });
+        let path = ipfs
+            .unixfs()
+            .add(
+                AddOpt::Stream(stream.boxed()),
+                AddOption {
+                    chunk: Chunker::Size(100),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        *path.root().cid().unwrap()
+    }
+
+    #[tokio::test]
+    async fn export_and_import_v1() {
+        let ipfs = crate::Node::new("test_node").await;
+        let content = (0..1000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let root = add_file(&ipfs, content.clone()).await;
+
+        let car = ipfs.export_car(root, CarSelector::All).await.unwrap();
+        assert_eq!(&car[..2], &[0x38, 0xa2], "header of a single CIDv0 root");
+
+        let other = crate::Node::new("other_node").await;
+        let imported = other.import_car(&car[..], true).await.unwrap();
+        assert_eq!(imported.roots, vec![root]);
+        // ten leaves and the root
+        assert_eq!(imported.blocks, 11);
+        assert!(other.is_pinned(&root).await.unwrap());
+
+        let data = other.cat_unixfs(root, None).await.unwrap();
+        assert_eq!(data, content);
+
+        // only the root
+        let car = ipfs.export_car(root, CarSelector::Depth(0)).await.unwrap();
+        let other = crate::Node::new("other_node").await;
+        let imported = other.import_car(&car[..], false).await.unwrap();
+        assert_eq!(imported.blocks, 1);
+    }
+
+    #[tokio::test]
+    async fn export_and_import_v2() {
+        let ipfs = crate::Node::new("test_node").await;
+        let content = (0..1000u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let root = add_file(&ipfs, content.clone()).await;
+
+        let v1 = ipfs.export_car(root, CarSelector::All).await.unwrap();
+        let car = ipfs.export_car(root, CarSelector::All).v2().await.unwrap();
+
+        assert_eq!(car[..11], V2_PRAGMA);
+        let field = |at: usize| u64::from_le_bytes(car[at..at + 8].try_into().unwrap());
+        let (data_offset, data_size, index_offset) = (field(27), field(35), field(43));
+        assert_eq!(data_offset, 51);
+        assert_eq!(data_size, v1.len() as u64);
+        assert_eq!(index_offset, data_offset + data_size);
+        assert_eq!(
+            &car[data_offset as usize..index_offset as usize],
+            &v1[..],
+            "the data is the CARv1"
+        );
+
+        // MultihashIndexSorted with one sha2-256 bucket of eleven 32 byte digests
+        let index = &car[index_offset as usize..];
+        assert_eq!(&index[..2], &[0x81, 0x08]);
+        assert_eq!(index[2..6], 1i32.to_le_bytes());
+        assert_eq!(index[6..14], 0x12u64.to_le_bytes());
+        assert_eq!(index[14..18], 1i32.to_le_bytes());
+        assert_eq!(index[18..22], 40u32.to_le_bytes());
+        assert_eq!(index[22..30], (11 * 40i64).to_le_bytes());
+        assert_eq!(index.len(), 30 + 11 * 40);
+
+        // every entry points at the section of its block
+        for entry in index[30..].chunks(40) {
+            let offset = u64::from_le_bytes(entry[32..].try_into().unwrap()) as usize;
+            let section = &v1[offset..];
+            // after the varint length and the multihash prefix of the CIDv0
+            let digest_at = section.iter().position(|b| *b & 0x80 == 0).unwrap() + 1 + 2;
+            assert_eq!(&section[digest_at..digest_at + 32], &entry[..32]);
+        }
+
+        let other = crate::Node::new("other_node").await;
+        let imported = other.import_car(&car[..], true).await.unwrap();
+        assert_eq!(imported.roots, vec![root]);
+        assert_eq!(imported.blocks, 11);
+
+        let data = other.cat_unixfs(root, None).await.unwrap();
+        assert_eq!(data, content);
+    }
+
+    #[tokio::test]
+    async fn import_rejects_corrupted_blocks() {
+        let ipfs = crate::Node::new("test_node").await;
+        let root = add_file(&ipfs, vec![1; 50]).await;
+
+        let mut car = ipfs
+            .export_car(root, CarSelector::All)
+            .await
+            .unwrap()
+            .to_vec();
+        let last = car.len() - 1;
+        car[last] ^= 0xff;
+
+        let other = crate::Node::new("other_node").await;
+        assert!(other.import_car(&car[..], false).await.is_err());
+        assert!(!other.repo().contains(&root).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn import_rejects_huge_lengths() {
+        let ipfs = crate::Node::new("test_node").await;
+
+        let mut huge = bytes::BytesMut::new();
+        super::put_varint(&mut huge, u64::MAX >> 1);
+        let error = ipfs.import_car(&huge[..], false).await.unwrap_err();
+        assert!(error.to_string().contains("header"), "{error}");
+
+        let root = add_file(&ipfs, vec![1; 50]).await;
+        let car = ipfs.export_car(root, CarSelector::All).await.unwrap();
+        let mut car = car.to_vec();
+        car.extend_from_slice(&huge);
+        let other = crate::Node::new("other_node").await;
+        let error = other.import_car(&car[..], false).await.unwrap_err();
+        assert!(error.to_string().contains("section"), "{error}");
+    }
+}
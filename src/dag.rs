@@ -709,7 +709,10 @@ impl ResolvedNode {
     /// Unwraps the dagpb block variant and turns others into UnexpectedResolved.
     /// This is useful wherever unixfs operations are continued after resolving an IpfsPath.
     pub fn into_unixfs_block(self) -> Result<Block, UnexpectedResolved> {
-        if self.source().codec() != <IpldCodec as Into<u64>>::into(IpldCodec::DagPb) {
+        let codec = self.source().codec();
+        if codec != <IpldCodec as Into<u64>>::into(IpldCodec::DagPb)
+            && codec != <IpldCodec as Into<u64>>::into(IpldCodec::Raw)
+        {
             Err(UnexpectedResolved::UnexpectedCodec(
                 IpldCodec::DagPb.into(),
                 self,
@@ -1,11 +1,26 @@
 //! IPNS functionality around [`Ipfs`].
 
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use libipld::Cid;
+use libp2p::PeerId;
+use parking_lot::RwLock;
+
 use crate::error::Error;
 use crate::p2p::DnsResolver;
 use crate::path::{IpfsPath, PathRoot};
 use crate::Ipfs;
 
 mod dnslink;
+pub(crate) mod pubsub;
+pub(crate) mod republisher;
+
+pub use republisher::IpnsRepublisherConfig;
 
 /// IPNS facade around [`Ipns`].
 #[derive(Clone, Debug)]
@@ -19,6 +34,148 @@ pub enum IpnsOption {
     Local,
     #[default]
     DHT,
+    /// Publish and resolve over pubsub, falling back to the DHT when resolving a name for which
+    /// no record has been seen yet.
+    PubSub,
+}
+
+/// Options used by [`Ipns::publish_with_options`].
+#[derive(Clone, Copy, Debug)]
+pub struct IpnsPublishOptions {
+    /// Where the record is published, in addition to the local datastore.
+    pub routing: IpnsOption,
+    /// How long the record remains valid.
+    pub lifetime: Duration,
+    /// How long resolvers may cache the record.
+    pub ttl: Duration,
+    /// Sequence of the record, which has to be higher than the sequence of the current record.
+    /// Defaults to the sequence following the current record.
+    pub sequence: Option<u64>,
+    /// Embed the public key in the record. Keys that cannot be inlined in the peer id, such as
+    /// RSA keys, are always embedded.
+    pub embed_public_key: bool,
+}
+
+impl Default for IpnsPublishOptions {
+    fn default() -> Self {
+        Self {
+            routing: IpnsOption::default(),
+            lifetime: Duration::from_secs(48 * 60 * 60),
+            ttl: Duration::from_secs(60),
+            sequence: None,
+            embed_public_key: false,
+        }
+    }
+}
+
+/// Options used by [`Ipns::resolve_with_options`].
+#[derive(Clone, Copy, Debug)]
+pub struct IpnsResolveOptions {
+    /// Where the record is looked up, in addition to the local datastore.
+    pub routing: IpnsOption,
+    /// How long to wait for records from the DHT.
+    pub timeout: Duration,
+    /// Amount of valid records to collect from the DHT before picking the freshest one. The
+    /// lookup stops as soon as it is reached, and fails if fewer records are found. When `None`,
+    /// records are collected until the lookup completes or times out.
+    pub quorum: Option<NonZeroUsize>,
+    /// Ignore records whose validity has ended.
+    pub reject_expired: bool,
+    /// Reuse records previously resolved from the DHT for the duration of their TTL.
+    pub cache: bool,
+}
+
+impl Default for IpnsResolveOptions {
+    fn default() -> Self {
+        Self {
+            routing: IpnsOption::default(),
+            timeout: Duration::from_secs(60 * 2),
+            quorum: None,
+            reject_expired: true,
+            cache: true,
+        }
+    }
+}
+
+/// Records resolved from the DHT, kept for the duration of their TTL.
+#[derive(Clone, Default)]
+pub(crate) struct RecordCache {
+    inner: Arc<RwLock<HashMap<PeerId, (rust_ipns::Record, Instant)>>>,
+}
+
+impl RecordCache {
+    fn get(&self, peer_id: &PeerId) -> Option<rust_ipns::Record> {
+        let cache = self.inner.read();
+        let (record, expires) = cache.get(peer_id)?;
+        (*expires > Instant::now()).then(|| record.clone())
+    }
+
+    /// Caches the record for its TTL, bounded by the end of its validity.
+    fn insert(&self, peer_id: PeerId, record: &rust_ipns::Record) {
+        let remaining = record
+            .validity()
+            .ok()
+            .and_then(|eol| {
+                (eol.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                    .to_std()
+                    .ok()
+            })
+            .unwrap_or_default();
+        let lifetime = Duration::from_nanos(record.ttl()).min(remaining);
+
+        let mut cache = self.inner.write();
+        match lifetime.is_zero() {
+            true => cache.remove(&peer_id),
+            false => cache.insert(peer_id, (record.clone(), Instant::now() + lifetime)),
+        };
+    }
+
+    fn remove(&self, peer_id: &PeerId) {
+        self.inner.write().remove(peer_id);
+    }
+}
+
+/// Returns the datastore key of the records of `peer_id`.
+fn datastore_key(peer_id: &PeerId) -> Result<String, Error> {
+    let hash = libipld::multihash::Multihash::from_bytes(&peer_id.to_bytes())?;
+    let cid = Cid::new_v1(0x72, hash);
+
+    //TODO: Determine if we want to encode the cid of the multihash in base32 or if we can just use the peer id instead
+    Ok(format!(
+        "/ipns/{}",
+        cid.to_string_of_base(libipld::multibase::Base::Base36Lower)?
+    ))
+}
+
+/// Decodes and verifies a record of `peer_id`.
+fn decode_record(peer_id: PeerId, data: &[u8]) -> Option<rust_ipns::Record> {
+    let record = rust_ipns::Record::decode(data).ok()?;
+    record.verify(peer_id).ok()?;
+    record.data().ok()?;
+    Some(record)
+}
+
+fn is_expired(record: &rust_ipns::Record) -> bool {
+    record
+        .validity()
+        .map(|eol| eol <= chrono::Utc::now())
+        .unwrap_or(true)
+}
+
+/// Appends the remaining segments of the resolved path to the value of the record.
+fn record_path<'a>(
+    record: &rust_ipns::Record,
+    remaining: impl Iterator<Item = &'a str>,
+) -> Result<IpfsPath, Error> {
+    let data = record.data()?;
+    let path = String::from_utf8_lossy(data.value()).to_string();
+
+    let mut internal_path = IpfsPath::from_str(&path)?;
+    internal_path
+        .path
+        .push_split(remaining)
+        .map_err(|_| crate::path::IpfsPathError::InvalidPath(path))?;
+    Ok(internal_path)
 }
 
 impl Ipns {
@@ -35,102 +192,172 @@ impl Ipns {
     }
 
     /// Resolves a ipns path to an ipld path.
-    // TODO: Implement ipns pubsub
-    // TODO: Maybe implement a check to the dht store itself too?
     pub async fn resolve(&self, path: &IpfsPath) -> Result<IpfsPath, Error> {
+        self.resolve_with_options(path, Default::default()).await
+    }
+
+    /// Resolves a ipns path to an ipld path, looking the record up with the given option in
+    /// addition to the records stored locally.
+    pub async fn resolve_with_option(
+        &self,
+        path: &IpfsPath,
+        option: IpnsOption,
+    ) -> Result<IpfsPath, Error> {
+        let options = IpnsResolveOptions {
+            routing: option,
+            ..Default::default()
+        };
+        self.resolve_with_options(path, options).await
+    }
+
+    /// Resolves a ipns path to an ipld path with the given options.
+    // TODO: Maybe implement a check to the dht store itself too?
+    pub async fn resolve_with_options(
+        &self,
+        path: &IpfsPath,
+        options: IpnsResolveOptions,
+    ) -> Result<IpfsPath, Error> {
         let path = path.to_owned();
         match path.root() {
             PathRoot::Ipld(_) => Ok(path),
             PathRoot::Ipns(peer) => {
-                use std::str::FromStr;
-                use std::time::Duration;
-
-                use futures::StreamExt;
-                use libipld::Cid;
-                use libp2p::PeerId;
+                let peer = *peer;
+                let path_iter = path.iter();
 
-                let mut path_iter = path.iter();
+                let usable =
+                    |record: &rust_ipns::Record| !(options.reject_expired && is_expired(record));
+
+                let mb = datastore_key(&peer)?;
+
+                //Although stored locally, we should verify the record anyway
+                let local = match self.ipfs.repo().data_store().get(mb.as_bytes()).await {
+                    Ok(Some(data)) => decode_record(peer, &data).filter(usable),
+                    _ => None,
+                };
+
+                let record = match options.routing {
+                    IpnsOption::Local => local.ok_or(anyhow::anyhow!("No records found"))?,
+                    IpnsOption::DHT => match local {
+                        Some(record) => record,
+                        None => self.resolve_cached_dht(peer, mb, &options).await?,
+                    },
+                    IpnsOption::PubSub => {
+                        if let Err(e) = pubsub::subscribe(&self.ipfs, peer).await {
+                            tracing::warn!(%peer, error = %e, "ipns: unable to subscribe");
+                        }
+
+                        let cached = self
+                            .ipfs
+                            .ipns_pubsub
+                            .get(&peer)
+                            .and_then(|data| decode_record(peer, &data))
+                            .filter(usable);
+
+                        let freshest = match (local, cached) {
+                            (Some(a), Some(b)) => match pubsub::compare(&a, &b) {
+                                std::cmp::Ordering::Less => Some(b),
+                                _ => Some(a),
+                            },
+                            (a, b) => a.or(b),
+                        };
+
+                        match freshest {
+                            Some(record) => record,
+                            None => self.resolve_cached_dht(peer, mb, &options).await?,
+                        }
+                    }
+                };
 
-                let hash: libipld::multihash::Multihash =
-                    libipld::multihash::Multihash::from_bytes(&peer.to_bytes())?;
+                record_path(&record, path_iter)
+            }
+            PathRoot::Dns(domain) => {
+                let path_iter = path.iter();
+                Ok(dnslink::resolve(self.resolver.unwrap_or_default(), domain, path_iter).await?)
+            }
+        }
+    }
 
-                let cid = Cid::new_v1(0x72, hash);
+    /// Returns the cached record of `peer` when caching is enabled and it is usable, otherwise
+    /// looks it up in the DHT and caches the result.
+    async fn resolve_cached_dht(
+        &self,
+        peer: PeerId,
+        key: String,
+        options: &IpnsResolveOptions,
+    ) -> Result<rust_ipns::Record, Error> {
+        let cached = self
+            .ipfs
+            .ipns_cache
+            .get(&peer)
+            .filter(|_| options.cache)
+            .filter(|record| !(options.reject_expired && is_expired(record)));
+
+        if let Some(record) = cached {
+            return Ok(record);
+        }
 
-                let mb = format!(
-                    "/ipns/{}",
-                    cid.to_string_of_base(libipld::multibase::Base::Base36Lower)?
-                );
+        let record = self.resolve_dht(peer, key, options).await?;
+        if options.cache {
+            self.ipfs.ipns_cache.insert(peer, &record);
+        }
+        Ok(record)
+    }
 
-                //TODO: Determine if we want to encode the cid of the multihash in base32 or if we can just use the peer id instead
-                // let mb = format!("/ipns/{}", peer);
-
-                let repo = self.ipfs.repo();
-                let datastore = repo.data_store();
-
-                if let Ok(Some(data)) = datastore.get(mb.as_bytes()).await {
-                    if let Ok(path) = rust_ipns::Record::decode(data).and_then(|record| {
-                        //Although stored locally, we should verify the record anyway
-                        record.verify(*peer)?;
-                        let data = record.data()?;
-                        let path = String::from_utf8_lossy(data.value());
-                        IpfsPath::from_str(&path)
-                            .and_then(|mut internal_path| {
-                                internal_path.path.push_split(path_iter.by_ref()).map_err(
-                                    |_| crate::path::IpfsPathError::InvalidPath(path.to_string()),
-                                )?;
-                                Ok(internal_path)
-                            })
-                            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
-                    }) {
-                        return Ok(path);
-                    }
+    /// Looks up the records of `peer` in the DHT, returning the freshest one.
+    async fn resolve_dht(
+        &self,
+        peer: PeerId,
+        key: String,
+        options: &IpnsResolveOptions,
+    ) -> Result<rust_ipns::Record, Error> {
+        let mut stream = self.ipfs.dht_get(key).await?;
+
+        let mut records = vec![];
+
+        let collect = async {
+            while let Some(record) = stream.next().await {
+                let Some(peer_id) = record
+                    .key
+                    .as_ref()
+                    .get(6..)
+                    .and_then(|key| PeerId::from_bytes(key).ok())
+                else {
+                    continue;
+                };
+                if peer_id != peer {
+                    continue;
                 }
 
-                let stream = self.ipfs.dht_get(mb).await?;
-
-                //TODO: Implement configurable timeout
-                let mut records = tokio::time::timeout(
-                    Duration::from_secs(60 * 2),
-                    stream
-                        .filter_map(|record| async move {
-                            let key = &record.key.as_ref()[6..];
-                            let record = rust_ipns::Record::decode(&record.value).ok()?;
-                            let peer_id = PeerId::from_bytes(key).ok()?;
-                            record.verify(peer_id).ok()?;
-                            Some(record)
-                        })
-                        .collect::<Vec<_>>(),
-                )
-                .await
-                .unwrap_or_default();
-
-                if records.is_empty() {
-                    anyhow::bail!("No records found")
+                let Some(record) = decode_record(peer_id, &record.value) else {
+                    continue;
+                };
+                if options.reject_expired && is_expired(&record) {
+                    continue;
                 }
 
-                records.sort_by_key(|record| record.sequence());
-
-                let record = records.last().ok_or(anyhow::anyhow!("No records found"))?;
-
-                let data = record.data()?;
+                records.push(record);
+                if matches!(options.quorum, Some(quorum) if records.len() >= quorum.get()) {
+                    break;
+                }
+            }
+        };
 
-                let path = String::from_utf8_lossy(data.value()).to_string();
+        _ = tokio::time::timeout(options.timeout, collect).await;
 
-                IpfsPath::from_str(&path)
-                    .map_err(anyhow::Error::from)
-                    .and_then(|mut internal_path| {
-                        internal_path
-                            .path
-                            .push_split(path_iter)
-                            .map_err(|_| crate::path::IpfsPathError::InvalidPath(path))?;
-                        Ok(internal_path)
-                    })
-            }
-            PathRoot::Dns(domain) => {
-                let path_iter = path.iter();
-                Ok(dnslink::resolve(self.resolver.unwrap_or_default(), domain, path_iter).await?)
+        if let Some(quorum) = options.quorum {
+            if records.len() < quorum.get() {
+                anyhow::bail!(
+                    "Found {} records, fewer than the quorum of {}",
+                    records.len(),
+                    quorum
+                );
             }
         }
+
+        records
+            .into_iter()
+            .max_by(pubsub::compare)
+            .ok_or(anyhow::anyhow!("No records found"))
     }
 
     pub async fn publish(
@@ -139,10 +366,21 @@ impl Ipns {
         path: &IpfsPath,
         option: Option<IpnsOption>,
     ) -> Result<IpfsPath, Error> {
-        use libipld::Cid;
-        use libp2p::kad::Quorum;
-        use std::str::FromStr;
+        let options = IpnsPublishOptions {
+            routing: option.unwrap_or_default(),
+            ..Default::default()
+        };
+        self.publish_with_options(key, path, options).await
+    }
 
+    /// Publishes a record pointing to `path` under the name of `key`, or of the node itself when
+    /// `key` is `None`.
+    pub async fn publish_with_options(
+        &self,
+        key: Option<&str>,
+        path: &IpfsPath,
+        options: IpnsPublishOptions,
+    ) -> Result<IpfsPath, Error> {
         let keypair = match key {
             Some(key) => self.ipfs.keystore().get_keypair(key).await?,
             None => self.ipfs.keypair().clone(),
@@ -150,15 +388,7 @@ impl Ipns {
 
         let peer_id = keypair.public().to_peer_id();
 
-        let hash: libipld::multihash::Multihash =
-            libipld::multihash::Multihash::from_bytes(&peer_id.to_bytes())?;
-
-        let cid = Cid::new_v1(0x72, hash);
-
-        let mb = format!(
-            "/ipns/{}",
-            cid.to_string_of_base(libipld::multibase::Base::Base36Lower)?
-        );
+        let mb = datastore_key(&peer_id)?;
 
         let repo = self.ipfs.repo();
 
@@ -177,33 +407,344 @@ impl Ipns {
 
             let ipfs_path = IpfsPath::from_str(&String::from_utf8_lossy(data.value()))?;
 
-            if ipfs_path.eq(path) {
+            if ipfs_path.eq(path) && options.sequence.is_none() && !is_expired(&record) {
                 return IpfsPath::from_str(&mb);
             }
 
+            if let Some(sequence) = options.sequence {
+                if sequence <= record.sequence() {
+                    anyhow::bail!(
+                        "sequence {sequence} is not higher than the current sequence {}",
+                        record.sequence()
+                    );
+                }
+            }
+
             // inc req of the record
             seq = record.sequence() + 1;
         }
 
+        let seq = options.sequence.unwrap_or(seq);
+
         let path_bytes = path.to_string();
 
-        let record = rust_ipns::Record::new(
-            &keypair,
-            path_bytes.as_bytes(),
-            chrono::Duration::hours(48),
-            seq,
-            60000,
-        )?;
+        let record = rust_ipns::RecordBuilder::new(path_bytes.as_bytes())
+            .lifetime(chrono::Duration::from_std(options.lifetime)?)
+            .sequence(seq)
+            .ttl(options.ttl.as_nanos().try_into().unwrap_or(u64::MAX))
+            .embed_public_key(options.embed_public_key)
+            .sign(&keypair)?;
 
         let bytes = record.encode()?;
 
         datastore.put(mb.as_bytes(), &bytes).await?;
 
-        match option.unwrap_or_default() {
-            IpnsOption::DHT => self.ipfs.dht_put(&mb, bytes, Quorum::One).await?,
+        self.ipfs.ipns_cache.remove(&peer_id);
+        republisher::track(&self.ipfs, &peer_id, options.routing).await?;
+
+        self.route(peer_id, bytes, options.routing).await?;
+
+        IpfsPath::from_str(&mb)
+    }
+
+    /// Announces the encoded record of `peer_id` through `routing`.
+    pub(crate) async fn route(
+        &self,
+        peer_id: PeerId,
+        bytes: Vec<u8>,
+        routing: IpnsOption,
+    ) -> Result<(), Error> {
+        use libp2p::kad::Quorum;
+
+        match routing {
+            IpnsOption::DHT => {
+                let key = datastore_key(&peer_id)?;
+                self.ipfs.dht_put(key, bytes, Quorum::One).await?
+            }
             IpnsOption::Local => {}
+            IpnsOption::PubSub => {
+                // as go-ipfs does, the record is put to the dht as well so that the nodes which
+                // were not subscribed to the name in time can still resolve it
+                let key = datastore_key(&peer_id)?;
+                if let Err(e) = self.ipfs.dht_put(key, bytes.clone(), Quorum::One).await {
+                    tracing::debug!(%peer_id, error = %e, "ipns: unable to put the record to the dht");
+                }
+
+                // kept around to answer peers fetching the record after joining the topic
+                self.ipfs.ipns_pubsub.insert(peer_id, &bytes);
+                pubsub::subscribe(&self.ipfs, peer_id).await?;
+
+                if let Err(e) = self
+                    .ipfs
+                    .pubsub_publish(pubsub::topic(&peer_id), bytes)
+                    .await
+                {
+                    match e.downcast_ref::<libp2p::gossipsub::PublishError>() {
+                        Some(libp2p::gossipsub::PublishError::InsufficientPeers) => {
+                            tracing::debug!(%peer_id, "ipns: no pubsub peers to publish to yet")
+                        }
+                        _ => return Err(e),
+                    }
+                }
+            }
         };
 
-        IpfsPath::from_str(&mb)
+        Ok(())
+    }
+
+    /// Lists the names whose records are republished in the background, which are the names of
+    /// the node key and of the keys in the keystore published by this node. Records published
+    /// before the routing of the names was remembered are not republished until published again.
+    pub async fn republished(&self) -> Result<Vec<PeerId>, Error> {
+        let keypairs = republisher::tracked_keys(&self.ipfs).await?;
+        Ok(keypairs
+            .iter()
+            .map(|keypair| keypair.public().to_peer_id())
+            .collect())
+    }
+
+    /// Stops republishing the records of `name` until a record is published for it again. This is
+    /// remembered across restarts.
+    pub async fn stop_republishing(&self, name: PeerId) -> Result<(), Error> {
+        republisher::untrack(&self.ipfs, &name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        datastore_key, IpnsOption, IpnsPublishOptions, IpnsRepublisherConfig, IpnsResolveOptions,
+    };
+    use crate::{Ipfs, IpfsPath, Node, UninitializedIpfsNoop};
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::{Cid, IpldCodec};
+    use libp2p::PeerId;
+    use std::time::Duration;
+
+    fn path_of(data: &[u8]) -> IpfsPath {
+        IpfsPath::from(Cid::new_v1(
+            IpldCodec::Raw.into(),
+            Code::Sha2_256.digest(data),
+        ))
+    }
+
+    async fn stored_record(node: &Ipfs) -> rust_ipns::Record {
+        stored_record_of(node, &node.keypair().public().to_peer_id()).await
+    }
+
+    async fn stored_record_of(node: &Ipfs, peer_id: &PeerId) -> rust_ipns::Record {
+        let key = datastore_key(peer_id).unwrap();
+        let data = node
+            .repo()
+            .data_store()
+            .get(key.as_bytes())
+            .await
+            .unwrap()
+            .unwrap();
+        rust_ipns::Record::decode(&data).unwrap()
+    }
+
+    fn local() -> IpnsPublishOptions {
+        IpnsPublishOptions {
+            routing: IpnsOption::Local,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn publish_with_explicit_sequence_lifetime_and_ttl() {
+        let node = Node::new("test_node").await;
+        let ipns = node.ipns();
+
+        let options = IpnsPublishOptions {
+            lifetime: Duration::from_secs(60 * 60),
+            ttl: Duration::from_secs(5),
+            sequence: Some(10),
+            ..local()
+        };
+        ipns.publish_with_options(None, &path_of(b"a"), options)
+            .await
+            .unwrap();
+
+        let record = stored_record(&node.ipfs).await;
+        assert_eq!(record.sequence(), 10);
+        assert_eq!(record.ttl(), 5_000_000_000);
+        let remaining = record.validity().unwrap().with_timezone(&chrono::Utc) - chrono::Utc::now();
+        assert!(remaining <= chrono::Duration::hours(1));
+        assert!(remaining > chrono::Duration::minutes(59));
+
+        // sequences have to move forward
+        let options = IpnsPublishOptions {
+            sequence: Some(10),
+            ..local()
+        };
+        assert!(ipns
+            .publish_with_options(None, &path_of(b"b"), options)
+            .await
+            .is_err());
+
+        ipns.publish_with_options(None, &path_of(b"b"), local())
+            .await
+            .unwrap();
+        assert_eq!(stored_record(&node.ipfs).await.sequence(), 11);
+    }
+
+    #[tokio::test]
+    async fn expired_records_are_rejected() {
+        let node = Node::new("test_node").await;
+        let ipns = node.ipns();
+        let name = IpfsPath::from(node.id);
+        let path = path_of(b"a");
+
+        let options = IpnsPublishOptions {
+            lifetime: Duration::from_millis(1),
+            ..local()
+        };
+        ipns.publish_with_options(None, &path, options)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        let options = IpnsResolveOptions {
+            routing: IpnsOption::Local,
+            ..Default::default()
+        };
+        assert!(ipns.resolve_with_options(&name, options).await.is_err());
+
+        let options = IpnsResolveOptions {
+            reject_expired: false,
+            ..options
+        };
+        assert_eq!(
+            ipns.resolve_with_options(&name, options).await.unwrap(),
+            path
+        );
+
+        // republishing the same path renews an expired record
+        ipns.publish_with_options(None, &path, local())
+            .await
+            .unwrap();
+        assert_eq!(stored_record(&node.ipfs).await.sequence(), 1);
+    }
+
+    #[tokio::test]
+    async fn dht_records_are_cached_for_their_ttl() {
+        let node = Node::new("test_node").await;
+        let ipns = node.ipns();
+        let name = IpfsPath::from(node.id);
+        let path = path_of(b"a");
+
+        ipns.publish_with_options(None, &path, local())
+            .await
+            .unwrap();
+        let record = stored_record(&node.ipfs).await;
+
+        // as if it had been resolved from the dht, without a local copy
+        let key = datastore_key(&node.id).unwrap();
+        node.repo()
+            .data_store()
+            .remove(key.as_bytes())
+            .await
+            .unwrap();
+        node.ipns_cache.insert(node.id, &record);
+
+        for routing in [IpnsOption::DHT, IpnsOption::PubSub] {
+            let options = IpnsResolveOptions {
+                routing,
+                timeout: Duration::from_secs(1),
+                ..Default::default()
+            };
+            assert_eq!(
+                ipns.resolve_with_options(&name, options).await.unwrap(),
+                path
+            );
+
+            let options = IpnsResolveOptions {
+                cache: false,
+                ..options
+            };
+            assert!(ipns.resolve_with_options(&name, options).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn republish_expiring_records() {
+        let ipfs = UninitializedIpfsNoop::new()
+            .set_ipns_republisher_config(IpnsRepublisherConfig {
+                interval: Some(Duration::from_millis(100)),
+                threshold: Duration::from_secs(60 * 60),
+                lifetime: Duration::from_secs(2 * 60 * 60),
+            })
+            .start()
+            .await
+            .unwrap();
+        let ipns = ipfs.ipns();
+
+        let own = ipfs.keypair().public().to_peer_id();
+        let other = ipfs
+            .keystore()
+            .generate_ed25519(Some("other"))
+            .await
+            .unwrap()
+            .to_peer_id();
+
+        // expiring within the threshold
+        let expiring = IpnsPublishOptions {
+            lifetime: Duration::from_secs(10 * 60),
+            ..local()
+        };
+        ipns.publish_with_options(None, &path_of(b"a"), expiring)
+            .await
+            .unwrap();
+        ipns.publish_with_options(Some("other"), &path_of(b"b"), local())
+            .await
+            .unwrap();
+
+        let mut names = ipns.republished().await.unwrap();
+        names.sort();
+        let mut expected = vec![own, other];
+        expected.sort();
+        assert_eq!(names, expected);
+
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while stored_record(&ipfs).await.sequence() == 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("record republished");
+
+        let record = stored_record(&ipfs).await;
+        assert_eq!(record.sequence(), 1);
+        assert_eq!(
+            record.data().unwrap().value(),
+            path_of(b"a").to_string().as_bytes()
+        );
+        let remaining = record.validity().unwrap().with_timezone(&chrono::Utc) - chrono::Utc::now();
+        assert!(remaining > chrono::Duration::minutes(119));
+
+        // valid well beyond the threshold
+        assert_eq!(stored_record_of(&ipfs, &other).await.sequence(), 0);
+
+        // local names are republished only locally
+        assert!(matches!(
+            super::republisher::routing(&ipfs, &own).await.unwrap(),
+            Some(IpnsOption::Local)
+        ));
+
+        ipns.stop_republishing(other).await.unwrap();
+        assert!(super::republisher::routing(&ipfs, &other)
+            .await
+            .unwrap()
+            .is_none());
+        assert_eq!(ipns.republished().await.unwrap(), vec![own]);
+
+        // publishing again resumes republishing
+        ipns.publish_with_options(Some("other"), &path_of(b"c"), local())
+            .await
+            .unwrap();
+        assert_eq!(ipns.republished().await.unwrap().len(), 2);
+
+        ipfs.exit_daemon().await;
     }
 }
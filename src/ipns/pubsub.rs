@@ -0,0 +1,216 @@
+//! IPNS over pubsub.
+//!
+//! Records of a name are published on the `/record/<base64url routing key>` gossipsub topic. Once
+//! subscribed, the freshest valid record seen on the topic is cached so that the name resolves
+//! without a DHT lookup, and is served over the fetch protocol to peers joining the topic later.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use libipld::multibase;
+use libp2p::gossipsub::MessageAcceptance;
+use libp2p::PeerId;
+use parking_lot::RwLock;
+use rust_ipns::{Record, ValidationOptions};
+
+use crate::error::Error;
+use crate::{Ipfs, PubsubEvent};
+
+/// Records received over pubsub, shared between the facade and the task answering fetch requests.
+#[derive(Clone, Default)]
+pub(crate) struct IpnsPubsub {
+    inner: Arc<RwLock<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    records: HashMap<PeerId, Vec<u8>>,
+    subscribed: HashSet<PeerId>,
+}
+
+/// The key under which records of `peer_id` are routed: `/ipns/` followed by the peer id bytes.
+pub(crate) fn routing_key(peer_id: &PeerId) -> Vec<u8> {
+    [&b"/ipns/"[..], &peer_id.to_bytes()].concat()
+}
+
+/// The pubsub topic on which records of `peer_id` are published.
+pub(crate) fn topic(peer_id: &PeerId) -> String {
+    format!(
+        "/record/{}",
+        multibase::Base::Base64Url.encode(routing_key(peer_id))
+    )
+}
+
+/// Orders records by sequence, then by the end of their validity.
+pub(crate) fn compare(a: &Record, b: &Record) -> Ordering {
+    a.sequence()
+        .cmp(&b.sequence())
+        .then_with(|| match (a.validity(), b.validity()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            (Ok(_), Err(_)) => Ordering::Greater,
+            (Err(_), Ok(_)) => Ordering::Less,
+            (Err(_), Err(_)) => Ordering::Equal,
+        })
+}
+
+/// Decodes the record of `peer_id`, if valid and not expired.
+fn decode_valid(peer_id: PeerId, data: &[u8]) -> Option<Record> {
+    let record = Record::decode(data).ok()?;
+    let options = ValidationOptions {
+        reject_expired: true,
+        ..Default::default()
+    };
+    record.verify_with(peer_id, &options).ok()?;
+    Some(record)
+}
+
+impl IpnsPubsub {
+    pub fn get(&self, peer_id: &PeerId) -> Option<Vec<u8>> {
+        self.inner.read().records.get(peer_id).cloned()
+    }
+
+    /// Looks a record up by its routing key, as requested over the fetch protocol.
+    pub fn get_by_key(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let peer_id = key
+            .strip_prefix(b"/ipns/")
+            .and_then(|bytes| PeerId::from_bytes(bytes).ok())?;
+        self.get(&peer_id)
+    }
+
+    /// Keeps the record if it is valid for `peer_id` and fresher than the one already cached.
+    /// Returns whether the record was kept.
+    pub fn insert(&self, peer_id: PeerId, data: &[u8]) -> bool {
+        let Some(record) = decode_valid(peer_id, data) else {
+            return false;
+        };
+
+        let mut state = self.inner.write();
+        if let Some(current) = state
+            .records
+            .get(&peer_id)
+            .and_then(|current| Record::decode(current).ok())
+        {
+            if compare(&record, &current) != Ordering::Greater {
+                return false;
+            }
+        }
+
+        state.records.insert(peer_id, data.to_vec());
+        true
+    }
+
+    /// Validates a record received on the topic of `peer_id` before it is forwarded, rejecting
+    /// invalid records and ignoring those older than the cached one.
+    fn validate(&self, peer_id: PeerId, data: &[u8]) -> MessageAcceptance {
+        let Some(record) = decode_valid(peer_id, data) else {
+            return MessageAcceptance::Reject;
+        };
+
+        match self
+            .get(&peer_id)
+            .and_then(|current| Record::decode(current).ok())
+        {
+            Some(current) if compare(&record, &current) == Ordering::Less => {
+                MessageAcceptance::Ignore
+            }
+            _ => MessageAcceptance::Accept,
+        }
+    }
+
+    fn mark_subscribed(&self, peer_id: PeerId) -> bool {
+        self.inner.write().subscribed.insert(peer_id)
+    }
+
+    fn unmark_subscribed(&self, peer_id: &PeerId) {
+        self.inner.write().subscribed.remove(peer_id);
+    }
+}
+
+/// Subscribes to the topic of `peer_id` unless already subscribed, fetching the current record
+/// from the peers already on the topic and keeping track of the records published afterwards.
+pub(crate) async fn subscribe(ipfs: &Ipfs, peer_id: PeerId) -> Result<(), Error> {
+    let cache = ipfs.ipns_pubsub.clone();
+    if !cache.mark_subscribed(peer_id) {
+        return Ok(());
+    }
+
+    let topic = topic(&peer_id);
+
+    let subscription = async {
+        let validator = {
+            let cache = cache.clone();
+            move |_, message: libp2p::gossipsub::Message| {
+                let acceptance = cache.validate(peer_id, &message.data);
+                async move { acceptance }
+            }
+        };
+        let messages = ipfs
+            .pubsub_subscribe_with_validator(topic.clone(), validator)
+            .await?;
+        let events = ipfs.pubsub_events(topic.clone()).await?;
+        Ok::<_, Error>((messages, events))
+    };
+
+    let (mut messages, mut events) = match subscription.await {
+        Ok(streams) => streams,
+        Err(e) => {
+            cache.unmark_subscribed(&peer_id);
+            return Err(e);
+        }
+    };
+
+    tokio::spawn({
+        let ipfs = ipfs.clone();
+        async move {
+            loop {
+                tokio::select! {
+                    message = messages.next() => match message {
+                        Some(message) => {
+                            if cache.insert(peer_id, &message.data) {
+                                tracing::debug!(%peer_id, "ipns: received record over pubsub");
+                            }
+                        }
+                        None => break,
+                    },
+                    Some(event) = events.next() => {
+                        // peers joining later may hold a fresher record than what we have seen
+                        if let PubsubEvent::Subscribe { peer_id: peer } = event {
+                            let ipfs = ipfs.clone();
+                            tokio::spawn(async move { fetch(&ipfs, peer_id, [peer]).await });
+                        }
+                    }
+                }
+            }
+            cache.unmark_subscribed(&peer_id);
+        }
+    });
+
+    let peers = ipfs.pubsub_peers(Some(topic)).await.unwrap_or_default();
+    fetch(ipfs, peer_id, peers).await;
+
+    Ok(())
+}
+
+/// Asks the given peers for the record of `peer_id`, keeping the freshest valid response.
+async fn fetch(ipfs: &Ipfs, peer_id: PeerId, peers: impl IntoIterator<Item = PeerId>) {
+    let key = routing_key(&peer_id);
+    let mut requests = peers
+        .into_iter()
+        .map(|peer| {
+            let key = key.clone();
+            async move {
+                tokio::time::timeout(Duration::from_secs(10), ipfs.fetch_record(peer, key)).await
+            }
+        })
+        .collect::<FuturesUnordered<_>>();
+
+    while let Some(response) = requests.next().await {
+        if let Ok(Ok(Some(data))) = response {
+            ipfs.ipns_pubsub.insert(peer_id, &data);
+        }
+    }
+}
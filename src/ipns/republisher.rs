@@ -0,0 +1,205 @@
+//! Background republishing of IPNS records.
+//!
+//! Every name published by the node, whether it belongs to the node key or to a key of the
+//! [`crate::Keystore`], is periodically checked and re-signed with the next sequence and a fresh
+//! validity before its record expires. The routing each name was last published through is stored
+//! in the datastore next to the record, and the record is republished only through it.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use futures::StreamExt;
+use libp2p::identity::Keypair;
+use libp2p::PeerId;
+
+use super::{datastore_key, decode_record, Ipns, IpnsOption};
+use crate::error::Error;
+use crate::Ipfs;
+
+/// Configuration of the IPNS republisher.
+#[derive(Clone, Copy, Debug)]
+pub struct IpnsRepublisherConfig {
+    /// How often the stored records are checked, `None` disabling the republisher.
+    pub interval: Option<Duration>,
+    /// Records are republished once less than this remains of their validity.
+    pub threshold: Duration,
+    /// Validity of the republished records.
+    pub lifetime: Duration,
+}
+
+impl Default for IpnsRepublisherConfig {
+    fn default() -> Self {
+        Self {
+            interval: Some(Duration::from_secs(60 * 60)),
+            threshold: Duration::from_secs(24 * 60 * 60),
+            lifetime: Duration::from_secs(48 * 60 * 60),
+        }
+    }
+}
+
+/// Datastore key of the routing the records of `peer_id` are republished through. Names without
+/// one are not republished.
+fn routing_key(peer_id: &PeerId) -> String {
+    format!("/local/ipns/republish/{peer_id}")
+}
+
+/// Remembers `routing` as the routing to republish the records of `peer_id` through.
+pub(crate) async fn track(ipfs: &Ipfs, peer_id: &PeerId, routing: IpnsOption) -> Result<(), Error> {
+    let routing: &[u8] = match routing {
+        IpnsOption::Local => b"local",
+        IpnsOption::DHT => b"dht",
+        IpnsOption::PubSub => b"pubsub",
+    };
+    ipfs.repo()
+        .data_store()
+        .put(routing_key(peer_id).as_bytes(), routing)
+        .await
+}
+
+/// Stops republishing the records of `peer_id`.
+pub(crate) async fn untrack(ipfs: &Ipfs, peer_id: &PeerId) -> Result<(), Error> {
+    ipfs.repo()
+        .data_store()
+        .remove(routing_key(peer_id).as_bytes())
+        .await
+}
+
+/// Returns the routing the records of `peer_id` are republished through, if any.
+pub(crate) async fn routing(ipfs: &Ipfs, peer_id: &PeerId) -> Result<Option<IpnsOption>, Error> {
+    let routing = ipfs
+        .repo()
+        .data_store()
+        .get(routing_key(peer_id).as_bytes())
+        .await?;
+
+    match routing.as_deref() {
+        None => Ok(None),
+        Some(b"local") => Ok(Some(IpnsOption::Local)),
+        Some(b"dht") => Ok(Some(IpnsOption::DHT)),
+        Some(b"pubsub") => Ok(Some(IpnsOption::PubSub)),
+        Some(_) => Err(anyhow::anyhow!("invalid ipns routing of {peer_id}")),
+    }
+}
+
+/// Returns the keys which have a record stored in the datastore and a routing to republish it
+/// through.
+pub(crate) async fn tracked_keys(ipfs: &Ipfs) -> Result<Vec<Keypair>, Error> {
+    let mut keypairs = vec![ipfs.keypair().clone()];
+    keypairs.extend(ipfs.keystore().keypairs().await?.collect::<Vec<_>>().await);
+
+    let datastore = ipfs.repo().data_store();
+    let mut seen = HashSet::new();
+    let mut tracked = vec![];
+
+    for keypair in keypairs {
+        let peer_id = keypair.public().to_peer_id();
+        if !seen.insert(peer_id) || routing(ipfs, &peer_id).await?.is_none() {
+            continue;
+        }
+
+        let key = datastore_key(&peer_id)?;
+        if let Ok(Some(_)) = datastore.get(key.as_bytes()).await {
+            tracked.push(keypair);
+        }
+    }
+
+    Ok(tracked)
+}
+
+/// Republishes the records of `keypair` expiring within the configured threshold. Returns whether
+/// the record was republished.
+pub(crate) async fn republish(
+    ipfs: &Ipfs,
+    keypair: &Keypair,
+    config: &IpnsRepublisherConfig,
+) -> Result<bool, Error> {
+    let peer_id = keypair.public().to_peer_id();
+    let key = datastore_key(&peer_id)?;
+    let datastore = ipfs.repo().data_store();
+
+    let Some(routing) = routing(ipfs, &peer_id).await? else {
+        return Ok(false);
+    };
+
+    let Some(data) = datastore.get(key.as_bytes()).await? else {
+        return Ok(false);
+    };
+
+    let record = decode_record(peer_id, &data)
+        .ok_or_else(|| anyhow::anyhow!("stored record of {peer_id} is invalid"))?;
+
+    let remaining = record
+        .validity()
+        .ok()
+        .and_then(|eol| {
+            (eol.with_timezone(&chrono::Utc) - chrono::Utc::now())
+                .to_std()
+                .ok()
+        })
+        .unwrap_or_default();
+
+    if remaining > config.threshold {
+        return Ok(false);
+    }
+
+    let data = record.data()?;
+    let mut builder = rust_ipns::RecordBuilder::new(data.value())
+        .lifetime(chrono::Duration::from_std(config.lifetime)?)
+        .sequence(record.sequence() + 1)
+        .ttl(record.ttl());
+
+    if !record.is_v1_compatible() {
+        builder = builder.v2_only();
+    }
+
+    for (name, value) in data.extensions() {
+        builder = builder.extension(name.clone(), value.clone());
+    }
+
+    let record = builder.sign(keypair)?;
+
+    let bytes = record.encode()?;
+    datastore.put(key.as_bytes(), &bytes).await?;
+    ipfs.ipns_cache.remove(&peer_id);
+
+    Ipns::new(ipfs.clone())
+        .route(peer_id, bytes, routing)
+        .await?;
+
+    Ok(true)
+}
+
+/// Periodically republishes the tracked records until the node exits.
+pub(crate) async fn run(ipfs: Ipfs, config: IpnsRepublisherConfig) {
+    let Some(interval) = config.interval else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        if ipfs.to_task.is_closed() {
+            break;
+        }
+
+        let keypairs = match tracked_keys(&ipfs).await {
+            Ok(keypairs) => keypairs,
+            Err(e) => {
+                tracing::warn!(error = %e, "ipns: unable to list the records to republish");
+                continue;
+            }
+        };
+
+        for keypair in keypairs {
+            let peer_id = keypair.public().to_peer_id();
+            match republish(&ipfs, &keypair, &config).await {
+                Ok(true) => tracing::debug!(%peer_id, "ipns: republished record"),
+                Ok(false) => {}
+                Err(e) => tracing::warn!(%peer_id, error = %e, "ipns: unable to republish"),
+            }
+        }
+    }
+}
@@ -25,11 +25,12 @@
 #[cfg(not(any(feature = "libp2p_bitswap", feature = "beetle_bitswap")))]
 compile_error!("Requires bitswap to be enabled");
 
+pub mod car;
 pub mod config;
 pub mod dag;
 pub mod error;
 pub mod ipns;
-mod keystore;
+pub mod keystore;
 pub mod p2p;
 pub mod path;
 pub mod refs;
@@ -52,10 +53,10 @@ use futures::{
     future::BoxFuture,
     sink::SinkExt,
     stream::{BoxStream, Stream},
-    StreamExt, TryStreamExt,
+    Future, StreamExt, TryStreamExt,
 };
 
-use keystore::Keystore;
+use keystore::{DataStoreKeyStorage, FsKeyStorage, KeyStorageKind, Keystore};
 
 #[cfg(feature = "beetle_bitswap")]
 use p2p::BitswapConfig;
@@ -64,11 +65,14 @@ use p2p::{
     IdentifyConfiguration, KadConfig, KadStoreConfig, PeerInfo, PubsubConfig, RelayConfig,
     SwarmConfig, TransportConfig,
 };
-use repo::{BlockStore, DataStore, GCConfig, GCTrigger, Lock, RepoInsertPin, RepoRemovePin};
+use repo::{
+    BlockStore, DataStore, GCConfig, GCTrigger, Lock, RepoInsertPin, RepoListPins, RepoRemovePin,
+    RepoUpdatePin, RepoVerify, RepoVerifyPins,
+};
 use tokio::task::JoinHandle;
 use tracing::Span;
 use tracing_futures::Instrument;
-use unixfs::{AddOpt, IpfsUnixfs, UnixfsAdd, UnixfsCat, UnixfsGet, UnixfsLs};
+use unixfs::{AddOpt, FilesRoot, IpfsFiles, IpfsUnixfs, UnixfsAdd, UnixfsCat, UnixfsGet, UnixfsLs};
 
 use std::{
     collections::{BTreeSet, HashMap, HashSet},
@@ -94,17 +98,22 @@ pub use self::{
     p2p::BehaviourEvent,
     p2p::KadResult,
     path::IpfsPath,
-    repo::{PinKind, PinMode},
+    repo::{
+        BadBlock, BlockProblem, BlockVerification, PinKind, PinMetadata, PinMode, PinVerification,
+    },
+    task::{ReproviderConfig, ReproviderStats},
 };
 
 pub type Block = libipld::Block<libipld::DefaultParams>;
 
 use libipld::{Cid, Ipld};
 
+use ipns::IpnsRepublisherConfig;
+
 pub use libp2p::{
     self,
     core::transport::ListenerId,
-    gossipsub::{MessageId, PublishError},
+    gossipsub::{MessageAcceptance, MessageId, PublishError},
     identity::Keypair,
     identity::PublicKey,
     kad::{Quorum, RecordKey as Key},
@@ -199,7 +208,7 @@ pub struct IpfsOptions {
     pub kad_configuration: Either<KadConfig, libp2p::kad::Config>,
 
     /// Kad Store Config
-    /// Note: Only supports MemoryStoreConfig at this time
+    /// Note: Records are held in memory and can optionally be persisted to the datastore
     pub kad_store_config: KadStoreConfig,
 
     /// Ping Configuration
@@ -216,6 +225,12 @@ pub struct IpfsOptions {
     /// Repo Provider option
     pub provider: RepoProvider,
 
+    /// Reprovider configuration
+    pub reprovider: ReproviderConfig,
+
+    /// IPNS republisher configuration
+    pub ipns_republisher: IpnsRepublisherConfig,
+
     /// The span for tracing purposes, `None` value is converted to `tracing::trace_span!("ipfs")`.
     ///
     /// All futures returned by `Ipfs`, background task actions and swarm actions are instrumented
@@ -273,6 +288,8 @@ impl Default for IpfsOptions {
             identify_configuration: Default::default(),
             addr_config: Default::default(),
             provider: Default::default(),
+            reprovider: Default::default(),
+            ipns_republisher: Default::default(),
             keystore: Keystore::in_memory(),
             connection_idle: Duration::from_secs(30),
             listening_addrs: vec![],
@@ -315,6 +332,9 @@ pub struct Ipfs {
     identify_conf: IdentifyConfiguration,
     to_task: Sender<IpfsEvent>,
     record_key_validator: HashMap<String, Arc<dyn Fn(&str) -> anyhow::Result<Key> + Sync + Send>>,
+    files_root: FilesRoot,
+    ipns_pubsub: ipns::pubsub::IpnsPubsub,
+    ipns_cache: ipns::RecordCache,
 }
 
 impl std::fmt::Debug for Ipfs {
@@ -350,13 +370,19 @@ enum IpfsEvent {
     Ban(PeerId, Channel<()>),
     /// Unban peer
     Unban(PeerId, Channel<()>),
-    PubsubSubscribe(String, Channel<Option<SubscriptionStream>>),
+    PubsubSubscribe(
+        String,
+        Option<p2p::gossipsub::MessageValidator>,
+        Channel<Option<SubscriptionStream>>,
+    ),
     PubsubUnsubscribe(String, Channel<Result<bool, Error>>),
     PubsubPublish(String, Bytes, Channel<Result<MessageId, PublishError>>),
     PubsubPeers(Option<String>, Channel<Vec<PeerId>>),
     GetBitswapPeers(Channel<BoxFuture<'static, Vec<PeerId>>>),
     WantList(Option<PeerId>, Channel<BoxFuture<'static, Vec<Cid>>>),
     PubsubSubscribed(Channel<Vec<String>>),
+    PubsubPeerScores(Channel<Vec<(PeerId, f64)>>),
+    PubsubSetApplicationScore(PeerId, f64, Channel<bool>),
     AddListeningAddress(Multiaddr, Channel<Multiaddr>),
     RemoveListeningAddress(Multiaddr, Channel<()>),
     Bootstrap(Channel<ReceiverChannel<KadResult>>),
@@ -399,7 +425,13 @@ enum IpfsEvent {
         Channel<HashMap<PeerId, Vec<Multiaddr>>>,
     ),
 
-    Exit,
+    /// Request a value from a peer over the fetch protocol
+    Fetch(PeerId, Vec<u8>, Channel<Option<Vec<u8>>>),
+    Reprovide(Channel<ReproviderStats>),
+    ReproviderStats(OneshotSender<ReproviderStats>),
+
+    /// Stops the background task, answering once the changes queued for the datastore are written
+    Exit(OneshotSender<()>),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -479,6 +511,7 @@ pub struct UninitializedIpfs<C: NetworkBehaviour<ToSwarm = void::Void> + Send> {
     custom_transport: Option<TTransportFn>,
     gc_config: Option<GCConfig>,
     gc_repo_duration: Option<Duration>,
+    keystore_kind: Option<KeyStorageKind>,
 }
 
 pub type UninitializedIpfsNoop = UninitializedIpfs<libp2p::swarm::dummy::Behaviour>;
@@ -505,6 +538,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             custom_transport: None,
             gc_config: None,
             gc_repo_duration: None,
+            keystore_kind: None,
         }
     }
 
@@ -758,6 +792,19 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         self
     }
 
+    /// Set the interval, batching and concurrency used when announcing blocks selected by
+    /// the [`RepoProvider`] option
+    pub fn set_reprovider_config(mut self, config: ReproviderConfig) -> Self {
+        self.options.reprovider = config;
+        self
+    }
+
+    /// Set how often and how early the IPNS records of the node are republished
+    pub fn set_ipns_republisher_config(mut self, config: IpnsRepublisherConfig) -> Self {
+        self.options.ipns_republisher = config;
+        self
+    }
+
     /// Set keypair
     pub fn set_keypair(mut self, keypair: Keypair) -> Self {
         self.keys = Some(keypair);
@@ -773,6 +820,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
     /// Set a keystore
     pub fn set_keystore(mut self, keystore: Keystore) -> Self {
         self.options.keystore = keystore;
+        self.keystore_kind = None;
+        self
+    }
+
+    /// Set the storage of the keystore created when starting the node, so the keys can be kept
+    /// over restarts. Overrides [`UninitializedIpfs::set_keystore`].
+    pub fn set_keystore_storage(mut self, kind: KeyStorageKind) -> Self {
+        self.keystore_kind = Some(kind);
         self
     }
 
@@ -824,6 +879,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             repo_handle,
             gc_config,
             gc_repo_duration,
+            keystore_kind,
             ..
         } = self;
 
@@ -892,7 +948,23 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
         let (to_task, receiver) = channel::<IpfsEvent>(1);
         let id_conf = options.identify_configuration.clone();
 
-        let keystore = options.keystore.clone();
+        let keystore = match keystore_kind {
+            Some(KeyStorageKind::Memory) => Keystore::in_memory(),
+            Some(KeyStorageKind::Filesystem(path)) => {
+                let path = match (path, &options.ipfs_path) {
+                    (Some(path), _) => path,
+                    (None, StoragePath::Disk(path)) => path.join("keystore"),
+                    (None, _) => {
+                        anyhow::bail!("Filesystem keystore requires a path or a repo on disk")
+                    }
+                };
+                Keystore::new(Arc::new(FsKeyStorage::new(path)))
+            }
+            Some(KeyStorageKind::DataStore) => {
+                Keystore::new(Arc::new(DataStoreKeyStorage::new(repo.clone())))
+            }
+            None => options.keystore.clone(),
+        };
 
         let ipfs = Ipfs {
             span: facade_span,
@@ -902,43 +974,21 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             keystore,
             to_task,
             record_key_validator,
+            files_root: FilesRoot::default(),
+            ipns_pubsub: Default::default(),
+            ipns_cache: Default::default(),
         };
 
-        //Note: If `All` or `Pinned` are used, we would have to auto adjust the amount of
-        //      provider records by adding the amount of blocks to the config.
-        //TODO: Add persistent layer for kad store
-        let blocks = match options.provider {
-            RepoProvider::None => vec![],
-            RepoProvider::All => repo.list_blocks().await.unwrap_or_default(),
-            RepoProvider::Pinned => {
-                repo.list_pins(None)
-                    .await
-                    .filter_map(|result| async move { result.map(|(cid, _)| cid).ok() })
-                    .collect()
-                    .await
-            }
-            RepoProvider::Roots => {
-                //TODO: Scan blockstore for root unixfs blocks
-                warn!("RepoProvider::Roots is not implemented... ignoring...");
-                vec![]
-            }
-        };
-
-        let count = blocks.len();
-
+        //Note: If `All` or `Pinned` are used, the limit of the provided keys is raised by the keys
+        //      of the strategy as the reprovider loads them.
         let store_config = &mut options.kad_store_config;
 
-        match store_config.memory.as_mut() {
-            Some(memory_config) => {
-                memory_config.max_provided_keys += count;
-            }
-            None => {
-                store_config.memory = Some(MemoryStoreConfig {
-                    //Provide a buffer to the max amount of provided keys
-                    max_provided_keys: (50 * 1024) + count,
-                    ..Default::default()
-                })
-            }
+        if store_config.memory.is_none() {
+            store_config.memory = Some(MemoryStoreConfig {
+                //Provide a buffer to the max amount of provided keys
+                max_provided_keys: 50 * 1024,
+                ..Default::default()
+            })
         }
 
         let swarm_config = options.swarm_configuration.clone();
@@ -1021,6 +1071,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
 
         let mut fut = task::IpfsTask::new(swarm, repo_events.fuse(), receiver.fuse(), repo);
         fut.swarm_event = swarm_event;
+        fut.ipns_pubsub = ipfs.ipns_pubsub.clone();
         fut.local_external_addr = local_external_addr;
 
         for addr in listening_addrs.into_iter() {
@@ -1033,22 +1084,16 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
             };
         }
 
-        for block in blocks {
-            if let Some(kad) = fut.swarm.behaviour_mut().kademlia.as_mut() {
-                let key = Key::from(block.hash().to_bytes());
-                match kad.start_providing(key) {
-                    Ok(id) => {
-                        let (tx, _rx) = oneshot_channel();
-                        fut.kad_subscriptions.insert(id, tx);
-                    }
-                    Err(e) => match e {
-                        libp2p::kad::store::Error::MaxProvidedKeys => break,
-                        _ => unreachable!(),
-                    },
-                };
-            }
+        fut.set_reprovider(options.provider, options.reprovider);
+        if options.provider != RepoProvider::None {
+            fut.reprovide(None);
         }
 
+        let republisher = tokio::spawn(ipns::republisher::run(
+            ipfs.clone(),
+            options.ipns_republisher,
+        ));
+
         tokio::spawn({
             async move {
                 //Note: For now this is not configurable as its meant for internal testing purposes but may change in the future
@@ -1059,6 +1104,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void> + Send> UninitializedIpfs<C> {
                 } else {
                     fut.run().instrument(swarm_span).await;
                 }
+
+                // the republisher holds on to the repo, and with it the repo lock, until stopped
+                republisher.abort();
             }
         });
         Ok(ipfs)
@@ -1076,11 +1124,16 @@ impl Ipfs {
         &self.repo
     }
 
-    /// Returns an [`IpfsFiles`] for files operations
+    /// Returns an [`IpfsUnixfs`] for files operations
     pub fn unixfs(&self) -> IpfsUnixfs {
         IpfsUnixfs::new(self.clone())
     }
 
+    /// Returns an [`IpfsFiles`] for operations on the mutable file system
+    pub fn files(&self) -> IpfsFiles {
+        IpfsFiles::new(self.clone())
+    }
+
     pub fn ipns(&self) -> Ipns {
         Ipns::new(self.clone())
     }
@@ -1174,17 +1227,60 @@ impl Ipfs {
         self.repo.is_pinned(cid).instrument(span).await
     }
 
-    /// Lists all pins, or the specific kind thereof.
+    /// Lists all pins, or the specific kind thereof. See [`RepoListPins::name`] for listing the
+    /// pins with a given name.
     ///
     /// # Crash unsafety
     ///
     /// Does not currently recover from partial recursive pin insertions.
-    pub async fn list_pins(
-        &self,
-        filter: Option<PinMode>,
-    ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>> {
-        let span = debug_span!(parent: &self.span, "list_pins", ?filter);
-        self.repo.list_pins(filter).instrument(span).await
+    pub fn list_pins(&self, filter: Option<PinMode>) -> RepoListPins {
+        self.repo().list_pins(filter).span(self.span.clone())
+    }
+
+    /// Replaces the recursive pin of `old` with a recursive pin of `new`, carrying over its name
+    /// and metadata.
+    ///
+    /// Both versions are walked together until they meet, so only the blocks which are not
+    /// part of `old` are fetched. The subtrees shared by both versions stay pinned, although
+    /// they may still be read locally to find out which blocks of `old` are no longer needed.
+    pub fn update_pin(&self, old: &Cid, new: &Cid) -> RepoUpdatePin {
+        self.repo().update_pin(old, new).span(self.span.clone())
+    }
+
+    /// Verifies that the blocks of every direct and recursive pin are present and match their
+    /// cids, yielding the outcome of each pin. Direct pins are checked for their root block only.
+    ///
+    /// Missing blocks can be fetched from the network with [`RepoVerifyPins::refetch`], as can
+    /// the blocks quarantined earlier, which are reported with a mismatching hash. The other
+    /// blocks with a mismatching hash are only reported.
+    pub fn verify_pins(&self) -> RepoVerifyPins {
+        self.repo().verify_pins().span(self.span.clone())
+    }
+
+    /// Checks every block of the blockstore against the multihash of its cid, yielding the
+    /// outcome of each block, then the blocks quarantined earlier as corrupted, followed by the
+    /// temporary files left behind by interrupted writes.
+    ///
+    /// Corrupted blocks are quarantined, or removed with [`RepoVerify::remove_corrupted`], so
+    /// that they are no longer served and can be fetched again. Removing them also deletes the
+    /// blocks quarantined earlier.
+    pub fn repo_verify(&self) -> RepoVerify {
+        self.repo().verify().span(self.span.clone())
+    }
+
+    /// Returns the name and metadata of a direct or recursive pin, if any were set.
+    pub async fn pin_metadata(&self, cid: &Cid) -> Result<Option<PinMetadata>, Error> {
+        let span = debug_span!(parent: &self.span, "pin_metadata", cid = %cid);
+        self.repo.pin_metadata(cid).instrument(span).await
+    }
+
+    /// Replaces the name and metadata of a direct or recursive pin.
+    pub async fn set_pin_metadata(&self, cid: &Cid, metadata: PinMetadata) -> Result<(), Error> {
+        let span = debug_span!(parent: &self.span, "set_pin_metadata", cid = %cid);
+        self.repo
+            .set_pin_metadata(cid, metadata)
+            .instrument(span)
+            .await
     }
 
     /// Read specific pins. When `requirement` is `Some`, all pins are required to be of the given
@@ -1473,7 +1569,44 @@ impl Ipfs {
 
             self.to_task
                 .clone()
-                .send(IpfsEvent::PubsubSubscribe(topic.clone(), tx))
+                .send(IpfsEvent::PubsubSubscribe(topic.clone(), None, tx))
+                .await?;
+
+            rx.await??
+                .ok_or_else(|| format_err!("already subscribed to {:?}", topic))
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Subscribes to a given topic like [`Ipfs::pubsub_subscribe`], with each message received
+    /// on the topic being passed to `validator` along with the peer that forwarded it. Only
+    /// accepted messages are delivered to the subscription and forwarded to other peers, while
+    /// rejected messages also penalize the peer that forwarded them. The validator replaces any
+    /// validator set by an earlier subscription to the topic. Messages taking longer than 10
+    /// seconds to validate, or arriving while 256 messages of the topic are being validated, are
+    /// ignored.
+    pub async fn pubsub_subscribe_with_validator<F, Fut>(
+        &self,
+        topic: impl Into<String>,
+        validator: F,
+    ) -> Result<SubscriptionStream, Error>
+    where
+        F: Fn(PeerId, libp2p::gossipsub::Message) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = MessageAcceptance> + Send + 'static,
+    {
+        async move {
+            let topic = topic.into();
+            let validator = p2p::gossipsub::MessageValidator::new(validator);
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::PubsubSubscribe(
+                    topic.clone(),
+                    Some(validator),
+                    tx,
+                ))
                 .await?;
 
             rx.await??
@@ -1593,6 +1726,46 @@ impl Ipfs {
         .await
     }
 
+    /// Returns the gossipsub score of the known pubsub peers, which is empty unless peer scoring
+    /// is enabled through [`p2p::PubsubConfig::scoring`]
+    pub async fn pubsub_peer_scores(&self) -> Result<Vec<(PeerId, f64)>, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::PubsubPeerScores(tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Sets the application specific score of a peer, weighted by
+    /// [`libp2p::gossipsub::PeerScoreParams::app_specific_weight`] in its gossipsub score.
+    ///
+    /// Returns false if peer scoring is disabled or the peer is unknown
+    pub async fn pubsub_set_application_score(
+        &self,
+        peer_id: PeerId,
+        score: f64,
+    ) -> Result<bool, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::PubsubSetApplicationScore(peer_id, score, tx))
+                .await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Returns the known wantlist for the local node when the `peer` is `None` or the wantlist of the given `peer`
     pub async fn bitswap_wantlist(
         &self,
@@ -1784,6 +1957,54 @@ impl Ipfs {
         }
     }
 
+    /// Requests the value stored under `key` from `peer` over the fetch protocol.
+    pub(crate) async fn fetch_record(
+        &self,
+        peer_id: PeerId,
+        key: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, Error> {
+        let (tx, rx) = oneshot_channel();
+
+        self.to_task
+            .clone()
+            .send(IpfsEvent::Fetch(peer_id, key, tx))
+            .await?;
+
+        rx.await?
+    }
+
+    /// Announces every block selected by the [`RepoProvider`] option to the DHT, resolving with
+    /// the statistics of the run once it completes. If a run is already in progress, this waits
+    /// for it to complete instead of starting another.
+    pub async fn reprovide(&self) -> Result<ReproviderStats, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task.clone().send(IpfsEvent::Reprovide(tx)).await?;
+
+            rx.await?
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    /// Returns the progress of the current reprovide run along with statistics of the previous
+    /// ones.
+    pub async fn reprovider_stats(&self) -> Result<ReproviderStats, Error> {
+        async move {
+            let (tx, rx) = oneshot_channel();
+
+            self.to_task
+                .clone()
+                .send(IpfsEvent::ReproviderStats(tx))
+                .await?;
+
+            Ok(rx.await?)
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
     /// Returns a list of peers closest to the given `PeerId`, as suggested by the DHT. The
     /// node must have at least one known peer in its routing table in order for the query
     /// to return any values.
@@ -2089,6 +2310,25 @@ impl Ipfs {
         refs::iplds_refs(self.repo(), iplds, max_depth, unique)
     }
 
+    /// Exports the DAG under `root`, or the part of it chosen with the `selector`, as a CARv1 or
+    /// with [`car::CarExport::v2`] as a CARv2 with an index.
+    ///
+    /// More information available at [`car::export`].
+    pub fn export_car(&self, root: Cid, selector: car::CarSelector) -> car::CarExport {
+        car::export(self.repo.clone(), root, selector).span(self.span.clone())
+    }
+
+    /// Imports the blocks of a CARv1 or CARv2, verifying each of them, and pins the roots
+    /// recursively with `pin_roots`.
+    pub async fn import_car<R>(&self, reader: R, pin_roots: bool) -> Result<car::CarImported, Error>
+    where
+        R: tokio::io::AsyncRead + Unpin + Send,
+    {
+        car::import(&self.repo, reader, pin_roots)
+            .instrument(self.span.clone())
+            .await
+    }
+
     /// Obtain the list of addresses of bootstrapper nodes that are currently used.
     pub async fn get_bootstraps(&self) -> Result<Vec<Multiaddr>, Error> {
         async move {
@@ -2266,7 +2506,10 @@ impl Ipfs {
         self.repo.shutdown();
 
         // ignoring the error because it'd mean that the background task had already been dropped
-        let _ = self.to_task.try_send(IpfsEvent::Exit);
+        let (tx, rx) = oneshot::channel();
+        if self.to_task.try_send(IpfsEvent::Exit(tx)).is_ok() {
+            let _ = rx.await;
+        }
     }
 }
 
@@ -2484,6 +2727,61 @@ mod tests {
         assert_eq!(block, new_block);
     }
 
+    #[tokio::test]
+    async fn test_get_inline_block() {
+        let ipfs = Node::new("test_node").await;
+
+        let data = b"hello block\n".to_vec();
+        let mh =
+            libipld::multihash::Multihash::wrap(rust_unixfs::IDENTITY_HASH_CODE, &data).unwrap();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), mh);
+
+        // resolved from the cid alone, without storage or network
+        let block = ipfs.get_block(&cid).await.unwrap();
+        assert_eq!(block.data(), data);
+
+        let block = Block::new(cid, data).unwrap();
+        ipfs.put_block(block).await.unwrap();
+        assert!(ipfs.repo().list_blocks().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reprovide_pinned_blocks() {
+        let ipfs = UninitializedIpfsNoop::new()
+            .with_default()
+            .set_provider(RepoProvider::Pinned)
+            .start()
+            .await
+            .unwrap();
+
+        let mut cids = vec![];
+        for data in [&b"pinned\n"[..], &b"not pinned\n"[..]] {
+            let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
+            let block = Block::new(cid, data.to_vec()).unwrap();
+            cids.push(ipfs.put_block(block).await.unwrap());
+        }
+        ipfs.insert_pin(&cids[0]).await.unwrap();
+
+        // wait for the newly pinned block to be announced
+        let stats = loop {
+            let stats = ipfs.reprovider_stats().await.unwrap();
+            if stats.total_provided + stats.total_failed == 1 && stats.in_flight == 0 {
+                break stats;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+        assert!(!stats.running);
+        // announced outside of a run, so not counted for the startup run
+        assert_eq!(stats.run_provided + stats.run_failed, 0);
+
+        let stats = ipfs.reprovide().await.unwrap();
+        assert!(!stats.running);
+        assert_eq!(stats.queued, 0);
+        assert_eq!(stats.run_provided + stats.run_failed, 1);
+        assert_eq!(stats.total_provided + stats.total_failed, 2);
+        assert!(stats.last_run_duration.is_some());
+    }
+
     #[tokio::test]
     async fn test_put_and_get_dag() {
         let ipfs = Node::new("test_node").await;
@@ -2505,4 +2803,160 @@ mod tests {
         ipfs.remove_pin(&cid).await.unwrap();
         assert!(!ipfs.is_pinned(&cid).await.unwrap());
     }
+
+    #[tokio::test]
+    async fn update_named_pin() {
+        let ipfs = Node::new("test_node").await;
+
+        let mut leaves = vec![];
+        for data in ["a", "b", "c"] {
+            leaves.push(ipfs.put_dag(ipld!(data)).await.unwrap());
+        }
+        let (a, b, c) = (leaves[0], leaves[1], leaves[2]);
+
+        let old = ipfs.put_dag(ipld!([a, b])).await.unwrap();
+        let new = ipfs.put_dag(ipld!([b, c])).await.unwrap();
+
+        ipfs.insert_pin(&old)
+            .recursive()
+            .name("site")
+            .metadata("version", "1")
+            .await
+            .unwrap();
+
+        let named = ipfs
+            .list_pins(None)
+            .name("site")
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(named, vec![(old, PinMode::Recursive)]);
+
+        ipfs.update_pin(&old, &new).await.unwrap();
+
+        let named = ipfs
+            .list_pins(None)
+            .name("site")
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(named, vec![(new, PinMode::Recursive)]);
+
+        let metadata = ipfs.pin_metadata(&new).await.unwrap().unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("site"));
+        assert_eq!(
+            metadata.values.get("version").map(String::as_str),
+            Some("1")
+        );
+
+        assert!(!ipfs.is_pinned(&old).await.unwrap());
+        assert!(!ipfs.is_pinned(&a).await.unwrap());
+        assert!(ipfs.is_pinned(&b).await.unwrap());
+        assert!(ipfs.is_pinned(&c).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn update_pin_keeps_blocks_below_shared_ones() {
+        let ipfs = Node::new("test_node").await;
+
+        let d = ipfs.put_dag(ipld!("d")).await.unwrap();
+        let m = ipfs.put_dag(ipld!([d])).await.unwrap();
+        let s = ipfs.put_dag(ipld!([m])).await.unwrap();
+
+        // `s` sits deep below `old` but right below `new`, so `d` is first reached from `new` alone
+        let mut old = s;
+        for _ in 0..4 {
+            old = ipfs.put_dag(ipld!([old])).await.unwrap();
+        }
+        let new = ipfs.put_dag(ipld!([d, s])).await.unwrap();
+
+        ipfs.insert_pin(&old).recursive().await.unwrap();
+        ipfs.update_pin(&old, &new).await.unwrap();
+
+        assert!(!ipfs.is_pinned(&old).await.unwrap());
+        for cid in [new, s, m, d] {
+            assert!(ipfs.is_pinned(&cid).await.unwrap());
+        }
+
+        ipfs.remove_pin(&new).recursive().await.unwrap();
+        for cid in [new, s, m, d] {
+            assert!(!ipfs.is_pinned(&cid).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn update_pin_keeps_blocks_below_unread_shared_ones() {
+        let ipfs = Node::new("test_node").await;
+
+        let d = ipfs.put_dag(ipld!("d")).await.unwrap();
+        let s = ipfs.put_dag(ipld!([d])).await.unwrap();
+        let t = ipfs.put_dag(ipld!([s])).await.unwrap();
+
+        // `d` is reached early from `old` alone, while `t` is deep below `old`
+        let p = ipfs.put_dag(ipld!([d, "p"])).await.unwrap();
+        let mut c = t;
+        for _ in 0..3 {
+            c = ipfs.put_dag(ipld!([c])).await.unwrap();
+        }
+        let old = ipfs.put_dag(ipld!([p, c])).await.unwrap();
+
+        // `t` is read first from `new` and found shared before `s` is reached, as the dag-json
+        // leaves sort after it
+        let mut links = vec![Ipld::Link(t)];
+        for i in 0..20 {
+            let leaf = ipfs
+                .put_dag(ipld!(i))
+                .codec(IpldCodec::DagJson)
+                .await
+                .unwrap();
+            links.push(Ipld::Link(leaf));
+        }
+        let new = ipfs.put_dag(Ipld::List(links)).await.unwrap();
+
+        ipfs.insert_pin(&old).recursive().await.unwrap();
+        ipfs.update_pin(&old, &new).await.unwrap();
+
+        for cid in [old, p, c] {
+            assert!(!ipfs.is_pinned(&cid).await.unwrap());
+        }
+        for cid in [new, t, s, d] {
+            assert!(ipfs.is_pinned(&cid).await.unwrap());
+        }
+
+        ipfs.remove_pin(&new).recursive().await.unwrap();
+        for cid in [new, t, s, d] {
+            assert!(!ipfs.is_pinned(&cid).await.unwrap());
+        }
+    }
+
+    #[tokio::test]
+    async fn pin_old_root_again_after_update() {
+        let ipfs = Node::new("test_node").await;
+
+        let mut leaves = vec![];
+        for data in ["a", "b", "c"] {
+            leaves.push(ipfs.put_dag(ipld!(data)).await.unwrap());
+        }
+        let (a, b, c) = (leaves[0], leaves[1], leaves[2]);
+
+        let old = ipfs.put_dag(ipld!([a, b])).await.unwrap();
+        let new = ipfs.put_dag(ipld!([b, c])).await.unwrap();
+
+        ipfs.insert_pin(&old).recursive().await.unwrap();
+        ipfs.update_pin(&old, &new).await.unwrap();
+        ipfs.insert_pin(&old).recursive().await.unwrap();
+
+        ipfs.remove_pin(&new).recursive().await.unwrap();
+        for cid in [old, a, b] {
+            assert!(ipfs.is_pinned(&cid).await.unwrap());
+        }
+        assert!(!ipfs.is_pinned(&c).await.unwrap());
+
+        ipfs.remove_pin(&old).recursive().await.unwrap();
+        for cid in [old, a, b] {
+            assert!(!ipfs.is_pinned(&cid).await.unwrap());
+        }
+    }
 }
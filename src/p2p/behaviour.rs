@@ -1,5 +1,6 @@
 use super::gossipsub::GossipsubStream;
-use super::{addressbook, protocol};
+use super::kadstore::KadStore;
+use super::{addressbook, fetch, protocol, PubsubMessageId};
 #[cfg(feature = "beetle_bitswap")]
 use bytes::Bytes;
 
@@ -27,7 +28,7 @@ use libp2p::core::Multiaddr;
 use libp2p::dcutr::Behaviour as Dcutr;
 use libp2p::identify::{Behaviour as Identify, Config as IdentifyConfig};
 use libp2p::identity::{Keypair, PeerId};
-use libp2p::kad::store::{MemoryStore, MemoryStoreConfig};
+use libp2p::kad::store::MemoryStoreConfig;
 use libp2p::kad::{
     Behaviour as Kademlia, BucketInserts as KademliaBucketInserts, Config as KademliaConfig,
     Record, StoreInserts as KademliaStoreInserts,
@@ -57,10 +58,11 @@ where
     pub bitswap: Toggle<Bitswap<DefaultParams>>,
     #[cfg(feature = "beetle_bitswap")]
     pub bitswap: Toggle<Bitswap<Repo>>,
-    pub kademlia: Toggle<Kademlia<MemoryStore>>,
+    pub kademlia: Toggle<Kademlia<KadStore>>,
     pub ping: Toggle<Ping>,
     pub identify: Toggle<Identify>,
     pub pubsub: Toggle<GossipsubStream>,
+    pub fetch: Toggle<fetch::Behaviour>,
     pub autonat: Toggle<autonat::Behaviour>,
     pub upnp: Toggle<libp2p::upnp::tokio::Behaviour>,
     pub block_list: libp2p_allow_block_list::Behaviour<BlockedPeers>,
@@ -235,6 +237,9 @@ pub enum RateLimit {
 #[derive(Default, Clone, Debug)]
 pub struct KadStoreConfig {
     pub memory: Option<MemoryStoreConfig>,
+    /// Persist records and provider records in the repo datastore so they survive a restart.
+    /// Entries that expired while the node was offline are discarded on startup.
+    pub persistent: bool,
 }
 #[derive(Clone, Debug)]
 pub struct KadConfig {
@@ -404,11 +409,12 @@ where
         .into();
 
         let store = {
-            //TODO: Make customizable
-            //TODO: Use persistent store for kad
             let config = options.kad_store_config.memory.clone().unwrap_or_default();
 
-            MemoryStore::with_config(peer_id, config)
+            match options.kad_store_config.persistent {
+                true => KadStore::persistent(peer_id, config, repo.clone()).await,
+                false => KadStore::memory(peer_id, config),
+            }
         };
 
         let kad_config = match options.kad_configuration.clone() {
@@ -416,7 +422,7 @@ where
             Either::Right(kad) => kad,
         };
 
-        let mut kademlia: Toggle<Kademlia<MemoryStore>> = Toggle::from(
+        let mut kademlia: Toggle<Kademlia<KadStore>> = Toggle::from(
             (protocols.kad).then(|| Kademlia::with_config(peer_id, store, kad_config)),
         );
 
@@ -488,20 +494,49 @@ where
 
             builder.validation_mode(pubsub_config.validate.into());
 
+            // messages are forwarded once accepted by the validator of their topic, if any
+            builder.validate_messages();
+
+            let mesh = pubsub_config.mesh;
+            builder
+                .mesh_n(mesh.mesh_n)
+                .mesh_n_low(mesh.mesh_n_low)
+                .mesh_n_high(mesh.mesh_n_high)
+                .mesh_outbound_min(mesh.mesh_outbound_min)
+                .gossip_lazy(mesh.gossip_lazy)
+                .heartbeat_interval(mesh.heartbeat_interval)
+                .flood_publish(pubsub_config.flood_publish);
+
+            if pubsub_config.message_id == PubsubMessageId::ContentAddressed {
+                builder.message_id_fn(|message: &libp2p::gossipsub::Message| {
+                    use libipld::multihash::{Code, MultihashDigest};
+                    Code::Sha2_256.digest(&message.data).to_bytes().into()
+                });
+            }
+
             let config = builder.build().map_err(anyhow::Error::from)?;
 
-            let gossipsub = libp2p::gossipsub::Behaviour::new(
+            let mut gossipsub = libp2p::gossipsub::Behaviour::new(
                 libp2p::gossipsub::MessageAuthenticity::Signed(keypair.clone()),
                 config,
             )
             .map_err(|e| anyhow::anyhow!("{}", e))?;
 
+            if let Some(scoring) = pubsub_config.scoring {
+                gossipsub
+                    .with_peer_score(scoring.params, scoring.thresholds)
+                    .map_err(|e| anyhow::anyhow!("{}", e))?;
+            }
+
             protocols
                 .pubsub
                 .then(|| GossipsubStream::from(gossipsub))
                 .into()
         };
 
+        // Serves the records of ipns pubsub topics to peers that join after they were published
+        let fetch = protocols.pubsub.then(fetch::new).into();
+
         // Maybe have this enable in conjunction with RelayClient?
         let dcutr = Toggle::from(protocols.dcutr.then(|| Dcutr::new(peer_id)));
         let relay_config = options.relay_server_config.clone().into();
@@ -550,6 +585,7 @@ where
                 identify,
                 autonat,
                 pubsub,
+                fetch,
                 dcutr,
                 relay,
                 relay_client,
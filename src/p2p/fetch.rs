@@ -0,0 +1,253 @@
+//! Implementation of the libp2p fetch protocol (`/libp2p/fetch/0.0.1`), used by peers joining an
+//! IPNS pubsub topic to ask the peers already subscribed for the latest record.
+
+use std::io;
+
+use async_trait::async_trait;
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use libp2p::request_response::{self, ProtocolSupport};
+use libp2p::StreamProtocol;
+
+pub const PROTOCOL: StreamProtocol = StreamProtocol::new("/libp2p/fetch/0.0.1");
+
+/// Upper bound of a single message, well above the 10 KiB limit of an IPNS record.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+pub type Behaviour = request_response::Behaviour<Codec>;
+
+pub fn new() -> Behaviour {
+    request_response::Behaviour::with_codec(
+        Codec,
+        [(PROTOCOL, ProtocolSupport::Full)],
+        Default::default(),
+    )
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchRequest {
+    pub identifier: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FetchStatus {
+    Ok = 0,
+    NotFound = 1,
+    Error = 2,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchResponse {
+    pub status: FetchStatus,
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Codec;
+
+#[async_trait]
+impl request_response::Codec for Codec {
+    type Protocol = StreamProtocol;
+    type Request = FetchRequest;
+    type Response = FetchResponse;
+
+    async fn read_request<T>(&mut self, _: &StreamProtocol, io: &mut T) -> io::Result<FetchRequest>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let message = read_message(io).await?;
+        let mut identifier = vec![];
+        for_each_field(&message, |field, value| {
+            if let (1, Field::Bytes(bytes)) = (field, value) {
+                identifier = bytes.to_vec();
+            }
+        })?;
+        Ok(FetchRequest { identifier })
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+    ) -> io::Result<FetchResponse>
+    where
+        T: AsyncRead + Unpin + Send,
+    {
+        let message = read_message(io).await?;
+        let mut status = FetchStatus::Ok;
+        let mut data = vec![];
+        for_each_field(&message, |field, value| match (field, value) {
+            (1, Field::Varint(0)) => status = FetchStatus::Ok,
+            (1, Field::Varint(1)) => status = FetchStatus::NotFound,
+            (1, Field::Varint(_)) => status = FetchStatus::Error,
+            (2, Field::Bytes(bytes)) => data = bytes.to_vec(),
+            _ => {}
+        })?;
+        Ok(FetchResponse { status, data })
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        request: FetchRequest,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let mut message = vec![];
+        put_bytes(&mut message, 1, &request.identifier);
+        write_message(io, &message).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &StreamProtocol,
+        io: &mut T,
+        response: FetchResponse,
+    ) -> io::Result<()>
+    where
+        T: AsyncWrite + Unpin + Send,
+    {
+        let mut message = vec![];
+        if response.status != FetchStatus::Ok {
+            put_varint(&mut message, 1 << 3);
+            put_varint(&mut message, response.status as u64);
+        }
+        if !response.data.is_empty() {
+            put_bytes(&mut message, 2, &response.data);
+        }
+        write_message(io, &message).await
+    }
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// Walks the fields of a protobuf message, skipping those of fixed size.
+fn for_each_field<'a>(mut message: &'a [u8], mut f: impl FnMut(u64, Field<'a>)) -> io::Result<()> {
+    while !message.is_empty() {
+        let (key, rest) = unsigned_varint::decode::u64(message).map_err(invalid_data)?;
+        message = rest;
+        let field = key >> 3;
+        match key & 0x7 {
+            0 => {
+                let (value, rest) = unsigned_varint::decode::u64(message).map_err(invalid_data)?;
+                message = rest;
+                f(field, Field::Varint(value));
+            }
+            2 => {
+                let (len, rest) = unsigned_varint::decode::usize(message).map_err(invalid_data)?;
+                if len > rest.len() {
+                    return Err(invalid_data("truncated field"));
+                }
+                let (bytes, rest) = rest.split_at(len);
+                message = rest;
+                f(field, Field::Bytes(bytes));
+            }
+            1 if message.len() >= 8 => message = &message[8..],
+            5 if message.len() >= 4 => message = &message[4..],
+            _ => return Err(invalid_data("unsupported wire type")),
+        }
+    }
+    Ok(())
+}
+
+fn put_varint(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(unsigned_varint::encode::u64(
+        value,
+        &mut unsigned_varint::encode::u64_buffer(),
+    ));
+}
+
+fn put_bytes(buffer: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+    put_varint(buffer, (field << 3) | 2);
+    put_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+/// Reads a message prefixed with its length as an unsigned varint.
+async fn read_message<T: AsyncRead + Unpin>(io: &mut T) -> io::Result<Vec<u8>> {
+    let mut buffer = unsigned_varint::encode::usize_buffer();
+    let mut len = None;
+    for i in 0..buffer.len() {
+        io.read_exact(&mut buffer[i..=i]).await?;
+        if unsigned_varint::decode::is_last(buffer[i]) {
+            let (value, _) = unsigned_varint::decode::usize(&buffer[..=i]).map_err(invalid_data)?;
+            len = Some(value);
+            break;
+        }
+    }
+
+    let len = len.ok_or_else(|| invalid_data("length prefix is too long"))?;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(invalid_data("message is too large"));
+    }
+
+    let mut message = vec![0; len];
+    io.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+async fn write_message<T: AsyncWrite + Unpin>(io: &mut T, message: &[u8]) -> io::Result<()> {
+    let mut frame = Vec::with_capacity(message.len() + 3);
+    put_varint(&mut frame, message.len() as u64);
+    frame.extend_from_slice(message);
+    io.write_all(&frame).await?;
+    io.close().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Codec, FetchRequest, FetchResponse, FetchStatus, PROTOCOL};
+    use futures::io::Cursor;
+    use libp2p::request_response::Codec as _;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let mut codec = Codec;
+
+        let request = FetchRequest {
+            identifier: b"/ipns/\x00\x24\x08\x01".to_vec(),
+        };
+        let mut io = Cursor::new(vec![]);
+        codec
+            .write_request(&PROTOCOL, &mut io, request.clone())
+            .await
+            .unwrap();
+        // length, then field 1 as bytes
+        assert_eq!(&io.get_ref()[..3], &[12, 0x0a, 10]);
+        io.set_position(0);
+        assert_eq!(
+            codec.read_request(&PROTOCOL, &mut io).await.unwrap(),
+            request
+        );
+
+        for response in [
+            FetchResponse {
+                status: FetchStatus::Ok,
+                data: b"record".to_vec(),
+            },
+            FetchResponse {
+                status: FetchStatus::NotFound,
+                data: vec![],
+            },
+        ] {
+            let mut io = Cursor::new(vec![]);
+            codec
+                .write_response(&PROTOCOL, &mut io, response.clone())
+                .await
+                .unwrap();
+            io.set_position(0);
+            assert_eq!(
+                codec.read_response(&PROTOCOL, &mut io).await.unwrap(),
+                response
+            );
+        }
+    }
+}
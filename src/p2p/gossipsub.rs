@@ -1,13 +1,16 @@
 use async_broadcast::TrySendError;
 use futures::channel::mpsc::{self as channel};
-use futures::stream::{FusedStream, Stream};
-use libp2p::gossipsub::PublishError;
+use futures::future::BoxFuture;
+use futures::stream::{FusedStream, FuturesUnordered, Stream};
+use futures::{Future, FutureExt};
+use libp2p::gossipsub::{MessageAcceptance, PublishError};
 use std::collections::HashMap;
 use std::fmt;
 use std::pin::Pin;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tracing::debug;
 
 use libp2p::core::{Endpoint, Multiaddr};
@@ -21,6 +24,40 @@ use libp2p::swarm::{
     ConnectionDenied, ConnectionId, NetworkBehaviour, THandler, THandlerInEvent, ToSwarm,
 };
 
+/// Decides whether a message of a topic is delivered to the subscribers and forwarded to other
+/// peers, given the peer it was received from.
+#[derive(Clone)]
+pub struct MessageValidator(
+    Arc<dyn Fn(PeerId, GossipsubMessage) -> BoxFuture<'static, MessageAcceptance> + Send + Sync>,
+);
+
+impl MessageValidator {
+    pub fn new<F, Fut>(validator: F) -> Self
+    where
+        F: Fn(PeerId, GossipsubMessage) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = MessageAcceptance> + Send + 'static,
+    {
+        Self(Arc::new(move |source, message| {
+            validator(source, message).boxed()
+        }))
+    }
+}
+
+impl fmt::Debug for MessageValidator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageValidator").finish()
+    }
+}
+
+type Validation = BoxFuture<'static, (MessageId, PeerId, GossipsubMessage, MessageAcceptance)>;
+
+/// How long a validator may take before its message is ignored.
+const VALIDATION_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most messages of a topic held back by their validator at a time, further messages of the topic
+/// being ignored until some validations complete.
+const MAX_PENDING_VALIDATIONS: usize = 256;
+
 /// Currently a thin wrapper around Gossipsub.
 /// Allows single subscription to a topic with only unbounded senders. Tracks the peers subscribed
 /// to different topics.
@@ -30,6 +67,15 @@ pub struct GossipsubStream {
 
     active_streams: HashMap<TopicHash, Arc<AtomicUsize>>,
 
+    // Validators of the topics, messages of other topics being accepted as they arrive.
+    validators: HashMap<TopicHash, MessageValidator>,
+
+    // Messages held back by gossipsub until their validator completes.
+    validations: FuturesUnordered<Validation>,
+
+    // Number of the validations in progress for each topic.
+    pending_validations: HashMap<TopicHash, usize>,
+
     // Gossipsub protocol
     gossipsub: Gossipsub,
 
@@ -141,6 +187,9 @@ impl From<Gossipsub> for GossipsubStream {
             gossipsub,
             unsubscriptions: (tx, rx),
             active_streams: Default::default(),
+            validators: Default::default(),
+            validations: Default::default(),
+            pending_validations: Default::default(),
         }
     }
 }
@@ -196,6 +245,24 @@ impl GossipsubStream {
         }
     }
 
+    /// Subscribes to a topic like [`GossipsubStream::subscribe`], with its messages being
+    /// delivered and forwarded only once accepted by `validator`. The validator replaces any
+    /// validator set by a previous subscription to the topic.
+    ///
+    /// Messages are ignored when their validation takes longer than 10 seconds, or when 256
+    /// messages of the topic are already being validated.
+    pub fn subscribe_with_validator(
+        &mut self,
+        topic: impl Into<String>,
+        validator: MessageValidator,
+    ) -> anyhow::Result<SubscriptionStream> {
+        let topic = topic.into();
+        let hash = Topic::new(topic.clone()).hash();
+        let stream = self.subscribe(topic)?;
+        self.validators.insert(hash, validator);
+        Ok(stream)
+    }
+
     /// Unsubscribes from a topic. Unsubscription is usually done through dropping the
     /// SubscriptionStream.
     ///
@@ -205,6 +272,7 @@ impl GossipsubStream {
         if let Some(sender) = self.streams.remove(&topic.hash()) {
             sender.close();
             self.active_streams.remove(&topic.hash());
+            self.validators.remove(&topic.hash());
             Ok(self.gossipsub.unsubscribe(&topic)?)
         } else {
             anyhow::bail!("Unable to unsubscribe from topic.")
@@ -234,11 +302,110 @@ impl GossipsubStream {
             .collect()
     }
 
+    /// Returns the score of the known peers, which is empty when peer scoring is disabled
+    pub fn peer_scores(&self) -> Vec<(PeerId, f64)> {
+        self.all_peers()
+            .filter_map(|(peer_id, _)| Some((*peer_id, self.peer_score(peer_id)?)))
+            .collect()
+    }
+
     /// Returns the list of currently subscribed topics. This can contain topics for which stream
     /// has been dropped but no messages have yet been received on the topics after the drop.
     pub fn subscribed_topics(&self) -> Vec<String> {
         self.streams.keys().map(|t| t.to_string()).collect()
     }
+
+    /// Starts validating `message` with `validator`, or ignores it when too many messages of its
+    /// topic are being validated already.
+    fn start_validation(
+        &mut self,
+        validator: &MessageValidator,
+        message_id: MessageId,
+        source: PeerId,
+        message: GossipsubMessage,
+        timeout: Duration,
+    ) {
+        let pending = self
+            .pending_validations
+            .entry(message.topic.clone())
+            .or_default();
+
+        if *pending >= MAX_PENDING_VALIDATIONS {
+            debug!(%source, topic = %message.topic, "too many pending pubsub validations");
+            self.complete_validation(&message_id, &source, message, MessageAcceptance::Ignore);
+            return;
+        }
+
+        *pending += 1;
+
+        let validation = (validator.0)(source, message.clone());
+        self.validations.push(
+            async move {
+                let acceptance = tokio::time::timeout(timeout, validation)
+                    .await
+                    .unwrap_or(MessageAcceptance::Ignore);
+                (message_id, source, message, acceptance)
+            }
+            .boxed(),
+        );
+    }
+
+    /// Releases the slot of a completed validation of a message of `topic`.
+    fn finish_validation(&mut self, topic: &TopicHash) {
+        use std::collections::hash_map::Entry;
+
+        if let Entry::Occupied(mut oe) = self.pending_validations.entry(topic.clone()) {
+            *oe.get_mut() -= 1;
+            if *oe.get() == 0 {
+                oe.remove();
+            }
+        }
+    }
+
+    /// Reports the outcome of the validation to gossipsub, which forwards accepted messages, and
+    /// delivers accepted messages to the subscribers.
+    fn complete_validation(
+        &mut self,
+        message_id: &MessageId,
+        source: &PeerId,
+        message: GossipsubMessage,
+        acceptance: MessageAcceptance,
+    ) {
+        use std::collections::hash_map::Entry;
+
+        let accepted = matches!(acceptance, MessageAcceptance::Accept);
+        if !accepted {
+            debug!(%source, ?acceptance, "pubsub message did not pass validation");
+        }
+
+        if let Err(e) = self
+            .gossipsub
+            .report_message_validation_result(message_id, source, acceptance)
+        {
+            debug!("unable to report the validation of a pubsub message: {e}");
+        }
+
+        if !accepted {
+            return;
+        }
+
+        let topic = message.topic.clone();
+        if let Entry::Occupied(oe) = self.streams.entry(topic) {
+            if let Err(TrySendError::Closed(_)) = oe.get().try_broadcast(message) {
+                // receiver has dropped
+                let (topic, _) = oe.remove_entry();
+                debug!("unsubscribing via SendError from {:?}", &topic);
+                assert!(
+                    self.gossipsub
+                        .unsubscribe(&Topic::new(topic.to_string()))
+                        .unwrap_or_default(),
+                    "Failed to unsubscribe following SendError"
+                );
+                self.active_streams.remove(&topic);
+                self.validators.remove(&topic);
+            }
+        }
+    }
 }
 
 impl NetworkBehaviour for GossipsubStream {
@@ -309,7 +476,6 @@ impl NetworkBehaviour for GossipsubStream {
         ctx: &mut Context,
     ) -> Poll<ToSwarm<libp2p::gossipsub::Event, THandlerInEvent<Self>>> {
         use futures::stream::StreamExt;
-        use std::collections::hash_map::Entry;
 
         loop {
             match self.unsubscriptions.1.poll_next_unpin(ctx) {
@@ -324,6 +490,7 @@ impl NetworkBehaviour for GossipsubStream {
                             "Failed to unsubscribe a dropped subscription"
                         );
                         self.active_streams.remove(&dropped);
+                        self.validators.remove(&dropped);
                     }
                 }
                 Poll::Ready(None) => unreachable!("we own the sender"),
@@ -332,22 +499,33 @@ impl NetworkBehaviour for GossipsubStream {
         }
 
         loop {
+            while let Poll::Ready(Some((message_id, source, message, acceptance))) =
+                self.validations.poll_next_unpin(ctx)
+            {
+                self.finish_validation(&message.topic);
+                self.complete_validation(&message_id, &source, message, acceptance);
+            }
+
             match futures::ready!(self.gossipsub.poll(ctx)) {
-                ToSwarm::GenerateEvent(GossipsubEvent::Message { message, .. }) => {
-                    let topic = message.topic.clone();
-                    if let Entry::Occupied(oe) = self.streams.entry(topic) {
-                        if let Err(TrySendError::Closed(_)) = oe.get().try_broadcast(message) {
-                            // receiver has dropped
-                            let (topic, _) = oe.remove_entry();
-                            debug!("unsubscribing via SendError from {:?}", &topic);
-                            assert!(
-                                self.gossipsub
-                                    .unsubscribe(&Topic::new(topic.to_string()))
-                                    .unwrap_or_default(),
-                                "Failed to unsubscribe following SendError"
-                            );
-                            self.active_streams.remove(&topic);
-                        }
+                ToSwarm::GenerateEvent(GossipsubEvent::Message {
+                    propagation_source,
+                    message_id,
+                    message,
+                }) => {
+                    match self.validators.get(&message.topic).cloned() {
+                        Some(validator) => self.start_validation(
+                            &validator,
+                            message_id,
+                            propagation_source,
+                            message,
+                            VALIDATION_TIMEOUT,
+                        ),
+                        None => self.complete_validation(
+                            &message_id,
+                            &propagation_source,
+                            message,
+                            MessageAcceptance::Accept,
+                        ),
                     }
                     continue;
                 }
@@ -370,3 +548,60 @@ impl NetworkBehaviour for GossipsubStream {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{GossipsubStream, MessageValidator, MAX_PENDING_VALIDATIONS};
+    use futures::StreamExt;
+    use libp2p::gossipsub::{
+        Behaviour, ConfigBuilder, IdentTopic, Message, MessageAcceptance, MessageAuthenticity,
+        MessageId,
+    };
+    use libp2p::PeerId;
+    use std::time::Duration;
+
+    fn stream() -> GossipsubStream {
+        let config = ConfigBuilder::default().build().unwrap();
+        let keypair = libp2p::identity::Keypair::generate_ed25519();
+        Behaviour::new(MessageAuthenticity::Signed(keypair), config)
+            .unwrap()
+            .into()
+    }
+
+    fn message(topic: &str, i: usize) -> (MessageId, Message) {
+        let message = Message {
+            source: None,
+            data: i.to_be_bytes().to_vec(),
+            sequence_number: None,
+            topic: IdentTopic::new(topic).hash(),
+        };
+        (MessageId::new(&i.to_be_bytes()), message)
+    }
+
+    #[tokio::test]
+    async fn slow_validations_are_ignored_and_bounded() {
+        let mut stream = stream();
+        let hanging = MessageValidator::new(|_, _| futures::future::pending());
+        let source = PeerId::random();
+
+        for i in 0..MAX_PENDING_VALIDATIONS + 10 {
+            let (id, message) = message("slow", i);
+            let timeout = Duration::from_millis(50);
+            stream.start_validation(&hanging, id, source, message, timeout);
+        }
+        // other topics have validations of their own
+        let (id, message) = message("other", 0);
+        stream.start_validation(&hanging, id, source, message, Duration::from_millis(50));
+
+        assert_eq!(stream.validations.len(), MAX_PENDING_VALIDATIONS + 1);
+
+        let mut ignored = 0;
+        while let Some((.., message, acceptance)) = stream.validations.next().await {
+            assert!(matches!(acceptance, MessageAcceptance::Ignore));
+            stream.finish_validation(&message.topic);
+            ignored += 1;
+        }
+        assert_eq!(ignored, MAX_PENDING_VALIDATIONS + 1);
+        assert!(stream.pending_validations.is_empty());
+    }
+}
@@ -0,0 +1,587 @@
+//! Kademlia record store that can persist records and provider records in the repo datastore.
+//!
+//! [`RecordStore`] is a synchronous trait polled from within the swarm, so all reads are served
+//! from an in-memory [`MemoryStore`]. The limit on the keys provided by the local node is enforced
+//! here instead, so that the reprovider can raise it by the keys it announces as it loads them. When persistence is enabled every successful mutation is
+//! also forwarded, in order, to a background task that writes it to the [`DataStore`] of the
+//! repo; the changes still queued when the node exits are written before its task finishes. On
+//! startup the persisted entries are loaded back, dropping anything that expired while the node
+//! was offline.
+//!
+//! [`DataStore`]: crate::repo::DataStore
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
+use libipld::multibase;
+use libp2p::kad::store::{Error, MemoryStore, MemoryStoreConfig, RecordStore, Result};
+use libp2p::kad::{ProviderRecord, Record, RecordKey};
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+
+use crate::repo::Repo;
+
+const RECORD_PREFIX: &str = "/kad/records/";
+const PROVIDER_PREFIX: &str = "/kad/providers/";
+
+/// [`RecordStore`] used by the kademlia behaviour.
+pub struct KadStore {
+    memory: MemoryStore,
+    persist: Option<UnboundedSender<StoreOp>>,
+    persist_task: Option<JoinHandle<()>>,
+    local_id: PeerId,
+    /// Keys currently provided by the local node.
+    provided: usize,
+    /// The configured limit of the keys provided by the local node.
+    max_provided_keys: usize,
+    /// Keys allowed on top of the configured limit for the keys of the reprovider.
+    reserved: usize,
+}
+
+enum StoreOp {
+    PutRecord(Record),
+    RemoveRecord(RecordKey),
+    PutProvider(ProviderRecord),
+    RemoveProvider(RecordKey, PeerId),
+    #[cfg(test)]
+    Flush(futures::channel::oneshot::Sender<()>),
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredRecord {
+    #[serde(with = "base64_bytes")]
+    key: Vec<u8>,
+    #[serde(with = "base64_bytes")]
+    value: Vec<u8>,
+    publisher: Option<PeerId>,
+    expires: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredProvider {
+    #[serde(with = "base64_bytes")]
+    key: Vec<u8>,
+    provider: PeerId,
+    addresses: Vec<Multiaddr>,
+    expires: Option<u64>,
+}
+
+impl KadStore {
+    /// Creates a store that only keeps records in memory.
+    pub fn memory(local_id: PeerId, config: MemoryStoreConfig) -> Self {
+        let max_provided_keys = config.max_provided_keys;
+        Self {
+            memory: MemoryStore::with_config(local_id, unlimited_provided(config)),
+            persist: None,
+            persist_task: None,
+            local_id,
+            provided: 0,
+            max_provided_keys,
+            reserved: 0,
+        }
+    }
+
+    /// Creates a store backed by the datastore of `repo`, loading any previously persisted
+    /// records and provider records that have not expired yet.
+    pub async fn persistent(local_id: PeerId, config: MemoryStoreConfig, repo: Repo) -> Self {
+        let max_provided_keys = config.max_provided_keys;
+        let mut memory = MemoryStore::with_config(local_id, unlimited_provided(config));
+        let mut expired = vec![];
+
+        let mut entries = repo.data_store().iter().await;
+        while let Some((key, value)) = entries.next().await {
+            let Ok(key_str) = std::str::from_utf8(&key) else {
+                continue;
+            };
+
+            if key_str.starts_with(RECORD_PREFIX) {
+                let record = match serde_json::from_slice::<StoredRecord>(&value) {
+                    Ok(stored) => stored.into_record(),
+                    Err(e) => {
+                        tracing::warn!(key = key_str, error = %e, "unable to decode kad record");
+                        None
+                    }
+                };
+                match record {
+                    Some(record) => {
+                        if let Err(e) = memory.put(record) {
+                            tracing::warn!(key = key_str, error = %e, "unable to load kad record");
+                        }
+                    }
+                    None => expired.push(key),
+                }
+            } else if key_str.starts_with(PROVIDER_PREFIX) {
+                let record = match serde_json::from_slice::<StoredProvider>(&value) {
+                    Ok(stored) => stored.into_record(),
+                    Err(e) => {
+                        tracing::warn!(key = key_str, error = %e, "unable to decode kad provider");
+                        None
+                    }
+                };
+                match record {
+                    Some(record) => {
+                        if let Err(e) = memory.add_provider(record) {
+                            tracing::warn!(key = key_str, error = %e, "unable to load kad provider");
+                        }
+                    }
+                    None => expired.push(key),
+                }
+            }
+        }
+        drop(entries);
+
+        for key in expired {
+            if let Err(e) = repo.data_store().remove(&key).await {
+                tracing::warn!(error = %e, "unable to remove expired kad entry");
+            }
+        }
+
+        let (tx, rx) = unbounded();
+        let task = tokio::spawn(persist_task(repo, rx));
+
+        Self {
+            provided: memory.provided().count(),
+            memory,
+            persist: Some(tx),
+            persist_task: Some(task),
+            local_id,
+            max_provided_keys,
+            reserved: 0,
+        }
+    }
+
+    /// Allows `keys` more keys to be provided by the local node than configured, unless as many
+    /// were already allowed.
+    pub(crate) fn reserve_provided(&mut self, keys: usize) {
+        self.reserved = self.reserved.max(keys);
+    }
+
+    /// Returns true if the local node provides `key`.
+    fn is_provided(&self, key: &RecordKey) -> bool {
+        self.memory
+            .providers(key)
+            .iter()
+            .any(|r| r.provider == self.local_id)
+    }
+
+    /// Stops forwarding the changes to the datastore, resolving once the changes made so far have
+    /// been written. Later changes are only kept in memory.
+    pub(crate) async fn shutdown(&mut self) {
+        self.persist = None;
+        if let Some(task) = self.persist_task.take() {
+            _ = task.await;
+        }
+    }
+
+    fn send(&self, op: StoreOp) {
+        if let Some(tx) = &self.persist {
+            _ = tx.unbounded_send(op);
+        }
+    }
+
+    /// Resolves once every change made before the call has been written to the datastore.
+    #[cfg(test)]
+    async fn flush(&self) {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        self.send(StoreOp::Flush(tx));
+        _ = rx.await;
+    }
+}
+
+async fn persist_task(repo: Repo, mut rx: UnboundedReceiver<StoreOp>) {
+    while let Some(op) = rx.next().await {
+        let datastore = repo.data_store();
+        let result = match op {
+            StoreOp::PutRecord(record) => {
+                let key = record_key(&record.key);
+                match serde_json::to_vec(&StoredRecord::from(&record)) {
+                    Ok(value) => datastore.put(key.as_bytes(), &value).await,
+                    Err(e) => Err(e.into()),
+                }
+            }
+            StoreOp::RemoveRecord(key) => datastore.remove(record_key(&key).as_bytes()).await,
+            StoreOp::PutProvider(record) => {
+                let key = provider_key(&record.key, &record.provider);
+                match serde_json::to_vec(&StoredProvider::from(&record)) {
+                    Ok(value) => datastore.put(key.as_bytes(), &value).await,
+                    Err(e) => Err(e.into()),
+                }
+            }
+            StoreOp::RemoveProvider(key, provider) => {
+                datastore
+                    .remove(provider_key(&key, &provider).as_bytes())
+                    .await
+            }
+            #[cfg(test)]
+            StoreOp::Flush(tx) => {
+                _ = tx.send(());
+                Ok(())
+            }
+        };
+
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "unable to persist kad store change");
+        }
+    }
+}
+
+/// Stores the bytes as base64 strings instead of the arrays of numbers serde_json would use.
+mod base64_bytes {
+    use libipld::multibase::Base;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&Base::Base64.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        Base::Base64
+            .decode(encoded)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Lifts the limit of the provided keys of `config`, which is enforced by [`KadStore`].
+fn unlimited_provided(config: MemoryStoreConfig) -> MemoryStoreConfig {
+    MemoryStoreConfig {
+        max_provided_keys: usize::MAX,
+        ..config
+    }
+}
+
+fn record_key(key: &RecordKey) -> String {
+    format!(
+        "{RECORD_PREFIX}{}",
+        multibase::Base::Base32Lower.encode(key.as_ref())
+    )
+}
+
+fn provider_key(key: &RecordKey, provider: &PeerId) -> String {
+    format!(
+        "{PROVIDER_PREFIX}{}/{provider}",
+        multibase::Base::Base32Lower.encode(key.as_ref())
+    )
+}
+
+/// Converts an [`Instant`] to milliseconds since the unix epoch.
+fn to_unix_millis(instant: Instant) -> u64 {
+    let now = Instant::now();
+    let wall = match instant.checked_duration_since(now) {
+        Some(ahead) => SystemTime::now() + ahead,
+        None => SystemTime::now() - now.duration_since(instant),
+    };
+    wall.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Converts milliseconds since the unix epoch back to an [`Instant`], returning `None` if that
+/// point in time has already passed.
+fn from_unix_millis(millis: u64) -> Option<Instant> {
+    let wall = UNIX_EPOCH + Duration::from_millis(millis);
+    let remaining = wall.duration_since(SystemTime::now()).ok()?;
+    Instant::now().checked_add(remaining)
+}
+
+impl From<&Record> for StoredRecord {
+    fn from(record: &Record) -> Self {
+        Self {
+            key: record.key.to_vec(),
+            value: record.value.clone(),
+            publisher: record.publisher,
+            expires: record.expires.map(to_unix_millis),
+        }
+    }
+}
+
+impl StoredRecord {
+    fn into_record(self) -> Option<Record> {
+        let expires = match self.expires {
+            Some(millis) => Some(from_unix_millis(millis)?),
+            None => None,
+        };
+        Some(Record {
+            key: RecordKey::from(self.key),
+            value: self.value,
+            publisher: self.publisher,
+            expires,
+        })
+    }
+}
+
+impl From<&ProviderRecord> for StoredProvider {
+    fn from(record: &ProviderRecord) -> Self {
+        Self {
+            key: record.key.to_vec(),
+            provider: record.provider,
+            addresses: record.addresses.clone(),
+            expires: record.expires.map(to_unix_millis),
+        }
+    }
+}
+
+impl StoredProvider {
+    fn into_record(self) -> Option<ProviderRecord> {
+        let expires = match self.expires {
+            Some(millis) => Some(from_unix_millis(millis)?),
+            None => None,
+        };
+        Some(ProviderRecord {
+            key: RecordKey::from(self.key),
+            provider: self.provider,
+            expires,
+            addresses: self.addresses,
+        })
+    }
+}
+
+impl RecordStore for KadStore {
+    type RecordsIter<'a> = <MemoryStore as RecordStore>::RecordsIter<'a>;
+    type ProvidedIter<'a> = <MemoryStore as RecordStore>::ProvidedIter<'a>;
+
+    fn get(&self, k: &RecordKey) -> Option<Cow<'_, Record>> {
+        self.memory.get(k)
+    }
+
+    fn put(&mut self, r: Record) -> Result<()> {
+        if self.persist.is_none() {
+            return self.memory.put(r);
+        }
+        self.memory.put(r.clone())?;
+        self.send(StoreOp::PutRecord(r));
+        Ok(())
+    }
+
+    fn remove(&mut self, k: &RecordKey) {
+        self.memory.remove(k);
+        self.send(StoreOp::RemoveRecord(k.clone()));
+    }
+
+    fn records(&self) -> Self::RecordsIter<'_> {
+        self.memory.records()
+    }
+
+    fn add_provider(&mut self, record: ProviderRecord) -> Result<()> {
+        let local = record.provider == self.local_id;
+        let newly_provided = local && !self.is_provided(&record.key);
+        if newly_provided && self.provided >= self.max_provided_keys.saturating_add(self.reserved) {
+            return Err(Error::MaxProvidedKeys);
+        }
+
+        if self.persist.is_none() {
+            self.memory.add_provider(record)?;
+            self.provided += usize::from(newly_provided);
+            return Ok(());
+        }
+
+        let before = self
+            .memory
+            .providers(&record.key)
+            .into_iter()
+            .map(|r| r.provider)
+            .collect::<HashSet<_>>();
+
+        self.memory.add_provider(record.clone())?;
+        self.provided += usize::from(newly_provided);
+
+        let after = self.memory.providers(&record.key);
+
+        // the memory store silently drops the record if it is further away than the current
+        // providers, and may evict the furthest one to make room for it
+        if after.iter().any(|r| r.provider == record.provider) {
+            self.send(StoreOp::PutProvider(record.clone()));
+        }
+
+        let after = after
+            .into_iter()
+            .map(|r| r.provider)
+            .collect::<HashSet<_>>();
+        for evicted in before.difference(&after) {
+            self.send(StoreOp::RemoveProvider(record.key.clone(), *evicted));
+        }
+
+        Ok(())
+    }
+
+    fn providers(&self, key: &RecordKey) -> Vec<ProviderRecord> {
+        self.memory.providers(key)
+    }
+
+    fn provided(&self) -> Self::ProvidedIter<'_> {
+        self.memory.provided()
+    }
+
+    fn remove_provider(&mut self, k: &RecordKey, p: &PeerId) {
+        if *p == self.local_id && self.is_provided(k) {
+            self.provided -= 1;
+        }
+        self.memory.remove_provider(k, p);
+        self.send(StoreOp::RemoveProvider(k.clone(), *p));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use libp2p::kad::store::{Error, MemoryStoreConfig, RecordStore};
+    use libp2p::kad::{ProviderRecord, Record, RecordKey};
+    use libp2p::PeerId;
+
+    use super::KadStore;
+    use crate::repo::Repo;
+
+    #[tokio::test]
+    async fn records_survive_reload() -> anyhow::Result<()> {
+        let repo = Repo::new_memory(None);
+        repo.init().await?;
+
+        let local = PeerId::random();
+        let remote = PeerId::random();
+
+        let mut store = KadStore::persistent(local, Default::default(), repo.clone()).await;
+
+        let mut record = Record::new(RecordKey::new(&"/test/a"), b"hello".to_vec());
+        record.expires = Some(Instant::now() + Duration::from_secs(60));
+        store.put(record.clone())?;
+        store.put(Record::new(RecordKey::new(&"/test/b"), b"world".to_vec()))?;
+        store.remove(&RecordKey::new(&"/test/b"));
+
+        let key = RecordKey::new(&"/test/provided");
+        store.add_provider(ProviderRecord::new(key.clone(), local, vec![]))?;
+        store.add_provider(ProviderRecord::new(
+            key.clone(),
+            remote,
+            vec!["/ip4/127.0.0.1/tcp/4001".parse()?],
+        ))?;
+
+        store.flush().await;
+        drop(store);
+
+        let store = KadStore::persistent(local, Default::default(), repo.clone()).await;
+
+        let loaded = store.get(&record.key).expect("record persisted");
+        assert_eq!(loaded.value, record.value);
+        let expires = loaded.expires.expect("expiry persisted");
+        let original = record.expires.unwrap();
+        let skew = if expires > original {
+            expires - original
+        } else {
+            original - expires
+        };
+        assert!(skew < Duration::from_secs(1));
+
+        assert!(store.get(&RecordKey::new(&"/test/b")).is_none());
+        assert_eq!(store.records().count(), 1);
+
+        let providers = store.providers(&key);
+        assert_eq!(providers.len(), 2);
+        let remote_record = providers.iter().find(|r| r.provider == remote).unwrap();
+        assert_eq!(remote_record.addresses.len(), 1);
+        assert_eq!(store.provided().count(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn queued_changes_are_written_on_shutdown() -> anyhow::Result<()> {
+        let repo = Repo::new_memory(None);
+        repo.init().await?;
+
+        let local = PeerId::random();
+        let mut store = KadStore::persistent(local, Default::default(), repo.clone()).await;
+
+        let key = RecordKey::new(&"/test/queued");
+        store.put(Record::new(key.clone(), b"hello".to_vec()))?;
+        store.shutdown().await;
+        drop(store);
+
+        // the bytes are stored as base64 instead of arrays of numbers
+        let stored = repo
+            .data_store()
+            .get(super::record_key(&key).as_bytes())
+            .await?
+            .expect("record written");
+        let stored = String::from_utf8(stored)?;
+        assert!(stored.contains("\"aGVsbG8\""), "{stored}");
+
+        let store = KadStore::persistent(local, Default::default(), repo.clone()).await;
+        assert_eq!(store.get(&key).expect("record persisted").value, b"hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn provided_keys_are_limited_unless_reserved() -> anyhow::Result<()> {
+        let local = PeerId::random();
+        let config = MemoryStoreConfig {
+            max_provided_keys: 1,
+            ..Default::default()
+        };
+        let mut store = KadStore::memory(local, config);
+
+        let record =
+            |key: &str, provider| ProviderRecord::new(RecordKey::new(&key), provider, vec![]);
+
+        store.add_provider(record("/a", local))?;
+        // providing the same key again and the records of other peers are not limited
+        store.add_provider(record("/a", local))?;
+        store.add_provider(record("/b", PeerId::random()))?;
+        assert!(matches!(
+            store.add_provider(record("/b", local)),
+            Err(Error::MaxProvidedKeys)
+        ));
+
+        store.reserve_provided(1);
+        store.add_provider(record("/b", local))?;
+        assert!(store.add_provider(record("/c", local)).is_err());
+
+        store.remove_provider(&RecordKey::new(&"/a"), &local);
+        store.add_provider(record("/c", local))?;
+        assert_eq!(store.provided().count(), 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn expired_entries_are_dropped_on_load() -> anyhow::Result<()> {
+        let repo = Repo::new_memory(None);
+        repo.init().await?;
+
+        let local = PeerId::random();
+        let mut store = KadStore::persistent(local, Default::default(), repo.clone()).await;
+
+        let mut record = Record::new(RecordKey::new(&"/test/expiring"), b"soon".to_vec());
+        record.expires = Some(Instant::now() + Duration::from_millis(50));
+        store.put(record)?;
+
+        let mut provider =
+            ProviderRecord::new(RecordKey::new(&"/test/expiring"), PeerId::random(), vec![]);
+        provider.expires = Some(Instant::now() + Duration::from_millis(50));
+        store.add_provider(provider)?;
+
+        store.flush().await;
+        drop(store);
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let store = KadStore::persistent(local, Default::default(), repo.clone()).await;
+        assert_eq!(store.records().count(), 0);
+        assert!(store
+            .providers(&RecordKey::new(&"/test/expiring"))
+            .is_empty());
+
+        let mut remaining = repo.data_store().iter().await;
+        use futures::StreamExt;
+        while let Some((key, _)) = remaining.next().await {
+            assert!(!key.starts_with(b"/kad/"), "expired entry left behind");
+        }
+
+        Ok(())
+    }
+}
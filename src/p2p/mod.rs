@@ -1,12 +1,13 @@
 //! P2P handling for IPFS nodes.
 use std::convert::TryInto;
 use std::num::{NonZeroU8, NonZeroUsize};
+use std::time::Duration;
 
 use crate::error::Error;
 use crate::repo::Repo;
 use crate::{IpfsOptions, TTransportFn};
 
-use libp2p::gossipsub::ValidationMode;
+use libp2p::gossipsub::{PeerScoreParams, PeerScoreThresholds, TopicScoreParams, ValidationMode};
 use libp2p::identify::Info as IdentifyInfo;
 use libp2p::identity::{Keypair, PublicKey};
 use libp2p::swarm::NetworkBehaviour;
@@ -16,10 +17,12 @@ use tracing::Span;
 
 pub(crate) mod addr;
 pub(crate) mod addressbook;
+pub(crate) mod fetch;
 pub(crate) mod peerbook;
 pub mod protocol;
 
 mod behaviour;
+mod kadstore;
 pub use self::addressbook::Config as AddressBookConfig;
 pub use self::behaviour::BehaviourEvent;
 pub use self::behaviour::IdentifyConfiguration;
@@ -29,6 +32,7 @@ pub use self::behaviour::{BitswapConfig, BitswapProtocol};
 
 pub use self::behaviour::{KadConfig, KadInserts, KadStoreConfig};
 pub use self::behaviour::{RateLimit, RelayConfig};
+pub use self::kadstore::KadStore;
 pub use self::transport::{DnsResolver, TransportConfig, UpgradeVersion};
 pub(crate) mod gossipsub;
 mod transport;
@@ -116,6 +120,188 @@ pub struct PubsubConfig {
 
     /// Validation
     pub validate: PubsubValidation,
+
+    /// Mesh parameters
+    pub mesh: PubsubMeshConfig,
+
+    /// How messages are identified, and therefore deduplicated
+    pub message_id: PubsubMessageId,
+
+    /// Publish own messages to all peers subscribed to the topic instead of only to the mesh
+    pub flood_publish: bool,
+
+    /// Peer scoring, disabled when `None`
+    pub scoring: Option<PubsubScoring>,
+}
+
+/// Gossipsub mesh parameters, named after their counterparts in the gossipsub specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PubsubMeshConfig {
+    /// Target amount of peers in the mesh of a topic (`D`)
+    pub mesh_n: usize,
+
+    /// Amount of peers below which peers are grafted to the mesh (`D_lo`)
+    pub mesh_n_low: usize,
+
+    /// Amount of peers above which peers are pruned from the mesh (`D_hi`)
+    pub mesh_n_high: usize,
+
+    /// Minimum amount of outbound peers kept in the mesh (`D_out`)
+    pub mesh_outbound_min: usize,
+
+    /// Amount of peers outside of the mesh to which gossip is emitted (`D_lazy`)
+    pub gossip_lazy: usize,
+
+    /// Interval between heartbeats maintaining the mesh
+    pub heartbeat_interval: Duration,
+}
+
+impl Default for PubsubMeshConfig {
+    fn default() -> Self {
+        Self {
+            mesh_n: 6,
+            mesh_n_low: 5,
+            mesh_n_high: 12,
+            mesh_outbound_min: 2,
+            gossip_lazy: 6,
+            heartbeat_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PubsubMessageId {
+    /// Identify messages by their source and sequence number
+    #[default]
+    SourceSequence,
+
+    /// Identify messages by the sha2-256 hash of their data, so that the same data is only
+    /// delivered once regardless of who published it
+    ContentAddressed,
+}
+
+/// Gossipsub peer scoring, penalizing peers that misbehave or do not contribute to the topics.
+#[derive(Debug, Clone, Default)]
+pub struct PubsubScoring {
+    /// Scoring parameters, including the per topic parameters
+    pub params: PeerScoreParams,
+
+    /// Scores below which peers are gossiped to, published to and graylisted
+    pub thresholds: PeerScoreThresholds,
+}
+
+impl PubsubScoring {
+    /// Sets the scoring parameters of a topic
+    pub fn with_topic(mut self, topic: impl Into<String>, params: TopicScoreParams) -> Self {
+        let topic = libp2p::gossipsub::IdentTopic::new(topic);
+        self.params.topics.insert(topic.hash(), params);
+        self
+    }
+}
+
+/// Compares the parameters field by field as they do not implement `PartialEq`. Floats are
+/// compared by their bits so that the comparison stays reflexive.
+impl PartialEq for PubsubScoring {
+    fn eq(&self, other: &Self) -> bool {
+        peer_score_params_eq(&self.params, &other.params)
+            && peer_score_thresholds_eq(&self.thresholds, &other.thresholds)
+    }
+}
+
+impl Eq for PubsubScoring {}
+
+fn peer_score_params_eq(a: &PeerScoreParams, b: &PeerScoreParams) -> bool {
+    let PeerScoreParams {
+        topics,
+        topic_score_cap,
+        app_specific_weight,
+        ip_colocation_factor_weight,
+        ip_colocation_factor_threshold,
+        ip_colocation_factor_whitelist,
+        behaviour_penalty_weight,
+        behaviour_penalty_threshold,
+        behaviour_penalty_decay,
+        decay_interval,
+        decay_to_zero,
+        retain_score,
+    } = a;
+
+    topics.len() == b.topics.len()
+        && topics.iter().all(|(topic, params)| {
+            b.topics
+                .get(topic)
+                .is_some_and(|other| topic_score_params_eq(params, other))
+        })
+        && topic_score_cap.to_bits() == b.topic_score_cap.to_bits()
+        && app_specific_weight.to_bits() == b.app_specific_weight.to_bits()
+        && ip_colocation_factor_weight.to_bits() == b.ip_colocation_factor_weight.to_bits()
+        && ip_colocation_factor_threshold.to_bits() == b.ip_colocation_factor_threshold.to_bits()
+        && *ip_colocation_factor_whitelist == b.ip_colocation_factor_whitelist
+        && behaviour_penalty_weight.to_bits() == b.behaviour_penalty_weight.to_bits()
+        && behaviour_penalty_threshold.to_bits() == b.behaviour_penalty_threshold.to_bits()
+        && behaviour_penalty_decay.to_bits() == b.behaviour_penalty_decay.to_bits()
+        && *decay_interval == b.decay_interval
+        && decay_to_zero.to_bits() == b.decay_to_zero.to_bits()
+        && *retain_score == b.retain_score
+}
+
+fn topic_score_params_eq(a: &TopicScoreParams, b: &TopicScoreParams) -> bool {
+    let TopicScoreParams {
+        topic_weight,
+        time_in_mesh_weight,
+        time_in_mesh_quantum,
+        time_in_mesh_cap,
+        first_message_deliveries_weight,
+        first_message_deliveries_decay,
+        first_message_deliveries_cap,
+        mesh_message_deliveries_weight,
+        mesh_message_deliveries_decay,
+        mesh_message_deliveries_cap,
+        mesh_message_deliveries_threshold,
+        mesh_message_deliveries_window,
+        mesh_message_deliveries_activation,
+        mesh_failure_penalty_weight,
+        mesh_failure_penalty_decay,
+        invalid_message_deliveries_weight,
+        invalid_message_deliveries_decay,
+    } = a;
+
+    topic_weight.to_bits() == b.topic_weight.to_bits()
+        && time_in_mesh_weight.to_bits() == b.time_in_mesh_weight.to_bits()
+        && *time_in_mesh_quantum == b.time_in_mesh_quantum
+        && time_in_mesh_cap.to_bits() == b.time_in_mesh_cap.to_bits()
+        && first_message_deliveries_weight.to_bits() == b.first_message_deliveries_weight.to_bits()
+        && first_message_deliveries_decay.to_bits() == b.first_message_deliveries_decay.to_bits()
+        && first_message_deliveries_cap.to_bits() == b.first_message_deliveries_cap.to_bits()
+        && mesh_message_deliveries_weight.to_bits() == b.mesh_message_deliveries_weight.to_bits()
+        && mesh_message_deliveries_decay.to_bits() == b.mesh_message_deliveries_decay.to_bits()
+        && mesh_message_deliveries_cap.to_bits() == b.mesh_message_deliveries_cap.to_bits()
+        && mesh_message_deliveries_threshold.to_bits()
+            == b.mesh_message_deliveries_threshold.to_bits()
+        && *mesh_message_deliveries_window == b.mesh_message_deliveries_window
+        && *mesh_message_deliveries_activation == b.mesh_message_deliveries_activation
+        && mesh_failure_penalty_weight.to_bits() == b.mesh_failure_penalty_weight.to_bits()
+        && mesh_failure_penalty_decay.to_bits() == b.mesh_failure_penalty_decay.to_bits()
+        && invalid_message_deliveries_weight.to_bits()
+            == b.invalid_message_deliveries_weight.to_bits()
+        && invalid_message_deliveries_decay.to_bits()
+            == b.invalid_message_deliveries_decay.to_bits()
+}
+
+fn peer_score_thresholds_eq(a: &PeerScoreThresholds, b: &PeerScoreThresholds) -> bool {
+    let PeerScoreThresholds {
+        gossip_threshold,
+        publish_threshold,
+        graylist_threshold,
+        accept_px_threshold,
+        opportunistic_graft_threshold,
+    } = a;
+
+    gossip_threshold.to_bits() == b.gossip_threshold.to_bits()
+        && publish_threshold.to_bits() == b.publish_threshold.to_bits()
+        && graylist_threshold.to_bits() == b.graylist_threshold.to_bits()
+        && accept_px_threshold.to_bits() == b.accept_px_threshold.to_bits()
+        && opportunistic_graft_threshold.to_bits() == b.opportunistic_graft_threshold.to_bits()
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -151,6 +337,10 @@ impl Default for PubsubConfig {
             max_transmit_size: 2 * 1024 * 1024,
             validate: PubsubValidation::Strict,
             floodsub_compat: false,
+            mesh: PubsubMeshConfig::default(),
+            message_id: PubsubMessageId::default(),
+            flood_publish: true,
+            scoring: None,
         }
     }
 }
@@ -231,3 +421,37 @@ impl libp2p::swarm::Executor for SpannedExecutor {
         tokio::task::spawn(future.instrument(self.0.clone()));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PubsubConfig, PubsubScoring};
+    use libp2p::gossipsub::TopicScoreParams;
+
+    #[test]
+    fn pubsub_configs_compare_scoring_params() {
+        let scored = |scoring: PubsubScoring| PubsubConfig {
+            scoring: Some(scoring),
+            ..Default::default()
+        };
+
+        let scoring = PubsubScoring::default().with_topic("topic", TopicScoreParams::default());
+        assert_eq!(scored(scoring.clone()), scored(scoring.clone()));
+        assert_ne!(scored(scoring.clone()), scored(PubsubScoring::default()));
+        assert_ne!(scored(scoring.clone()), PubsubConfig::default());
+
+        let mut weighted = scoring.clone();
+        weighted.params.app_specific_weight = 2.0;
+        assert_ne!(scored(scoring.clone()), scored(weighted));
+
+        let topic = TopicScoreParams {
+            topic_weight: f64::NAN,
+            ..Default::default()
+        };
+        let nan = PubsubScoring::default().with_topic("topic", topic);
+        assert_eq!(scored(nan.clone()), scored(nan));
+
+        let mut thresholds = scoring.clone();
+        thresholds.thresholds.gossip_threshold = -1.0;
+        assert_ne!(scored(scoring), scored(thresholds));
+    }
+}
@@ -1,7 +1,8 @@
 use crate::error::Error;
 use crate::repo::paths::{block_path, filestem_to_block_cid};
+use crate::repo::verify::is_intact;
 use crate::repo::{BlockPut, BlockStore};
-use crate::repo::{BlockRm, BlockRmError};
+use crate::repo::{BlockQuarantined, BlockRm, BlockRmError};
 use crate::Block;
 use async_trait::async_trait;
 use futures::stream::BoxStream;
@@ -20,6 +21,10 @@ use super::RepoBlockCommand;
 /// File system backed block store.
 ///
 /// For information on path mangling, please see `block_path` and `filestem_to_block_cid`.
+///
+/// Unless disabled with [`FsBlockStore::with_verify_on_read`], blocks are checked against their
+/// cid when read. A block found corrupted is quarantined by renaming its file to `.corrupted`
+/// and [`BlockQuarantined`] is returned, so that it is never served and can be fetched again.
 #[derive(Debug)]
 pub struct FsBlockStore {
     path: PathBuf,
@@ -30,16 +35,24 @@ pub struct FsBlockStoreTask {
     timeout: Duration,
     temp: HashMap<Cid, Delay>,
     path: PathBuf,
+    verify_on_read: bool,
     rx: futures::channel::mpsc::Receiver<RepoBlockCommand>,
 }
 
 impl FsBlockStore {
     pub fn new(path: PathBuf, duration: Duration) -> Self {
+        Self::with_verify_on_read(path, duration, true)
+    }
+
+    /// Creates a blockstore which checks the blocks against their cid when read only if
+    /// `verify_on_read` is set.
+    pub fn with_verify_on_read(path: PathBuf, duration: Duration, verify_on_read: bool) -> Self {
         let (tx, rx) = futures::channel::mpsc::channel(1);
         let mut task = FsBlockStoreTask {
             path: path.clone(),
             timeout: duration,
             temp: HashMap::new(),
+            verify_on_read,
             rx,
         };
 
@@ -161,6 +174,65 @@ impl BlockStore for FsBlockStore {
         rx.await.map_err(anyhow::Error::from)?
     }
 
+    async fn get_unchecked(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let _ = self
+            .tx
+            .clone()
+            .send(RepoBlockCommand::GetUnchecked {
+                cid: *cid,
+                response: tx,
+            })
+            .await;
+        rx.await.map_err(anyhow::Error::from)?
+    }
+
+    async fn quarantine(&self, cid: &Cid) -> Result<bool, Error> {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let _ = self
+            .tx
+            .clone()
+            .send(RepoBlockCommand::Quarantine {
+                cid: *cid,
+                response: tx,
+            })
+            .await;
+        rx.await.map_err(anyhow::Error::from)?
+    }
+
+    async fn temp_files(&self) -> Result<Vec<PathBuf>, Error> {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let _ = self
+            .tx
+            .clone()
+            .send(RepoBlockCommand::TempFiles { response: tx })
+            .await;
+        rx.await.map_err(anyhow::Error::from)?
+    }
+
+    async fn quarantined(&self) -> Result<Vec<Cid>, Error> {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let _ = self
+            .tx
+            .clone()
+            .send(RepoBlockCommand::Quarantined { response: tx })
+            .await;
+        rx.await.map_err(anyhow::Error::from)?
+    }
+
+    async fn remove_quarantined(&self, cid: &Cid) -> Result<bool, Error> {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        let _ = self
+            .tx
+            .clone()
+            .send(RepoBlockCommand::RemoveQuarantined {
+                cid: *cid,
+                response: tx,
+            })
+            .await;
+        rx.await.map_err(anyhow::Error::from)?
+    }
+
     async fn wipe(&self) {
         let (tx, rx) = futures::channel::oneshot::channel();
         let _ = self
@@ -210,6 +282,21 @@ impl FsBlockStoreTask {
                         RepoBlockCommand::List { response } => {
                             let _ = response.send(self.list().await);
                         }
+                        RepoBlockCommand::GetUnchecked { cid, response } => {
+                            let _ = response.send(self.get_unchecked(&cid).await);
+                        }
+                        RepoBlockCommand::Quarantine { cid, response } => {
+                            let _ = response.send(self.quarantine(&cid).await);
+                        }
+                        RepoBlockCommand::TempFiles { response } => {
+                            let _ = response.send(self.temp_files().await);
+                        }
+                        RepoBlockCommand::Quarantined { response } => {
+                            let _ = response.send(self.quarantined().await);
+                        }
+                        RepoBlockCommand::RemoveQuarantined { cid, response } => {
+                            let _ = response.send(self.remove_quarantined(&cid).await);
+                        }
                         RepoBlockCommand::Wipe { response } => {
                             let _ = response.send({
                                 self.wipe().await;
@@ -236,7 +323,23 @@ impl FsBlockStoreTask {
         Ok(metadata.is_file())
     }
 
-    async fn get(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+    async fn get(&mut self, cid: &Cid) -> Result<Option<Block>, Error> {
+        let Some(block) = self.get_unchecked(cid).await? else {
+            return Ok(None);
+        };
+
+        if !self.verify_on_read || is_intact(cid, block.data()) {
+            return Ok(Some(block));
+        }
+
+        warn!(cid = %cid, "block does not match its cid, quarantining it");
+        match self.quarantine(cid).await? {
+            true => Err(BlockQuarantined(*cid).into()),
+            false => Ok(None),
+        }
+    }
+
+    async fn get_unchecked(&self, cid: &Cid) -> Result<Option<Block>, Error> {
         let path = block_path(self.path.clone(), cid);
 
         let cid = *cid;
@@ -256,8 +359,7 @@ impl FsBlockStoreTask {
 
             let mut data = Vec::with_capacity(len as usize);
             file.read_to_end(&mut data)?;
-            let block = Block::new(cid, data)?;
-            Ok(Some(block))
+            Ok(Some(Block::new_unchecked(cid, data)))
         })
         .await?
     }
@@ -356,6 +458,18 @@ impl FsBlockStoreTask {
         }
     }
 
+    /// Renames the file of a corrupted block so that it is no longer listed nor read.
+    async fn quarantine(&mut self, cid: &Cid) -> Result<bool, Error> {
+        let path = block_path(self.path.clone(), cid);
+        self.temp.remove(cid);
+
+        match fs::rename(&path, path.with_extension("corrupted")).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     async fn cleanup(&mut self, refs: BoxStream<'_, Cid>) -> Result<Vec<Cid>, Error> {
         let mut refs = refs.collect::<BTreeSet<_>>().await;
         refs.extend(self.temp.keys().cloned());
@@ -419,6 +533,53 @@ impl FsBlockStoreTask {
         Ok(vec)
     }
 
+    async fn temp_files(&self) -> Result<Vec<PathBuf>, Error> {
+        self.files_with_extension("tmp").await
+    }
+
+    async fn quarantined(&self) -> Result<Vec<Cid>, Error> {
+        let files = self.files_with_extension("corrupted").await?;
+        Ok(files
+            .iter()
+            .filter_map(|path| filestem_to_block_cid(path.file_stem()))
+            .collect())
+    }
+
+    async fn remove_quarantined(&mut self, cid: &Cid) -> Result<bool, Error> {
+        let path = block_path(self.path.clone(), cid).with_extension("corrupted");
+
+        match fs::remove_file(path).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Lists the files of the shard directories with the given extension.
+    async fn files_with_extension(&self, extension: &str) -> Result<Vec<PathBuf>, Error> {
+        let stream = ReadDirStream::new(fs::read_dir(&self.path).await?);
+
+        let files = stream
+            .try_filter_map(|d| async move {
+                Ok(if d.file_type().await?.is_dir() {
+                    Some(ReadDirStream::new(fs::read_dir(d.path()).await?))
+                } else {
+                    None
+                })
+            })
+            .try_flatten()
+            .try_filter_map(|d| {
+                let path = d.path();
+                futures::future::ready(Ok(
+                    (path.extension() == Some(extension.as_ref())).then_some(path)
+                ))
+            })
+            .try_collect()
+            .await?;
+
+        Ok(files)
+    }
+
     async fn wipe(&mut self) {}
 }
 
@@ -641,4 +802,61 @@ mod tests {
         single.remove(&cid).await.unwrap().unwrap();
         assert_eq!(single.list().await.unwrap().len(), 0);
     }
+
+    #[tokio::test]
+    async fn verify_on_read() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().to_path_buf();
+
+        let data = b"1".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        let block = Block::new(cid, data).unwrap();
+
+        let unchecked = FsBlockStore::with_verify_on_read(path.clone(), Duration::ZERO, false);
+        unchecked.init().await.unwrap();
+        unchecked.put(block).await.unwrap();
+
+        let file = block_path(path.clone(), &cid);
+        std::fs::write(&file, b"2").unwrap();
+
+        let read = unchecked.get(&cid).await.unwrap().unwrap();
+        assert_eq!(read.data(), b"2");
+
+        let store = FsBlockStore::new(path, Duration::ZERO);
+        let e = store.get(&cid).await.unwrap_err();
+        assert!(e.is::<BlockQuarantined>());
+        assert_eq!(store.get(&cid).await.unwrap(), None);
+        assert!(!store.contains(&cid).await.unwrap());
+        assert!(store.list().await.unwrap().is_empty());
+        assert_eq!(
+            std::fs::read(file.with_extension("corrupted")).unwrap(),
+            b"2"
+        );
+
+        assert_eq!(store.quarantined().await.unwrap(), vec![cid]);
+        assert!(store.remove_quarantined(&cid).await.unwrap());
+        assert!(!store.remove_quarantined(&cid).await.unwrap());
+        assert!(store.quarantined().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn lists_temp_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().to_path_buf();
+
+        let store = FsBlockStore::new(path.clone(), Duration::ZERO);
+        store.init().await.unwrap();
+
+        let data = b"1".to_vec();
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(&data));
+        store.put(Block::new(cid, data).unwrap()).await.unwrap();
+        assert!(store.temp_files().await.unwrap().is_empty());
+
+        // as left behind by a write interrupted before the rename
+        let temp = block_path(path, &cid).with_extension("tmp");
+        std::fs::write(&temp, b"1").unwrap();
+
+        assert_eq!(store.temp_files().await.unwrap(), vec![temp]);
+        assert_eq!(store.list().await.unwrap(), vec![cid]);
+    }
 }
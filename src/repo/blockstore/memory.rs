@@ -208,6 +208,21 @@ impl MemBlockTask {
                         } => {
                             let _ = response.send(self.cleanup(refs).await);
                         },
+                        RepoBlockCommand::GetUnchecked { cid, response } => {
+                            let _ = response.send(self.get(&cid).await);
+                        }
+                        RepoBlockCommand::Quarantine { cid, response } => {
+                            let _ = response.send(self.remove(&cid).await.map(|res| res.is_ok()));
+                        }
+                        RepoBlockCommand::TempFiles { response } => {
+                            let _ = response.send(Ok(vec![]));
+                        }
+                        RepoBlockCommand::Quarantined { response } => {
+                            let _ = response.send(Ok(vec![]));
+                        }
+                        RepoBlockCommand::RemoveQuarantined { response, .. } => {
+                            let _ = response.send(Ok(false));
+                        }
                         RepoBlockCommand::Wipe { response } => {
                             let _ = response.send({
                                 self.wipe().await;
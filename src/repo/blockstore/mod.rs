@@ -1,5 +1,6 @@
 use futures::stream::BoxStream;
 use libipld::Cid;
+use std::path::PathBuf;
 
 use crate::{Block, Channel};
 
@@ -39,6 +40,24 @@ pub(crate) enum RepoBlockCommand {
     List {
         response: Channel<Vec<Cid>>,
     },
+    GetUnchecked {
+        cid: Cid,
+        response: Channel<Option<Block>>,
+    },
+    Quarantine {
+        cid: Cid,
+        response: Channel<bool>,
+    },
+    TempFiles {
+        response: Channel<Vec<PathBuf>>,
+    },
+    Quarantined {
+        response: Channel<Vec<Cid>>,
+    },
+    RemoveQuarantined {
+        cid: Cid,
+        response: Channel<bool>,
+    },
     Wipe {
         response: Channel<()>,
     },
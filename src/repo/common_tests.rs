@@ -54,7 +54,7 @@ macro_rules! pinstore_interface_tests {
             use libipld::Cid;
             use std::convert::TryFrom;
             use $crate::repo::common_tests::DSTestContext;
-            use $crate::repo::{PinKind, PinMode, PinStore};
+            use $crate::repo::{PinKind, PinMetadata, PinMode, PinStore};
 
             #[tokio::test]
             async fn pin_direct_twice_is_good() {
@@ -329,6 +329,114 @@ macro_rules! pinstore_interface_tests {
                 // go-ipfs it's different than path resolving
                 assert_eq!(e.to_string(), "already pinned recursively");
             }
+
+            #[tokio::test]
+            async fn metadata_follows_the_pin() {
+                let repo = DSTestContext::with($factory).await;
+
+                let root = Cid::try_from("QmX5S2xLu32K6WxWnyLeChQFbDHy79ULV9feJYH2Hy9bgp").unwrap();
+                let empty =
+                    Cid::try_from("QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH").unwrap();
+
+                let mut metadata = PinMetadata {
+                    name: Some("root".into()),
+                    ..Default::default()
+                };
+                metadata.values.insert("kind".into(), "test".into());
+
+                repo.set_pin_metadata(&root, metadata.clone())
+                    .await
+                    .expect_err("cannot set metadata of an unpinned block");
+
+                repo.insert_direct_pin(&root).await.unwrap();
+                repo.set_pin_metadata(&root, metadata.clone())
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    repo.pin_metadata(&root).await.unwrap(),
+                    Some(metadata.clone())
+                );
+
+                // upgrading the direct pin keeps the metadata
+                repo.insert_recursive_pin(
+                    &root,
+                    futures::stream::iter(vec![Ok(empty.clone())]).boxed(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(repo.pin_metadata(&root).await.unwrap(), Some(metadata));
+                assert_eq!(repo.pin_metadata(&empty).await.unwrap(), None);
+
+                repo.remove_recursive_pin(
+                    &root,
+                    futures::stream::iter(vec![Ok(empty.clone())]).boxed(),
+                )
+                .await
+                .unwrap();
+                assert_eq!(repo.pin_metadata(&root).await.unwrap(), None);
+            }
+
+            #[tokio::test]
+            async fn update_recursive_pin_applies_difference() {
+                use libipld::multihash::{Code, MultihashDigest};
+
+                let repo = DSTestContext::with($factory).await;
+
+                let cid_of = |data: &[u8]| {
+                    Cid::new_v1(libipld::IpldCodec::Raw.into(), Code::Sha2_256.digest(data))
+                };
+                let (old, new) = (cid_of(b"old"), cid_of(b"new"));
+                let (removed, shared, added) =
+                    (cid_of(b"removed"), cid_of(b"shared"), cid_of(b"added"));
+
+                repo.insert_recursive_pin(
+                    &old,
+                    futures::stream::iter(vec![Ok(removed), Ok(shared)]).boxed(),
+                )
+                .await
+                .unwrap();
+
+                let metadata = PinMetadata {
+                    name: Some("site".into()),
+                    ..Default::default()
+                };
+                repo.set_pin_metadata(&old, metadata.clone()).await.unwrap();
+
+                repo.update_recursive_pin(
+                    &old,
+                    &new,
+                    futures::stream::iter(vec![Ok(added)]).boxed(),
+                    futures::stream::iter(vec![Ok(removed)]).boxed(),
+                )
+                .await
+                .unwrap();
+
+                let mut all = repo
+                    .list(None)
+                    .await
+                    .try_collect::<HashedMap<Cid, PinMode>>()
+                    .await
+                    .unwrap();
+
+                assert_eq!(all.remove(&new), Some(PinMode::Recursive));
+                assert_eq!(all.remove(&shared), Some(PinMode::Indirect));
+                assert_eq!(all.remove(&added), Some(PinMode::Indirect));
+                assert!(all.is_empty(), "{:?}", all);
+
+                assert_eq!(repo.pin_metadata(&new).await.unwrap(), Some(metadata));
+                assert_eq!(repo.pin_metadata(&old).await.unwrap(), None);
+
+                let e = repo
+                    .update_recursive_pin(
+                        &old,
+                        &new,
+                        futures::stream::empty().boxed(),
+                        futures::stream::empty().boxed(),
+                    )
+                    .await
+                    .unwrap_err();
+                assert_eq!(e.to_string(), "not pinned recursively");
+            }
         }
     };
 }
@@ -1,7 +1,11 @@
 //! Persistent filesystem backed pin store. See [`FsDataStore`] for more information.
 use crate::error::Error;
-use crate::repo::paths::{filestem_to_pin_cid, pin_path};
-use crate::repo::{DataStore, PinKind, PinMode, PinModeRequirement, PinStore, References};
+use crate::repo::paths::{
+    filestem_to_key, filestem_to_pin_cid, is_long_key_path, key_path, pin_path,
+};
+use crate::repo::{
+    DataStore, PinKind, PinMetadata, PinMode, PinModeRequirement, PinStore, References,
+};
 use async_trait::async_trait;
 use core::convert::TryFrom;
 use futures::stream::TryStreamExt;
@@ -20,6 +24,11 @@ use tokio_util::either::Either;
 /// [`FsBlockStore`] sharded two level storage. Direct have empty files, recursive pins record all of
 /// their indirect descendants. Pin files are separated by their file extensions.
 ///
+/// Key-value pairs are stored under `data`, one file per key, with the key escaped into the file
+/// name by [`key_path`]. Keys too long for a file name are hashed into it instead, and their files
+/// start with the varint prefixed key. Values are written into a temporary file which is then
+/// renamed over the previous value, so a crash never leaves a partially written value behind.
+///
 /// When modifying, single lock is used.
 ///
 /// For the [`crate::repo::PinStore`] implementation see `fs/pinstore.rs`.
@@ -46,13 +55,12 @@ impl FsDataStore {
     }
 }
 
-/// The column operations are all unimplemented pending at least downscoping of the
-/// DataStore trait itself.
 #[async_trait]
 impl DataStore for FsDataStore {
     async fn init(&self) -> Result<(), Error> {
         // Although `pins` directory is created when inserting a data, is it not created when there are any attempts at listing the pins (thus causing to fail)
         tokio::fs::create_dir_all(&self.path.join("pins")).await?;
+        tokio::fs::create_dir_all(&self.path.join("data")).await?;
         Ok(())
     }
 
@@ -60,24 +68,122 @@ impl DataStore for FsDataStore {
         Ok(())
     }
 
-    async fn contains(&self, _key: &[u8]) -> Result<bool, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn contains(&self, key: &[u8]) -> Result<bool, Error> {
+        let path = key_path(self.path.join("data"), key);
+
+        if is_long_key_path(&path) {
+            // a file with another key of the same hash is not a match
+            return Ok(self.get(key).await?.is_some());
+        }
+
+        match fs::metadata(path).await {
+            Ok(metadata) => Ok(metadata.is_file()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
     }
 
-    async fn get(&self, _key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, Error> {
+        // no locking, the values are replaced atomically
+        let path = key_path(self.path.join("data"), key);
+
+        let contents = match fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        if !is_long_key_path(&path) {
+            return Ok(Some(contents));
+        }
+
+        match split_long_key(contents) {
+            Some((stored, value)) if stored == key => Ok(Some(value)),
+            Some(_) => Ok(None),
+            None => Err(anyhow::anyhow!("invalid value file {:?}", path)),
+        }
     }
 
-    async fn put(&self, _key: &[u8], _value: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn put(&self, key: &[u8], value: &[u8]) -> Result<(), Error> {
+        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await?;
+
+        let path = key_path(self.path.join("data"), key);
+        let value = if is_long_key_path(&path) {
+            let mut buffer = unsigned_varint::encode::usize_buffer();
+            let len = unsigned_varint::encode::usize(key.len(), &mut buffer);
+            [len, key, value].concat()
+        } else {
+            value.to_vec()
+        };
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit; // move into threadpool thread
+            let _entered = span.enter();
+
+            std::fs::create_dir_all(path.parent().expect("shard parent has to exist"))?;
+
+            let temp_path = path.with_extension("temp");
+
+            if let Err(e) = sync_write_value(&path, &temp_path, &value) {
+                if let Err(e) = std::fs::remove_file(&temp_path) {
+                    warn!("failed to cleanup temporary file: {}", e);
+                }
+                return Err(e);
+            }
+
+            Ok::<_, Error>(())
+        })
+        .await??;
+
+        Ok(())
     }
 
-    async fn remove(&self, _key: &[u8]) -> Result<(), Error> {
-        Err(anyhow::anyhow!("not implemented"))
+    async fn remove(&self, key: &[u8]) -> Result<(), Error> {
+        let _permit = self.lock.acquire().await?;
+
+        let path = key_path(self.path.join("data"), key);
+
+        match fs::remove_file(path).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
     }
 
     async fn iter(&self) -> futures::stream::BoxStream<'static, (Vec<u8>, Vec<u8>)> {
-        futures::stream::empty().boxed()
+        // no locking, like with pins; keys removed while iterating are skipped
+        let path = self.path.join("data");
+
+        let st = async_stream::stream! {
+            let keys = list_keyfiles(path);
+            futures::pin_mut!(keys);
+
+            while let Some(next) = keys.next().await {
+                let (key, path) = match next {
+                    Ok(next) => next,
+                    Err(e) => {
+                        warn!("failed to list the datastore: {}", e);
+                        break;
+                    }
+                };
+
+                match fs::read(&path).await {
+                    Ok(value) => match key {
+                        Some(key) => yield (key, value),
+                        None => match split_long_key(value) {
+                            Some((key, value)) => yield (key, value),
+                            None => warn!("invalid value file {:?}", path),
+                        },
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => warn!("failed to read {:?}: {}", path, e),
+                }
+            }
+        };
+
+        st.boxed()
     }
 
     async fn wipe(&self) {}
@@ -236,6 +342,8 @@ impl PinStore for FsDataStore {
             match std::fs::remove_file(&path) {
                 Ok(_) => {
                     trace!("direct pin removed");
+                    path.set_extension("metadata");
+                    sync_remove_metadata(&path);
                     Ok(())
                 }
                 Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
@@ -294,6 +402,8 @@ impl PinStore for FsDataStore {
             if !any {
                 Err(anyhow::anyhow!("not pinned or pinned indirectly"))
             } else {
+                path.set_extension("metadata");
+                sync_remove_metadata(&path);
                 Ok(())
             }
         })
@@ -506,6 +616,128 @@ impl PinStore for FsDataStore {
         // for the first of the duplicates
         Ok(response.into_iter().flatten().collect())
     }
+
+    async fn pin_metadata(&self, target: &Cid) -> Result<Option<PinMetadata>, Error> {
+        let mut path = pin_path(self.path.join("pins"), target);
+        path.set_extension("metadata");
+
+        match tokio::fs::read(path).await {
+            Ok(contents) => Ok(Some(serde_json::from_slice(&contents)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn set_pin_metadata(&self, target: &Cid, metadata: PinMetadata) -> Result<(), Error> {
+        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await?;
+
+        let mut path = pin_path(self.path.join("pins"), target);
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let _entered = span.enter();
+
+            if sync_read_direct_or_recursive(&mut path).is_none() {
+                return Err(anyhow::anyhow!("not pinned or pinned indirectly"));
+            }
+
+            path.set_extension("metadata");
+
+            if metadata.is_empty() {
+                sync_remove_metadata(&path);
+                return Ok(());
+            }
+
+            let value = serde_json::to_vec(&metadata)?;
+            sync_write_value(&path, &path.with_extension("metadata_temp"), &value)
+        })
+        .await??;
+
+        Ok(())
+    }
+
+    async fn update_recursive_pin(
+        &self,
+        old: &Cid,
+        new: &Cid,
+        added: References<'_>,
+        removed: References<'_>,
+    ) -> Result<(), Error> {
+        let added = added.try_collect::<std::collections::BTreeSet<_>>().await?;
+        let removed = removed
+            .try_collect::<std::collections::BTreeSet<_>>()
+            .await?;
+
+        let permit = Semaphore::acquire_owned(Arc::clone(&self.lock)).await?;
+
+        let base = self.path.join("pins");
+
+        if read_direct_or_recursive(pin_path(base.clone(), old)).await? != Some(PinMode::Recursive)
+        {
+            return Err(anyhow::anyhow!("not pinned recursively"));
+        }
+
+        // the recursive pin files list every indirect block, so the new one is the old one with
+        // the difference applied
+        let (_, references) = read_recursively_pinned(base.clone(), *old).await?;
+        let mut set = references
+            .into_iter()
+            .filter(|cid| !removed.contains(cid))
+            .collect::<std::collections::BTreeSet<_>>();
+        set.extend(added);
+        set.remove(new);
+
+        let mut old_path = pin_path(base.clone(), old);
+        let mut path = pin_path(base, new);
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let _entered = span.enter();
+
+            std::fs::create_dir_all(path.parent().expect("shard parent has to exist"))?;
+            let count = set.len();
+            let cids = set.into_iter().map(|cid| cid.to_string());
+
+            path.set_extension("recursive_temp");
+
+            let file = std::fs::File::create(&path)?;
+
+            if let Err(e) = sync_write_recursive_pin(file, count, cids) {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    warn!("failed to cleanup temporary file: {}", e);
+                }
+                return Err(e);
+            }
+
+            std::fs::rename(&path, path.with_extension("recursive"))?;
+
+            old_path.set_extension("metadata");
+            path.set_extension("metadata");
+            match std::fs::rename(&old_path, &path) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            for (path, ext) in [(&mut path, "direct"), (&mut old_path, "recursive")] {
+                path.set_extension(ext);
+                match std::fs::remove_file(&path) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => warn!("failed to remove {:?} when updating pin: {}", path, e),
+                }
+            }
+
+            Ok::<_, Error>(())
+        })
+        .await??;
+
+        Ok(())
+    }
 }
 
 impl FsDataStore {
@@ -552,6 +784,74 @@ impl FsDataStore {
     }
 }
 
+/// Lists the key files written by [`DataStore::put`] along with the keys decoded from their names,
+/// or `None` for the long keys stored in the file.
+fn list_keyfiles(
+    path: PathBuf,
+) -> impl futures::stream::Stream<Item = Result<(Option<Vec<u8>>, PathBuf), Error>> + 'static {
+    futures::stream::once(fs::read_dir(path))
+        .map_ok(ReadDirStream::new)
+        .try_flatten()
+        .and_then(|d| async move {
+            // map over the shard directories
+            Ok(if d.file_type().await?.is_dir() {
+                Either::Left(ReadDirStream::new(fs::read_dir(d.path()).await?))
+            } else {
+                Either::Right(empty())
+            })
+        })
+        .try_flatten()
+        .map_err(Error::new)
+        // the temporary files left over from a crash while writing are skipped
+        .try_filter_map(|d| {
+            let path = d.path();
+
+            let maybe_tuple = if path.extension() == Some("data".as_ref()) {
+                filestem_to_key(path.file_stem()).map(|key| (Some(key), path))
+            } else if is_long_key_path(&path) {
+                Some((None, path))
+            } else {
+                None
+            };
+
+            futures::future::ready(Ok(maybe_tuple))
+        })
+}
+
+/// Splits the contents of a file of a long key into the key and the value.
+fn split_long_key(mut contents: Vec<u8>) -> Option<(Vec<u8>, Vec<u8>)> {
+    let (len, rest) = unsigned_varint::decode::usize(&contents).ok()?;
+    let start = contents.len() - rest.len();
+    let end = start
+        .checked_add(len)
+        .filter(|end| *end <= contents.len())?;
+    let value = contents.split_off(end);
+    contents.drain(..start);
+    Some((contents, value))
+}
+
+/// Writes the value into the temporary file, then renames it over the previous value.
+fn sync_write_value(
+    path: &std::path::Path,
+    temp_path: &std::path::Path,
+    value: &[u8],
+) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut file = std::fs::File::create(temp_path)?;
+    file.write_all(value)?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(temp_path, path)?;
+
+    // make the rename itself durable as well
+    #[cfg(unix)]
+    std::fs::File::open(path.parent().expect("shard parent has to exist"))?.sync_all()?;
+
+    Ok(())
+}
+
 /// Reads our serialized format for recusive pins, which is JSON array of stringified Cids.
 ///
 /// On file not found error returns an empty Vec as if nothing had happened. This is because we
@@ -604,6 +904,15 @@ fn sync_read_direct_or_recursive(block_path: &mut PathBuf) -> Option<PinMode> {
     None
 }
 
+/// Removes the metadata file of a pin, which may not exist.
+fn sync_remove_metadata(path: &std::path::Path) {
+    match std::fs::remove_file(path) {
+        Ok(_) => trace!("pin metadata removed"),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => warn!("failed to remove pin metadata {:?}: {}", path, e),
+    }
+}
+
 fn sync_write_recursive_pin(
     file: std::fs::File,
     count: usize,
@@ -634,3 +943,115 @@ crate::pinstore_interface_tests!(
     common_tests,
     crate::repo::datastore::flatfs::FsDataStore::new
 );
+
+#[cfg(test)]
+mod test {
+    use crate::repo::{datastore::flatfs::FsDataStore, DataStore};
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn test_kv_datastore() {
+        let tmp = tempfile::tempdir().unwrap();
+        let store = FsDataStore::new(tmp.path().into());
+        let key = [1, 2, 3, 4];
+        let value = [5, 6, 7, 8];
+
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+
+        let contains = store.contains(&key);
+        assert!(!contains.await.unwrap());
+        let get = store.get(&key);
+        assert_eq!(get.await.unwrap(), None);
+        store.remove(&key).await.unwrap();
+
+        let put = store.put(&key, &value);
+        put.await.unwrap();
+        let contains = store.contains(&key);
+        assert!(contains.await.unwrap());
+        let get = store.get(&key);
+        assert_eq!(get.await.unwrap(), Some(value.to_vec()));
+
+        store.remove(&key).await.unwrap();
+        let contains = store.contains(&key);
+        assert!(!contains.await.unwrap());
+        let get = store.get(&key);
+        assert_eq!(get.await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn kv_datastore_persists_and_iterates() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let store = FsDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+        store.put(b"/ipns/first", b"1").await.unwrap();
+        store.put(b"/ipns/second", b"2").await.unwrap();
+        store.put(b"/ipns/second", b"22").await.unwrap();
+        store.put(b"", b"empty").await.unwrap();
+        drop(store);
+
+        // a leftover from an interrupted write
+        std::fs::write(tmp.path().join("data").join("leftover.temp"), b"x").unwrap();
+
+        let store = FsDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+        store.open().await.unwrap();
+
+        assert_eq!(
+            store.get(b"/ipns/second").await.unwrap(),
+            Some(b"22".to_vec())
+        );
+
+        let mut all = store.iter().await.collect::<Vec<_>>().await;
+        all.sort();
+
+        assert_eq!(
+            all,
+            vec![
+                (b"".to_vec(), b"empty".to_vec()),
+                (b"/ipns/first".to_vec(), b"1".to_vec()),
+                (b"/ipns/second".to_vec(), b"22".to_vec()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn kv_datastore_long_keys() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let store = FsDataStore::new(tmp.path().into());
+        store.init().await.unwrap();
+
+        let key = vec![b'k'; 300];
+        let other = vec![b'o'; 300];
+        store.put(&key, b"value").await.unwrap();
+        store.put(&other, b"other").await.unwrap();
+        assert!(store.contains(&key).await.unwrap());
+        assert_eq!(store.get(&key).await.unwrap(), Some(b"value".to_vec()));
+
+        let mut all = store.iter().await.collect::<Vec<_>>().await;
+        all.sort();
+        assert_eq!(
+            all,
+            vec![
+                (key.clone(), b"value".to_vec()),
+                (other.clone(), b"other".to_vec())
+            ]
+        );
+
+        store.remove(&key).await.unwrap();
+        assert!(!store.contains(&key).await.unwrap());
+        assert_eq!(store.get(&key).await.unwrap(), None);
+
+        // a file holding another key, as after a hash collision, does not count as the key
+        let data = tmp.path().join("data");
+        std::fs::copy(
+            crate::repo::paths::key_path(data.clone(), &other),
+            crate::repo::paths::key_path(data, &key),
+        )
+        .unwrap();
+        assert!(!store.contains(&key).await.unwrap());
+        assert_eq!(store.get(&key).await.unwrap(), None);
+    }
+}
@@ -1,5 +1,5 @@
 use crate::error::Error;
-use crate::repo::{DataStore, PinKind, PinMode, PinModeRequirement, PinStore};
+use crate::repo::{DataStore, PinKind, PinMetadata, PinMode, PinModeRequirement, PinStore};
 use async_trait::async_trait;
 use futures::StreamExt;
 use libipld::{cid, Cid};
@@ -10,7 +10,7 @@ use std::collections::hash_map::Entry;
 
 // FIXME: Transition to Persistent Map to make iterating more consistent
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 /// Describes an in-memory `DataStore`.
@@ -20,6 +20,33 @@ pub struct MemDataStore {
     // this could also be PinDocument however doing any serialization allows to see the required
     // error types easier
     pin: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+    // always locked after `pin`
+    indirect: Mutex<IndirectPins>,
+}
+
+/// The blocks pinned through each recursive pin.
+///
+/// The documents of the blocks name a recursive pin by an id, which is the v1 cid of the root the
+/// pin was created with. Updating the pin keeps its id, so that the documents of the blocks shared
+/// by both versions are left untouched.
+#[derive(Debug, Default)]
+struct IndirectPins {
+    // keyed by the v1 cid of the root
+    blocks: HashMap<Cid, HashSet<Cid>>,
+    // ids of the updated pins, keyed by the v1 cid of their current root
+    ids: HashMap<Cid, Cid>,
+    // the other way around
+    roots: HashMap<Cid, Cid>,
+}
+
+impl IndirectPins {
+    fn id(&self, root: &Cid) -> Cid {
+        self.ids.get(root).copied().unwrap_or(*root)
+    }
+
+    fn root(&self, id: &Cid) -> Cid {
+        self.roots.get(id).copied().unwrap_or(*id)
+    }
 }
 
 impl MemDataStore {
@@ -62,6 +89,7 @@ impl MemDataStore {
                         cid::Version::V1 => 1,
                     },
                     indirect_by: Vec::new(),
+                    metadata: None,
                 };
 
                 doc.update(true, kind).unwrap();
@@ -90,6 +118,11 @@ impl MemDataStore {
                     return Ok(false);
                 }
 
+                if !doc.direct && !doc.recursive.is_set() {
+                    // the metadata goes with the direct or recursive pin
+                    doc.metadata = None;
+                }
+
                 if doc.can_remove() {
                     oe.remove();
                 } else {
@@ -103,6 +136,32 @@ impl MemDataStore {
             Entry::Vacant(_) => Err(anyhow::anyhow!("not pinned")),
         }
     }
+
+    /// Makes `id` available for a new recursive pin by naming the updated pin which uses it by
+    /// its current root instead.
+    fn release_id(
+        g: &mut OwnedMutexGuard<HashMap<Vec<u8>, Vec<u8>>>,
+        indirect: &mut IndirectPins,
+        id: &Cid,
+    ) -> Result<(), Error> {
+        let Some(root) = indirect.roots.remove(id) else {
+            return Ok(());
+        };
+        indirect.ids.remove(&root);
+
+        // the root may itself be the id of a pin updated since
+        Self::release_id(g, indirect, &root)?;
+
+        let old_kind = PinKind::IndirectFrom(id);
+        let kind = PinKind::IndirectFrom(&root);
+        for block in indirect.blocks.get(&root).into_iter().flatten() {
+            if g.contains_key(&block.to_bytes()) && Self::remove_pin(g, block, &old_kind)? {
+                Self::insert_pin(g, block, &kind)?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -157,14 +216,22 @@ impl PinStore for MemDataStore {
         // inmem version doesn't need to be all that great. this could be for nothing, if the root
         // was already pinned.
 
+        let mut indirect_g = self.indirect.lock().await;
+        Self::release_id(&mut g, &mut indirect_g, &target_v1)?;
+
         let mut count = 0;
+        let mut indirect = HashSet::new();
         let kind = PinKind::IndirectFrom(&target_v1);
         while let Some(next) = refs.try_next().await? {
             // no rollback, nothing
             Self::insert_pin(&mut g, &next, &kind)?;
+            indirect.insert(next);
             count += 1;
         }
 
+        indirect_g.blocks.insert(target_v1, indirect);
+        drop(indirect_g);
+
         let kind = PinKind::Recursive(count as u64);
         Self::insert_pin(&mut g, target, &kind)?;
 
@@ -196,8 +263,9 @@ impl PinStore for MemDataStore {
                 Self::remove_pin(&mut g, target, &PinKind::Direct)?;
                 return Ok(());
             }
-            Some(Ok(PinKind::IndirectFrom(cid))) => {
-                return Err(anyhow::anyhow!("pinned indirectly through {}", cid))
+            Some(Ok(PinKind::IndirectFrom(id))) => {
+                let root = self.indirect.lock().await.root(&id);
+                return Err(anyhow::anyhow!("pinned indirectly through {}", root));
             }
             // same here as above with the same message
             _ => return Err(anyhow::anyhow!("not pinned or pinned indirectly")),
@@ -213,12 +281,19 @@ impl PinStore for MemDataStore {
             Cid::new_v1(target.codec(), target.hash().to_owned())
         };
 
-        let kind = PinKind::IndirectFrom(&target_v1);
+        let mut indirect_g = self.indirect.lock().await;
+        let id = indirect_g.id(&target_v1);
+
+        let kind = PinKind::IndirectFrom(&id);
         while let Some(next) = refs.try_next().await? {
             // no rollback, nothing
             Self::remove_pin(&mut g, &next, &kind)?;
         }
 
+        indirect_g.blocks.remove(&target_v1);
+        indirect_g.ids.remove(&target_v1);
+        indirect_g.roots.remove(&id);
+
         Ok(())
     }
 
@@ -259,6 +334,7 @@ impl PinStore for MemDataStore {
         requirement: Option<PinMode>,
     ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error> {
         let g = self.pin.lock().await;
+        let indirect = self.indirect.lock().await;
 
         let requirement = PinModeRequirement::from(requirement);
 
@@ -273,6 +349,9 @@ impl PinStore for MemDataStore {
                         // None from document is bad result, since the document shouldn't exist in the
                         // first place
                         let mode = match doc.pick_kind() {
+                            Some(Ok(PinKind::IndirectFrom(id))) => {
+                                PinKind::IndirectFrom(indirect.root(&id))
+                            }
                             Some(Ok(kind)) => kind,
                             Some(Err(invalid_cid)) => return Err(Error::new(invalid_cid)),
                             None => {
@@ -310,6 +389,112 @@ impl PinStore for MemDataStore {
             })
             .collect::<Result<Vec<_>, _>>()
     }
+
+    async fn pin_metadata(&self, target: &Cid) -> Result<Option<PinMetadata>, Error> {
+        let g = self.pin.lock().await;
+
+        match g.get(&target.to_bytes()) {
+            Some(raw) => {
+                let doc: PinDocument = serde_json::from_slice(raw)?;
+                Ok(doc.metadata)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn set_pin_metadata(&self, target: &Cid, metadata: PinMetadata) -> Result<(), Error> {
+        let mut g = self.pin.lock().await;
+
+        let raw = g
+            .get_mut(&target.to_bytes())
+            .ok_or_else(|| anyhow::anyhow!("not pinned or pinned indirectly"))?;
+
+        let mut doc: PinDocument = serde_json::from_slice(raw)?;
+        if !doc.direct && !doc.recursive.is_set() {
+            return Err(anyhow::anyhow!("not pinned or pinned indirectly"));
+        }
+
+        doc.metadata = (!metadata.is_empty()).then_some(metadata);
+        raw.clear();
+        serde_json::to_writer(raw, &doc)?;
+        Ok(())
+    }
+
+    async fn update_recursive_pin(
+        &self,
+        old: &Cid,
+        new: &Cid,
+        mut added: crate::repo::References<'_>,
+        mut removed: crate::repo::References<'_>,
+    ) -> Result<(), Error> {
+        use futures::TryStreamExt;
+
+        let mut g = Mutex::lock_owned(Arc::clone(&self.pin)).await;
+
+        let doc: PinDocument = match g.get(&old.to_bytes()) {
+            Some(raw) => serde_json::from_slice(raw)?,
+            None => return Err(anyhow::anyhow!("not pinned recursively")),
+        };
+
+        let old_count = match doc.recursive {
+            Recursive::Count(count) => count,
+            _ => return Err(anyhow::anyhow!("not pinned recursively")),
+        };
+
+        let old_v1 = Cid::new_v1(old.codec(), old.hash().to_owned());
+        let new_v1 = Cid::new_v1(new.codec(), new.hash().to_owned());
+
+        Self::insert_pin(&mut g, new, &PinKind::RecursiveIntention)?;
+
+        // the new root takes over the id of the old one, leaving the documents of the blocks
+        // shared by both versions as they are
+        let mut indirect_g = self.indirect.lock().await;
+        let id = indirect_g.id(&old_v1);
+        indirect_g.ids.remove(&old_v1);
+        indirect_g.roots.remove(&id);
+        if id != new_v1 {
+            indirect_g.ids.insert(new_v1, id);
+            indirect_g.roots.insert(id, new_v1);
+        }
+        let mut indirect = indirect_g.blocks.remove(&old_v1).unwrap_or_default();
+
+        let kind = PinKind::IndirectFrom(&id);
+        if indirect.remove(new) {
+            Self::remove_pin(&mut g, new, &kind)?;
+        }
+
+        let mut count = old_count;
+        while let Some(next) = removed.try_next().await? {
+            // blocks missing from a partial pin were never pinned
+            if g.contains_key(&next.to_bytes()) && Self::remove_pin(&mut g, &next, &kind)? {
+                count = count.saturating_sub(1);
+            }
+            indirect.remove(&next);
+        }
+
+        while let Some(next) = added.try_next().await? {
+            if Self::insert_pin(&mut g, &next, &kind)? {
+                count += 1;
+            }
+            indirect.insert(next);
+        }
+
+        indirect_g.blocks.insert(new_v1, indirect);
+        drop(indirect_g);
+
+        Self::remove_pin(&mut g, old, &PinKind::Recursive(old_count))?;
+        Self::insert_pin(&mut g, new, &PinKind::Recursive(count))?;
+
+        if let Some(metadata) = doc.metadata {
+            let raw = g.get_mut(&new.to_bytes()).expect("inserted above");
+            let mut doc: PinDocument = serde_json::from_slice(raw)?;
+            doc.metadata = Some(metadata);
+            raw.clear();
+            serde_json::to_writer(raw, &doc)?;
+        }
+
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -365,6 +550,7 @@ impl DataStore for MemDataStore {
     async fn wipe(&self) {
         self.inner.lock().await.clear();
         self.pin.lock().await.clear();
+        *self.indirect.lock().await = Default::default();
     }
 }
 
@@ -401,6 +587,8 @@ struct PinDocument {
     cid_version: u8,
     // using the cidv1 versions of all cids here, not sure if that makes sense or is important
     indirect_by: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    metadata: Option<PinMetadata>,
 }
 
 impl PinDocument {
@@ -635,6 +823,7 @@ mod tests {
             recursive: Recursive::Not,
             cid_version: 0,
             indirect_by: Vec::new(),
+            metadata: None,
         };
 
         assert!(doc.update(true, &PinKind::Direct).unwrap());
@@ -1,6 +1,6 @@
 use crate::error::Error;
 use crate::repo::{DataStore, PinModeRequirement};
-use crate::repo::{PinKind, PinMode, PinStore, References};
+use crate::repo::{PinKind, PinMetadata, PinMode, PinStore, References};
 use async_trait::async_trait;
 use either::Either;
 use futures::stream::{StreamExt, TryStreamExt};
@@ -245,16 +245,25 @@ impl PinStore for RedbDataStore {
 
                 let already_pinned = get_pinned_mode(Either::Right(&mut table), &target)?;
 
+                let mut metadata = None;
+
                 match already_pinned {
                     Some((PinMode::Recursive, _)) => return Ok(()),
-                    Some((PinMode::Direct, key)) | Some((PinMode::Indirect, key)) => {
+                    Some((PinMode::Direct, key)) => {
+                        // the metadata of a direct pin carries over to the recursive one
+                        metadata = table
+                            .remove(key.as_bytes())?
+                            .map(|value| value.value().to_vec());
+                    }
+                    Some((PinMode::Indirect, key)) => {
                         table.remove(key.as_bytes())?;
                     }
                     None => {}
                 }
 
                 let recursive_key = get_pin_key(&target, &PinMode::Recursive);
-                table.insert(recursive_key.as_bytes(), recursive_value())?;
+                let value = metadata.as_deref().unwrap_or(recursive_value());
+                table.insert(recursive_key.as_bytes(), value)?;
 
                 let target_value = indirect_value(&target);
 
@@ -496,6 +505,133 @@ impl PinStore for RedbDataStore {
         })
         .await?
     }
+
+    async fn pin_metadata(&self, target: &Cid) -> Result<Option<PinMetadata>, Error> {
+        let target = target.to_owned();
+        let db = self.get_db();
+
+        tokio::task::spawn_blocking(move || {
+            let read_tx = db.begin_read()?;
+            let table = read_tx.open_table(PINTABLE)?;
+
+            for mode in &[PinMode::Direct, PinMode::Recursive] {
+                if let Some(value) = table.get(get_pin_key(&target, mode).as_bytes())? {
+                    return metadata_from_value(value.value());
+                }
+            }
+            Ok(None)
+        })
+        .await?
+    }
+
+    async fn set_pin_metadata(&self, target: &Cid, metadata: PinMetadata) -> Result<(), Error> {
+        let value = metadata_value(&metadata)?;
+        let target = target.to_owned();
+        let db = self.get_db();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            let tx = db.begin_write()?;
+            {
+                let mut table = tx.open_table(PINTABLE)?;
+
+                match get_pinned_mode(Either::Right(&mut table), &target)? {
+                    Some((PinMode::Direct, key)) | Some((PinMode::Recursive, key)) => {
+                        table.insert(key.as_bytes(), value.as_slice())?;
+                    }
+                    _ => return Err(anyhow::anyhow!("not pinned or pinned indirectly")),
+                }
+            }
+            tx.commit()?;
+
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?
+    }
+
+    async fn update_recursive_pin(
+        &self,
+        old: &Cid,
+        new: &Cid,
+        added: References<'_>,
+        removed: References<'_>,
+    ) -> Result<(), Error> {
+        let added = added.try_collect::<BTreeSet<_>>().await?;
+        let removed = removed.try_collect::<BTreeSet<_>>().await?;
+
+        let old = old.to_owned();
+        let new = new.to_owned();
+        let db = self.get_db();
+
+        let span = tracing::Span::current();
+
+        tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            let tx = db.begin_write()?;
+            {
+                let mut table = tx.open_table(PINTABLE)?;
+
+                let old_key = match get_pinned_mode(Either::Right(&mut table), &old)? {
+                    Some((PinMode::Recursive, key)) => key,
+                    _ => return Err(anyhow::anyhow!("not pinned recursively")),
+                };
+
+                let mut value = table
+                    .remove(old_key.as_bytes())?
+                    .map(|value| value.value().to_vec())
+                    .filter(|value| !value.is_empty());
+
+                match get_pinned_mode(Either::Right(&mut table), &new)? {
+                    Some((PinMode::Recursive, _)) => {
+                        return Err(anyhow::anyhow!("already pinned recursively"))
+                    }
+                    Some((mode, key)) => {
+                        let previous = table
+                            .remove(key.as_bytes())?
+                            .map(|value| value.value().to_vec());
+                        if mode == PinMode::Direct && value.is_none() {
+                            value = previous;
+                        }
+                    }
+                    None => {}
+                }
+
+                let recursive_key = get_pin_key(&new, &PinMode::Recursive);
+                let value = value.as_deref().unwrap_or(recursive_value());
+                table.insert(recursive_key.as_bytes(), value)?;
+
+                // the blocks shared by both versions keep naming the old root as their source
+                for cid in &removed {
+                    if let Some((PinMode::Indirect, key)) =
+                        get_pinned_mode(Either::Right(&mut table), cid)?
+                    {
+                        table.remove(key.as_bytes())?;
+                    }
+                }
+
+                let target_value = indirect_value(&new);
+
+                for cid in &added {
+                    if get_pinned_mode(Either::Right(&mut table), cid)?.is_some() {
+                        continue;
+                    }
+
+                    let indirect_key = get_pin_key(cid, &PinMode::Indirect);
+                    table.insert(indirect_key.as_bytes(), target_value.as_bytes())?;
+                }
+            }
+
+            tx.commit()?;
+            Ok::<_, anyhow::Error>(())
+        })
+        .await?
+    }
 }
 
 /// Name the empty value stored for direct pins; the pin key itself describes the mode and the cid.
@@ -508,6 +644,22 @@ fn recursive_value() -> &'static [u8] {
     Default::default()
 }
 
+/// Name the value stored for direct and recursive pins with a name or metadata.
+fn metadata_value(metadata: &PinMetadata) -> Result<Vec<u8>, Error> {
+    if metadata.is_empty() {
+        return Ok(vec![]);
+    }
+    Ok(serde_json::to_vec(metadata)?)
+}
+
+/// Inverse of [`metadata_value`], also accepting [`direct_value`] and [`recursive_value`].
+fn metadata_from_value(bytes: &[u8]) -> Result<Option<PinMetadata>, Error> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(bytes)?))
+}
+
 /// Name the value stored for indirect pins, currently only the most recent recursive pin.
 fn indirect_value(recursively_pinned: &Cid) -> String {
     recursively_pinned.to_string()
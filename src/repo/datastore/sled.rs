@@ -1,6 +1,6 @@
 use crate::error::Error;
 use crate::repo::{DataStore, PinModeRequirement};
-use crate::repo::{PinKind, PinMode, PinStore, References};
+use crate::repo::{PinKind, PinMetadata, PinMode, PinStore, References};
 use async_trait::async_trait;
 use futures::stream::{StreamExt, TryStreamExt};
 use libipld::cid::Cid;
@@ -199,18 +199,27 @@ impl PinStore for SledDataStore {
             db.transaction::<_, _, Infallible>(move |tx_tree| {
                 let already_pinned = get_pinned_mode(tx_tree, &target)?;
 
+                let mut metadata = None;
+
                 match already_pinned {
                     Some((PinMode::Recursive, _)) => return Ok(()),
                     Some((PinMode::Direct, key)) | Some((PinMode::Indirect, key)) => {
                         // FIXME: this is probably another lapse in tests that both direct and
                         // indirect can be removed when inserting recursive?
-                        tx_tree.remove(key.as_str())?;
+                        let value = tx_tree.remove(key.as_str())?;
+                        // the metadata of a direct pin carries over to the recursive one
+                        if key.starts_with("pin.d.") {
+                            metadata = value;
+                        }
                     }
                     None => {}
                 }
 
                 let recursive_key = get_pin_key(&target, &PinMode::Recursive);
-                tx_tree.insert(recursive_key.as_str(), recursive_value())?;
+                match metadata {
+                    Some(value) => tx_tree.insert(recursive_key.as_str(), value)?,
+                    None => tx_tree.insert(recursive_key.as_str(), recursive_value())?,
+                };
 
                 let target_value = indirect_value(&target);
 
@@ -469,6 +478,127 @@ impl PinStore for SledDataStore {
         })
         .await?
     }
+
+    async fn pin_metadata(&self, target: &Cid) -> Result<Option<PinMetadata>, Error> {
+        let target = target.to_owned();
+        let db = self.get_db().to_owned();
+
+        tokio::task::spawn_blocking(move || {
+            for mode in &[PinMode::Direct, PinMode::Recursive] {
+                if let Some(value) = db.get(get_pin_key(&target, mode).as_str())? {
+                    return metadata_from_value(&value);
+                }
+            }
+            Ok(None)
+        })
+        .await?
+    }
+
+    async fn set_pin_metadata(&self, target: &Cid, metadata: PinMetadata) -> Result<(), Error> {
+        use ConflictableTransactionError::Abort;
+        let value = metadata_value(&metadata)?;
+        let target = target.to_owned();
+        let db = self.get_db().to_owned();
+
+        let span = tracing::Span::current();
+
+        let res = tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            db.transaction(|tx_tree| {
+                match get_pinned_mode(tx_tree, &target)? {
+                    Some((PinMode::Direct, key)) | Some((PinMode::Recursive, key)) => {
+                        tx_tree.insert(key.as_str(), value.as_slice())?;
+                    }
+                    _ => return Err(Abort(anyhow::anyhow!("not pinned or pinned indirectly"))),
+                }
+
+                tx_tree.flush();
+                Ok(())
+            })
+        })
+        .await?;
+
+        launder(res)
+    }
+
+    async fn update_recursive_pin(
+        &self,
+        old: &Cid,
+        new: &Cid,
+        added: References<'_>,
+        removed: References<'_>,
+    ) -> Result<(), Error> {
+        use ConflictableTransactionError::Abort;
+        let added = added.try_collect::<BTreeSet<_>>().await?;
+        let removed = removed.try_collect::<BTreeSet<_>>().await?;
+
+        let old = old.to_owned();
+        let new = new.to_owned();
+        let db = self.get_db().to_owned();
+
+        let span = tracing::Span::current();
+
+        let res = tokio::task::spawn_blocking(move || {
+            let span = tracing::trace_span!(parent: &span, "blocking");
+            let _g = span.enter();
+
+            db.transaction(|tx_tree| {
+                let old_key = match get_pinned_mode(tx_tree, &old)? {
+                    Some((PinMode::Recursive, key)) => key,
+                    _ => return Err(Abort(anyhow::anyhow!("not pinned recursively"))),
+                };
+
+                let mut value = tx_tree
+                    .remove(old_key.as_str())?
+                    .filter(|value| !value.is_empty());
+
+                match get_pinned_mode(tx_tree, &new)? {
+                    Some((PinMode::Recursive, _)) => {
+                        return Err(Abort(anyhow::anyhow!("already pinned recursively")))
+                    }
+                    Some((mode, key)) => {
+                        let previous = tx_tree.remove(key.as_str())?;
+                        if mode == PinMode::Direct && value.is_none() {
+                            value = previous;
+                        }
+                    }
+                    None => {}
+                }
+
+                let recursive_key = get_pin_key(&new, &PinMode::Recursive);
+                match value {
+                    Some(value) => tx_tree.insert(recursive_key.as_str(), value)?,
+                    None => tx_tree.insert(recursive_key.as_str(), recursive_value())?,
+                };
+
+                // the blocks shared by both versions keep naming the old root as their source
+                for cid in &removed {
+                    if let Some((PinMode::Indirect, key)) = get_pinned_mode(tx_tree, cid)? {
+                        tx_tree.remove(key.as_str())?;
+                    }
+                }
+
+                let target_value = indirect_value(&new);
+
+                for cid in &added {
+                    if get_pinned_mode(tx_tree, cid)?.is_some() {
+                        continue;
+                    }
+
+                    let indirect_key = get_pin_key(cid, &PinMode::Indirect);
+                    tx_tree.insert(indirect_key.as_str(), target_value.as_str())?;
+                }
+
+                tx_tree.flush();
+                Ok(())
+            })
+        })
+        .await?;
+
+        launder(res)
+    }
 }
 
 /// Name the empty value stored for direct pins; the pin key itself describes the mode and the cid.
@@ -481,6 +611,22 @@ fn recursive_value() -> &'static [u8] {
     Default::default()
 }
 
+/// Name the value stored for direct and recursive pins with a name or metadata.
+fn metadata_value(metadata: &PinMetadata) -> Result<Vec<u8>, Error> {
+    if metadata.is_empty() {
+        return Ok(vec![]);
+    }
+    Ok(serde_json::to_vec(metadata)?)
+}
+
+/// Inverse of [`metadata_value`], also accepting [`direct_value`] and [`recursive_value`].
+fn metadata_from_value(bytes: &[u8]) -> Result<Option<PinMetadata>, Error> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(bytes)?))
+}
+
 /// Name the value stored for indirect pins, currently only the most recent recursive pin.
 fn indirect_value(recursively_pinned: &Cid) -> String {
     recursively_pinned.to_string()
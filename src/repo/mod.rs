@@ -13,9 +13,10 @@ use libipld::cid::Cid;
 use libipld::{Ipld, IpldCodec};
 use libp2p::identity::PeerId;
 use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
 use std::borrow::Borrow;
-use std::collections::{BTreeSet, HashMap};
-use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
@@ -34,6 +35,12 @@ pub mod lock;
 /// Path mangling done for pins and blocks
 pub(crate) mod paths;
 
+mod verify;
+
+pub use verify::{
+    BadBlock, BlockProblem, BlockVerification, PinVerification, RepoVerify, RepoVerifyPins,
+};
+
 /// Describes the outcome of `BlockStore::put_block`.
 #[derive(Debug, PartialEq, Eq)]
 pub enum BlockPut {
@@ -60,6 +67,11 @@ pub enum BlockRmError {
     NotFound(Cid),
 }
 
+/// Returned by `BlockStore::get` when the block did not match its cid and was quarantined.
+#[derive(Debug, thiserror::Error)]
+#[error("block {0} does not match its cid and was quarantined")]
+pub struct BlockQuarantined(pub Cid);
+
 /// This API is being discussed and evolved, which will likely lead to breakage.
 #[async_trait]
 pub trait BlockStore: Debug + Send + Sync + 'static {
@@ -82,6 +94,28 @@ pub trait BlockStore: Debug + Send + Sync + 'static {
     async fn remove_garbage(&self, references: BoxStream<'static, Cid>) -> Result<Vec<Cid>, Error>;
     /// Returns a list of the blocks (Cids), in the blockstore.
     async fn list(&self) -> Result<Vec<Cid>, Error>;
+    /// Returns a block from the blockstore without checking its data against the cid.
+    async fn get_unchecked(&self, cid: &Cid) -> Result<Option<Block>, Error> {
+        self.get(cid).await
+    }
+    /// Moves a corrupted block out of the blockstore, keeping it aside for inspection where
+    /// supported. Returns whether the block was present.
+    async fn quarantine(&self, cid: &Cid) -> Result<bool, Error> {
+        Ok(self.remove(cid).await?.is_ok())
+    }
+    /// Returns the temporary files left behind by interrupted writes.
+    async fn temp_files(&self) -> Result<Vec<PathBuf>, Error> {
+        Ok(vec![])
+    }
+    /// Returns the blocks kept aside by [`BlockStore::quarantine`].
+    async fn quarantined(&self) -> Result<Vec<Cid>, Error> {
+        Ok(vec![])
+    }
+    /// Deletes the data of a block kept aside by [`BlockStore::quarantine`]. Returns whether it
+    /// was present.
+    async fn remove_quarantined(&self, _cid: &Cid) -> Result<bool, Error> {
+        Ok(false)
+    }
     /// Wipes the blockstore.
     async fn wipe(&self) {}
 }
@@ -216,6 +250,39 @@ pub trait PinStore: Debug + Send + Sync + Unpin + 'static {
         ids: Vec<Cid>,
         requirement: Option<PinMode>,
     ) -> Result<Vec<(Cid, PinKind<Cid>)>, Error>;
+
+    /// Returns the name and metadata of a direct or recursive pin, `None` if none were set.
+    async fn pin_metadata(&self, target: &Cid) -> Result<Option<PinMetadata>, Error>;
+
+    /// Replaces the name and metadata of a direct or recursive pin. The metadata is removed
+    /// together with the pin.
+    async fn set_pin_metadata(&self, target: &Cid, metadata: PinMetadata) -> Result<(), Error>;
+
+    /// Moves the recursive pin of `old` over to `new` along with its metadata. `added` are the
+    /// blocks referenced only by `new` and `removed` the ones referenced only by `old`; the
+    /// blocks referenced by both stay pinned.
+    async fn update_recursive_pin(
+        &self,
+        old: &Cid,
+        new: &Cid,
+        added: References<'_>,
+        removed: References<'_>,
+    ) -> Result<(), Error>;
+}
+
+/// The optional name and key/value metadata of a direct or recursive pin.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PinMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub values: BTreeMap<String, String>,
+}
+
+impl PinMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && self.values.is_empty()
+    }
 }
 
 /// `PinMode` is the description of pin type for quering purposes.
@@ -399,10 +466,19 @@ pub enum RepoEvent {
     UnwantBlock(Cid),
     /// Signals the posession of a new block.
     NewBlock(Block),
+    /// Signals a newly inserted direct or recursive pin.
+    NewPin(Cid, PinMode),
     /// Signals the removal of a block.
     RemovedBlock(Cid),
 }
 
+/// Returns the block inlined into the Cid with the identity hash. Such blocks are never stored or
+/// fetched as the Cid already contains the block.
+fn inline_block(cid: &Cid) -> Option<Block> {
+    (cid.hash().code() == rust_unixfs::IDENTITY_HASH_CODE)
+        .then(|| Block::new_unchecked(*cid, cid.hash().digest().to_vec()))
+}
+
 impl Repo {
     pub fn new(repo_type: &mut StoragePath, duration: Option<Duration>) -> Self {
         match repo_type {
@@ -650,8 +726,13 @@ impl Repo {
         }
     }
 
-    /// Puts a block into the block store.
+    /// Puts a block into the block store. Blocks inlined into their Cid with the identity hash are
+    /// not stored.
     pub async fn put_block(&self, block: Block) -> Result<(Cid, BlockPut), Error> {
+        if inline_block(block.cid()).is_some() {
+            return Ok((*block.cid(), BlockPut::Existed));
+        }
+
         let _guard = self.inner.gclock.read().await;
         let (cid, res) = self.inner.block_store.put(block.clone()).await?;
 
@@ -672,7 +753,8 @@ impl Repo {
     }
 
     /// Retrives a block from the block store, or starts fetching it from the network and awaits
-    /// until it has been fetched.
+    /// until it has been fetched. Blocks inlined into the Cid with the identity hash are returned
+    /// without either.
     #[inline]
     pub async fn get_block(
         &self,
@@ -803,11 +885,27 @@ impl Repo {
 
     /// Retrieves a block from the block store if it's available locally.
     pub async fn get_block_now(&self, cid: &Cid) -> Result<Option<Block>, Error> {
-        self.inner.block_store.get(cid).await
+        if let Some(block) = inline_block(cid) {
+            return Ok(Some(block));
+        }
+
+        match self.inner.block_store.get(cid).await {
+            Err(e) if e.is::<BlockQuarantined>() => {
+                // sending only fails if the background task has exited
+                if let Some(mut events) = self.repo_channel() {
+                    let _ = events.send(RepoEvent::RemovedBlock(*cid)).await;
+                }
+                Ok(None)
+            }
+            result => result,
+        }
     }
 
     /// Check to determine if blockstore contain a block
     pub async fn contains(&self, cid: &Cid) -> Result<bool, Error> {
+        if inline_block(cid).is_some() {
+            return Ok(true);
+        }
         self.inner.block_store.contains(cid).await
     }
 
@@ -954,32 +1052,211 @@ impl Repo {
         self.inner.data_store.remove_recursive_pin(cid, refs).await
     }
 
-    /// Function to perform a basic cleanup of unpinned blocks
+    /// Function to perform a basic cleanup of unpinned blocks. The tree of the files root, see
+    /// [`crate::Ipfs::files`], is kept even when it is not pinned, as go-ipfs keeps it.
     pub async fn cleanup(&self) -> Result<Vec<Cid>, Error> {
-        let pinned = self
+        let mut pinned = self
             .list_pins(None)
             .await
             .try_filter_map(|(cid, _)| futures::future::ready(Ok(Some(cid))))
             .try_collect::<BTreeSet<_>>()
             .await?;
 
+        pinned.extend(self.files_root_refs().await?);
+
         let refs = futures::stream::iter(pinned);
 
         let removed_blocks = self.inner.block_store.remove_garbage(refs.boxed()).await?;
         Ok(removed_blocks)
     }
 
+    /// Returns the locally available blocks of the files root, including the root.
+    async fn files_root_refs(&self) -> Result<Vec<Cid>, Error> {
+        let root = match self
+            .inner
+            .data_store
+            .get(crate::unixfs::mfs::ROOT_KEY)
+            .await?
+        {
+            Some(bytes) => Cid::try_from(bytes)?,
+            None => return Ok(vec![]),
+        };
+
+        let Some(block) = self.get_block_now(&root).await? else {
+            return Ok(vec![]);
+        };
+
+        let ipld = block.decode::<IpldCodec, Ipld>()?;
+        let mut refs = crate::refs::IpldRefs::default()
+            .with_only_unique()
+            .with_existing_blocks()
+            .refs_of_resolved(self, vec![(root, ipld)])
+            .map_ok(|crate::refs::Edge { destination, .. }| destination)
+            .try_collect::<Vec<_>>()
+            .await?;
+        refs.push(root);
+        Ok(refs)
+    }
+
     /// Checks if a `Cid` is pinned.
     pub async fn is_pinned(&self, cid: &Cid) -> Result<bool, Error> {
         self.inner.data_store.is_pinned(cid).await
     }
 
-    pub async fn list_pins(
+    /// Lists the pins, or the specific kind thereof.
+    pub fn list_pins(&self, mode: impl Into<Option<PinMode>>) -> RepoListPins {
+        RepoListPins::new(self.clone(), mode.into())
+    }
+
+    /// Replaces the pin of `old` with a recursive pin of `new`, keeping its name and metadata.
+    /// Only the parts of `new` which differ from `old` are traversed.
+    pub fn update_pin(&self, old: &Cid, new: &Cid) -> RepoUpdatePin {
+        RepoUpdatePin::new(self.clone(), *old, *new)
+    }
+
+    /// Verifies the blocks of every direct and recursive pin.
+    pub fn verify_pins(&self) -> RepoVerifyPins {
+        RepoVerifyPins::new(self.clone())
+    }
+
+    /// Verifies every block of the blockstore against its cid.
+    pub fn verify(&self) -> RepoVerify {
+        RepoVerify::new(self.clone())
+    }
+
+    /// Returns the name and metadata of a direct or recursive pin.
+    pub async fn pin_metadata(&self, cid: &Cid) -> Result<Option<PinMetadata>, Error> {
+        self.inner.data_store.pin_metadata(cid).await
+    }
+
+    /// Replaces the name and metadata of a direct or recursive pin.
+    pub async fn set_pin_metadata(&self, cid: &Cid, metadata: PinMetadata) -> Result<(), Error> {
+        self.inner.data_store.set_pin_metadata(cid, metadata).await
+    }
+
+    /// Returns the blocks referenced only by `new` and the ones referenced only by `old`.
+    ///
+    /// Both roots are walked together, one block from each side at a time, and neither walk
+    /// descends into a block once it is known to be referenced by both roots, as everything below
+    /// it is too. Only the blocks found below `new` alone are fetched. A block of `old` may still
+    /// sit below a shared block whose links were never read, so the shared blocks are only read
+    /// further, locally, for as long as some block of `old` is about to lose its pin.
+    async fn pin_diff(
         &self,
-        mode: impl Into<Option<PinMode>>,
-    ) -> futures::stream::BoxStream<'static, Result<(Cid, PinMode), Error>> {
-        let mode = mode.into();
-        self.inner.data_store.list(mode).await
+        old: Cid,
+        new: Cid,
+        local: bool,
+    ) -> Result<(BTreeSet<Cid>, BTreeSet<Cid>), Error> {
+        let mut old_links: HashMap<Cid, BTreeSet<Cid>> = HashMap::new();
+        let mut new_links: HashMap<Cid, BTreeSet<Cid>> = HashMap::new();
+        let mut shared = HashSet::new();
+        let mut old_pending = VecDeque::from([old]);
+        let mut new_pending = VecDeque::from([new]);
+
+        // marks `cid` and the blocks known to be below it as shared
+        let share = |cid: Cid,
+                     shared: &mut HashSet<Cid>,
+                     old_links: &HashMap<Cid, BTreeSet<Cid>>,
+                     new_links: &HashMap<Cid, BTreeSet<Cid>>| {
+            let mut pending = vec![cid];
+            while let Some(cid) = pending.pop() {
+                if shared.insert(cid) {
+                    if let Some(links) = old_links.get(&cid).or_else(|| new_links.get(&cid)) {
+                        pending.extend(links.iter().copied());
+                    }
+                }
+            }
+        };
+
+        while !old_pending.is_empty() || !new_pending.is_empty() {
+            if let Some(cid) = old_pending.pop_front() {
+                if shared.contains(&cid) || old_links.contains_key(&cid) {
+                    // already known
+                } else if new_links.contains_key(&cid) {
+                    share(cid, &mut shared, &old_links, &new_links);
+                } else {
+                    // blocks missing from a partial pin are treated as leaves, as when unpinning
+                    let mut links = BTreeSet::new();
+                    if let Some(block) = self.get_block_now(&cid).await? {
+                        block.references(&mut links)?;
+                        old_pending.extend(links.iter().copied());
+                    }
+                    old_links.insert(cid, links);
+                }
+            }
+
+            if let Some(cid) = new_pending.pop_front() {
+                if shared.contains(&cid) || new_links.contains_key(&cid) {
+                    // already known
+                } else if old_links.contains_key(&cid) {
+                    share(cid, &mut shared, &old_links, &new_links);
+                } else {
+                    let block = if local {
+                        match self.get_block_now(&cid).await? {
+                            Some(block) => block,
+                            None => continue,
+                        }
+                    } else {
+                        self.get_block(&cid, &[], false).await?
+                    };
+
+                    let mut links = BTreeSet::new();
+                    block.references(&mut links)?;
+                    new_pending.extend(links.iter().copied());
+                    new_links.insert(cid, links);
+                }
+            }
+        }
+
+        let mut added = new_links
+            .keys()
+            .filter(|cid| **cid != new && !shared.contains(*cid))
+            .copied()
+            .collect::<BTreeSet<_>>();
+        let mut removed = old_links
+            .keys()
+            .filter(|cid| **cid != old && !shared.contains(*cid))
+            .copied()
+            .collect::<BTreeSet<_>>();
+
+        // look for the blocks about to lose their pin below the shared blocks which were not read
+        let mut pending = shared
+            .iter()
+            .filter(|cid| !old_links.contains_key(*cid) && !new_links.contains_key(*cid))
+            .copied()
+            .collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+        while !removed.is_empty() {
+            let Some(cid) = pending.pop() else {
+                break;
+            };
+
+            if !seen.insert(cid) {
+                continue;
+            }
+
+            added.remove(&cid);
+            removed.remove(&cid);
+
+            match old_links.get(&cid).or_else(|| new_links.get(&cid)) {
+                Some(links) => pending.extend(links.iter().copied()),
+                None => {
+                    if let Some(block) = self.get_block_now(&cid).await? {
+                        let mut links = BTreeSet::new();
+                        block.references(&mut links)?;
+                        pending.extend(links);
+                    }
+                }
+            }
+        }
+
+        // the old root loses its recursive pin but may still be referenced by the new one, in
+        // which case the walk from the new root has reached it
+        if shared.contains(&old) {
+            added.insert(old);
+        }
+
+        Ok((added, removed))
     }
 
     pub async fn query_pins(
@@ -1004,6 +1281,7 @@ pub struct RepoInsertPin {
     span: Option<Span>,
     recursive: bool,
     local: bool,
+    metadata: PinMetadata,
     refs: crate::refs::IpldRefs,
 }
 
@@ -1014,6 +1292,7 @@ impl RepoInsertPin {
             cid,
             recursive: false,
             local: false,
+            metadata: Default::default(),
             refs: Default::default(),
             span: None,
         }
@@ -1025,6 +1304,18 @@ impl RepoInsertPin {
         self
     }
 
+    /// Name of the pin
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.metadata.name = Some(name.into());
+        self
+    }
+
+    /// Key/value metadata stored along with the pin
+    pub fn metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.values.insert(key.into(), value.into());
+        self
+    }
+
     /// Pin local blocks only
     pub fn local(mut self) -> Self {
         self.local = true;
@@ -1068,6 +1359,7 @@ impl std::future::IntoFuture for RepoInsertPin {
         let local = self.local;
         let span = self.span.unwrap_or(Span::current());
         let recursive = self.recursive;
+        let metadata = self.metadata;
         let repo = self.repo;
         let span = debug_span!(parent: &span, "insert_pin", cid = %cid, recursive);
         async move {
@@ -1089,6 +1381,18 @@ impl std::future::IntoFuture for RepoInsertPin {
 
                 repo.insert_recursive_pin(&cid, st).await?
             }
+
+            if !metadata.is_empty() {
+                repo.set_pin_metadata(&cid, metadata).await?;
+            }
+
+            let mode = match recursive {
+                true => PinMode::Recursive,
+                false => PinMode::Direct,
+            };
+            if let Some(mut events) = repo.repo_channel() {
+                let _ = events.send(RepoEvent::NewPin(cid, mode)).await;
+            }
             Ok(())
         }
         .instrument(span)
@@ -1171,3 +1475,155 @@ impl std::future::IntoFuture for RepoRemovePin {
         .boxed()
     }
 }
+
+pub struct RepoListPins {
+    repo: Repo,
+    mode: Option<PinMode>,
+    name: Option<String>,
+    span: Option<Span>,
+}
+
+impl RepoListPins {
+    pub fn new(repo: Repo, mode: Option<PinMode>) -> Self {
+        Self {
+            repo,
+            mode,
+            name: None,
+            span: None,
+        }
+    }
+
+    /// List only the direct and recursive pins with the given name
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set tracing span
+    pub fn span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl std::future::IntoFuture for RepoListPins {
+    type Output = BoxStream<'static, Result<(Cid, PinMode), Error>>;
+
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let span = self.span.unwrap_or(Span::current());
+        let mode = self.mode;
+        let name = self.name;
+        let repo = self.repo;
+
+        let span = debug_span!(parent: &span, "list_pins", ?mode, ?name);
+        async move {
+            let st = repo.inner.data_store.list(mode).await;
+
+            let Some(name) = name else {
+                return st;
+            };
+
+            st.try_filter_map(move |(cid, mode)| {
+                let repo = repo.clone();
+                let name = name.clone();
+                async move {
+                    // indirect pins are never named
+                    if mode == PinMode::Indirect {
+                        return Ok(None);
+                    }
+
+                    let metadata = repo.pin_metadata(&cid).await?;
+                    let matches = metadata.and_then(|metadata| metadata.name) == Some(name);
+                    Ok(matches.then_some((cid, mode)))
+                }
+            })
+            .boxed()
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+pub struct RepoUpdatePin {
+    repo: Repo,
+    old: Cid,
+    new: Cid,
+    local: bool,
+    span: Option<Span>,
+}
+
+impl RepoUpdatePin {
+    pub fn new(repo: Repo, old: Cid, new: Cid) -> Self {
+        Self {
+            repo,
+            old,
+            new,
+            local: false,
+            span: None,
+        }
+    }
+
+    /// Pin local blocks only
+    pub fn local(mut self) -> Self {
+        self.local = true;
+        self
+    }
+
+    /// Set tracing span
+    pub fn span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl std::future::IntoFuture for RepoUpdatePin {
+    type Output = Result<(), anyhow::Error>;
+
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let old = self.old;
+        let new = self.new;
+        let local = self.local;
+        let span = self.span.unwrap_or(Span::current());
+        let repo = self.repo;
+
+        let span = debug_span!(parent: &span, "update_pin", old = %old, new = %new);
+        async move {
+            if old == new {
+                return Ok(());
+            }
+
+            let _g = repo.inner.gclock.read().await;
+
+            // errors out when the old root is not pinned recursively
+            repo.query_pins(vec![old], PinMode::Recursive).await?;
+
+            if repo.query_pins(vec![new], PinMode::Recursive).await.is_ok() {
+                return Err(anyhow::anyhow!("{} is already pinned recursively", new));
+            }
+
+            let (added, removed) = repo.pin_diff(old, new, local).await?;
+            trace!(added = added.len(), removed = removed.len(), "updating pin");
+
+            let added = futures::stream::iter(added.into_iter().map(Ok)).boxed();
+            let removed = futures::stream::iter(removed.into_iter().map(Ok)).boxed();
+
+            repo.inner
+                .data_store
+                .update_recursive_pin(&old, &new, added, removed)
+                .await?;
+
+            if let Some(mut events) = repo.repo_channel() {
+                let _ = events
+                    .send(RepoEvent::NewPin(new, PinMode::Recursive))
+                    .await;
+            }
+            Ok(())
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
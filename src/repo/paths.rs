@@ -1,4 +1,5 @@
 use core::convert::TryFrom;
+use libipld::multihash::{Code, MultihashDigest};
 use libipld::{cid, multibase, Cid};
 use std::path::PathBuf;
 
@@ -57,6 +58,48 @@ pub fn filestem_to_pin_cid(file_stem: Option<&std::ffi::OsStr>) -> Option<Cid> {
     })
 }
 
+/// Longest encoded key used as a file name, well below the 255 byte limit of most filesystems.
+const MAX_KEY_FILE_NAME: usize = 128;
+
+/// Path for a key of the [`crate::repo::DataStore`] key-value half of the filesystem datastore.
+/// The key is escaped by encoding it as multibase base32, which keeps the filenames safe on every
+/// filesystem regardless of the bytes in the key. The file name must be converted back to the key
+/// using [`filestem_to_key`].
+///
+/// Keys too long to be file names are named by the base32 encoded sha2-256 of the key with the
+/// `long` extension instead, and the file has to store the key along with the value, see
+/// [`is_long_key_path`].
+pub fn key_path(mut base: PathBuf, key: &[u8]) -> PathBuf {
+    let encoded = multibase::encode(multibase::Base::Base32Lower, key);
+    let (name, extension) = if encoded.len() <= MAX_KEY_FILE_NAME {
+        (encoded, "data")
+    } else {
+        let hash = Code::Sha2_256.digest(key);
+        let name = multibase::encode(multibase::Base::Base32Lower, hash.digest());
+        (name, "long")
+    };
+    // short keys do not have enough characters to select the shard from, so pad them
+    let padded = format!("{name:_>3}");
+    let start = padded.len() - 3;
+    base.push(&padded[start..start + 2]);
+    base.push(name);
+    base.set_extension(extension);
+    base
+}
+
+/// Returns true if the path was produced by [`key_path`] for a key too long to be the file name.
+pub fn is_long_key_path(path: &std::path::Path) -> bool {
+    path.extension() == Some("long".as_ref())
+}
+
+/// Decodes the file stem produced by [`key_path`], ignoring errors.
+pub fn filestem_to_key(file_stem: Option<&std::ffi::OsStr>) -> Option<Vec<u8>> {
+    file_stem
+        .and_then(|stem| stem.to_str())
+        .and_then(|s| multibase::decode(s).ok())
+        .map(|(_, key)| key)
+}
+
 /// second-to-last/2 sharding, just by taking the two characters from suffix ignoring the last
 /// character from an ASCII encoded key string to be prepended as the directory or "shard".
 ///
@@ -164,6 +207,43 @@ mod tests {
         assert_eq!(super::filestem_to_block_cid(pin_path.file_stem()), None);
     }
 
+    #[test]
+    fn key_to_path_and_back() {
+        for (key, expected) in [
+            (
+                &b"/local/filesroot"[..],
+                "some_root/po/bf5wg6y3bnqxwm2lmmvzxe33poq.data",
+            ),
+            (&b""[..], "some_root/__/b.data"),
+            (&b"\xff"[..], "some_root/b7/b74.data"),
+        ] {
+            let path = super::key_path(PathBuf::from("some_root"), key);
+            assert_eq!(path, Path::new(expected));
+            assert_eq!(
+                super::filestem_to_key(path.file_stem()).as_deref(),
+                Some(key)
+            );
+        }
+    }
+
+    #[test]
+    fn long_key_is_hashed() {
+        let key = vec![b'k'; 300];
+        let path = super::key_path(PathBuf::from("some_root"), &key);
+        assert!(super::is_long_key_path(&path));
+
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.len() < 64, "{name}");
+        assert_eq!(path, super::key_path(PathBuf::from("some_root"), &key));
+
+        let shorter = super::key_path(PathBuf::from("some_root"), &key[..299]);
+        assert_ne!(path, shorter);
+        assert!(!super::is_long_key_path(&super::key_path(
+            PathBuf::from("some_root"),
+            &key[..10]
+        )));
+    }
+
     #[test]
     fn shard_example() {
         let mut path = PathBuf::from("some_root");
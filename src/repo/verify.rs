@@ -0,0 +1,529 @@
+//! Verification of the blocks of the blockstore and of the direct and recursive pins.
+
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{FutureExt, SinkExt, StreamExt, TryStreamExt};
+use libipld::error::InvalidMultihash;
+use libipld::multihash::{Code, MultihashDigest};
+use libipld::Cid;
+use tracing::Span;
+use tracing_futures::Instrument;
+
+use super::{PinMode, Repo, RepoEvent};
+use crate::error::Error;
+
+/// Why a block of a pin failed verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockProblem {
+    /// The block is not in the blockstore.
+    Missing,
+    /// The data of the block does not match the multihash of its cid.
+    HashMismatch,
+}
+
+/// A block of a pin which failed verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BadBlock {
+    pub cid: Cid,
+    pub problem: BlockProblem,
+    /// Whether the missing block was fetched from the network.
+    pub refetched: bool,
+}
+
+/// Outcome of verifying a single direct or recursive pin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinVerification {
+    pub cid: Cid,
+    pub mode: PinMode,
+    pub bad_blocks: Vec<BadBlock>,
+}
+
+impl PinVerification {
+    /// Returns true if every block of the pin is now present and intact.
+    pub fn is_ok(&self) -> bool {
+        self.bad_blocks.iter().all(|block| block.refetched)
+    }
+}
+
+/// Outcome of checking a single entry of the blockstore.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockVerification {
+    /// The data of the block matches its cid.
+    Valid(Cid),
+    /// The data of the block does not match its cid. The block was quarantined, now or by an
+    /// earlier read or verification, or removed.
+    Corrupted(Cid),
+    /// A temporary file left behind by an interrupted write.
+    OrphanedTempFile(PathBuf),
+}
+
+/// Returns true if the data matches the multihash of the cid. Data hashed with an unsupported
+/// function cannot be checked and is considered intact.
+pub(crate) fn is_intact(cid: &Cid, data: &[u8]) -> bool {
+    match Code::try_from(cid.hash().code()) {
+        Ok(code) => code.digest(data).digest() == cid.hash().digest(),
+        Err(_) => true,
+    }
+}
+
+pub struct RepoVerifyPins {
+    repo: Repo,
+    refetch: bool,
+    timeout: Duration,
+    span: Option<Span>,
+}
+
+impl RepoVerifyPins {
+    pub fn new(repo: Repo) -> Self {
+        Self {
+            repo,
+            refetch: false,
+            timeout: Duration::from_secs(60),
+            span: None,
+        }
+    }
+
+    /// Fetch the missing blocks from the network
+    pub fn refetch(mut self) -> Self {
+        self.refetch = true;
+        self
+    }
+
+    /// Duration to fetch a missing block from the network before giving up on it
+    pub fn timeout(mut self, duration: Duration) -> Self {
+        self.timeout = duration;
+        self
+    }
+
+    /// Set tracing span
+    pub fn span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl std::future::IntoFuture for RepoVerifyPins {
+    type Output = BoxStream<'static, Result<PinVerification, Error>>;
+
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let span = self.span.unwrap_or(Span::current());
+        let refetch = self.refetch;
+        let timeout = self.timeout;
+        let repo = self.repo;
+
+        let span = debug_span!(parent: &span, "verify_pins", refetch);
+        async move {
+            let quarantined = match repo.inner.block_store.quarantined().await {
+                Ok(cids) => Arc::new(cids.into_iter().collect::<BTreeSet<_>>()),
+                Err(e) => return futures::stream::once(async { Err(e) }).boxed(),
+            };
+
+            let recursive = repo.list_pins(PinMode::Recursive).await;
+            let direct = repo.list_pins(PinMode::Direct).await;
+
+            recursive
+                .chain(direct)
+                .and_then(move |(cid, mode)| {
+                    let repo = repo.clone();
+                    let quarantined = quarantined.clone();
+                    async move {
+                        verify_pin(&repo, cid, mode, &quarantined, refetch, timeout).await
+                    }
+                })
+                .boxed()
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+pub struct RepoVerify {
+    repo: Repo,
+    remove: bool,
+    span: Option<Span>,
+}
+
+impl RepoVerify {
+    pub fn new(repo: Repo) -> Self {
+        Self {
+            repo,
+            remove: false,
+            span: None,
+        }
+    }
+
+    /// Remove the corrupted blocks instead of quarantining them, along with the blocks
+    /// quarantined earlier
+    pub fn remove_corrupted(mut self) -> Self {
+        self.remove = true;
+        self
+    }
+
+    /// Set tracing span
+    pub fn span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl std::future::IntoFuture for RepoVerify {
+    type Output = BoxStream<'static, Result<BlockVerification, Error>>;
+
+    type IntoFuture = BoxFuture<'static, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        let span = self.span.unwrap_or(Span::current());
+        let remove = self.remove;
+        let repo = self.repo;
+
+        let span = debug_span!(parent: &span, "repo_verify", remove);
+        async move {
+            let stream = async_stream::try_stream! {
+                // listed first so that the blocks quarantined below are not reported twice
+                let quarantined = repo.inner.block_store.quarantined().await?;
+
+                for cid in repo.list_blocks().await? {
+                    if let Some(cid) = verify_block(&repo, cid, remove).await? {
+                        yield cid;
+                    }
+                }
+
+                for cid in quarantined {
+                    if remove {
+                        repo.inner.block_store.remove_quarantined(&cid).await?;
+                    }
+                    yield BlockVerification::Corrupted(cid);
+                }
+
+                for path in repo.inner.block_store.temp_files().await? {
+                    yield BlockVerification::OrphanedTempFile(path);
+                }
+            };
+
+            stream.boxed()
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+/// Checks a single block against its cid, moving it out of the blockstore if corrupted. Returns
+/// `None` if the block was removed in the meantime.
+async fn verify_block(
+    repo: &Repo,
+    cid: Cid,
+    remove: bool,
+) -> Result<Option<BlockVerification>, Error> {
+    let _g = repo.inner.gclock.read().await;
+    let block_store = &repo.inner.block_store;
+
+    let Some(block) = block_store.get_unchecked(&cid).await? else {
+        return Ok(None);
+    };
+
+    if is_intact(&cid, block.data()) {
+        return Ok(Some(BlockVerification::Valid(cid)));
+    }
+
+    warn!(cid = %cid, remove, "block does not match its cid");
+    let removed = match remove {
+        true => block_store.remove(&cid).await?.is_ok(),
+        false => block_store.quarantine(&cid).await?,
+    };
+
+    if removed {
+        // sending only fails if the background task has exited
+        if let Some(mut events) = repo.repo_channel() {
+            let _ = events.send(RepoEvent::RemovedBlock(cid)).await;
+        }
+    }
+
+    Ok(Some(BlockVerification::Corrupted(cid)))
+}
+
+/// Checks the root of a direct pin, or every block of a recursive pin. The blocks in
+/// `quarantined` were found corrupted earlier and are reported as such.
+async fn verify_pin(
+    repo: &Repo,
+    root: Cid,
+    mode: PinMode,
+    quarantined: &BTreeSet<Cid>,
+    refetch: bool,
+    timeout: Duration,
+) -> Result<PinVerification, Error> {
+    let _g = repo.inner.gclock.read().await;
+
+    let mut bad_blocks = vec![];
+    let mut seen = BTreeSet::new();
+    let mut pending = vec![root];
+
+    while let Some(cid) = pending.pop() {
+        if !seen.insert(cid) {
+            continue;
+        }
+
+        let mismatch = BadBlock {
+            cid,
+            problem: BlockProblem::HashMismatch,
+            refetched: false,
+        };
+
+        // read unchecked, as the blockstore would quarantine a corrupted block and report it
+        // missing
+        let stored = match super::inline_block(&cid) {
+            Some(block) => Ok(Some(block)),
+            None => repo.inner.block_store.get_unchecked(&cid).await,
+        };
+
+        let block = match stored {
+            Ok(Some(block)) if is_intact(block.cid(), block.data()) => block,
+            Ok(Some(_)) => {
+                bad_blocks.push(mismatch);
+                continue;
+            }
+            Err(e) if e.downcast_ref::<InvalidMultihash>().is_some() => {
+                bad_blocks.push(mismatch);
+                continue;
+            }
+            Err(e) => return Err(e),
+            Ok(None) => {
+                let block = match refetch {
+                    true => tokio::time::timeout(timeout, repo.get_block(&cid, &[], false))
+                        .await
+                        .ok()
+                        .and_then(Result::ok),
+                    false => None,
+                };
+
+                let problem = match quarantined.contains(&cid) {
+                    true => BlockProblem::HashMismatch,
+                    false => BlockProblem::Missing,
+                };
+
+                bad_blocks.push(BadBlock {
+                    cid,
+                    problem,
+                    refetched: block.is_some(),
+                });
+
+                match block {
+                    Some(block) => block,
+                    None => continue,
+                }
+            }
+        };
+
+        if mode != PinMode::Recursive {
+            continue;
+        }
+
+        let mut links = BTreeSet::new();
+        if let Err(e) = block.references(&mut links) {
+            debug!(cid = %cid, error = %e, "unable to read the links of a pinned block");
+        }
+        pending.extend(links);
+    }
+
+    trace!(cid = %root, bad = bad_blocks.len(), "verified pin");
+
+    Ok(PinVerification {
+        cid: root,
+        mode,
+        bad_blocks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BadBlock, BlockProblem, BlockVerification};
+    use crate::repo::paths::block_path;
+    use crate::repo::{Repo, RepoEvent};
+    use crate::{Block, Node, PinMode};
+    use futures::TryStreamExt;
+    use libipld::multihash::{Code, MultihashDigest};
+    use libipld::{ipld, Cid, IpldCodec};
+
+    fn raw_block(data: &[u8]) -> Block {
+        let cid = Cid::new_v1(IpldCodec::Raw.into(), Code::Sha2_256.digest(data));
+        Block::new(cid, data.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reports_missing_and_corrupted_blocks() {
+        let ipfs = Node::new("test_node").await;
+
+        let (a, b) = (raw_block(b"a"), raw_block(b"b"));
+        let a = ipfs.put_block(a).await.unwrap();
+        // stored under the cid of other data, as if corrupted on disk
+        let corrupted = Block::new_unchecked(*raw_block(b"c").cid(), b"not c".to_vec());
+        let c = ipfs.put_block(corrupted).await.unwrap();
+        let root = ipfs.put_dag(ipld!([a, b.cid(), c])).await.unwrap();
+
+        ipfs.insert_pin(&root).recursive().local().await.unwrap();
+        let d = ipfs.put_block(raw_block(b"d")).await.unwrap();
+        ipfs.insert_pin(&d).await.unwrap();
+
+        let verified = ipfs
+            .verify_pins()
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(verified.len(), 2);
+
+        let recursive = &verified[0];
+        assert_eq!((recursive.cid, recursive.mode), (root, PinMode::Recursive));
+        assert!(!recursive.is_ok());
+        let mut bad_blocks = recursive.bad_blocks.clone();
+        bad_blocks.sort_by_key(|block| block.cid);
+        let mut expected = vec![
+            BadBlock {
+                cid: *b.cid(),
+                problem: BlockProblem::Missing,
+                refetched: false,
+            },
+            BadBlock {
+                cid: c,
+                problem: BlockProblem::HashMismatch,
+                refetched: false,
+            },
+        ];
+        expected.sort_by_key(|block| block.cid);
+        assert_eq!(bad_blocks, expected);
+
+        let direct = &verified[1];
+        assert_eq!((direct.cid, direct.mode), (d, PinMode::Direct));
+        assert!(direct.is_ok());
+    }
+
+    #[tokio::test]
+    async fn repo_verify_quarantines_corrupted_blocks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::new_fs(tmp.path(), None);
+        repo.init().await.unwrap();
+
+        let (a, b) = (raw_block(b"a"), raw_block(b"b"));
+        repo.put_block(a.clone()).await.unwrap();
+        repo.put_block(b.clone()).await.unwrap();
+
+        let blockstore = tmp.path().join("blockstore");
+        std::fs::write(block_path(blockstore.clone(), b.cid()), b"not b").unwrap();
+        let temp = block_path(blockstore, a.cid()).with_extension("tmp");
+        std::fs::write(&temp, b"a").unwrap();
+
+        let verified = repo.verify().await.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(verified.len(), 3);
+        assert!(verified.contains(&BlockVerification::Valid(*a.cid())));
+        assert!(verified.contains(&BlockVerification::Corrupted(*b.cid())));
+        assert_eq!(verified[2], BlockVerification::OrphanedTempFile(temp));
+
+        assert!(!repo.contains(b.cid()).await.unwrap());
+        assert_eq!(repo.list_blocks().await.unwrap(), vec![*a.cid()]);
+
+        // the block can be stored again once quarantined
+        repo.put_block(b.clone()).await.unwrap();
+        assert_eq!(repo.get_block_now(b.cid()).await.unwrap(), Some(b));
+    }
+
+    #[tokio::test]
+    async fn quarantined_blocks_are_reported_and_removed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let repo = Repo::new_fs(tmp.path(), None);
+        repo.init().await.unwrap();
+
+        let b = raw_block(b"b");
+        repo.put_block(b.clone()).await.unwrap();
+        let root = repo.put_block(raw_block(b"root")).await.unwrap().0;
+        let blockstore = tmp.path().join("blockstore");
+        std::fs::write(block_path(blockstore.clone(), b.cid()), b"not b").unwrap();
+
+        // quarantined when read
+        let mut events = repo.initialize_channel();
+        assert_eq!(repo.get_block_now(b.cid()).await.unwrap(), None);
+        assert!(matches!(
+            events.try_next().unwrap(),
+            Some(RepoEvent::RemovedBlock(cid)) if cid == *b.cid()
+        ));
+        drop(events);
+
+        repo.insert_direct_pin(b.cid()).await.unwrap();
+        let verified = repo
+            .verify_pins()
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(
+            verified[0].bad_blocks,
+            vec![BadBlock {
+                cid: *b.cid(),
+                problem: BlockProblem::HashMismatch,
+                refetched: false,
+            }]
+        );
+
+        let verified = repo.verify().await.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(
+            verified,
+            vec![
+                BlockVerification::Valid(root),
+                BlockVerification::Corrupted(*b.cid())
+            ]
+        );
+
+        let file = block_path(blockstore, b.cid()).with_extension("corrupted");
+        assert!(file.exists());
+        let verified = repo
+            .verify()
+            .remove_corrupted()
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert!(verified.contains(&BlockVerification::Corrupted(*b.cid())));
+        assert!(!file.exists());
+
+        let verified = repo.verify().await.try_collect::<Vec<_>>().await.unwrap();
+        assert_eq!(verified, vec![BlockVerification::Valid(root)]);
+    }
+
+    #[tokio::test]
+    async fn refetches_missing_blocks() {
+        let a = Node::new("a").await;
+        let b = Node::new("b").await;
+
+        let leaf = raw_block(b"leaf");
+        b.put_block(leaf.clone()).await.unwrap();
+        let root = a.put_dag(ipld!([leaf.cid()])).await.unwrap();
+        a.insert_pin(&root).recursive().local().await.unwrap();
+
+        a.connect(b.addrs[0].clone()).await.unwrap();
+
+        let verified = a
+            .verify_pins()
+            .refetch()
+            .await
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+
+        assert_eq!(verified.len(), 1);
+        assert_eq!(
+            verified[0].bad_blocks,
+            vec![BadBlock {
+                cid: *leaf.cid(),
+                problem: BlockProblem::Missing,
+                refetched: true,
+            }]
+        );
+        assert!(verified[0].is_ok());
+        assert!(a.repo().contains(leaf.cid()).await.unwrap());
+    }
+}
@@ -5,11 +5,10 @@ use futures::{
         mpsc::{unbounded, Receiver, UnboundedSender},
         oneshot,
     },
-    stream::Fuse,
+    stream::{BoxStream, Fuse},
     FutureExt, StreamExt,
 };
 
-#[cfg(feature = "beetle_bitswap")]
 use futures::SinkExt;
 
 use crate::TSwarmEvent;
@@ -24,8 +23,7 @@ use beetle_bitswap_next::BitswapEvent;
 #[cfg(feature = "libp2p_bitswap")]
 use libp2p_bitswap_next::BitswapEvent;
 
-#[cfg(feature = "libp2p_bitswap")]
-use libipld::Cid;
+use libipld::{Cid, Ipld, IpldCodec};
 
 #[cfg(feature = "beetle_bitswap")]
 use tokio::task::JoinHandle;
@@ -33,8 +31,8 @@ use tokio::task::JoinHandle;
 use wasm_timer::Interval;
 
 use std::{
-    collections::{hash_map::Entry, HashMap, HashSet},
-    time::Duration,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+    time::{Duration, Instant, SystemTime},
 };
 
 use std::pin::Pin;
@@ -43,8 +41,11 @@ use std::task::{Context, Poll};
 use crate::{config::BOOTSTRAP_NODES, IpfsEvent, TSwarmEventFn};
 
 use crate::{
+    ipns::pubsub::IpnsPubsub,
+    p2p::fetch::{FetchRequest, FetchResponse, FetchStatus},
     p2p::TSwarm,
-    repo::{Repo, RepoEvent},
+    repo::{PinMode, Repo, RepoEvent},
+    RepoProvider,
 };
 
 pub use crate::{p2p::BehaviourEvent, p2p::KadResult};
@@ -65,6 +66,7 @@ use libp2p::{
     },
     mdns::Event as MdnsEvent,
     rendezvous::{Cookie, Namespace},
+    request_response::{self, OutboundRequestId},
     swarm::{ConnectionId, SwarmEvent},
 };
 
@@ -103,6 +105,9 @@ pub(crate) struct IpfsTask<C: NetworkBehaviour<ToSwarm = void::Void>> {
     pub(crate) pending_disconnection: HashMap<PeerId, Vec<Channel<()>>>,
     pub(crate) pending_add_listener: HashMap<ListenerId, Channel<Multiaddr>>,
     pub(crate) pending_remove_listener: HashMap<ListenerId, Channel<()>>,
+    pub(crate) reprovider: Reprovider,
+    pub(crate) ipns_pubsub: IpnsPubsub,
+    pub(crate) pending_fetch: HashMap<OutboundRequestId, Channel<Option<Vec<u8>>>>,
 }
 
 impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
@@ -137,14 +142,27 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             pending_connection: Default::default(),
             pending_add_listener: Default::default(),
             pending_remove_listener: Default::default(),
+            reprovider: Default::default(),
+            ipns_pubsub: Default::default(),
+            pending_fetch: Default::default(),
         }
     }
+
+    /// Sets the strategy and configuration used to announce blocks to the DHT.
+    pub fn set_reprovider(&mut self, strategy: RepoProvider, config: ReproviderConfig) {
+        self.timer.reprovide = config
+            .interval
+            .filter(|_| strategy != RepoProvider::None)
+            .map(Interval::new);
+        self.reprovider = Reprovider::new(strategy, config);
+    }
 }
 
 pub(crate) struct TaskTimer {
     #[cfg(feature = "beetle_bitswap")]
     pub(crate) session_cleanup: Interval,
     pub(crate) event_cleanup: Interval,
+    pub(crate) reprovide: Option<Interval>,
 }
 
 impl Default for TaskTimer {
@@ -157,10 +175,205 @@ impl Default for TaskTimer {
             #[cfg(feature = "beetle_bitswap")]
             session_cleanup,
             event_cleanup,
+            reprovide: None,
+        }
+    }
+}
+
+/// Configuration of the reprovider, which announces the blocks selected by the [`RepoProvider`]
+/// strategy to the DHT so they remain discoverable after their provider records expire.
+#[derive(Clone, Copy, Debug)]
+pub struct ReproviderConfig {
+    /// Interval between two full runs. `None` disables the periodic runs, leaving only the run at
+    /// startup and those triggered with [`Ipfs::reprovide`](crate::Ipfs::reprovide).
+    pub interval: Option<Duration>,
+    /// Amount of keys loaded from the repo at a time.
+    pub batch_size: usize,
+    /// Maximum amount of provide queries in flight.
+    pub concurrency: usize,
+}
+
+impl Default for ReproviderConfig {
+    fn default() -> Self {
+        Self {
+            interval: Some(Duration::from_secs(12 * 60 * 60)),
+            batch_size: 1024,
+            concurrency: 32,
         }
     }
 }
 
+/// Progress of the reprovider and statistics of its previous runs.
+#[derive(Clone, Debug, Default)]
+pub struct ReproviderStats {
+    /// Whether a full run is in progress.
+    pub running: bool,
+    /// Keys waiting to be announced.
+    pub queued: usize,
+    /// Provide queries currently in flight.
+    pub in_flight: usize,
+    /// Keys announced during the current run, or the last one if none is in progress.
+    pub run_provided: usize,
+    /// Keys that could not be announced during the current run, or the last one.
+    pub run_failed: usize,
+    /// When the current or last run started.
+    pub last_run_started: Option<SystemTime>,
+    /// How long the last completed run took.
+    pub last_run_duration: Option<Duration>,
+    /// Keys announced since the node started.
+    pub total_provided: usize,
+    /// Keys that could not be announced since the node started.
+    pub total_failed: usize,
+}
+
+pub(crate) enum ReproviderMsg {
+    /// A batch of keys to announce as part of the current run.
+    Keys(Vec<Cid>),
+    /// A batch of keys to announce outside of a run, such as the blocks of a new pin.
+    NewKeys(Vec<Cid>),
+    /// All keys of the current run have been loaded.
+    Collected,
+}
+
+pub(crate) struct Reprovider {
+    strategy: RepoProvider,
+    config: ReproviderConfig,
+    /// Keys waiting to be announced, and whether they belong to the current run.
+    queue: VecDeque<(Cid, bool)>,
+    in_flight: HashMap<QueryId, bool>,
+    tx: futures::channel::mpsc::Sender<ReproviderMsg>,
+    rx: Receiver<ReproviderMsg>,
+    collecting: bool,
+    /// Keys loaded for the current run so far.
+    run_keys: usize,
+    /// Set once the kademlia store refuses to provide more keys, until the next run.
+    exhausted: bool,
+    run_started: Option<Instant>,
+    waiters: Vec<Channel<ReproviderStats>>,
+    stats: ReproviderStats,
+}
+
+impl Default for Reprovider {
+    fn default() -> Self {
+        Self::new(RepoProvider::None, ReproviderConfig::default())
+    }
+}
+
+impl Reprovider {
+    fn new(strategy: RepoProvider, config: ReproviderConfig) -> Self {
+        // a single slot so that collecting keys is paced by how fast they are announced
+        let (tx, rx) = futures::channel::mpsc::channel(1);
+        Self {
+            strategy,
+            config,
+            queue: VecDeque::new(),
+            in_flight: HashMap::new(),
+            tx,
+            rx,
+            collecting: false,
+            run_keys: 0,
+            exhausted: false,
+            run_started: None,
+            waiters: vec![],
+            stats: ReproviderStats::default(),
+        }
+    }
+
+    fn stats(&self) -> ReproviderStats {
+        ReproviderStats {
+            queued: self.queue.len(),
+            in_flight: self.in_flight.len(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Marks the start of a full run, returning false if one is already in progress.
+    fn begin_run(&mut self) -> bool {
+        if self.stats.running {
+            return false;
+        }
+        self.stats.running = true;
+        self.stats.run_provided = 0;
+        self.stats.run_failed = 0;
+        self.stats.last_run_started = Some(SystemTime::now());
+        self.run_started = Some(Instant::now());
+        self.collecting = true;
+        self.run_keys = 0;
+        self.exhausted = false;
+        true
+    }
+
+    /// Queues `keys`, unless no more keys can be provided.
+    fn enqueue(&mut self, keys: Vec<Cid>, in_run: bool) {
+        if !self.exhausted {
+            self.queue.extend(keys.into_iter().map(|cid| (cid, in_run)));
+        }
+    }
+
+    fn record(&mut self, provided: bool, in_run: bool) {
+        match provided {
+            true => {
+                self.stats.run_provided += usize::from(in_run);
+                self.stats.total_provided += 1;
+            }
+            false => {
+                self.stats.run_failed += usize::from(in_run);
+                self.stats.total_failed += 1;
+            }
+        }
+    }
+
+    /// Completes the current run once every key has been loaded and announced.
+    fn try_complete(&mut self) {
+        if !self.stats.running
+            || self.collecting
+            || !self.queue.is_empty()
+            || !self.in_flight.is_empty()
+        {
+            return;
+        }
+
+        self.stats.running = false;
+        self.stats.last_run_duration = self.run_started.take().map(|start| start.elapsed());
+
+        debug!(
+            provided = self.stats.run_provided,
+            failed = self.stats.run_failed,
+            "reprovider: run completed"
+        );
+
+        let stats = self.stats();
+        for ret in self.waiters.drain(..) {
+            let _ = ret.send(Ok(stats.clone()));
+        }
+    }
+}
+
+/// Returns the keys selected by the given strategy.
+pub(crate) async fn provider_keys(repo: &Repo, strategy: RepoProvider) -> BoxStream<'static, Cid> {
+    match strategy {
+        RepoProvider::None => futures::stream::empty().boxed(),
+        RepoProvider::All => {
+            futures::stream::iter(repo.list_blocks().await.unwrap_or_default()).boxed()
+        }
+        RepoProvider::Pinned => repo
+            .list_pins(None)
+            .await
+            .filter_map(|result| futures::future::ready(result.map(|(cid, _)| cid).ok()))
+            .boxed(),
+        RepoProvider::Roots => repo
+            .list_pins(None)
+            .await
+            .filter_map(|result| {
+                futures::future::ready(match result {
+                    Ok((cid, PinMode::Direct | PinMode::Recursive)) => Some(cid),
+                    _ => None,
+                })
+            })
+            .boxed(),
+    }
+}
+
 impl<C: NetworkBehaviour<ToSwarm = void::Void>> futures::Future for IpfsTask<C> {
     type Output = ();
 
@@ -187,6 +400,21 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> futures::Future for IpfsTask<C>
             }
         }
 
+        if self.reprovider.queue.is_empty() {
+            while let Poll::Ready(Some(msg)) = self.reprovider.rx.poll_next_unpin(cx) {
+                self.handle_reprovider_msg(msg);
+                if !self.reprovider.queue.is_empty() {
+                    break;
+                }
+            }
+        }
+
+        if let Some(timer) = self.timer.reprovide.as_mut() {
+            if timer.poll_next_unpin(cx).is_ready() {
+                self.reprovide(None);
+            }
+        }
+
         if self.timer.event_cleanup.poll_next_unpin(cx).is_ready() {
             self.pubsub_event_stream.retain(|ch| !ch.is_closed());
         }
@@ -225,21 +453,40 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
         let mut session_cleanup = tokio::time::interval(Duration::from_secs(5 * 60));
         let mut event_cleanup = tokio::time::interval(Duration::from_secs(60));
 
-        loop {
+        let reprovide_enabled = self.reprovider.config.interval.is_some()
+            && self.reprovider.strategy != RepoProvider::None;
+        let reprovide_period = self
+            .reprovider
+            .config
+            .interval
+            .unwrap_or(Duration::from_secs(60 * 60));
+        let mut reprovide_timer = tokio::time::interval_at(
+            tokio::time::Instant::now() + reprovide_period,
+            reprovide_period,
+        );
+        reprovide_timer.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        let exited = loop {
             tokio::select! {
                 biased;
                 Some(swarm) = self.swarm.next() => {
                     self.handle_swarm_event(swarm);
                 },
                 Some(event) = self.from_facade.next() => {
-                    if matches!(event, IpfsEvent::Exit) {
-                        break;
+                    if let IpfsEvent::Exit(exited) = event {
+                        break exited;
                     }
                     self.handle_event(event);
                 },
                 Some(repo) = self.repo_events.next() => {
                     self.handle_repo_event(repo);
                 },
+                Some(msg) = self.reprovider.rx.next(), if self.reprovider.queue.is_empty() => {
+                    self.handle_reprovider_msg(msg);
+                },
+                _ = reprovide_timer.tick(), if reprovide_enabled => {
+                    self.reprovide(None);
+                }
                 _ = event_cleanup.tick() => {
                     self.pubsub_event_stream.retain(|ch| !ch.is_closed());
                 }
@@ -268,7 +515,14 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     }
                 }
             }
+        };
+
+        // the records still queued would otherwise be lost
+        if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
+            kad.store_mut().shutdown().await;
         }
+
+        let _ = exited.send(());
     }
 
     #[cfg(feature = "beetle_bitswap")]
@@ -300,6 +554,152 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
         }
     }
 
+    /// Starts a full reprovide run unless one is already in progress, in which case `ret` is
+    /// notified when that run completes.
+    pub(crate) fn reprovide(&mut self, ret: Option<Channel<ReproviderStats>>) {
+        if self.swarm.behaviour().kademlia.as_ref().is_none() {
+            if let Some(ret) = ret {
+                let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
+            }
+            return;
+        }
+
+        self.reprovider.waiters.extend(ret);
+
+        if !self.reprovider.begin_run() {
+            return;
+        }
+
+        debug!(strategy = ?self.reprovider.strategy, "reprovider: starting run");
+
+        let repo = self.repo.clone();
+        let strategy = self.reprovider.strategy;
+        let batch_size = self.reprovider.config.batch_size.max(1);
+        let mut tx = self.reprovider.tx.clone();
+
+        tokio::spawn(async move {
+            let mut keys = provider_keys(&repo, strategy).await.chunks(batch_size);
+            while let Some(keys) = keys.next().await {
+                if tx.send(ReproviderMsg::Keys(keys)).await.is_err() {
+                    return;
+                }
+            }
+            let _ = tx.send(ReproviderMsg::Collected).await;
+        });
+    }
+
+    fn handle_reprovider_msg(&mut self, msg: ReproviderMsg) {
+        match msg {
+            ReproviderMsg::Keys(keys) => {
+                // the keys of the run are allowed on top of the configured limit of the store
+                self.reprovider.run_keys += keys.len();
+                if let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() {
+                    kad.store_mut().reserve_provided(self.reprovider.run_keys);
+                }
+                self.reprovider.enqueue(keys, true);
+            }
+            ReproviderMsg::NewKeys(keys) => self.reprovider.enqueue(keys, false),
+            ReproviderMsg::Collected => self.reprovider.collecting = false,
+        }
+        self.reprovide_pump();
+    }
+
+    /// Starts provide queries for queued keys, up to the configured concurrency.
+    fn reprovide_pump(&mut self) {
+        let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
+            self.reprovider.queue.clear();
+            self.reprovider.try_complete();
+            return;
+        };
+
+        let concurrency = self.reprovider.config.concurrency.max(1);
+
+        while self.reprovider.in_flight.len() < concurrency {
+            let Some((cid, in_run)) = self.reprovider.queue.pop_front() else {
+                break;
+            };
+
+            match kad.start_providing(Key::from(cid.hash().to_bytes())) {
+                Ok(id) => {
+                    self.reprovider.in_flight.insert(id, in_run);
+                }
+                Err(libp2p::kad::store::Error::MaxProvidedKeys) => {
+                    warn!("reprovider: maximum amount of provided keys reached, stopping");
+                    self.reprovider.record(false, in_run);
+                    self.reprovider.queue.clear();
+                    self.reprovider.exhausted = true;
+                    break;
+                }
+                Err(e) => {
+                    warn!("reprovider: unable to provide {cid}: {e}");
+                    self.reprovider.record(false, in_run);
+                }
+            }
+        }
+
+        self.reprovider.try_complete();
+    }
+
+    /// Queues keys announced outside of a full run, such as newly added blocks or pins.
+    fn reprovide_new_keys(&mut self, keys: Vec<Cid>) {
+        if self.swarm.behaviour().kademlia.as_ref().is_none() {
+            return;
+        }
+        self.reprovider.enqueue(keys, false);
+        self.reprovide_pump();
+    }
+
+    fn reprovide_new_block(&mut self, cid: Cid) {
+        if self.reprovider.strategy == RepoProvider::All {
+            self.reprovide_new_keys(vec![cid]);
+        }
+    }
+
+    fn reprovide_new_pin(&mut self, cid: Cid, mode: PinMode) {
+        match self.reprovider.strategy {
+            RepoProvider::Roots => self.reprovide_new_keys(vec![cid]),
+            RepoProvider::Pinned => {
+                self.reprovide_new_keys(vec![cid]);
+
+                if mode != PinMode::Recursive || self.swarm.behaviour().kademlia.as_ref().is_none()
+                {
+                    return;
+                }
+
+                // the blocks of a recursive pin are announced as they are walked
+                let repo = self.repo.clone();
+                let batch_size = self.reprovider.config.batch_size.max(1);
+                let mut tx = self.reprovider.tx.clone();
+
+                tokio::spawn(async move {
+                    let Ok(Some(block)) = repo.get_block_now(&cid).await else {
+                        return;
+                    };
+                    let Ok(ipld) = block.decode::<IpldCodec, Ipld>() else {
+                        return;
+                    };
+
+                    let mut keys = crate::refs::IpldRefs::default()
+                        .with_only_unique()
+                        .with_existing_blocks()
+                        .refs_of_resolved(repo.clone(), vec![(cid, ipld)])
+                        .filter_map(|edge| {
+                            futures::future::ready(edge.ok().map(|edge| edge.destination))
+                        })
+                        .chunks(batch_size)
+                        .boxed();
+
+                    while let Some(keys) = keys.next().await {
+                        if tx.send(ReproviderMsg::NewKeys(keys)).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+            RepoProvider::All | RepoProvider::None => {}
+        }
+    }
+
     fn emit_pubsub_event(&self, event: InnerPubsubEvent) {
         for ch in &self.pubsub_event_stream {
             let ch = ch.clone();
@@ -422,6 +822,13 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                             .and_then(|kad| kad.query(&id))
                             .is_none()
                         {
+                            if let StartProviding(provided) = &result {
+                                if let Some(in_run) = self.reprovider.in_flight.remove(&id) {
+                                    self.reprovider.record(provided.is_ok(), in_run);
+                                    self.reprovide_pump();
+                                }
+                            }
+
                             match result {
                                 // these subscriptions return actual values
                                 GetClosestPeers(_) | GetProviders(_) | GetRecord(_) => {}
@@ -818,6 +1225,57 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 topic: topic.to_string(),
                 peer_id,
             }),
+            SwarmEvent::Behaviour(BehaviourEvent::Fetch(event)) => match event {
+                request_response::Event::Message {
+                    peer,
+                    message:
+                        request_response::Message::Request {
+                            request, channel, ..
+                        },
+                } => {
+                    let response = match self.ipns_pubsub.get_by_key(&request.identifier) {
+                        Some(data) => FetchResponse {
+                            status: FetchStatus::Ok,
+                            data,
+                        },
+                        None => FetchResponse {
+                            status: FetchStatus::NotFound,
+                            data: vec![],
+                        },
+                    };
+                    if let Some(fetch) = self.swarm.behaviour_mut().fetch.as_mut() {
+                        if fetch.send_response(channel, response).is_err() {
+                            debug!("fetch: unable to respond to {peer}");
+                        }
+                    }
+                }
+                request_response::Event::Message {
+                    message:
+                        request_response::Message::Response {
+                            request_id,
+                            response,
+                        },
+                    ..
+                } => {
+                    if let Some(ret) = self.pending_fetch.remove(&request_id) {
+                        let result = match response.status {
+                            FetchStatus::Ok => Ok(Some(response.data)),
+                            FetchStatus::NotFound => Ok(None),
+                            FetchStatus::Error => Err(anyhow!("peer failed to fetch the value")),
+                        };
+                        let _ = ret.send(result);
+                    }
+                }
+                request_response::Event::OutboundFailure {
+                    request_id, error, ..
+                } => {
+                    if let Some(ret) = self.pending_fetch.remove(&request_id) {
+                        let _ = ret.send(Err(anyhow::Error::from(error)));
+                    }
+                }
+                request_response::Event::InboundFailure { .. }
+                | request_response::Event::ResponseSent { .. } => {}
+            },
             SwarmEvent::Behaviour(BehaviourEvent::Ping(event)) => match event {
                 libp2p::ping::Event {
                     peer,
@@ -1096,13 +1554,18 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 self.swarm.behaviour_mut().block_list.unblock_peer(peer);
                 let _ = ret.send(Ok(()));
             }
-            IpfsEvent::PubsubSubscribe(topic, ret) => {
+            IpfsEvent::PubsubSubscribe(topic, validator, ret) => {
                 let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
                     let _ = ret.send(Err(anyhow!("pubsub protocol is disabled")));
                     return;
                 };
 
-                let _ = ret.send(Ok(pubsub.subscribe(topic).ok()));
+                let stream = match validator {
+                    Some(validator) => pubsub.subscribe_with_validator(topic, validator),
+                    None => pubsub.subscribe(topic),
+                };
+
+                let _ = ret.send(Ok(stream.ok()));
             }
             IpfsEvent::PubsubUnsubscribe(topic, ret) => {
                 let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
@@ -1144,6 +1607,22 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
 
                 let _ = ret.send(Ok(pubsub.subscribed_topics()));
             }
+            IpfsEvent::PubsubPeerScores(ret) => {
+                let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
+                    let _ = ret.send(Err(anyhow!("pubsub protocol is disabled")));
+                    return;
+                };
+
+                let _ = ret.send(Ok(pubsub.peer_scores()));
+            }
+            IpfsEvent::PubsubSetApplicationScore(peer_id, score, ret) => {
+                let Some(pubsub) = self.swarm.behaviour_mut().pubsub.as_mut() else {
+                    let _ = ret.send(Err(anyhow!("pubsub protocol is disabled")));
+                    return;
+                };
+
+                let _ = ret.send(Ok(pubsub.set_application_score(&peer_id, score)));
+            }
             // IpfsEvent::WantList(peer, ret) => {
             //     let list = if let Some(peer) = peer {
             //         self.swarm
@@ -1408,6 +1887,19 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 };
                 let _ = ret.send(future);
             }
+            IpfsEvent::Fetch(peer_id, key, ret) => {
+                let Some(fetch) = self.swarm.behaviour_mut().fetch.as_mut() else {
+                    let _ = ret.send(Err(anyhow!("fetch protocol is disabled")));
+                    return;
+                };
+
+                let id = fetch.send_request(&peer_id, FetchRequest { identifier: key });
+                self.pending_fetch.insert(id, ret);
+            }
+            IpfsEvent::Reprovide(ret) => self.reprovide(Some(ret)),
+            IpfsEvent::ReproviderStats(ret) => {
+                let _ = ret.send(self.reprovider.stats());
+            }
             IpfsEvent::DhtMode(mode, ret) => {
                 let Some(kad) = self.swarm.behaviour_mut().kademlia.as_mut() else {
                     let _ = ret.send(Err(anyhow!("kad protocol is disabled")));
@@ -1684,7 +2176,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                     }
                 }
             }
-            IpfsEvent::Exit => {
+            IpfsEvent::Exit(_) => {
                 // FIXME: we could do a proper teardown
             }
         }
@@ -1764,6 +2256,7 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
             }
             RepoEvent::UnwantBlock(_cid) => {}
             RepoEvent::NewBlock(block) => {
+                let cid = *block.cid();
                 if let Some(bitswap) = self.swarm.behaviour().bitswap.as_ref() {
                     let client = bitswap.client().clone();
                     let server = bitswap.server().cloned();
@@ -1782,8 +2275,9 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                         }
                     });
                 }
-                // let _ = ret.send(Err(anyhow!("not actively providing blocks yet")));
+                self.reprovide_new_block(cid);
             }
+            RepoEvent::NewPin(cid, mode) => self.reprovide_new_pin(cid, mode),
             RepoEvent::RemovedBlock(cid) => self.swarm.behaviour_mut().stop_providing_block(&cid),
         }
     }
@@ -1802,7 +2296,8 @@ impl<C: NetworkBehaviour<ToSwarm = void::Void>> IpfsTask<C> {
                 }
             }
             RepoEvent::UnwantBlock(_) => {}
-            RepoEvent::NewBlock(_) => {}
+            RepoEvent::NewBlock(block) => self.reprovide_new_block(*block.cid()),
+            RepoEvent::NewPin(cid, mode) => self.reprovide_new_pin(cid, mode),
             RepoEvent::RemovedBlock(_) => {}
         }
     }
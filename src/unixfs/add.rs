@@ -1,10 +1,17 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
 
 use crate::{repo::Repo, Block};
+use anyhow::anyhow;
 use bytes::Bytes;
 use either::Either;
 use futures::{future::BoxFuture, stream::BoxStream, FutureExt, Stream, StreamExt, TryFutureExt};
-use rust_unixfs::file::adder::{Chunker, FileAdderBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use libipld::{cid::Version, multihash::Code, Cid};
+use rust_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+use rust_unixfs::file::adder::{Chunker, Collector, FileAdder, FileAdderBuilder, TrickleCollector};
+use rust_unixfs::Metadata;
 use tokio_util::io::ReaderStream;
 use tracing::{Instrument, Span};
 
@@ -14,14 +21,50 @@ use super::UnixfsStatus;
 
 #[derive(Clone, Debug, Copy)]
 pub struct AddOption {
+    /// Chunker used to split the file into leaf blocks. Can be parsed from the go-ipfs chunker
+    /// strings, e.g. `"size-262144"`, `"rabin-262144"` or `"buzhash"`.
     pub chunk: Chunker,
+    /// Use the trickle layout for the file dags instead of the balanced one
+    /// (`ipfs add --trickle`).
+    pub trickle: bool,
+    /// Store the leaves as raw blocks instead of wrapping them in dag-pb unixfs nodes
+    /// (`ipfs add --raw-leaves`).
+    pub raw_leaves: bool,
+    /// Version of the Cids created. Using [`Version::V0`] with a hash other than sha2-256 is not
+    /// possible, so such combination is upgraded to [`Version::V1`].
+    pub cid_version: Version,
+    /// Hash function used for the blocks (`ipfs add --hash`).
+    pub hash: Code,
+    /// Inline blocks of at most [`AddOption::inline_limit`] bytes into their Cids with the
+    /// identity hash instead of storing them (`ipfs add --inline`).
+    pub inline: bool,
+    /// Size limit for inlined blocks, at most [`rust_unixfs::MAX_INLINE_LIMIT`]. Defaults to 32.
+    pub inline_limit: usize,
+    /// Include the files and directories starting with a dot when adding a directory
+    /// (`ipfs add --hidden`).
+    pub hidden: bool,
+    /// Add the files and directories symlinks point to instead of the symlinks themselves, which
+    /// are added as unixfs symlinks by default.
+    pub follow_symlinks: bool,
+    /// Store the permissions of the files and directories (`ipfs add --preserve-mode`).
+    pub preserve_mode: bool,
+    /// Store the modification times of the files and directories (`ipfs add --preserve-mtime`).
+    pub preserve_mtime: bool,
+    /// Directories with more entries than the threshold are added as HAMT sharded directories.
+    /// Defaults to `None`, in which case directories are sharded like go-ipfs shards them, once
+    /// their links would take up more than 256 KiB.
+    pub shard_threshold: Option<usize>,
     pub pin: bool,
     pub provide: bool,
     pub wrap: bool,
 }
 
 pub enum AddOpt<'a> {
+    /// Path to a file, or a directory which is added recursively.
     File(PathBuf),
+    /// Path to a file, or a directory which is added recursively leaving out the entries matching
+    /// the `ignore` rules.
+    FileWithIgnore { path: PathBuf, ignore: IgnoreRules },
     Stream {
         name: Option<String>,
         total: Option<usize>,
@@ -39,6 +82,17 @@ impl Default for AddOption {
     fn default() -> Self {
         Self {
             chunk: Chunker::Size(256 * 1024),
+            trickle: false,
+            raw_leaves: false,
+            cid_version: Version::V0,
+            hash: Code::Sha2_256,
+            inline: false,
+            inline_limit: 32,
+            hidden: false,
+            follow_symlinks: false,
+            preserve_mode: false,
+            preserve_mtime: false,
+            shard_threshold: None,
             pin: false,
             provide: false,
             wrap: false,
@@ -46,6 +100,15 @@ impl Default for AddOption {
     }
 }
 
+/// Gitignore style rules for leaving out files and directories when adding a directory.
+#[derive(Clone, Debug, Default)]
+pub struct IgnoreRules {
+    /// Rules, one per line (`ipfs add --ignore`).
+    pub rules: Vec<String>,
+    /// File of rules (`ipfs add --ignore-rules-path`).
+    pub rules_path: Option<PathBuf>,
+}
+
 pub struct UnixfsAdd<'a> {
     span: Option<Span>,
     stream: BoxStream<'a, UnixfsStatus>,
@@ -83,16 +146,140 @@ pub fn add<'a>(which: Either<&Ipfs, &Repo>, options: AddOpt<'a>, opt: AddOption)
 
         let mut written = 0;
 
-        let (name, total_size, mut stream) = match options {
+        let (options, ignore) = match options {
+            AddOpt::FileWithIgnore { path, ignore } => (AddOpt::File(path), ignore),
+            options => (options, IgnoreRules::default()),
+        };
+
+        let directory = match &options {
+            AddOpt::File(path) => match tokio::fs::metadata(path).await {
+                Ok(metadata) if metadata.is_dir() => Some(path.clone()),
+                _ => None,
+            },
+            AddOpt::Stream { .. } | AddOpt::FileWithIgnore { .. } => None,
+        };
+
+        if let Some(root) = directory {
+            let (name, entries) = match walk_directory(&root, &opt, &ignore).await {
+                Ok(walked) => walked,
+                Err(e) => {
+                    yield UnixfsStatus::FailedStatus { written, total_size: None, error: Some(e) };
+                    return;
+                }
+            };
+
+            let total_size = Some(
+                entries
+                    .iter()
+                    .filter(|entry| matches!(entry.kind, EntryKind::File))
+                    .map(|entry| entry.metadata.len() as usize)
+                    .sum::<usize>(),
+            );
+
+            yield UnixfsStatus::ProgressStatus { written, total_size };
+
+            let mut opts = match tree_options(&opt) {
+                Ok(opts) => opts,
+                Err(e) => {
+                    yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                    return;
+                }
+            };
+            if opt.wrap {
+                opts.wrap_with_directory();
+            }
+            opts.shard_threshold(opt.shard_threshold);
+
+            let mut tree = BufferingTreeBuilder::new(opts);
+
+            for entry in entries {
+                let metadata = unixfs_metadata(&entry.metadata, &opt);
+                let result = match entry.kind {
+                    EntryKind::Directory => tree
+                        .set_metadata(&entry.tree_path, metadata)
+                        .map_err(anyhow::Error::from),
+                    EntryKind::File => {
+                        match import_file(&repo, &entry.path, &opt, metadata, &mut written).await {
+                            Ok((cid, size)) => tree
+                                .put_link(&entry.tree_path, cid, size)
+                                .map_err(anyhow::Error::from),
+                            Err(e) => Err(e),
+                        }
+                    }
+                    EntryKind::Symlink(target) => match symlink_block(&target, &opt) {
+                        Ok(block) => {
+                            let (cid, size) = (*block.cid(), block.data().len() as u64);
+                            match repo.put_block(block).await {
+                                Ok(_) => tree
+                                    .put_link(&entry.tree_path, cid, size)
+                                    .map_err(anyhow::Error::from),
+                                Err(e) => Err(e),
+                            }
+                        }
+                        Err(e) => Err(e),
+                    },
+                };
+
+                if let Err(e) = result {
+                    yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                    return;
+                }
+
+                yield UnixfsStatus::ProgressStatus { written, total_size };
+            }
+
+            let mut iter = tree.build();
+            let mut root_cid = None;
+
+            while let Some(node) = iter.next_borrowed() {
+                let result = match node {
+                    Ok(node) => match Block::new(*node.cid, node.block.into()) {
+                        Ok(block) => repo.put_block(block).await,
+                        Err(e) => Err(e),
+                    },
+                    Err(e) => Err(e.into()),
+                };
+
+                match result {
+                    Ok((cid, _)) => root_cid = Some(cid),
+                    Err(e) => {
+                        yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                        return;
+                    }
+                }
+            }
+
+            let path = match root_cid {
+                Some(cid) if opt.wrap => IpfsPath::from(cid).sub_path(&name),
+                Some(cid) => Ok(IpfsPath::from(cid)),
+                None => Err(anyhow!("no cid available")),
+            };
+
+            let path = match path {
+                Ok(path) => path,
+                Err(e) => {
+                    yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                    return;
+                }
+            };
+
+            pin_and_provide(ipfs, &repo, &path, &opt).await;
+
+            yield UnixfsStatus::CompletedStatus { path, written, total_size };
+            return;
+        }
+
+        let (name, total_size, metadata, mut stream) = match options {
             AddOpt::File(path) => match tokio::fs::File::open(path.clone())
                 .and_then(|file| async move {
-                    let size = file.metadata().await?.len() as usize;
+                    let metadata = file.metadata().await?;
+                    let size = metadata.len() as usize;
 
                     let stream = ReaderStream::new(file);
 
                     let name: Option<String> = path.file_name().map(|f| f.to_string_lossy().to_string());
 
-                    Ok((name, Some(size), stream.boxed()))
+                    Ok((name, Some(size), Some(metadata), stream.boxed()))
                 }).await {
                     Ok(s) => s,
                     Err(e) => {
@@ -100,12 +287,21 @@ pub fn add<'a>(which: Either<&Ipfs, &Repo>, options: AddOpt<'a>, opt: AddOption)
                         return;
                     }
                 },
-            AddOpt::Stream { name, total, stream } => (name, total, stream),
+            AddOpt::Stream { name, total, stream } => (name, total, None, stream),
+            AddOpt::FileWithIgnore { .. } => unreachable!("converted into AddOpt::File above"),
         };
 
-        let mut adder = FileAdderBuilder::default()
-            .with_chunker(opt.chunk)
-            .build();
+        let metadata = metadata
+            .map(|metadata| unixfs_metadata(&metadata, &opt))
+            .unwrap_or_default();
+
+        let mut adder = match file_adder(&opt, metadata) {
+            Ok(adder) => adder,
+            Err(e) => {
+                yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                return;
+            }
+        };
 
         yield UnixfsStatus::ProgressStatus { written, total_size };
 
@@ -179,8 +375,9 @@ pub fn add<'a>(which: Either<&Ipfs, &Repo>, options: AddOpt<'a>, opt: AddOption)
             if let Some(name) = name {
                 let result = {
                     let repo = repo.clone();
+                    let opts = tree_options(&opt);
                     async move {
-                        let mut opts = rust_unixfs::dir::builder::TreeOptions::default();
+                        let mut opts = opts?;
                         opts.wrap_with_directory();
 
                         let mut tree = rust_unixfs::dir::builder::BufferingTreeBuilder::new(opts);
@@ -214,33 +411,270 @@ pub fn add<'a>(which: Either<&Ipfs, &Repo>, options: AddOpt<'a>, opt: AddOption)
             }
         }
 
-        let cid = path.root().cid().copied().expect("Cid is apart of the path");
+        pin_and_provide(ipfs, &repo, &path, &opt).await;
+
+        yield UnixfsStatus::CompletedStatus { path, written, total_size }
+    };
 
-        if opt.pin {
-            if let Ok(false) = repo.is_pinned(&cid).await {
-                if let Err(e) = repo.insert_pin(&cid, true, true).await {
-                    error!("Unable to pin {cid}: {e}");
+    UnixfsAdd {
+        stream: stream.boxed(),
+        span: None,
+    }
+}
+
+fn file_adder(opt: &AddOption, metadata: Metadata) -> anyhow::Result<FileAdder> {
+    let adder = FileAdderBuilder::default()
+        .with_chunker(opt.chunk)
+        .with_collector(if opt.trickle {
+            Collector::from(TrickleCollector::default())
+        } else {
+            Collector::default()
+        })
+        .with_raw_leaves(opt.raw_leaves)
+        .with_cid_version(opt.cid_version)
+        .with_hash(opt.hash)?
+        .with_inline_limit(opt.inline.then_some(opt.inline_limit))
+        .with_metadata(metadata)
+        .build();
+    Ok(adder)
+}
+
+/// Returns the options for building the directories with the Cid version, hash and inlining of
+/// the files.
+fn tree_options(opt: &AddOption) -> anyhow::Result<TreeOptions> {
+    let mut opts = TreeOptions::default();
+    opts.cid_version(opt.cid_version);
+    opts.hash(opt.hash)?;
+    opts.inline_limit(opt.inline.then_some(opt.inline_limit));
+    Ok(opts)
+}
+
+async fn pin_and_provide(ipfs: Option<Ipfs>, repo: &Repo, path: &IpfsPath, opt: &AddOption) {
+    let cid = path
+        .root()
+        .cid()
+        .copied()
+        .expect("Cid is apart of the path");
+
+    if opt.pin {
+        if let Ok(false) = repo.is_pinned(&cid).await {
+            if let Err(e) = repo.insert_pin(&cid, true, true).await {
+                error!("Unable to pin {cid}: {e}");
+            }
+        }
+    }
+
+    let provide = opt.provide;
+    tokio::spawn(async move {
+        if provide {
+            if let Some(ipfs) = ipfs {
+                if let Err(e) = ipfs.provide(cid).await {
+                    error!("Unable to provide {cid}: {e}");
                 }
             }
         }
+    });
+}
 
-        tokio::spawn(async move {
-            if opt.provide {
-                if let Some(ipfs) = ipfs {
-                    if let Err(e) = ipfs.provide(cid).await {
-                        error!("Unable to provide {cid}: {e}");
+/// Converts the filesystem metadata to the unixfs metadata which was asked to be preserved.
+fn unixfs_metadata(metadata: &std::fs::Metadata, opt: &AddOption) -> Metadata {
+    let mut unixfs = Metadata::default();
+
+    #[cfg(unix)]
+    if opt.preserve_mode {
+        use std::os::unix::fs::PermissionsExt;
+        unixfs = unixfs.with_mode(metadata.permissions().mode());
+    }
+
+    if opt.preserve_mtime {
+        if let Ok(modified) = metadata.modified() {
+            let (seconds, nanos) = match modified.duration_since(UNIX_EPOCH) {
+                Ok(since) => (since.as_secs() as i64, since.subsec_nanos()),
+                Err(e) => {
+                    // before the epoch the nanoseconds still count forward from the seconds
+                    let before = e.duration();
+                    match before.subsec_nanos() {
+                        0 => (-(before.as_secs() as i64), 0),
+                        nanos => (-(before.as_secs() as i64) - 1, 1_000_000_000 - nanos),
                     }
                 }
+            };
+            unixfs = unixfs.with_mtime(seconds, nanos);
+        }
+    }
+
+    unixfs
+}
+
+enum EntryKind {
+    Directory,
+    File,
+    Symlink(String),
+}
+
+/// An entry found while walking the directory being added.
+struct DirEntry {
+    path: PathBuf,
+    /// Slash separated path in the added tree, starting with the name of the added directory.
+    tree_path: String,
+    kind: EntryKind,
+    metadata: std::fs::Metadata,
+}
+
+/// Walks the directory at `root`, leaving out the hidden and ignored entries. Returns the name of
+/// the directory and the entries, where each directory comes before its contents.
+async fn walk_directory(
+    root: &Path,
+    opt: &AddOption,
+    ignore: &IgnoreRules,
+) -> anyhow::Result<(String, Vec<DirEntry>)> {
+    let root = tokio::fs::canonicalize(root).await?;
+    let name = root
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("{} has no valid utf-8 name", root.display()))?
+        .to_string();
+
+    let ignored = ignore_rules(&root, ignore)?;
+
+    let mut entries = vec![DirEntry {
+        path: root.clone(),
+        tree_path: name.clone(),
+        kind: EntryKind::Directory,
+        metadata: tokio::fs::metadata(&root).await?,
+    }];
+
+    // guards against symlink loops when following symlinks
+    let mut visited = HashSet::from([root.clone()]);
+    let mut pending = vec![(root, name.clone())];
+
+    while let Some((dir, tree_path)) = pending.pop() {
+        let mut read_dir = tokio::fs::read_dir(&dir).await?;
+        let mut children = Vec::new();
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            let path = entry.path();
+            let file_name = entry.file_name();
+            let file_name = file_name
+                .to_str()
+                .ok_or_else(|| anyhow!("{} is not valid utf-8", path.display()))?;
+
+            if !opt.hidden && file_name.starts_with('.') {
+                continue;
             }
-        });
 
-        yield UnixfsStatus::CompletedStatus { path, written, total_size }
-    };
+            let mut metadata = tokio::fs::symlink_metadata(&path).await?;
 
-    UnixfsAdd {
-        stream: stream.boxed(),
-        span: None,
+            if metadata.is_symlink() && opt.follow_symlinks {
+                // dangling symlinks and symlink loops cannot be followed, so they are added as
+                // symlinks
+                match tokio::fs::metadata(&path).await {
+                    Ok(target) => metadata = target,
+                    Err(e) => debug!(path = %path.display(), error = %e, "not following symlink"),
+                }
+                if metadata.is_dir() && !visited.insert(tokio::fs::canonicalize(&path).await?) {
+                    continue;
+                }
+            }
+
+            if ignored.matched(&path, metadata.is_dir()).is_ignore() {
+                continue;
+            }
+
+            let kind = if metadata.is_symlink() {
+                let target = tokio::fs::read_link(&path).await?;
+                let target = target
+                    .to_str()
+                    .ok_or_else(|| anyhow!("{} target is not valid utf-8", path.display()))?;
+                EntryKind::Symlink(target.to_string())
+            } else if metadata.is_dir() {
+                EntryKind::Directory
+            } else {
+                EntryKind::File
+            };
+
+            children.push(DirEntry {
+                path,
+                tree_path: format!("{tree_path}/{file_name}"),
+                kind,
+                metadata,
+            });
+        }
+
+        children.sort_unstable_by(|a, b| a.tree_path.cmp(&b.tree_path));
+
+        for child in children {
+            if let EntryKind::Directory = child.kind {
+                pending.push((child.path.clone(), child.tree_path.clone()));
+            }
+            entries.push(child);
+        }
+    }
+
+    Ok((name, entries))
+}
+
+fn ignore_rules(root: &Path, ignore: &IgnoreRules) -> anyhow::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(root);
+
+    if let Some(path) = &ignore.rules_path {
+        if let Some(e) = builder.add(path) {
+            return Err(e.into());
+        }
     }
+
+    for rule in &ignore.rules {
+        builder.add_line(None, rule)?;
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Adds the file at `path`, returning the Cid of the root and the cumulative size of the blocks.
+async fn import_file(
+    repo: &Repo,
+    path: &Path,
+    opt: &AddOption,
+    metadata: Metadata,
+    written: &mut usize,
+) -> anyhow::Result<(Cid, u64)> {
+    let mut stream = ReaderStream::new(tokio::fs::File::open(path).await?);
+    let mut adder = file_adder(opt, metadata)?;
+    let mut total_size = 0;
+
+    while let Some(buffer) = stream.next().await {
+        let buffer = buffer?;
+        let mut total = 0;
+        while total < buffer.len() {
+            let (blocks, consumed) = adder.push(&buffer[total..]);
+            for (cid, block) in blocks {
+                total_size += block.len() as u64;
+                repo.put_block(Block::new(cid, block)?).await?;
+            }
+            total += consumed;
+            *written += consumed;
+        }
+    }
+
+    let mut root = None;
+    for (cid, block) in adder.finish() {
+        total_size += block.len() as u64;
+        repo.put_block(Block::new(cid, block)?).await?;
+        root = Some(cid);
+    }
+
+    let cid = root.ok_or_else(|| anyhow!("no cid available for {}", path.display()))?;
+    Ok((cid, total_size))
+}
+
+fn symlink_block(target: &str, opt: &AddOption) -> anyhow::Result<Block> {
+    let mut data = Vec::new();
+    rust_unixfs::symlink::serialize_symlink_block(target, &mut data);
+
+    let inline_limit = opt.inline.then_some(opt.inline_limit);
+    let cid = rust_unixfs::dag_pb_cid(opt.cid_version, opt.hash, &data, inline_limit);
+
+    Block::new(cid, data)
 }
 
 impl<'a> Stream for UnixfsAdd<'a> {
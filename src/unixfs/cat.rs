@@ -1,14 +1,20 @@
 use crate::{dag::IpldDag, repo::Repo, Block, Ipfs};
 use async_stream::stream;
-use bytes::Bytes;
+use bytes::{Buf, Bytes};
 use either::Either;
 use futures::future::BoxFuture;
 use futures::stream::{BoxStream, Stream};
 use futures::{FutureExt, StreamExt, TryStreamExt};
+use libipld::IpldCodec;
 use libp2p::PeerId;
 use rust_unixfs::file::visit::IdleFileVisit;
+use std::io::SeekFrom;
 use std::ops::Range;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 use std::{borrow::Borrow, time::Duration};
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
 use tracing::{Instrument, Span};
 
 use super::TraversalFailed;
@@ -52,27 +58,73 @@ pub fn cat<'a>(
 
         // Get the root block to start the traversal. The stream does not expose any of the file
         // metadata. To get to it the user needs to create a Visitor over the first block.
-        let block = match starting_point.into() {
-            StartingPoint::Left(path) => match dag
-                .resolve_with_session(session, path.clone(), true, providers, local_only, timeout)
-                .await
-                .map_err(TraversalFailed::Resolving)
-                .and_then(|(resolved, _)| {
-                    resolved.into_unixfs_block().map_err(TraversalFailed::Path)
-                }) {
-                    Ok(block) => block,
-                    Err(e) => {
-                        yield Err(e);
-                        return;
-                    }
-                },
-            StartingPoint::Right(block) => block,
+        let block = match root_block(&dag, session, starting_point.into(), providers, local_only, timeout).await {
+            Ok(block) => block,
+            Err(e) => {
+                yield Err(e);
+                return;
+            }
         };
 
+        let mut stream = visit_file(repo, session, block, visit, providers, local_only, timeout);
+        while let Some(item) = stream.next().await {
+            yield item;
+        }
+    };
+
+    UnixfsCat {
+        stream: stream.boxed(),
+        span: None,
+    }
+}
+
+async fn root_block(
+    dag: &IpldDag,
+    session: Option<u64>,
+    starting_point: StartingPoint,
+    providers: &[PeerId],
+    local_only: bool,
+    timeout: Option<Duration>,
+) -> Result<Block, TraversalFailed> {
+    match starting_point {
+        StartingPoint::Left(path) => dag
+            .resolve_with_session(session, path, true, providers, local_only, timeout)
+            .await
+            .map_err(TraversalFailed::Resolving)
+            .and_then(|(resolved, _)| resolved.into_unixfs_block().map_err(TraversalFailed::Path)),
+        StartingPoint::Right(block) => Ok(block),
+    }
+}
+
+fn is_raw(block: &Block) -> bool {
+    block.cid().codec() == <IpldCodec as Into<u64>>::into(IpldCodec::Raw)
+}
+
+/// Visits the file starting from the root `block`, producing the bytes of the target range of the
+/// `visit` and loading only the blocks covering the range.
+fn visit_file<'a, P>(
+    repo: Repo,
+    session: Option<u64>,
+    block: Block,
+    visit: IdleFileVisit,
+    providers: P,
+    local_only: bool,
+    timeout: Option<Duration>,
+) -> BoxStream<'a, Result<Bytes, TraversalFailed>>
+where
+    P: Borrow<[PeerId]> + Send + 'a,
+{
+    let stream = stream! {
         let mut cache = None;
         // Start the visit from the root block. We need to move the both components as Options into the
         // stream as we can't yet return them from this Future context.
-        let (visit, bytes) = match visit.start(block.data()) {
+        let started = if is_raw(&block) {
+            Ok(visit.start_raw(block.data()))
+        } else {
+            visit.start(block.data())
+        };
+
+        let (visit, bytes) = match started {
             Ok((bytes, _, _, visit)) => {
                 let bytes = if !bytes.is_empty() {
                     Some(Bytes::copy_from_slice(bytes))
@@ -105,8 +157,7 @@ pub fn cat<'a>(
             // going. Not that we have any "operation" concept of the Want yet.
             let (next, _) = visit.pending_links();
 
-            let borrow = repo.borrow();
-            let block = match borrow.get_block_with_session(session, next, providers, local_only, timeout).await {
+            let block = match repo.get_block_with_session(session, next, providers.borrow(), local_only, timeout).await {
                 Ok(block) => block,
                 Err(e) => {
                     yield Err(TraversalFailed::Loading(*next, e));
@@ -134,9 +185,194 @@ pub fn cat<'a>(
         }
     };
 
-    UnixfsCat {
-        stream: stream.boxed(),
-        span: None,
+    stream.boxed()
+}
+
+/// Creates a seekable reader over an UnixFS file. Unlike [`cat`], which streams the file from the
+/// start or over a fixed range, the reader can be moved around the file with [`AsyncSeek`] and
+/// only loads the blocks covering the parts that are read.
+///
+/// Resolves the starting point and loads the root block of the file before returning.
+pub async fn cat_reader(
+    which: Either<&Ipfs, &Repo>,
+    starting_point: impl Into<StartingPoint>,
+    providers: &[PeerId],
+    local_only: bool,
+    timeout: Option<Duration>,
+) -> Result<UnixfsReader, TraversalFailed> {
+    let (repo, dag, session) = match which {
+        Either::Left(ipfs) => (
+            ipfs.repo().clone(),
+            ipfs.dag(),
+            Some(crate::BITSWAP_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst)),
+        ),
+        Either::Right(repo) => {
+            let session = repo
+                .is_online()
+                .then(|| crate::BITSWAP_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst));
+            (repo.clone(), IpldDag::from(repo.clone()), session)
+        }
+    };
+
+    let block = root_block(
+        &dag,
+        session,
+        starting_point.into(),
+        providers,
+        local_only,
+        timeout,
+    )
+    .await?;
+
+    let size = if is_raw(&block) {
+        block.data().len() as u64
+    } else {
+        // an empty range reads nothing but the size from the root block
+        match IdleFileVisit::default()
+            .with_target_range(0..0)
+            .start(block.data())
+        {
+            Ok((_, size, _, _)) => size,
+            Err(e) => return Err(TraversalFailed::Walking(*block.cid(), e)),
+        }
+    };
+
+    Ok(UnixfsReader {
+        repo,
+        session,
+        block,
+        providers: providers.into(),
+        local_only,
+        timeout,
+        size,
+        position: 0,
+        buffer: Bytes::new(),
+        stream: None,
+    })
+}
+
+/// Seekable reader over an UnixFS file, created with [`cat_reader`].
+///
+/// Reading continues the walk of the file from the current position. Seeking outside of the bytes
+/// already loaded stops the walk, which is started again from the new position on the next read.
+pub struct UnixfsReader {
+    repo: Repo,
+    session: Option<u64>,
+    block: Block,
+    providers: Arc<[PeerId]>,
+    local_only: bool,
+    timeout: Option<Duration>,
+    size: u64,
+    position: u64,
+    /// Bytes at the position which have been loaded but not read yet.
+    buffer: Bytes,
+    stream: Option<BoxStream<'static, Result<Bytes, TraversalFailed>>>,
+}
+
+impl UnixfsReader {
+    /// Returns the size of the file.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the current position in the file.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+impl std::fmt::Debug for UnixfsReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UnixfsReader")
+            .field("cid", self.block.cid())
+            .field("size", &self.size)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+impl AsyncRead for UnixfsReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = &mut *self;
+
+        loop {
+            if this.position >= this.size || buf.remaining() == 0 {
+                return Poll::Ready(Ok(()));
+            }
+
+            if !this.buffer.is_empty() {
+                let len = this.buffer.len().min(buf.remaining());
+                buf.put_slice(&this.buffer.split_to(len));
+                this.position += len as u64;
+                return Poll::Ready(Ok(()));
+            }
+
+            let stream = this.stream.get_or_insert_with(|| {
+                let visit = IdleFileVisit::default().with_target_range(this.position..this.size);
+                visit_file(
+                    this.repo.clone(),
+                    this.session,
+                    this.block.clone(),
+                    visit,
+                    this.providers.clone(),
+                    this.local_only,
+                    this.timeout,
+                )
+            });
+
+            match futures::ready!(stream.poll_next_unpin(cx)) {
+                Some(Ok(bytes)) => this.buffer = bytes,
+                Some(Err(e)) => {
+                    this.stream = None;
+                    return Poll::Ready(Err(std::io::Error::new(std::io::ErrorKind::Other, e)));
+                }
+                None => {
+                    this.stream = None;
+                    return Poll::Ready(Ok(()));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncSeek for UnixfsReader {
+    fn start_seek(mut self: Pin<&mut Self>, position: SeekFrom) -> std::io::Result<()> {
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        let target = target.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+
+        let this = &mut *self;
+        match target.checked_sub(this.position) {
+            // within the loaded bytes the walk can continue
+            Some(skip) if skip < this.buffer.len() as u64 => {
+                this.buffer.advance(skip as usize);
+            }
+            Some(0) => {}
+            _ => {
+                this.buffer.clear();
+                this.stream = None;
+            }
+        }
+        this.position = target;
+
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<std::io::Result<u64>> {
+        Poll::Ready(Ok(self.position))
     }
 }
 
@@ -1,9 +1,16 @@
-use std::{path::Path, time::Duration};
+use std::{
+    collections::HashSet,
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
 
 use either::Either;
 use futures::{future::BoxFuture, stream::BoxStream, FutureExt, Stream, StreamExt};
 use libp2p::PeerId;
-use rust_unixfs::walk::{ContinuedWalk, Walker};
+use rust_unixfs::{
+    walk::{ContinuedWalk, Walker},
+    Metadata,
+};
 use tokio::io::AsyncWriteExt;
 use tracing::{Instrument, Span};
 
@@ -41,16 +48,6 @@ pub fn get<'a, P: AsRef<Path>>(
         let mut total_size = None;
         let mut written = 0;
 
-        let mut file = match tokio::fs::File::create(dest)
-            .await
-            .map_err(TraversalFailed::Io) {
-                Ok(f) => f,
-                Err(e) => {
-                    yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e.into()) };
-                    return;
-                }
-            };
-
         let block  = match dag
             .resolve_with_session(session, path.clone(), true, providers, local_only, timeout)
             .await
@@ -64,9 +61,20 @@ pub fn get<'a, P: AsRef<Path>>(
         };
 
         let cid = block.cid();
-        let root_name = block.cid().to_string();
 
-        let mut walker = Walker::new(*cid, root_name);
+        // with an empty root name the paths of the entries are relative to `dest`
+        let mut walker = Walker::new(*cid, String::new());
+
+        // the file currently being written, and its path
+        let mut file = None;
+
+        // the directories are walked before their entries, which would update the mtime, so the
+        // metadata is set only after everything has been written
+        let mut directories = vec![];
+
+        // the entries written so far, as a tree with duplicate names could otherwise write through
+        // a symlink it created earlier
+        let mut created = HashSet::new();
 
         while walker.should_continue() {
             let (next, _) = walker.pending_links();
@@ -81,42 +89,91 @@ pub fn get<'a, P: AsRef<Path>>(
 
             match walker.next(block_data, &mut cache) {
                 Ok(ContinuedWalk::Bucket(..)) => {}
-                Ok(ContinuedWalk::File(segment, _, _, _, size)) => {
+                Ok(ContinuedWalk::File(segment, _, entry, metadata, size)) => {
 
                     if segment.is_first() {
-                        total_size = Some(size as usize);
-                        yield UnixfsStatus::ProgressStatus { written, total_size };
+                        let target = match claim_path(&mut created, &dest, entry).await {
+                            Ok(target) => target,
+                            Err(e) => {
+                                yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                                return;
+                            }
+                        };
+
+                        if entry.as_os_str().is_empty() {
+                            total_size = Some(size as usize);
+                            yield UnixfsStatus::ProgressStatus { written, total_size };
+                        }
+
+                        match create_file(&target).await {
+                            Ok(f) => file = Some((f, target)),
+                            Err(e) => {
+                                yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                                return;
+                            }
+                        }
                     }
-                    // even if the largest of files can have 256 kB blocks and about the same
-                    // amount of content, try to consume it in small parts not to grow the buffers
-                    // too much.
 
-                    let mut n = 0usize;
+                    let Some((f, _)) = file.as_mut() else {
+                        yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("file segment without a file")) };
+                        return;
+                    };
+
                     let slice = segment.as_ref();
-                    let total = slice.len();
 
-                    while n < total {
-                        let next = &slice[n..];
-                        n += next.len();
-                        if let Err(e) = file.write_all(next).await {
-                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
-                            return;
-                        }
-                        if let Err(e) = file.sync_all().await {
+                    if !slice.is_empty() {
+                        if let Err(e) = f.write_all(slice).await {
                             yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
                             return;
                         }
 
-                        written += n;
+                        written += slice.len();
                         yield UnixfsStatus::ProgressStatus { written, total_size };
                     }
 
                     if segment.is_last() {
+                        let (f, target) = file.take().expect("file was opened on the first segment");
+
+                        if let Err(e) = finish_file(f, &target, metadata).await {
+                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                            return;
+                        }
+
                         yield UnixfsStatus::ProgressStatus { written, total_size };
                     }
                 },
-                Ok(ContinuedWalk::Directory( .. )) | Ok(ContinuedWalk::RootDirectory( .. )) => {}, //TODO
-                Ok(ContinuedWalk::Symlink( .. )) => {},
+                Ok(ContinuedWalk::Directory(_, entry, metadata)) | Ok(ContinuedWalk::RootDirectory(_, entry, metadata)) => {
+                    let result = match claim_path(&mut created, &dest, entry).await {
+                        Ok(target) => tokio::fs::create_dir_all(&target)
+                            .await
+                            .map(|_| target)
+                            .map_err(anyhow::Error::from),
+                        Err(e) => Err(e),
+                    };
+
+                    match result {
+                        Ok(target) => directories.push((target, metadata.clone())),
+                        Err(e) => {
+                            yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                            return;
+                        }
+                    }
+
+                    yield UnixfsStatus::ProgressStatus { written, total_size };
+                },
+                Ok(ContinuedWalk::Symlink(link, _, entry, metadata)) => {
+                    let result = match claim_path(&mut created, &dest, entry).await {
+                        Ok(target) => create_symlink(&target, link, metadata).await,
+                        Err(e) => Err(e),
+                    };
+
+                    if let Err(e) = result {
+                        yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                        return;
+                    }
+
+                    yield UnixfsStatus::ProgressStatus { written, total_size };
+                },
                 Err(e) => {
                     yield UnixfsStatus::FailedStatus { written, total_size, error: Some(anyhow::anyhow!("{e}")) };
                     return;
@@ -124,6 +181,15 @@ pub fn get<'a, P: AsRef<Path>>(
             };
         };
 
+        // deepest directories first, so setting the metadata of a directory does not touch its
+        // parent
+        for (target, metadata) in directories.iter().rev() {
+            if let Err(e) = set_metadata(target, metadata).await {
+                yield UnixfsStatus::FailedStatus { written, total_size, error: Some(e) };
+                return;
+            }
+        }
+
         yield UnixfsStatus::CompletedStatus { path, written, total_size };
     };
 
@@ -133,6 +199,131 @@ pub fn get<'a, P: AsRef<Path>>(
     }
 }
 
+/// Returns the path under `dest` for the entry at `path` of the walk, refusing the paths which
+/// could escape `dest`.
+fn target_path(dest: &Path, path: &Path) -> Result<PathBuf, anyhow::Error> {
+    if !path
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        anyhow::bail!("invalid path in the unixfs tree: {}", path.display());
+    }
+
+    // joining an empty path would add a trailing separator
+    match path.as_os_str().is_empty() {
+        true => Ok(dest.to_path_buf()),
+        false => Ok(dest.join(path)),
+    }
+}
+
+/// Returns the path under `dest` for the entry at `path` like [`target_path`], refusing the
+/// entries already written and the paths leading through symlinks.
+async fn claim_path(
+    created: &mut HashSet<PathBuf>,
+    dest: &Path,
+    path: &Path,
+) -> Result<PathBuf, anyhow::Error> {
+    let target = target_path(dest, path)?;
+
+    if !created.insert(path.to_path_buf()) {
+        anyhow::bail!("duplicate path in the unixfs tree: {}", path.display());
+    }
+
+    // `dest` itself was chosen by the caller, only the entries under it are checked
+    let mut ancestor = dest.to_path_buf();
+    for component in path.components() {
+        ancestor.push(component);
+        match tokio::fs::symlink_metadata(&ancestor).await {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                anyhow::bail!("refusing to write through symlink {}", ancestor.display());
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => break,
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    Ok(target)
+}
+
+/// Creates the file at `target`, replacing a previous file but never following a symlink.
+async fn create_file(target: &Path) -> Result<tokio::fs::File, anyhow::Error> {
+    match tokio::fs::symlink_metadata(target).await {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            anyhow::bail!("refusing to write through symlink {}", target.display());
+        }
+        Ok(metadata) if metadata.is_file() => tokio::fs::remove_file(target).await?,
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    // a symlink created in between makes this fail instead of being followed
+    let file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)
+        .await
+        .map_err(TraversalFailed::Io)?;
+
+    Ok(file)
+}
+
+async fn finish_file(
+    mut file: tokio::fs::File,
+    target: &Path,
+    metadata: &Metadata,
+) -> Result<(), anyhow::Error> {
+    file.flush().await?;
+    file.sync_all().await?;
+    drop(file);
+
+    set_metadata(target, metadata).await
+}
+
+async fn create_symlink(
+    target: &Path,
+    link: &[u8],
+    metadata: &Metadata,
+) -> Result<(), anyhow::Error> {
+    let link = std::str::from_utf8(link)?;
+
+    #[cfg(unix)]
+    tokio::fs::symlink(link, target).await?;
+
+    #[cfg(windows)]
+    tokio::fs::symlink_file(link, target).await?;
+
+    set_metadata(target, metadata).await
+}
+
+/// Sets the mode and mtime recorded in the unixfs metadata, if any. Neither is followed through
+/// symlinks, and the mode is limited to the permission bits, dropping setuid, setgid and sticky.
+async fn set_metadata(path: &Path, metadata: &Metadata) -> Result<(), anyhow::Error> {
+    let symlink = tokio::fs::symlink_metadata(path)
+        .await?
+        .file_type()
+        .is_symlink();
+
+    #[cfg(unix)]
+    if let (Some(mode), false) = (metadata.mode(), symlink) {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = std::fs::Permissions::from_mode(mode & 0o777);
+        tokio::fs::set_permissions(path, permissions).await?;
+    }
+
+    if let Some(mtime) = metadata.mtime_as_filetime() {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || match symlink {
+            true => filetime::set_symlink_file_times(&path, mtime, mtime),
+            false => filetime::set_file_mtime(&path, mtime),
+        })
+        .await??;
+    }
+
+    Ok(())
+}
+
 pub struct UnixfsGet<'a> {
     stream: BoxStream<'a, UnixfsStatus>,
     span: Option<Span>,
@@ -0,0 +1,840 @@
+//! Mutable file system (MFS) over the unixfs trees in the repo, like `ipfs files` of go-ipfs.
+//!
+//! The files are kept in a unixfs directory, the root of which is stored in the [`DataStore`] and
+//! pinned recursively with a pin named [`PIN_NAME`]. Every change writes the changed directories
+//! up to the root as new blocks and moves that pin to the new root, leaving the previous tree to be
+//! collected once nothing else pins it. Pins which were not created for the files, even of the
+//! same Cids, are never removed. Like go-ipfs, the garbage collection keeps the tree of the
+//! current root in any case, so the files stay even when the only pin of the root was created by
+//! someone else and is later removed.
+//!
+//! [`DataStore`]: crate::repo::DataStore
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Error};
+use bytes::Bytes;
+use either::Either;
+use libipld::{Cid, IpldCodec};
+use rust_unixfs::dir::builder::{BufferingTreeBuilder, TreeOptions};
+use rust_unixfs::file::adder::FileAdder;
+use rust_unixfs::file::visit::IdleFileVisit;
+use tokio::sync::Mutex;
+
+use crate::{Block, Ipfs, IpfsPath};
+
+/// The key of the root Cid in the datastore, the same go-ipfs uses.
+pub(crate) const ROOT_KEY: &[u8] = b"/local/filesroot";
+
+/// The name of the recursive pin of the root.
+const PIN_NAME: &str = "/local/filesroot";
+
+/// The entries of a directory by their names, with their Cids and cumulative sizes.
+type Entries = BTreeMap<String, (Cid, u64)>;
+
+/// The root of the files shared by the clones of [`Ipfs`], loaded from the datastore on first use.
+/// Holding the lock for the whole of an operation keeps the operations from overwriting each other.
+#[derive(Clone, Default)]
+pub(crate) struct FilesRoot(Arc<Mutex<Option<Cid>>>);
+
+/// Mutable file system facade around [`Ipfs`].
+#[derive(Clone, Debug)]
+pub struct IpfsFiles {
+    ipfs: Ipfs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Directory,
+}
+
+/// Information about a file or a directory, as returned by [`IpfsFiles::stat`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileStat {
+    pub cid: Cid,
+    pub file_type: FileType,
+    /// Size of the file contents, or zero for directories.
+    pub size: u64,
+    /// Size of the whole DAG under the Cid.
+    pub cumulative_size: u64,
+    /// Number of links in the root block.
+    pub blocks: usize,
+}
+
+/// Entry of a directory listing, as returned by [`IpfsFiles::ls`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileEntry {
+    pub name: String,
+    pub file_type: FileType,
+    /// Size of the file contents, or zero for directories.
+    pub size: u64,
+    pub cid: Cid,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriteOption {
+    /// Byte offset to begin writing at, at most the size of the file.
+    pub offset: u64,
+    /// Create the file if it does not exist.
+    pub create: bool,
+    /// Truncate the file to size zero before writing.
+    pub truncate: bool,
+    /// Create the parent directories if they do not exist.
+    pub parents: bool,
+}
+
+impl IpfsFiles {
+    pub fn new(ipfs: Ipfs) -> Self {
+        IpfsFiles { ipfs }
+    }
+
+    /// Creates a directory. With `parents`, the missing parent directories are created as well
+    /// and an existing directory is not an error.
+    pub async fn mkdir(&self, path: &str, parents: bool) -> Result<(), Error> {
+        let (dir, name) = split_last(path)?;
+        let mut root = self.ipfs.files_root.0.lock().await;
+        let current = self.load(&mut root).await?;
+
+        if let Some(cid) = self.resolve(current, &segments(path)?).await? {
+            return match self.read_dir(cid).await? {
+                Some(_) if parents => Ok(()),
+                _ => Err(anyhow!("{path} already exists")),
+            };
+        }
+
+        let empty = self.write_dir(Entries::new()).await?;
+        let (updated, _) = self
+            .modify(current, &dir, parents, |entries| {
+                entries.insert(name.to_owned(), empty);
+                Ok(())
+            })
+            .await?;
+
+        self.commit(&mut root, updated).await
+    }
+
+    /// Writes `data` to the file at `path`, replacing the bytes from the offset onwards.
+    ///
+    /// The whole file is read and written again, which makes this costly for large files.
+    pub async fn write(
+        &self,
+        path: &str,
+        data: impl Into<Bytes>,
+        opt: WriteOption,
+    ) -> Result<(), Error> {
+        let data = data.into();
+        let (dir, name) = split_last(path)?;
+        let mut root = self.ipfs.files_root.0.lock().await;
+        let current = self.load(&mut root).await?;
+
+        let mut content = match self.resolve(current, &segments(path)?).await {
+            Ok(Some(cid)) => {
+                if self.read_dir(cid).await?.is_some() {
+                    return Err(anyhow!("{path} is a directory"));
+                }
+                if opt.truncate {
+                    Vec::new()
+                } else {
+                    let cat = crate::unixfs::cat(
+                        Either::Left(&self.ipfs),
+                        IpfsPath::from(cid),
+                        None,
+                        &[],
+                        false,
+                        None,
+                    );
+                    cat.await?.to_vec()
+                }
+            }
+            Ok(None) if opt.create => Vec::new(),
+            Ok(None) => return Err(anyhow!("{path} does not exist")),
+            Err(e) => return Err(e),
+        };
+
+        let offset = usize::try_from(opt.offset)?;
+        if offset > content.len() {
+            return Err(anyhow!(
+                "offset was past the end of {path} ({offset} > {})",
+                content.len()
+            ));
+        }
+        let end = offset
+            .checked_add(data.len())
+            .ok_or_else(|| anyhow!("writing {} bytes at {offset} overflows", data.len()))?;
+        if content.len() < end {
+            content.resize(end, 0);
+        }
+        content[offset..end].copy_from_slice(&data);
+
+        let file = self.write_file(&content).await?;
+        let (updated, _) = self
+            .modify(current, &dir, opt.parents, |entries| {
+                entries.insert(name.to_owned(), file);
+                Ok(())
+            })
+            .await?;
+
+        self.commit(&mut root, updated).await
+    }
+
+    /// Moves the file or directory at `from` to `to`. If `to` is an existing directory, the entry
+    /// is moved into it.
+    pub async fn mv(&self, from: &str, to: &str) -> Result<(), Error> {
+        let source = segments(from)?;
+        let (from_dir, from_name) = split_last(from)?;
+        let mut root = self.ipfs.files_root.0.lock().await;
+        let current = self.load(&mut root).await?;
+
+        let link = self
+            .link(current, &source)
+            .await?
+            .ok_or_else(|| anyhow!("{from} does not exist"))?;
+
+        let mut target = segments(to)?;
+        if let Some(cid) = self.resolve(current, &target).await? {
+            if self.read_dir(cid).await?.is_none() {
+                return Err(anyhow!("{to} already exists"));
+            }
+            target.push(from_name);
+        }
+
+        if target.starts_with(&source) {
+            return Err(anyhow!("cannot move {from} into itself"));
+        }
+
+        let (to_name, to_dir) = target
+            .split_last()
+            .ok_or_else(|| anyhow!("cannot replace the root"))?;
+
+        let (inserted, _) = self
+            .modify(current, to_dir, false, |entries| {
+                insert_new(entries, to_name, link, to)
+            })
+            .await?;
+        let (updated, _) = self
+            .modify(inserted, &from_dir, false, |entries| {
+                entries.remove(from_name);
+                Ok(())
+            })
+            .await?;
+
+        self.commit(&mut root, updated).await
+    }
+
+    /// Copies a file or a directory to `to` from either another path of the files or an `/ipfs/`
+    /// or `/ipns/` path. With `parents`, the missing parent directories are created.
+    pub async fn cp(&self, from: &str, to: &str, parents: bool) -> Result<(), Error> {
+        let (dir, name) = split_last(to)?;
+        let mut root = self.ipfs.files_root.0.lock().await;
+        let current = self.load(&mut root).await?;
+
+        let link = if from.starts_with("/ipfs/") || from.starts_with("/ipns/") {
+            let path = from.parse::<IpfsPath>()?;
+            let (resolved, _) = self.ipfs.dag().resolve(path, true, &[], false).await?;
+            let cid = *resolved.into_unixfs_block()?.cid();
+            (cid, self.stat_cid(cid).await?.cumulative_size)
+        } else {
+            self.link(current, &segments(from)?)
+                .await?
+                .ok_or_else(|| anyhow!("{from} does not exist"))?
+        };
+
+        let (updated, _) = self
+            .modify(current, &dir, parents, |entries| {
+                insert_new(entries, name, link, to)
+            })
+            .await?;
+
+        self.commit(&mut root, updated).await
+    }
+
+    /// Removes the file or the directory at `path`. Directories are only removed with
+    /// `recursive`.
+    pub async fn rm(&self, path: &str, recursive: bool) -> Result<(), Error> {
+        let (dir, name) = split_last(path)?;
+        let mut root = self.ipfs.files_root.0.lock().await;
+        let current = self.load(&mut root).await?;
+
+        let cid = self
+            .resolve(current, &segments(path)?)
+            .await?
+            .ok_or_else(|| anyhow!("{path} does not exist"))?;
+
+        if !recursive && self.read_dir(cid).await?.is_some() {
+            return Err(anyhow!("{path} is a directory, use recursive to remove it"));
+        }
+
+        let (updated, _) = self
+            .modify(current, &dir, false, |entries| {
+                entries.remove(name);
+                Ok(())
+            })
+            .await?;
+
+        self.commit(&mut root, updated).await
+    }
+
+    /// Returns information about the file or the directory at `path`.
+    pub async fn stat(&self, path: &str) -> Result<FileStat, Error> {
+        let mut root = self.ipfs.files_root.0.lock().await;
+        let current = self.load(&mut root).await?;
+
+        let cid = self
+            .resolve(current, &segments(path)?)
+            .await?
+            .ok_or_else(|| anyhow!("{path} does not exist"))?;
+
+        self.stat_cid(cid).await
+    }
+
+    /// Lists the entries of the directory at `path`, or the file at `path` by itself.
+    pub async fn ls(&self, path: &str) -> Result<Vec<FileEntry>, Error> {
+        let segments = segments(path)?;
+        let mut root = self.ipfs.files_root.0.lock().await;
+        let current = self.load(&mut root).await?;
+
+        let cid = self
+            .resolve(current, &segments)
+            .await?
+            .ok_or_else(|| anyhow!("{path} does not exist"))?;
+
+        let entries = match self.read_dir(cid).await? {
+            Some(entries) => entries,
+            None => {
+                let name = segments.last().copied().unwrap_or_default().to_owned();
+                Entries::from([(name, (cid, 0))])
+            }
+        };
+
+        let mut listed = Vec::with_capacity(entries.len());
+        for (name, (cid, _)) in entries {
+            let stat = self.stat_cid(cid).await?;
+            listed.push(FileEntry {
+                name,
+                file_type: stat.file_type,
+                size: stat.size,
+                cid,
+            });
+        }
+
+        Ok(listed)
+    }
+
+    /// Makes sure the root is stored and pinned, returning the Cid of `path`. The root can be
+    /// snapshotted with the path `/`.
+    ///
+    /// The pin of the root is moved on the next change. To keep a snapshot of the root pinned,
+    /// give its pin a name of its own with [`Ipfs::set_pin_metadata`].
+    pub async fn flush(&self, path: &str) -> Result<Cid, Error> {
+        let mut root = self.ipfs.files_root.0.lock().await;
+        let current = self.load(&mut root).await?;
+        self.commit(&mut root, current).await?;
+
+        self.resolve(current, &segments(path)?)
+            .await?
+            .ok_or_else(|| anyhow!("{path} does not exist"))
+    }
+
+    /// Returns the current root, reading it from the datastore or creating an empty one when
+    /// needed.
+    async fn load(&self, root: &mut Option<Cid>) -> Result<Cid, Error> {
+        if let Some(cid) = *root {
+            return Ok(cid);
+        }
+
+        let stored = self.ipfs.repo().data_store().get(ROOT_KEY).await?;
+        let cid = match stored {
+            Some(bytes) => Cid::try_from(bytes)?,
+            None => {
+                let (cid, _) = self.write_dir(Entries::new()).await?;
+                self.commit(root, cid).await?;
+                cid
+            }
+        };
+
+        *root = Some(cid);
+        Ok(cid)
+    }
+
+    /// Pins and stores the `updated` root. The pin of the previous root is moved to the updated
+    /// root, or removed when the updated root is already pinned recursively by someone else; the
+    /// garbage collection keeps the stored root regardless.
+    async fn commit(&self, root: &mut Option<Cid>, updated: Cid) -> Result<(), Error> {
+        let repo = self.ipfs.repo();
+
+        let previous = match *root {
+            Some(previous) if previous != updated && self.owns_pin(&previous).await? => {
+                Some(previous)
+            }
+            _ => None,
+        };
+
+        let pinned = repo
+            .query_pins(vec![updated], crate::PinMode::Recursive)
+            .await
+            .is_ok();
+
+        match (previous, pinned) {
+            (Some(previous), false) => repo.update_pin(&previous, &updated).await?,
+            (Some(previous), true) => repo.remove_pin(&previous).recursive().await?,
+            (None, false) => repo.pin(&updated).recursive().name(PIN_NAME).await?,
+            (None, true) => {}
+        }
+
+        repo.data_store().put(ROOT_KEY, &updated.to_bytes()).await?;
+        *root = Some(updated);
+
+        Ok(())
+    }
+
+    /// Returns true if `cid` is pinned with the pin created for the root.
+    async fn owns_pin(&self, cid: &Cid) -> Result<bool, Error> {
+        let metadata = self.ipfs.repo().pin_metadata(cid).await?;
+        Ok(metadata.and_then(|metadata| metadata.name).as_deref() == Some(PIN_NAME))
+    }
+
+    /// Applies `f` on the entries of the directory at `dir` and writes the directories from it up
+    /// to the root, returning the new root.
+    async fn modify<R>(
+        &self,
+        root: Cid,
+        dir: &[&str],
+        parents: bool,
+        f: impl FnOnce(&mut Entries) -> Result<R, Error>,
+    ) -> Result<(Cid, R), Error> {
+        let mut directories = vec![self
+            .read_dir(root)
+            .await?
+            .ok_or_else(|| anyhow!("root is not a directory"))?];
+
+        for (depth, name) in dir.iter().enumerate() {
+            let path = || format!("/{}", dir[..=depth].join("/"));
+            let parent = directories.last().expect("there is always the root");
+
+            let entries = match parent.get(*name) {
+                Some((cid, _)) => self
+                    .read_dir(*cid)
+                    .await?
+                    .ok_or_else(|| anyhow!("{} is not a directory", path()))?,
+                None if parents => Entries::new(),
+                None => return Err(anyhow!("{} does not exist", path())),
+            };
+
+            directories.push(entries);
+        }
+
+        let result = f(directories.last_mut().expect("there is always the root"))?;
+
+        let mut link = self
+            .write_dir(directories.pop().expect("there is always the root"))
+            .await?;
+
+        for name in dir.iter().rev() {
+            let mut parent = directories.pop().expect("parents were pushed first");
+            parent.insert((*name).to_owned(), link);
+            link = self.write_dir(parent).await?;
+        }
+
+        Ok((link.0, result))
+    }
+
+    async fn resolve(&self, root: Cid, segments: &[&str]) -> Result<Option<Cid>, Error> {
+        Ok(self.link(root, segments).await?.map(|(cid, _)| cid))
+    }
+
+    /// Returns the Cid and the cumulative size of the link at the path of `segments`.
+    async fn link(&self, root: Cid, segments: &[&str]) -> Result<Option<(Cid, u64)>, Error> {
+        let mut link = (root, 0);
+
+        for (depth, segment) in segments.iter().enumerate() {
+            let entries = self
+                .read_dir(link.0)
+                .await?
+                .ok_or_else(|| anyhow!("/{} is not a directory", segments[..depth].join("/")))?;
+
+            match entries.get(*segment) {
+                Some(next) => link = *next,
+                None => return Ok(None),
+            }
+        }
+
+        if segments.is_empty() {
+            link.1 = self.stat_cid(root).await?.cumulative_size;
+        }
+
+        Ok(Some(link))
+    }
+
+    /// Reads the entries of the directory, or returns `None` if the Cid is not a directory.
+    async fn read_dir(&self, cid: Cid) -> Result<Option<Entries>, Error> {
+        let mut entries = Entries::new();
+        let mut pending = vec![cid];
+
+        while let Some(cid) = pending.pop() {
+            if cid.codec() == u64::from(IpldCodec::Raw) {
+                return Ok(None);
+            }
+
+            let block = self.ipfs.repo().get_block(&cid, &[], false).await?;
+
+            let listed = match rust_unixfs::dir::list(block.data()) {
+                Ok(listed) => listed,
+                Err(rust_unixfs::ResolveError::UnexpectedType(_)) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+
+            entries.extend(
+                listed
+                    .entries
+                    .into_iter()
+                    .map(|(name, cid, total_size)| (name, (cid, total_size))),
+            );
+            pending.extend(listed.buckets);
+        }
+
+        Ok(Some(entries))
+    }
+
+    /// Writes the directory, returning its Cid and cumulative size.
+    async fn write_dir(&self, entries: Entries) -> Result<(Cid, u64), Error> {
+        let mut opts = TreeOptions::default();
+        opts.wrap_with_directory();
+
+        let mut builder = BufferingTreeBuilder::new(opts);
+        for (name, (cid, total_size)) in entries {
+            builder.put_link(&name, cid, total_size)?;
+        }
+
+        let mut iter = builder.build();
+        let mut link = None;
+
+        while let Some(node) = iter.next_borrowed() {
+            let node = node?;
+            let block = Block::new(*node.cid, node.block.to_vec())?;
+            link = Some((*node.cid, node.total_size));
+            self.ipfs.repo().put_block(block).await?;
+        }
+
+        link.ok_or_else(|| anyhow!("no directory was created"))
+    }
+
+    /// Writes the file, returning its Cid and cumulative size.
+    async fn write_file(&self, content: &[u8]) -> Result<(Cid, u64), Error> {
+        let mut adder = FileAdder::default();
+        let mut blocks = Vec::new();
+
+        let mut written = 0;
+        while written < content.len() {
+            let (produced, consumed) = adder.push(&content[written..]);
+            blocks.extend(produced);
+            written += consumed;
+        }
+        blocks.extend(adder.finish());
+
+        let mut link = None;
+        let mut total_size = 0;
+        for (cid, block) in blocks {
+            total_size += block.len() as u64;
+            link = Some(cid);
+            self.ipfs.repo().put_block(Block::new(cid, block)?).await?;
+        }
+
+        let cid = link.ok_or_else(|| anyhow!("no file was created"))?;
+        Ok((cid, total_size))
+    }
+
+    async fn stat_cid(&self, cid: Cid) -> Result<FileStat, Error> {
+        let block = self.ipfs.repo().get_block(&cid, &[], false).await?;
+        let data = block.data();
+
+        if cid.codec() == u64::from(IpldCodec::Raw) {
+            return Ok(FileStat {
+                cid,
+                file_type: FileType::File,
+                size: data.len() as u64,
+                cumulative_size: data.len() as u64,
+                blocks: 0,
+            });
+        }
+
+        let node = libipld::pb::PbNode::from_bytes(data.to_vec().into())?;
+        let cumulative_size = data.len() as u64
+            + node
+                .links
+                .iter()
+                .map(|link| link.size.unwrap_or_default())
+                .sum::<u64>();
+
+        let (file_type, size) = match rust_unixfs::dir::list(data) {
+            Ok(_) => (FileType::Directory, 0),
+            Err(_) => {
+                let (_, size, _, _) = IdleFileVisit::default()
+                    .with_target_range(0..0)
+                    .start(data)?;
+                (FileType::File, size)
+            }
+        };
+
+        Ok(FileStat {
+            cid,
+            file_type,
+            size,
+            cumulative_size,
+            blocks: node.links.len(),
+        })
+    }
+}
+
+fn insert_new(
+    entries: &mut Entries,
+    name: &str,
+    link: (Cid, u64),
+    path: &str,
+) -> Result<(), Error> {
+    if entries.contains_key(name) {
+        return Err(anyhow!("{path} already exists"));
+    }
+    entries.insert(name.to_owned(), link);
+    Ok(())
+}
+
+/// Splits the absolute `path` into its segments.
+fn segments(path: &str) -> Result<Vec<&str>, Error> {
+    let relative = path
+        .strip_prefix('/')
+        .ok_or_else(|| anyhow!("{path:?} is not an absolute path"))?;
+
+    relative
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| match segment {
+            "." | ".." => Err(anyhow!("{path:?} cannot contain {segment:?}")),
+            segment => Ok(segment),
+        })
+        .collect()
+}
+
+/// Splits the absolute `path` into the segments of the parent directory and the last segment.
+fn split_last(path: &str) -> Result<(Vec<&str>, &str), Error> {
+    let mut segments = segments(path)?;
+    let last = segments
+        .pop()
+        .ok_or_else(|| anyhow!("cannot replace the root"))?;
+    Ok((segments, last))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FileType, WriteOption};
+
+    #[tokio::test]
+    async fn write_and_read_files() {
+        let ipfs = crate::Node::new("test_node").await;
+        let files = ipfs.files();
+
+        files.mkdir("/a/b", true).await.unwrap();
+        files
+            .write(
+                "/a/b/hello.txt",
+                &b"hello world"[..],
+                WriteOption {
+                    create: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        // overwrite in the middle and extend past the end
+        files
+            .write(
+                "/a/b/hello.txt",
+                &b"there, world!"[..],
+                WriteOption {
+                    offset: 6,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let stat = files.stat("/a/b/hello.txt").await.unwrap();
+        assert_eq!(stat.file_type, FileType::File);
+        assert_eq!(stat.size, 19);
+
+        let data = ipfs.cat_unixfs(stat.cid, None).await.unwrap();
+        assert_eq!(&data[..], b"hello there, world!");
+
+        files
+            .write(
+                "/a/b/hello.txt",
+                &b"bye"[..],
+                WriteOption {
+                    truncate: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(files.stat("/a/b/hello.txt").await.unwrap().size, 3);
+
+        // writing may extend the file but not start past its end
+        for offset in [4, u64::MAX] {
+            let write = WriteOption {
+                offset,
+                ..Default::default()
+            };
+            assert!(files
+                .write("/a/b/hello.txt", &b"x"[..], write)
+                .await
+                .is_err());
+        }
+
+        // without create, writing to a missing file fails
+        assert!(files
+            .write("/a/missing", &b"x"[..], WriteOption::default())
+            .await
+            .is_err());
+
+        let listed = files.ls("/a").await.unwrap();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].name, "b");
+        assert_eq!(listed[0].file_type, FileType::Directory);
+
+        assert_eq!(
+            files.stat("/a").await.unwrap().file_type,
+            FileType::Directory
+        );
+    }
+
+    #[tokio::test]
+    async fn move_copy_and_remove() {
+        let ipfs = crate::Node::new("test_node").await;
+        let files = ipfs.files();
+
+        let create = WriteOption {
+            create: true,
+            parents: true,
+            ..Default::default()
+        };
+        files.write("/docs/one", &b"1"[..], create).await.unwrap();
+        files.write("/docs/two", &b"2"[..], create).await.unwrap();
+
+        files.mv("/docs/one", "/first").await.unwrap();
+        files.mkdir("/archive", false).await.unwrap();
+        // moving onto a directory moves into it
+        files.mv("/docs", "/archive").await.unwrap();
+        assert!(files.mv("/archive", "/archive/docs/inside").await.is_err());
+
+        let names = |listed: Vec<super::FileEntry>| {
+            listed
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(files.ls("/").await.unwrap()), ["archive", "first"]);
+        assert_eq!(names(files.ls("/archive/docs").await.unwrap()), ["two"]);
+
+        // copying from an /ipfs/ path
+        let two = files.stat("/archive/docs/two").await.unwrap().cid;
+        files
+            .cp(&format!("/ipfs/{two}"), "/copies/two", true)
+            .await
+            .unwrap();
+        files.cp("/first", "/copies/first", false).await.unwrap();
+        assert!(files.cp("/first", "/copies/first", false).await.is_err());
+        assert_eq!(names(files.ls("/copies").await.unwrap()), ["first", "two"]);
+
+        assert!(files.rm("/archive", false).await.is_err());
+        files.rm("/archive", true).await.unwrap();
+        files.rm("/first", false).await.unwrap();
+        assert_eq!(names(files.ls("/").await.unwrap()), ["copies"]);
+        assert!(files.stat("/first").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn root_is_persisted_and_pinned() {
+        let ipfs = crate::Node::new("test_node").await;
+        let files = ipfs.files();
+
+        files
+            .write(
+                "/kept",
+                &b"kept"[..],
+                WriteOption {
+                    create: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        files.mkdir("/removed", false).await.unwrap();
+        files.rm("/removed", false).await.unwrap_err();
+        files.rm("/removed", true).await.unwrap();
+
+        let root = files.flush("/").await.unwrap();
+        assert!(ipfs.is_pinned(&root).await.unwrap());
+
+        let stored = ipfs.repo().data_store().get(super::ROOT_KEY).await.unwrap();
+        assert_eq!(stored, Some(root.to_bytes()));
+
+        // only the current root is pinned recursively, and collecting the garbage keeps the files
+        let pins = ipfs.list_pins(Some(crate::PinMode::Recursive)).await;
+        let pins = futures::TryStreamExt::try_collect::<Vec<_>>(pins)
+            .await
+            .unwrap();
+        assert_eq!(pins.len(), 1);
+
+        ipfs.repo().cleanup().await.unwrap();
+        let kept = files.stat("/kept").await.unwrap();
+        let data = ipfs.cat_unixfs(kept.cid, None).await.unwrap();
+        assert_eq!(&data[..], b"kept");
+    }
+
+    #[tokio::test]
+    async fn foreign_pins_are_not_removed() {
+        // blocks are collected right away instead of after the default two minutes
+        let ipfs = crate::UninitializedIpfsNoop::new()
+            .set_temp_pin_duration(std::time::Duration::ZERO)
+            .start()
+            .await
+            .unwrap();
+        let files = ipfs.files();
+
+        files.mkdir("/dir", false).await.unwrap();
+        let snapshot = files.flush("/").await.unwrap();
+        assert!(files.owns_pin(&snapshot).await.unwrap());
+
+        // taking over the pin of a snapshot keeps it pinned across changes
+        let metadata = crate::PinMetadata {
+            name: Some("snapshot".into()),
+            ..Default::default()
+        };
+        ipfs.set_pin_metadata(&snapshot, metadata).await.unwrap();
+
+        files.mkdir("/other", false).await.unwrap();
+        let changed = files.flush("/").await.unwrap();
+        assert_ne!(changed, snapshot);
+        assert!(files.owns_pin(&changed).await.unwrap());
+        assert!(!files.owns_pin(&snapshot).await.unwrap());
+
+        // returning to the snapshot root removes only the pin of the files
+        files.rm("/other", true).await.unwrap();
+        assert_eq!(files.flush("/").await.unwrap(), snapshot);
+        assert!(!ipfs.is_pinned(&changed).await.unwrap());
+
+        let metadata = ipfs.repo().pin_metadata(&snapshot).await.unwrap().unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("snapshot"));
+
+        // the files outlive the removal of the only pin of their root
+        ipfs.remove_pin(&snapshot).recursive().await.unwrap();
+        let removed = ipfs.repo().cleanup().await.unwrap();
+        assert!(removed.contains(&changed));
+        assert!(!removed.contains(&snapshot));
+        assert_eq!(files.ls("/").await.unwrap()[0].name, "dir");
+        files.mkdir("/dir/more", false).await.unwrap();
+    }
+}
@@ -1,7 +1,7 @@
 //! Adaptation for `ipfs-unixfs` crate functionality on top of [`crate::Ipfs`].
 //!
-//! Adding files and directory structures is supported but not exposed via an API. See examples and
-//! `ipfs-http`.
+//! Files and whole directory structures can be added with [`IpfsUnixfs::add`], which adds a
+//! directory recursively when given a path to one.
 
 use std::{ops::Range, path::PathBuf, time::Duration};
 
@@ -21,10 +21,15 @@ mod add;
 mod cat;
 mod get;
 mod ls;
-pub use add::{add, add_file, AddOption, UnixfsAdd};
-pub use cat::{cat, StartingPoint, UnixfsCat};
+pub(crate) mod mfs;
+mod tar;
+pub use add::{add, add_file, AddOption, IgnoreRules, UnixfsAdd};
+pub use cat::{cat, cat_reader, StartingPoint, UnixfsCat, UnixfsReader};
 pub use get::{get, UnixfsGet};
 pub use ls::{ls, NodeItem, UnixfsLs};
+pub(crate) use mfs::FilesRoot;
+pub use mfs::{FileEntry, FileStat, FileType, IpfsFiles, WriteOption};
+pub use tar::{tar, UnixfsTar};
 
 use crate::{
     dag::{ResolveError, UnexpectedResolved},
@@ -37,6 +42,8 @@ pub struct IpfsUnixfs {
 
 pub enum AddOpt<'a> {
     Path(PathBuf),
+    /// Path to a file, or a directory which is added leaving out the entries matching the rules.
+    PathWithIgnore(PathBuf, IgnoreRules),
     Stream(BoxStream<'a, std::io::Result<Bytes>>),
     StreamWithName(String, BoxStream<'a, std::io::Result<Bytes>>),
 }
@@ -148,13 +155,37 @@ impl IpfsUnixfs {
         )
     }
 
-    /// Add a file from either a file or stream
+    /// Creates a seekable reader over an UnixFS file, which only loads the blocks covering the
+    /// parts of the file being read.
+    pub async fn cat_reader(
+        &self,
+        starting_point: impl Into<StartingPoint>,
+        peers: &[PeerId],
+        local: bool,
+        timeout: Option<Duration>,
+    ) -> Result<UnixfsReader, TraversalFailed> {
+        cat_reader(
+            Either::Left(&self.ipfs),
+            starting_point,
+            peers,
+            local,
+            timeout,
+        )
+        .await
+    }
+
+    /// Add a file from either a file or stream, or a directory with everything under it.
     ///
     /// To create an owned version of the stream, please use `ipfs::unixfs::add` or `ipfs::unixfs::add_file` directly.
     pub fn add<'a, I: Into<AddOpt<'a>>>(&self, item: I, option: AddOption) -> UnixfsAdd<'a> {
         let item = item.into();
         match item {
             AddOpt::Path(path) => add_file(Either::Left(&self.ipfs), path, option),
+            AddOpt::PathWithIgnore(path, ignore) => add(
+                Either::Left(&self.ipfs),
+                add::AddOpt::FileWithIgnore { path, ignore },
+                option,
+            ),
             AddOpt::Stream(stream) => add(
                 Either::Left(&self.ipfs),
                 add::AddOpt::Stream {
@@ -176,7 +207,8 @@ impl IpfsUnixfs {
         }
     }
 
-    /// Retreive a file and saving it to a local path.
+    /// Retreive a file and saving it to a local path. A directory is recreated at the path with
+    /// all of its entries, including the symlinks and the mode and mtime metadata.
     ///
     /// To create an owned version of the stream, please use `ipfs::unixfs::get` directly.
     pub fn get<'a, P: AsRef<std::path::Path>>(
@@ -190,6 +222,20 @@ impl IpfsUnixfs {
         get(Either::Left(&self.ipfs), path, dest, peers, local, timeout)
     }
 
+    /// Creates a stream of a tar archive of the file or directory tree at the path, which can be
+    /// compressed with [`UnixfsTar::gzip`].
+    ///
+    /// To create an owned version of the stream, please use `ipfs::unixfs::tar` directly.
+    pub fn tar<'a>(
+        &self,
+        path: IpfsPath,
+        peers: &'a [PeerId],
+        local: bool,
+        timeout: Option<Duration>,
+    ) -> UnixfsTar<'a> {
+        tar(Either::Left(&self.ipfs), path, peers, local, timeout)
+    }
+
     /// List directory contents
     pub fn ls<'a>(
         &self,
@@ -243,6 +289,14 @@ pub enum TraversalFailed {
     #[error("walk failed on {}", .0)]
     Walking(Cid, #[source] FileReadFailed),
 
+    /// Walking the directory tree failed
+    #[error("walk failed on {}", .0)]
+    Traversing(Cid, #[source] ll::walk::Error),
+
+    /// An entry of the tree cannot be written into a tar archive
+    #[error("{}: {}", .0.display(), .1)]
+    Archiving(PathBuf, &'static str),
+
     #[error(transparent)]
     Io(std::io::Error),
 }
@@ -277,4 +331,547 @@ mod tests {
             "matches cid from go-ipfs 0.6.0"
         );
     }
+
+    #[tokio::test]
+    async fn add_and_cat_raw_leaves() {
+        use super::{AddOpt, AddOption};
+        use futures::StreamExt;
+        use rust_unixfs::file::adder::Chunker;
+
+        let ipfs = crate::Node::new("test_node").await;
+
+        let content = b"foobar\n";
+
+        for (chunk, expected) in [
+            (
+                Chunker::Size(256 * 1024),
+                Some("bafkreifoybygix7fh3r3g5rqle3wcnhqldgdg4shzf4k3ulyw3gn7mabt4"),
+            ),
+            (Chunker::Size(2), None),
+        ] {
+            let stream = futures::stream::once(async { Ok(bytes::Bytes::from_static(content)) });
+            let path = ipfs
+                .unixfs()
+                .add(
+                    AddOpt::Stream(stream.boxed()),
+                    AddOption {
+                        chunk,
+                        raw_leaves: true,
+                        ..Default::default()
+                    },
+                )
+                .await
+                .unwrap();
+
+            let cid = *path.root().cid().unwrap();
+            if let Some(expected) = expected {
+                assert_eq!(cid.to_string(), expected);
+            }
+
+            let data = ipfs
+                .unixfs()
+                .cat(path.clone(), None, &[], true, None)
+                .await
+                .unwrap();
+            assert_eq!(&data[..], content);
+
+            let data = ipfs
+                .unixfs()
+                .cat(path, Some(1..5), &[], true, None)
+                .await
+                .unwrap();
+            assert_eq!(&data[..], &content[1..5]);
+        }
+    }
+
+    #[tokio::test]
+    async fn add_and_cat_inlined() {
+        use super::{AddOpt, AddOption};
+        use futures::StreamExt;
+
+        let ipfs = crate::Node::new("test_node").await;
+
+        let content = b"foobar\n";
+        let stream = futures::stream::once(async { Ok(bytes::Bytes::from_static(content)) });
+        let path = ipfs
+            .unixfs()
+            .add(
+                AddOpt::Stream(stream.boxed()),
+                AddOption {
+                    inline: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let cid = *path.root().cid().unwrap();
+        assert_eq!(cid.hash().code(), rust_unixfs::IDENTITY_HASH_CODE);
+
+        // nothing was stored, yet the block resolves without going to the network
+        assert!(ipfs.repo().list_blocks().await.unwrap().is_empty());
+        assert!(ipfs.repo().contains(&cid).await.unwrap());
+
+        let data = ipfs
+            .unixfs()
+            .cat(path, None, &[], true, None)
+            .await
+            .unwrap();
+        assert_eq!(&data[..], content);
+    }
+
+    #[tokio::test]
+    async fn add_directory_with_cid_version_1() {
+        use super::{AddOpt, AddOption};
+        use libipld::multihash::Code;
+
+        let ipfs = crate::Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/file"), b"file\n").unwrap();
+
+        let options = AddOption {
+            cid_version: libipld::cid::Version::V1,
+            raw_leaves: true,
+            wrap: true,
+            ..Default::default()
+        };
+
+        let path = ipfs
+            .unixfs()
+            .add(AddOpt::Path(root.clone()), options)
+            .await
+            .unwrap();
+        let cid = path.root().cid().unwrap();
+        assert_eq!(cid.version(), libipld::cid::Version::V1);
+        assert_eq!(cid.codec(), 0x70);
+
+        let file = ipfs
+            .unixfs()
+            .cat(path.sub_path("sub/file").unwrap(), None, &[], true, None)
+            .await
+            .unwrap();
+        assert_eq!(&file[..], b"file\n");
+
+        let unsupported = ipfs
+            .unixfs()
+            .add(
+                AddOpt::Path(root),
+                AddOption {
+                    hash: Code::Sha2_512,
+                    ..options
+                },
+            )
+            .await;
+        assert!(unsupported.is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn add_directory_with_dangling_symlink() {
+        use super::{AddOpt, AddOption};
+
+        let ipfs = crate::Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("file"), b"file\n").unwrap();
+        std::os::unix::fs::symlink("file", root.join("link")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("dangling")).unwrap();
+
+        let path = ipfs
+            .unixfs()
+            .add(
+                AddOpt::Path(root),
+                AddOption {
+                    cid_version: libipld::cid::Version::V1,
+                    follow_symlinks: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let file = ipfs
+            .unixfs()
+            .cat(path.sub_path("link").unwrap(), None, &[], true, None)
+            .await
+            .unwrap();
+        assert_eq!(&file[..], b"file\n");
+
+        let root = ipfs.repo().get_block_now(path.root().cid().unwrap()).await;
+        let root = libipld::pb::PbNode::from_bytes(root.unwrap().unwrap().data().to_vec().into());
+        let dangling = root
+            .unwrap()
+            .links
+            .into_iter()
+            .find(|link| link.name.as_deref() == Some("dangling"))
+            .unwrap();
+        assert_eq!(dangling.cid.version(), libipld::cid::Version::V1);
+
+        let mut expected = vec![];
+        rust_unixfs::symlink::serialize_symlink_block("missing", &mut expected);
+        let block = ipfs.repo().get_block_now(&dangling.cid).await.unwrap();
+        assert_eq!(block.unwrap().data(), &expected[..]);
+    }
+
+    #[tokio::test]
+    async fn add_directory() {
+        use super::{AddOpt, AddOption, IgnoreRules};
+        use crate::IpfsPath;
+
+        let ipfs = crate::Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        std::fs::create_dir_all(root.join("sub/empty")).unwrap();
+        std::fs::write(root.join(".hidden"), b"hidden").unwrap();
+        std::fs::write(root.join("skipped.log"), b"ignored").unwrap();
+        std::fs::write(root.join("sub/nested.txt"), b"nested\n").unwrap();
+        for i in 0..20 {
+            std::fs::write(root.join(format!("file-{i}")), format!("{i}\n")).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("sub/nested.txt", root.join("link")).unwrap();
+
+        let path = ipfs
+            .unixfs()
+            .add(
+                AddOpt::PathWithIgnore(
+                    root,
+                    IgnoreRules {
+                        rules: vec!["*.log".into()],
+                        ..Default::default()
+                    },
+                ),
+                AddOption {
+                    shard_threshold: Some(8),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let cat = |path: IpfsPath| {
+            let ipfs = ipfs.clone();
+            async move { ipfs.unixfs().cat(path, None, &[], true, None).await }
+        };
+
+        let nested = cat(path.sub_path("sub/nested.txt").unwrap()).await.unwrap();
+        assert_eq!(&nested[..], b"nested\n");
+
+        for i in 0..20 {
+            let file = cat(path.sub_path(&format!("file-{i}")).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(file, format!("{i}\n").into_bytes());
+        }
+
+        assert!(cat(path.sub_path(".hidden").unwrap()).await.is_err());
+        assert!(cat(path.sub_path("skipped.log").unwrap()).await.is_err());
+
+        // the root has more entries than the threshold, so the links are prefixed with the buckets
+        let root = ipfs.repo().get_block_now(path.root().cid().unwrap()).await;
+        let root = libipld::pb::PbNode::from_bytes(root.unwrap().unwrap().data().to_vec().into());
+        let names = root
+            .unwrap()
+            .links
+            .into_iter()
+            .filter_map(|link| link.name)
+            .collect::<Vec<_>>();
+        assert!(names.iter().any(|name| name == "9Efile-0"));
+        assert!(!names.iter().any(|name| name == "file-0"));
+    }
+
+    #[tokio::test]
+    async fn get_directory() {
+        use super::{AddOpt, AddOption};
+
+        let ipfs = crate::Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        std::fs::create_dir_all(root.join("sub/empty")).unwrap();
+        std::fs::write(root.join("sub/nested.txt"), b"nested\n").unwrap();
+        for i in 0..20 {
+            std::fs::write(root.join(format!("file-{i}")), format!("{i}\n")).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("sub/nested.txt", root.join("link")).unwrap();
+
+        let mtime = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(root.join("sub/nested.txt"), mtime).unwrap();
+        filetime::set_file_mtime(root.join("sub"), mtime).unwrap();
+
+        let path = ipfs
+            .unixfs()
+            .add(
+                AddOpt::Path(root),
+                AddOption {
+                    shard_threshold: Some(8),
+                    preserve_mode: true,
+                    preserve_mtime: true,
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let dest = tmp.path().join("dest");
+        ipfs.unixfs()
+            .get(path.clone(), &dest, &[], true, None)
+            .await
+            .unwrap();
+
+        let single = tmp.path().join("single.txt");
+        ipfs.unixfs()
+            .get(path.sub_path("file-1").unwrap(), &single, &[], true, None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(single).unwrap(), b"1\n");
+
+        assert_eq!(
+            std::fs::read(dest.join("sub/nested.txt")).unwrap(),
+            b"nested\n"
+        );
+        assert!(dest.join("sub/empty").is_dir());
+        for i in 0..20 {
+            let file = std::fs::read(dest.join(format!("file-{i}"))).unwrap();
+            assert_eq!(file, format!("{i}\n").into_bytes());
+        }
+
+        #[cfg(unix)]
+        assert_eq!(
+            std::fs::read_link(dest.join("link")).unwrap(),
+            std::path::Path::new("sub/nested.txt")
+        );
+
+        for entry in ["sub", "sub/nested.txt"] {
+            let metadata = std::fs::metadata(dest.join(entry)).unwrap();
+            assert_eq!(
+                filetime::FileTime::from_last_modification_time(&metadata),
+                mtime
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn get_refuses_duplicate_names() {
+        use libipld::multihash::{Code, MultihashDigest};
+        use libipld::pb::{PbLink, PbNode};
+
+        let ipfs = crate::Node::new("test_node").await;
+        let tmp = tempfile::tempdir().unwrap();
+        let outside = tmp.path().join("outside");
+        std::fs::create_dir(&outside).unwrap();
+
+        let put = |data: Vec<u8>| {
+            let ipfs = ipfs.clone();
+            async move {
+                let cid = libipld::Cid::new_v0(Code::Sha2_256.digest(&data)).unwrap();
+                ipfs.put_block(crate::Block::new(cid, data).unwrap())
+                    .await
+                    .unwrap()
+            }
+        };
+        let directory = |links: Vec<(&str, libipld::Cid)>| PbNode {
+            links: links
+                .into_iter()
+                .map(|(name, cid)| PbLink {
+                    cid,
+                    name: Some(name.into()),
+                    size: None,
+                })
+                .collect(),
+            data: Some(bytes::Bytes::from_static(&[0x08, 0x01])),
+        };
+
+        let mut symlink = Vec::new();
+        rust_unixfs::symlink::serialize_symlink_block(outside.to_str().unwrap(), &mut symlink);
+        let symlink = put(symlink).await;
+
+        let mut adder = rust_unixfs::file::adder::FileAdder::default();
+        let (blocks, _) = adder.push(b"payload");
+        assert_eq!(blocks.count(), 0);
+        let (file, block) = adder.finish().next().unwrap();
+        put(block).await;
+
+        // a symlink and then a file of the same name
+        let root = put(directory(vec![("a", symlink), ("a", file)])
+            .into_bytes()
+            .into())
+        .await;
+        let dest = tmp.path().join("dest");
+        let error = ipfs
+            .unixfs()
+            .get(crate::IpfsPath::from(root), &dest, &[], true, None)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("duplicate path"), "{error}");
+
+        // a symlink and then a directory of the same name
+        let inner = put(directory(vec![("x", file)]).into_bytes().into()).await;
+        let root = put(directory(vec![("a", symlink), ("a", inner)])
+            .into_bytes()
+            .into())
+        .await;
+        let dest = tmp.path().join("dest-dir");
+        let error = ipfs
+            .unixfs()
+            .get(crate::IpfsPath::from(root), &dest, &[], true, None)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("duplicate path"), "{error}");
+
+        // a symlink already in the destination
+        let root = put(directory(vec![("a", inner)]).into_bytes().into()).await;
+        let dest = tmp.path().join("dest-existing");
+        std::fs::create_dir(&dest).unwrap();
+        std::os::unix::fs::symlink(&outside, dest.join("a")).unwrap();
+        let error = ipfs
+            .unixfs()
+            .get(crate::IpfsPath::from(root), &dest, &[], true, None)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("through symlink"), "{error}");
+
+        assert_eq!(std::fs::read_dir(&outside).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn tar_directory() {
+        use super::{AddOpt, AddOption};
+        use std::io::Read;
+
+        let ipfs = crate::Node::new("test_node").await;
+
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path().join("root");
+        let long = "long-".repeat(30);
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("sub/nested.txt"), b"nested\n").unwrap();
+        std::fs::write(root.join(&long), vec![1u8; 1000]).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("sub/nested.txt", root.join("link")).unwrap();
+
+        let path = ipfs
+            .unixfs()
+            .add(AddOpt::Path(root), AddOption::default())
+            .await
+            .unwrap();
+        let cid = path.root().cid().copied().unwrap();
+
+        let archive = ipfs
+            .unixfs()
+            .tar(path.clone(), &[], true, None)
+            .await
+            .unwrap();
+
+        let mut entries = std::collections::BTreeMap::new();
+        for entry in tar::Archive::new(&archive[..]).entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().into_owned();
+            let kind = entry.header().entry_type();
+            let link = entry
+                .link_name()
+                .unwrap()
+                .map(|link| link.to_string_lossy().into_owned());
+            let mut data = vec![];
+            entry.read_to_end(&mut data).unwrap();
+            entries.insert(name, (kind, link, data));
+        }
+
+        let entry = |name: &str| &entries[&format!("{cid}/{name}")];
+        assert_eq!(entries[&cid.to_string()].0, tar::EntryType::Directory);
+        assert_eq!(entry("sub").0, tar::EntryType::Directory);
+        assert_eq!(entry("sub/nested.txt").2, b"nested\n");
+        assert_eq!(entry(&long).2, vec![1u8; 1000]);
+        #[cfg(unix)]
+        assert_eq!(
+            entry("link").1.as_deref(),
+            Some("sub/nested.txt"),
+            "{entries:?}"
+        );
+
+        let compressed = ipfs
+            .unixfs()
+            .tar(path, &[], true, None)
+            .gzip(6)
+            .await
+            .unwrap();
+
+        let mut decompressed = vec![];
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, archive);
+    }
+
+    #[tokio::test]
+    async fn seek_and_read() {
+        use super::{AddOpt, AddOption};
+        use futures::StreamExt;
+        use rust_unixfs::file::adder::Chunker;
+        use std::io::SeekFrom;
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+        let ipfs = crate::Node::new("test_node").await;
+
+        let content = (0..100_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let data = bytes::Bytes::from(content.clone());
+        let stream = futures::stream::once(async move { Ok(data) });
+        let path = ipfs
+            .unixfs()
+            .add(
+                AddOpt::Stream(stream.boxed()),
+                AddOption {
+                    chunk: Chunker::Size(1000),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let cid = *path.root().cid().unwrap();
+
+        // blocks before the range are not needed, so remove the first leaf
+        let root = ipfs.repo().get_block_now(&cid).await.unwrap().unwrap();
+        let first = libipld::pb::PbNode::from_bytes(root.data().to_vec().into())
+            .unwrap()
+            .links[0]
+            .cid;
+        ipfs.repo().remove_block(&first, false).await.unwrap();
+
+        let mut reader = ipfs
+            .unixfs()
+            .cat_reader(cid, &[], true, None)
+            .await
+            .unwrap();
+        assert_eq!(reader.size(), content.len() as u64);
+
+        let mut buf = vec![0; 4000];
+        reader.seek(SeekFrom::Start(50_500)).await.unwrap();
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, content[50_500..54_500]);
+
+        // a short seek forward continues with the loaded bytes
+        reader.seek(SeekFrom::Current(10)).await.unwrap();
+        reader.read_exact(&mut buf[..100]).await.unwrap();
+        assert_eq!(buf[..100], content[54_510..54_610]);
+
+        reader.seek(SeekFrom::End(-500)).await.unwrap();
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, content[99_500..]);
+
+        // seeking back to the removed block fails to read it
+        reader.seek(SeekFrom::Start(10)).await.unwrap();
+        assert!(reader.read_exact(&mut buf[..10]).await.is_err());
+
+        assert!(reader.seek(SeekFrom::Current(-20)).await.is_err());
+    }
 }
@@ -0,0 +1,536 @@
+//! Tar archives of UnixFS trees, like `ipfs get --archive`. The archive is produced while walking
+//! the tree, so nothing is written to the local disk.
+//!
+//! The tar helper was originally taken and modified from the dependency version of `tar-rs`. The
+//! most important copied parts are related to the long file name and long link name support.
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use bytes::{buf::BufMut, Bytes, BytesMut};
+use either::Either;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::{future::BoxFuture, stream::BoxStream, FutureExt, Stream, StreamExt, TryStreamExt};
+use libp2p::PeerId;
+use rust_unixfs::walk::{ContinuedWalk, Walker};
+use rust_unixfs::Metadata;
+use tar::{EntryType, Header};
+use tracing::{Instrument, Span};
+
+use crate::{dag::IpldDag, repo::Repo, Ipfs, IpfsPath};
+
+use super::TraversalFailed;
+
+/// Creates a stream of the bytes of a tar archive of the file, directory or symlink at the path.
+/// Like with `ipfs get`, the entries of the archive are under a directory named after the Cid of
+/// the resolved root.
+///
+/// The archive can be compressed with [`UnixfsTar::gzip`].
+pub fn tar<'a>(
+    which: Either<&Ipfs, &Repo>,
+    path: IpfsPath,
+    providers: &'a [PeerId],
+    local_only: bool,
+    timeout: Option<Duration>,
+) -> UnixfsTar<'a> {
+    let (repo, dag, session) = match which {
+        Either::Left(ipfs) => (
+            ipfs.repo().clone(),
+            ipfs.dag(),
+            Some(crate::BITSWAP_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst)),
+        ),
+        Either::Right(repo) => {
+            let session = repo
+                .is_online()
+                .then(|| crate::BITSWAP_ID.fetch_add(1, std::sync::atomic::Ordering::SeqCst));
+            (repo.clone(), IpldDag::from(repo.clone()), session)
+        }
+    };
+
+    let stream = async_stream::try_stream! {
+        let (resolved, _) = dag
+            .resolve_with_session(session, path, true, providers, local_only, timeout)
+            .await
+            .map_err(TraversalFailed::Resolving)?;
+
+        let block = resolved.into_unixfs_block().map_err(TraversalFailed::Path)?;
+
+        let root = *block.cid();
+
+        let mut cache = None;
+        let mut tar_helper = TarHelper::with_capacity(16 * 1024);
+
+        // the entries are put under the root named after the cid, like go-ipfs does
+        let mut walker = Walker::new(root, root.to_string());
+
+        while walker.should_continue() {
+            let (next, _) = walker.pending_links();
+            let next = *next;
+
+            let block = repo
+                .get_block_with_session(session, &next, providers, local_only, timeout)
+                .await
+                .map_err(|e| TraversalFailed::Loading(next, e))?;
+
+            let item = walker
+                .next(block.data(), &mut cache)
+                .map_err(|e| TraversalFailed::Traversing(next, e))?;
+
+            match item {
+                ContinuedWalk::Bucket(..) => {}
+                ContinuedWalk::File(segment, _, path, metadata, size) => {
+                    if segment.is_first() {
+                        let headers = tar_helper
+                            .apply_file(path, metadata, size)
+                            .map_err(|reason| TraversalFailed::Archiving(path.into(), reason))?;
+
+                        for bytes in headers.into_iter().flatten() {
+                            yield bytes;
+                        }
+                    }
+
+                    // even if the largest of files can have 256 kB blocks and about the same
+                    // amount of content, try to consume it in small parts not to grow the buffers
+                    // too much.
+
+                    let mut n = 0usize;
+                    let slice = segment.as_ref();
+                    let total = slice.len();
+
+                    while n < total {
+                        let next = tar_helper.buffer_file_contents(&slice[n..]);
+                        n += next.len();
+                        yield next;
+                    }
+
+                    if segment.is_last() {
+                        if let Some(zeroes) = tar_helper.pad(size) {
+                            yield zeroes;
+                        }
+                    }
+                }
+                ContinuedWalk::Directory(_, path, metadata)
+                | ContinuedWalk::RootDirectory(_, path, metadata) => {
+                    let headers = tar_helper
+                        .apply_directory(path, metadata)
+                        .map_err(|reason| TraversalFailed::Archiving(path.into(), reason))?;
+
+                    for bytes in headers.into_iter().flatten() {
+                        yield bytes;
+                    }
+                }
+                ContinuedWalk::Symlink(bytes, _, path, metadata) => {
+                    // converting a symlink is the most tricky part
+                    let target = std::str::from_utf8(bytes).map_err(|_| {
+                        TraversalFailed::Archiving(path.into(), "symlink target is not utf-8")
+                    })?;
+                    let target = Path::new(target);
+
+                    let headers = tar_helper
+                        .apply_symlink(path, target, metadata)
+                        .map_err(|reason| TraversalFailed::Archiving(path.into(), reason))?;
+
+                    for bytes in headers.into_iter().flatten() {
+                        yield bytes;
+                    }
+                }
+            };
+        }
+
+        // the archive ends with two zeroed records
+        yield tar_helper.end();
+    };
+
+    UnixfsTar {
+        stream: stream.boxed(),
+        encoder: None,
+        span: None,
+    }
+}
+
+pub struct UnixfsTar<'a> {
+    stream: BoxStream<'a, Result<Bytes, TraversalFailed>>,
+    encoder: Option<GzEncoder<Vec<u8>>>,
+    span: Option<Span>,
+}
+
+impl<'a> UnixfsTar<'a> {
+    /// Compresses the archive with gzip, producing a `.tar.gz`. The level is from 0 (none) to 9
+    /// (best).
+    pub fn gzip(mut self, level: u32) -> Self {
+        self.encoder = Some(GzEncoder::new(Vec::new(), Compression::new(level.min(9))));
+        self
+    }
+
+    pub fn span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+}
+
+impl<'a> Stream for UnixfsTar<'a> {
+    type Item = Result<Bytes, TraversalFailed>;
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        use std::task::Poll;
+
+        let this = &mut *self;
+
+        let Some(encoder) = this.encoder.as_mut() else {
+            return this.stream.poll_next_unpin(cx);
+        };
+
+        loop {
+            match futures::ready!(this.stream.poll_next_unpin(cx)) {
+                Some(Ok(bytes)) => {
+                    if let Err(e) = encoder.write_all(&bytes) {
+                        return Poll::Ready(Some(Err(TraversalFailed::Io(e))));
+                    }
+
+                    // the encoder buffers internally, so keep going until it has output
+                    let compressed = std::mem::take(encoder.get_mut());
+                    if !compressed.is_empty() {
+                        return Poll::Ready(Some(Ok(compressed.into())));
+                    }
+                }
+                Some(Err(e)) => {
+                    // the archive cannot be completed so the compression ends here as well
+                    this.encoder = None;
+                    return Poll::Ready(Some(Err(e)));
+                }
+                None => {
+                    let encoder = this.encoder.take().expect("encoder exists");
+                    return Poll::Ready(match encoder.finish() {
+                        Ok(compressed) => Some(Ok(compressed.into())),
+                        Err(e) => Some(Err(TraversalFailed::Io(e))),
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl<'a> std::future::IntoFuture for UnixfsTar<'a> {
+    type Output = Result<Bytes, TraversalFailed>;
+
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(mut self) -> Self::IntoFuture {
+        let span = self.span.take().unwrap_or(Span::current());
+        async move {
+            let mut data = vec![];
+            while let Some(bytes) = self.try_next().await? {
+                data.extend(bytes);
+            }
+            Ok(data.into())
+        }
+        .instrument(span)
+        .boxed()
+    }
+}
+
+/// Tar helper uses some private parts of the `tar-rs` crate to append the headers and the
+/// contents to a `bytes::BytesMut` instead of using the `std::io` interfaces.
+struct TarHelper {
+    bufsize: usize,
+    bytes: BytesMut,
+    header: Header,
+    long_filename_header: Header,
+    zeroes: Bytes,
+}
+
+impl TarHelper {
+    fn with_capacity(n: usize) -> Self {
+        let bytes = BytesMut::with_capacity(n);
+
+        // these are 512 a piece
+        let header = Self::new_default_header();
+        let long_filename_header = Self::new_long_filename_header();
+        let zeroes = Bytes::from(vec![0u8; 1024]);
+
+        Self {
+            bufsize: n,
+            bytes,
+            header,
+            long_filename_header,
+            zeroes,
+        }
+    }
+
+    fn new_default_header() -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+
+        header
+    }
+
+    fn new_long_filename_header() -> tar::Header {
+        let mut long_filename_header = tar::Header::new_gnu();
+        long_filename_header.set_mode(0o644);
+
+        {
+            let name = b"././@LongLink";
+            let gnu_header = long_filename_header.as_gnu_mut().unwrap();
+            // since we are reusing the header, zero out all of the bytes
+            gnu_header.name = [0; 100];
+            gnu_header.name[..name.len()].copy_from_slice(name);
+        }
+
+        long_filename_header.set_mtime(0);
+        long_filename_header.set_uid(0);
+        long_filename_header.set_gid(0);
+
+        long_filename_header
+    }
+
+    fn apply_file(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+        total_size: u64,
+    ) -> Result<[Option<Bytes>; 3], &'static str> {
+        let mut ret: [Option<Bytes>; 3] = Default::default();
+
+        if let Err(e) = self.header.set_path(path) {
+            let data =
+                prepare_long_header(&mut self.header, &mut self.long_filename_header, path, e)?;
+
+            self.bytes.put_slice(self.long_filename_header.as_bytes());
+            self.bytes.put_slice(data);
+            self.bytes.put_u8(0);
+            ret[0] = Some(self.bytes.split().freeze());
+
+            ret[1] = self.pad(data.len() as u64 + 1);
+        }
+
+        self.header.set_size(total_size);
+        self.header.set_entry_type(EntryType::Regular);
+        Self::set_metadata(&mut self.header, metadata, 0o0644);
+        self.header.set_cksum();
+
+        self.bytes.put_slice(self.header.as_bytes());
+
+        ret[2] = Some(self.bytes.split().freeze());
+        Ok(ret)
+    }
+
+    fn buffer_file_contents(&mut self, contents: &[u8]) -> Bytes {
+        assert!(!contents.is_empty());
+        let remaining = contents.len();
+        let taken = self.bufsize.min(remaining);
+
+        self.bytes.put_slice(&contents[..taken]);
+        self.bytes.split().freeze()
+    }
+
+    fn apply_directory(
+        &mut self,
+        path: &Path,
+        metadata: &Metadata,
+    ) -> Result<[Option<Bytes>; 3], &'static str> {
+        let mut ret: [Option<Bytes>; 3] = Default::default();
+
+        if let Err(e) = self.header.set_path(path) {
+            let data =
+                prepare_long_header(&mut self.header, &mut self.long_filename_header, path, e)?;
+
+            self.bytes.put_slice(self.long_filename_header.as_bytes());
+            self.bytes.put_slice(data);
+            self.bytes.put_u8(0);
+            ret[0] = Some(self.bytes.split().freeze());
+            ret[1] = self.pad(data.len() as u64 + 1);
+        }
+
+        self.header.set_size(0);
+        self.header.set_entry_type(EntryType::Directory);
+        Self::set_metadata(&mut self.header, metadata, 0o0755);
+
+        self.header.set_cksum();
+        self.bytes.put_slice(self.header.as_bytes());
+
+        ret[2] = Some(self.bytes.split().freeze());
+
+        Ok(ret)
+    }
+
+    fn apply_symlink(
+        &mut self,
+        path: &Path,
+        target: &Path,
+        metadata: &Metadata,
+    ) -> Result<[Option<Bytes>; 5], &'static str> {
+        let mut ret: [Option<Bytes>; 5] = Default::default();
+
+        if let Err(e) = self.header.set_path(path) {
+            let data =
+                prepare_long_header(&mut self.header, &mut self.long_filename_header, path, e)?;
+
+            self.bytes.put_slice(self.long_filename_header.as_bytes());
+            self.bytes.put_slice(data);
+            self.bytes.put_u8(0);
+            ret[0] = Some(self.bytes.split().freeze());
+
+            ret[1] = self.pad(data.len() as u64 + 1);
+        }
+
+        if self.header.set_link_name(target).is_err() {
+            let data = path2bytes(target);
+
+            if data.len() < self.header.as_old().linkname.len() {
+                return Err("symlink target cannot be put inside tar");
+            }
+
+            // this is another long header trick, but this time we have a different entry type and
+            // similarly the long file name is written as a separate entry with its own headers.
+
+            self.long_filename_header.set_size(data.len() as u64 + 1);
+            self.long_filename_header
+                .set_entry_type(tar::EntryType::new(b'K'));
+            self.long_filename_header.set_cksum();
+
+            self.bytes.put_slice(self.long_filename_header.as_bytes());
+            self.bytes.put_slice(data);
+            self.bytes.put_u8(0);
+            ret[2] = Some(self.bytes.split().freeze());
+
+            ret[3] = self.pad(data.len() as u64 + 1);
+        }
+
+        Self::set_metadata(&mut self.header, metadata, 0o0644);
+        self.header.set_size(0);
+        self.header.set_entry_type(tar::EntryType::Symlink);
+        self.header.set_cksum();
+
+        self.bytes.put_slice(self.header.as_bytes());
+        ret[4] = Some(self.bytes.split().freeze());
+
+        // the link name is not overwritten by the following entries
+        self.header = Self::new_default_header();
+
+        Ok(ret)
+    }
+
+    /// Content in tar is padded to 512 byte sectors which might be configurable as well.
+    fn pad(&self, total_size: u64) -> Option<Bytes> {
+        let padding = 512 - (total_size % 512);
+        if padding < 512 {
+            Some(self.zeroes.slice(..padding as usize))
+        } else {
+            None
+        }
+    }
+
+    /// The two zeroed records ending the archive.
+    fn end(&self) -> Bytes {
+        self.zeroes.clone()
+    }
+
+    fn set_metadata(header: &mut tar::Header, metadata: &Metadata, default_mode: u32) {
+        header.set_mode(
+            metadata
+                .mode()
+                .map(|mode| mode & 0o7777)
+                .unwrap_or(default_mode),
+        );
+
+        header.set_mtime(
+            metadata
+                .mtime()
+                .and_then(|(seconds, _)| u64::try_from(seconds).ok())
+                .unwrap_or(0),
+        );
+    }
+}
+
+/// Returns the raw bytes we need to write as a new entry into the tar.
+fn prepare_long_header<'a>(
+    header: &mut tar::Header,
+    long_filename_header: &mut tar::Header,
+    path: &'a Path,
+    _error: std::io::Error,
+) -> Result<&'a [u8], &'static str> {
+    #[cfg(unix)]
+    /// On unix this operation can never fail.
+    fn bytes2path(bytes: Cow<[u8]>) -> std::io::Result<Cow<Path>> {
+        use std::ffi::{OsStr, OsString};
+        use std::os::unix::prelude::*;
+
+        Ok(match bytes {
+            Cow::Borrowed(bytes) => Cow::Borrowed(Path::new(OsStr::from_bytes(bytes))),
+            Cow::Owned(bytes) => Cow::Owned(PathBuf::from(OsString::from_vec(bytes))),
+        })
+    }
+
+    #[cfg(windows)]
+    /// On windows we cannot accept non-Unicode bytes because it
+    /// is impossible to convert it to UTF-16.
+    fn bytes2path(bytes: Cow<[u8]>) -> std::io::Result<Cow<Path>> {
+        match bytes {
+            Cow::Borrowed(bytes) => {
+                let s = std::str::from_utf8(bytes).map_err(|_| not_unicode(bytes))?;
+                Ok(Cow::Borrowed(Path::new(s)))
+            }
+            Cow::Owned(bytes) => {
+                let s = String::from_utf8(bytes).map_err(|uerr| not_unicode(&uerr.into_bytes()))?;
+                Ok(Cow::Owned(PathBuf::from(s)))
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    fn not_unicode(v: &[u8]) -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::Other,
+            format!(
+                "only Unicode paths are supported on Windows: {}",
+                String::from_utf8_lossy(v)
+            ),
+        )
+    }
+
+    // we **only** have utf8 paths as protobuf has already parsed this file
+    // name and all of the previous ones as utf8.
+
+    let data = path2bytes(path);
+
+    let max = header.as_old().name.len();
+
+    if data.len() < max {
+        return Err("path cannot be put inside tar");
+    }
+
+    // the plus one is documented as compliance with GNU tar, probably the null byte
+    // termination?
+    long_filename_header.set_size(data.len() as u64 + 1);
+    long_filename_header.set_entry_type(tar::EntryType::new(b'L'));
+    long_filename_header.set_cksum();
+
+    // we still need to figure out the truncated path we put into the header
+    let path = bytes2path(Cow::Borrowed(&data[..max]))
+        .expect("quite certain we have no non-utf8 paths here");
+    header
+        .set_path(&path)
+        .expect("we already made sure the path is of fitting length");
+
+    Ok(data)
+}
+
+#[cfg(unix)]
+fn path2bytes(p: &Path) -> &[u8] {
+    use std::os::unix::prelude::*;
+    p.as_os_str().as_bytes()
+}
+
+#[cfg(windows)]
+fn path2bytes(p: &Path) -> &[u8] {
+    p.as_os_str()
+        .to_str()
+        .expect("we should only have unicode compatible bytes even on windows")
+        .as_bytes()
+}
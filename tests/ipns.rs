@@ -0,0 +1,71 @@
+use std::time::Duration;
+
+use libipld::{
+    multihash::{Code, MultihashDigest},
+    Cid, IpldCodec,
+};
+use rust_ipfs::{ipns::IpnsOption, IpfsPath};
+
+mod common;
+use common::{spawn_nodes, Topology};
+
+fn path_of(data: &[u8]) -> IpfsPath {
+    IpfsPath::from(Cid::new_v1(
+        IpldCodec::Raw.into(),
+        Code::Sha2_256.digest(data),
+    ))
+}
+
+#[tokio::test]
+async fn publish_and_resolve_over_pubsub() {
+    let nodes = spawn_nodes::<2>(Topology::Line).await;
+    let (a, b) = (&nodes[0], &nodes[1]);
+
+    let name = IpfsPath::from(a.id);
+    let first = path_of(b"first");
+
+    // published before anyone else is subscribed, so b has to fetch it after joining the topic
+    a.ipns()
+        .publish(None, &first, Some(IpnsOption::PubSub))
+        .await
+        .unwrap();
+
+    let resolved = tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            if let Ok(path) = b
+                .ipns()
+                .resolve_with_option(&name, IpnsOption::PubSub)
+                .await
+            {
+                break path;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("record fetched from the publisher");
+    assert_eq!(resolved, first);
+
+    // updates are then pushed over the topic
+    let second = path_of(b"second");
+    a.ipns()
+        .publish(None, &second, Some(IpnsOption::PubSub))
+        .await
+        .unwrap();
+
+    tokio::time::timeout(Duration::from_secs(10), async {
+        loop {
+            let path = b
+                .ipns()
+                .resolve_with_option(&name, IpnsOption::PubSub)
+                .await
+                .unwrap();
+            if path == second {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("update received over pubsub");
+}
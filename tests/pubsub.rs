@@ -1,6 +1,8 @@
 use futures::future::pending;
 use futures::stream::StreamExt;
-use rust_ipfs::Node;
+use libp2p::multiaddr::Protocol;
+use rust_ipfs::p2p::{PubsubConfig, PubsubMessageId, PubsubScoring};
+use rust_ipfs::{Ipfs, Node, UninitializedIpfsNoop};
 use std::time::Duration;
 use tokio::time::timeout;
 
@@ -349,3 +351,179 @@ async fn pubsub_interop() {
         "timed out before both nodes appeared as pubsub peers"
     );
 }
+
+#[tokio::test]
+async fn validator_filters_forwarded_messages() {
+    use libp2p::gossipsub::MessageAcceptance;
+
+    // a > b > c, with b relaying the messages of a to c
+    let nodes = spawn_nodes::<3>(Topology::Line).await;
+    let (a, b, c) = (&nodes[0], &nodes[1], &nodes[2]);
+    let topic = "validated".to_owned();
+
+    let _a_msgs = a.pubsub_subscribe(topic.clone()).await.unwrap();
+    let mut b_msgs = b
+        .pubsub_subscribe_with_validator(topic.clone(), |_, message| async move {
+            match message.data.starts_with(b"spam") {
+                true => MessageAcceptance::Reject,
+                false => MessageAcceptance::Accept,
+            }
+        })
+        .await
+        .unwrap();
+    let mut c_msgs = c.pubsub_subscribe(topic.clone()).await.unwrap();
+
+    // keep publishing until the mesh is formed and b relays to c
+    timeout(Duration::from_secs(20), async {
+        loop {
+            // fails until a has seen the subscription of b
+            let _ = a.pubsub_publish(topic.clone(), b"hello".to_vec()).await;
+            if let Ok(Some(message)) = timeout(Duration::from_millis(500), c_msgs.next()).await {
+                assert_eq!(message.data, b"hello");
+                break;
+            }
+        }
+    })
+    .await
+    .expect("message relayed by b");
+
+    a.pubsub_publish(topic.clone(), b"spam".to_vec())
+        .await
+        .unwrap();
+    a.pubsub_publish(topic.clone(), b"bye".to_vec())
+        .await
+        .unwrap();
+
+    let message = timeout(Duration::from_secs(5), c_msgs.next())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(message.data, b"bye");
+    assert!(timeout(Duration::from_millis(500), c_msgs.next())
+        .await
+        .is_err());
+
+    // b only saw the accepted messages
+    let mut seen = vec![];
+    while let Ok(Some(message)) = timeout(Duration::from_millis(200), b_msgs.next()).await {
+        seen.push(message.data);
+    }
+    assert!(!seen.is_empty());
+    assert!(seen.iter().all(|data| data != b"spam"));
+    assert_eq!(seen.last().unwrap(), b"bye");
+}
+
+async fn pubsub_node(config: PubsubConfig) -> (Ipfs, libp2p::Multiaddr) {
+    let ipfs = UninitializedIpfsNoop::new()
+        .with_pubsub(config)
+        .start()
+        .await
+        .unwrap();
+    let addr = ipfs
+        .add_listening_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+        .await
+        .unwrap()
+        .with(Protocol::P2p(ipfs.keypair().public().to_peer_id()));
+    (ipfs, addr)
+}
+
+#[tokio::test]
+async fn peer_scores() {
+    use libp2p::gossipsub::{PeerScoreParams, TopicScoreParams};
+
+    let config = || PubsubConfig {
+        scoring: Some(
+            PubsubScoring {
+                params: PeerScoreParams {
+                    app_specific_weight: 1.0,
+                    ..Default::default()
+                },
+                thresholds: Default::default(),
+            }
+            .with_topic("scored", TopicScoreParams::default()),
+        ),
+        ..Default::default()
+    };
+
+    let (a, _) = pubsub_node(config()).await;
+    let (b, b_addr) = pubsub_node(config()).await;
+    let b_id = b.keypair().public().to_peer_id();
+
+    a.connect(b_addr).await.unwrap();
+    let _a_msgs = a.pubsub_subscribe("scored").await.unwrap();
+    let _b_msgs = b.pubsub_subscribe("scored").await.unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        while !a
+            .pubsub_peer_scores()
+            .await
+            .unwrap()
+            .iter()
+            .any(|(peer_id, _)| *peer_id == b_id)
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("b is scored by a");
+
+    assert!(a.pubsub_set_application_score(b_id, 10.0).await.unwrap());
+    let scores = a.pubsub_peer_scores().await.unwrap();
+    let (_, score) = scores.iter().find(|(peer_id, _)| *peer_id == b_id).unwrap();
+    assert!(
+        *score >= 10.0,
+        "score {score} includes the application score"
+    );
+
+    // scoring is disabled by default
+    let c = Node::new("test_node").await;
+    assert!(c.pubsub_peer_scores().await.unwrap().is_empty());
+    assert!(!c.pubsub_set_application_score(b_id, 10.0).await.unwrap());
+}
+
+#[tokio::test]
+async fn content_addressed_message_ids() {
+    let config = PubsubConfig {
+        message_id: PubsubMessageId::ContentAddressed,
+        ..Default::default()
+    };
+
+    let (a, _) = pubsub_node(config.clone()).await;
+    let (b, b_addr) = pubsub_node(config).await;
+    let b_id = b.keypair().public().to_peer_id();
+
+    a.connect(b_addr).await.unwrap();
+    let _a_msgs = a.pubsub_subscribe("content").await.unwrap();
+    let mut b_msgs = b.pubsub_subscribe("content").await.unwrap();
+
+    timeout(Duration::from_secs(10), async {
+        while !a
+            .pubsub_peers(Some("content".into()))
+            .await
+            .unwrap()
+            .contains(&b_id)
+        {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("b subscribed");
+
+    // the same data is a duplicate even with a different sequence number
+    let first = a.pubsub_publish("content", b"same".to_vec()).await.unwrap();
+    assert!(a.pubsub_publish("content", b"same".to_vec()).await.is_err());
+    let other = a
+        .pubsub_publish("content", b"other".to_vec())
+        .await
+        .unwrap();
+    assert_ne!(first, other);
+
+    let received = timeout(
+        Duration::from_secs(5),
+        b_msgs.by_ref().take(2).collect::<Vec<_>>(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(received[0].data, b"same");
+    assert_eq!(received[1].data, b"other");
+}
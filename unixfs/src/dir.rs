@@ -77,6 +77,53 @@ pub fn resolve<'needle>(
     }
 }
 
+/// Lists the links of a single block of a `dag-pb` or UnixFS directory (normal, sharded).
+///
+/// For a HAMT sharded directory, the bucket index prefixes are removed from the names of the
+/// entries and the buckets listed in [`DirectoryLinks::buckets`] need to be listed in turn to find
+/// all of the entries.
+#[allow(clippy::result_large_err)]
+pub fn list(block: &[u8]) -> Result<DirectoryLinks, ResolveError> {
+    let (links, sharded) = match FlatUnixFs::try_parse(block) {
+        Ok(hamt) if hamt.data.Type == UnixFsType::HAMTShard => {
+            (check_hamtshard_supported(hamt)?.links, true)
+        }
+        Ok(flat) if flat.data.Type == UnixFsType::Directory => {
+            (check_directory_supported(flat)?.links, false)
+        }
+        Err(ParsingFailed::InvalidUnixFs(_, PBNode { Links: links, .. }))
+        | Err(ParsingFailed::NoData(PBNode { Links: links, .. })) => (links, false),
+        Ok(other) => return Err(ResolveError::UnexpectedType(other.data.Type.into())),
+        Err(ParsingFailed::InvalidDagPb(e)) => return Err(ResolveError::Read(e)),
+    };
+
+    let mut listed = DirectoryLinks::default();
+
+    for (i, link) in links.into_iter().enumerate() {
+        let name = link.Name.as_deref().unwrap_or_default().to_owned();
+        let total_size = link.Tsize.unwrap_or_default();
+        let cid = try_convert_cid(i, link)?;
+
+        match name.get(2..) {
+            // the magic number of two comes from the fanout (256), like in the lookup
+            Some("") if sharded => listed.buckets.push(cid),
+            Some(name) if sharded => listed.entries.push((name.to_owned(), cid, total_size)),
+            _ => listed.entries.push((name, cid, total_size)),
+        }
+    }
+
+    Ok(listed)
+}
+
+/// The links of a single block of a directory, see [`list`].
+#[derive(Debug, Default)]
+pub struct DirectoryLinks {
+    /// The names, Cids and cumulative sizes of the entries.
+    pub entries: Vec<(String, Cid, u64)>,
+    /// The buckets of a HAMT sharded directory holding the rest of the entries.
+    pub buckets: Vec<Cid>,
+}
+
 fn try_convert_cid(nth: usize, link: PBLink<'_>) -> Result<Cid, InvalidCidInLink> {
     let hash = link.Hash.as_deref().unwrap_or_default();
     Cid::try_from(hash).map_err(|e| InvalidCidInLink::from((nth, link, e)))
@@ -213,7 +260,7 @@ impl MultipleMatchingLinks {
 #[cfg(test)]
 mod tests {
 
-    use super::{resolve, MaybeResolved};
+    use super::{list, resolve, MaybeResolved};
     use crate::test_support::FakeBlockstore;
     use core::convert::TryFrom;
     use hex_literal::hex;
@@ -297,4 +344,25 @@ mod tests {
             "QmRgutAxd8t7oGkSm4wmeuByG6M51wcTso6cubDdQtuEfL"
         );
     }
+
+    #[test]
+    fn list_sharded_directory() {
+        let blocks = FakeBlockstore::with_fixtures();
+
+        let mut pending =
+            vec![Cid::try_from("QmQXUANxYGpkwMTWQUdZBPx9jqfFP7acNgL4FHRWkndKCe").unwrap()];
+        let mut names = Vec::new();
+
+        while let Some(cid) = pending.pop() {
+            let listed = list(blocks.get_by_cid(&cid)).unwrap();
+            names.extend(listed.entries.into_iter().map(|(name, ..)| name));
+            pending.extend(listed.buckets);
+        }
+
+        assert!(names.iter().any(|name| name == "non_sharded_dir"));
+        assert!(names.iter().all(|name| !name.is_empty()));
+
+        let payload = hex!("0a130802120d666f6f6261720a666f6f626172180d");
+        list(&payload[..]).unwrap_err();
+    }
 }
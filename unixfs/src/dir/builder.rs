@@ -1,4 +1,6 @@
 use core::fmt;
+use libipld::cid::Version;
+use libipld::multihash::Code;
 use libipld::Cid;
 
 mod dir_builder;
@@ -13,6 +15,8 @@ pub use buffered::BufferingTreeBuilder;
 mod custom_pb;
 use custom_pb::CustomFlatUnixFs;
 
+mod hamt;
+
 enum Entry {
     Leaf(Leaf),
     Directory(DirBuilder),
@@ -55,6 +59,11 @@ impl fmt::Debug for Leaf {
 pub struct TreeOptions {
     block_size_limit: Option<u64>,
     wrap_with_directory: bool,
+    inline_limit: Option<usize>,
+    cid_version: Version,
+    hash: Code,
+    shard_threshold: Option<usize>,
+    sharding_size: Option<u64>,
 }
 
 impl Default for TreeOptions {
@@ -62,6 +71,11 @@ impl Default for TreeOptions {
         TreeOptions {
             block_size_limit: Some(512 * 1024),
             wrap_with_directory: false,
+            inline_limit: None,
+            cid_version: Version::V0,
+            hash: Code::Sha2_256,
+            shard_threshold: None,
+            sharding_size: Some(256 * 1024),
         }
     }
 }
@@ -78,6 +92,41 @@ impl TreeOptions {
     pub fn wrap_with_directory(&mut self) {
         self.wrap_with_directory = true;
     }
+
+    /// Inline directory nodes of at most `limit` bytes into their Cids using the identity hash.
+    /// The limit cannot be larger than [`crate::MAX_INLINE_LIMIT`]. Defaults to `None`, or no
+    /// inlining.
+    pub fn inline_limit(&mut self, limit: Option<usize>) {
+        self.inline_limit = limit;
+    }
+
+    /// Configures the Cid version of the directory nodes. Cid version 0 is only possible with
+    /// sha2-256, for other hash functions version 1 will be used. Defaults to version 0.
+    pub fn cid_version(&mut self, version: Version) {
+        self.cid_version = version;
+    }
+
+    /// Configures the hash function of the directory nodes, failing for others than the
+    /// [`crate::SUPPORTED_HASHES`]. Defaults to sha2-256.
+    pub fn hash(&mut self, hash: Code) -> Result<(), crate::UnsupportedHash> {
+        self.hash = crate::check_hash(hash)?;
+        Ok(())
+    }
+
+    /// Directories with more entries than the `threshold` are created as HAMT sharded
+    /// directories, like go-ipfs creates them. Defaults to `None`, or no sharding by the number
+    /// of entries.
+    pub fn shard_threshold(&mut self, threshold: Option<usize>) {
+        self.shard_threshold = threshold;
+    }
+
+    /// Directories are created as HAMT sharded directories once the estimated size of their links,
+    /// the lengths of the names and the Cids, reaches `size` bytes. Defaults to 256 KiB like
+    /// go-ipfs, which makes directories too large for a single block sharded. If the size is set
+    /// to `None`, directories are only sharded by [`TreeOptions::shard_threshold`].
+    pub fn sharding_size(&mut self, size: Option<u64>) {
+        self.sharding_size = size;
+    }
 }
 
 /// Tree building failure cases.
@@ -127,9 +176,11 @@ impl std::error::Error for TreeBuildingFailed {}
 pub enum TreeConstructionFailed {
     /// Failed to serialize the protobuf node for the directory
     Protobuf(quick_protobuf::Error),
-    /// The resulting directory would be too large and HAMT sharding is yet to be implemented or
-    /// denied.
+    /// The resulting directory or shard would be too large, which happens when HAMT sharding has
+    /// been disabled with `TreeOptions::sharding_size`.
     TooLargeBlock(u64),
+    /// Two names in a sharded directory had the same hash, which cannot be sharded.
+    HashCollision(String),
 }
 
 impl fmt::Display for TreeConstructionFailed {
@@ -139,6 +190,7 @@ impl fmt::Display for TreeConstructionFailed {
         match self {
             Protobuf(e) => write!(fmt, "serialization failed: {e}"),
             TooLargeBlock(size) => write!(fmt, "attempted to create block of {size} bytes"),
+            HashCollision(name) => write!(fmt, "hash of {name:?} collides with another name"),
         }
     }
 }
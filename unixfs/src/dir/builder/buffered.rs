@@ -186,7 +186,8 @@ impl BufferingTreeBuilder {
 #[cfg(test)]
 mod tests {
     use super::{
-        super::OwnedTreeNode, BufferingTreeBuilder, Metadata, TreeBuildingFailed, TreeOptions,
+        super::{OwnedTreeNode, TreeConstructionFailed},
+        BufferingTreeBuilder, Metadata, TreeBuildingFailed, TreeOptions,
     };
     use core::convert::TryFrom;
     use libipld::multihash::{Code, MultihashDigest};
@@ -395,6 +396,264 @@ mod tests {
         verify_results(expected, actual);
     }
 
+    #[test]
+    fn inline_small_directories() {
+        let mut opts = TreeOptions::default();
+        opts.inline_limit(Some(32));
+        let mut builder = BufferingTreeBuilder::new(opts);
+        builder.put_link("a/b/c", some_cid(0), 1).unwrap();
+        builder.put_link("a/bb", some_cid(1), 1).unwrap();
+
+        let actual = builder
+            .build()
+            .map(|res| res.map(|n| (n.path, n.cid, n.block)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(actual.len(), 2);
+
+        // "a/b" only has a single link, but even it is over the limit with the sha2-256 Cid
+        for (path, cid, block) in &actual {
+            assert!(block.len() > 32, "{path} is only {} bytes", block.len());
+            assert_eq!(cid.version(), libipld::cid::Version::V0, "{path}");
+        }
+
+        let mut opts = TreeOptions::default();
+        opts.inline_limit(Some(crate::MAX_INLINE_LIMIT));
+        let mut builder = BufferingTreeBuilder::new(opts);
+        builder.put_link("a/b/c", some_cid(0), 1).unwrap();
+        builder.put_link("a/bb", some_cid(1), 1).unwrap();
+
+        let actual = builder
+            .build()
+            .map(|res| res.map(|n| (n.path, n.cid, n.block)))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        let (path, cid, block) = &actual[0];
+        assert_eq!(path, "a/b");
+        assert_eq!(cid.codec(), 0x70);
+        assert_eq!(cid.hash().code(), crate::IDENTITY_HASH_CODE);
+        assert_eq!(cid.hash().digest(), &block[..]);
+
+        // the root links to the inlined Cid, which makes it too large
+        let (path, cid, _) = &actual[1];
+        assert_eq!(path, "a");
+        assert_eq!(cid.version(), libipld::cid::Version::V0);
+    }
+
+    #[test]
+    fn sharded_directory() {
+        use crate::dir::{resolve, MaybeResolved};
+        use std::collections::HashMap;
+
+        let mut opts = TreeOptions::default();
+        opts.shard_threshold(Some(16));
+        let mut builder = BufferingTreeBuilder::new(opts);
+
+        // enough entries for some buckets to have sub-shards
+        for i in 0..1000 {
+            builder
+                .put_link(&format!("a/file-{i}"), some_cid(i), 1)
+                .unwrap();
+        }
+
+        for i in 0..16 {
+            builder
+                .put_link(&format!("a/b/{i}"), some_cid(i), 1)
+                .unwrap();
+        }
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+
+        let root = nodes.last().unwrap();
+        assert_eq!(root.path, "a");
+        assert!(nodes.iter().filter(|node| node.path == "a").count() > 2);
+
+        // at the threshold, "a/b" is a regular directory
+        let b = nodes.iter().find(|node| node.path == "a/b").unwrap();
+        let b = crate::pb::FlatUnixFs::try_from(&b.block[..]).unwrap();
+        assert_eq!(b.data.Type, crate::pb::UnixFsType::Directory);
+
+        let blocks = nodes
+            .iter()
+            .map(|node| (node.cid, &node.block[..]))
+            .collect::<HashMap<_, _>>();
+
+        let lookup = |needle: &str| {
+            let mut result = resolve(blocks[&root.cid], needle, &mut None).unwrap();
+            loop {
+                match result {
+                    MaybeResolved::Found(cid) => return Some(cid),
+                    MaybeResolved::NotFound => return None,
+                    MaybeResolved::NeedToLoadMore(lookup) => {
+                        let next = *lookup.pending_links().0;
+                        result = lookup.continue_walk(blocks[&next], &mut None).unwrap();
+                    }
+                }
+            }
+        };
+
+        for i in 0..1000 {
+            assert_eq!(lookup(&format!("file-{i}")), Some(some_cid(i)));
+        }
+        assert_eq!(lookup("b"), Some(b_cid(&nodes)));
+        assert_eq!(lookup("file-1000"), None);
+
+        fn b_cid(nodes: &[OwnedTreeNode]) -> Cid {
+            nodes.iter().find(|node| node.path == "a/b").unwrap().cid
+        }
+    }
+
+    #[test]
+    fn huge_directory_is_sharded() {
+        use crate::dir::{resolve, MaybeResolved};
+        use std::collections::HashMap;
+
+        const ENTRIES: usize = 100_000;
+
+        let put_links = |builder: &mut BufferingTreeBuilder| {
+            for i in 0..ENTRIES {
+                builder
+                    .put_link(&format!("a/file-{i}"), some_cid(i), 1)
+                    .unwrap();
+            }
+        };
+
+        // a flat directory of this size would be far over the block size limit
+        let mut opts = TreeOptions::default();
+        opts.sharding_size(None);
+        let mut builder = BufferingTreeBuilder::new(opts);
+        put_links(&mut builder);
+
+        assert!(matches!(
+            builder.build().collect::<Result<Vec<_>, _>>(),
+            Err(TreeConstructionFailed::TooLargeBlock(_))
+        ));
+
+        let mut builder = BufferingTreeBuilder::default();
+        put_links(&mut builder);
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+
+        let root = nodes.last().unwrap();
+        let parsed = crate::pb::FlatUnixFs::try_from(&root.block[..]).unwrap();
+        assert_eq!(parsed.data.Type, crate::pb::UnixFsType::HAMTShard);
+        assert_eq!(parsed.links.len(), 256);
+
+        let total_size = nodes
+            .iter()
+            .map(|node| node.block.len() as u64)
+            .sum::<u64>()
+            + ENTRIES as u64;
+        assert_eq!(root.total_size, total_size);
+
+        // every entry is found once in the buckets, prefixed with the bucket index
+        let mut entries = HashMap::new();
+        for node in &nodes {
+            let shard = crate::pb::FlatUnixFs::try_from(&node.block[..]).unwrap();
+            assert_eq!(shard.data.Type, crate::pb::UnixFsType::HAMTShard);
+
+            for link in shard.links {
+                let name = link.Name.as_deref().unwrap();
+                if name.len() > 2 {
+                    let cid = Cid::try_from(link.Hash.as_deref().unwrap()).unwrap();
+                    assert!(entries.insert(name[2..].to_owned(), cid).is_none());
+                }
+            }
+        }
+
+        assert_eq!(entries.len(), ENTRIES);
+        for i in 0..ENTRIES {
+            assert_eq!(entries[&format!("file-{i}")], some_cid(i));
+        }
+
+        // looking up walks the buckets
+        let mut result = resolve(&root.block, "file-0", &mut None).unwrap();
+        let blocks = nodes
+            .iter()
+            .map(|node| (node.cid, &node.block[..]))
+            .collect::<HashMap<_, _>>();
+        let found = loop {
+            match result {
+                MaybeResolved::Found(cid) => break cid,
+                MaybeResolved::NotFound => panic!("file-0 not found"),
+                MaybeResolved::NeedToLoadMore(lookup) => {
+                    let next = *lookup.pending_links().0;
+                    result = lookup.continue_walk(blocks[&next], &mut None).unwrap();
+                }
+            }
+        };
+        assert_eq!(found, some_cid(0));
+    }
+
+    #[test]
+    fn small_directory_is_not_sharded() {
+        let mut builder = BufferingTreeBuilder::default();
+        for i in 0..1000 {
+            builder
+                .put_link(&format!("a/file-{i}"), some_cid(i), 1)
+                .unwrap();
+        }
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(nodes.len(), 1);
+
+        let root = crate::pb::FlatUnixFs::try_from(&nodes[0].block[..]).unwrap();
+        assert_eq!(root.data.Type, crate::pb::UnixFsType::Directory);
+    }
+
+    #[test]
+    fn cid_version_and_hash() {
+        let mut opts = TreeOptions::default();
+        opts.cid_version(libipld::cid::Version::V1);
+        opts.hash(Code::Blake3_256).unwrap();
+        opts.shard_threshold(Some(1));
+        opts.wrap_with_directory();
+        let mut builder = BufferingTreeBuilder::new(opts);
+        builder.put_link("a/b", some_cid(0), 1).unwrap();
+        builder.put_link("a/c", some_cid(1), 1).unwrap();
+        builder.put_link("d", some_cid(2), 1).unwrap();
+
+        // the sharded "a" as well as the wrapping directory
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(nodes.len(), 2);
+        let a = crate::pb::FlatUnixFs::try_from(&nodes[0].block[..]).unwrap();
+        assert_eq!(a.data.Type, crate::pb::UnixFsType::HAMTShard);
+        for node in nodes {
+            assert_eq!(node.cid.version(), libipld::cid::Version::V1);
+            assert_eq!(node.cid.codec(), 0x70);
+            assert_eq!(
+                node.cid,
+                Cid::new_v1(0x70, Code::Blake3_256.digest(&node.block))
+            );
+        }
+
+        let mut opts = TreeOptions::default();
+        assert!(opts.hash(Code::Sha2_512).is_err());
+    }
+
+    #[test]
+    fn directory_metadata() {
+        let mut builder = BufferingTreeBuilder::default();
+        builder
+            .set_metadata(
+                "a",
+                Metadata::default()
+                    .with_mode(0o40755)
+                    .with_mtime(1_600_000_000, 0),
+            )
+            .unwrap();
+        builder.put_link("a/b", some_cid(0), 1).unwrap();
+
+        let nodes = builder.build().collect::<Result<Vec<_>, _>>().unwrap();
+        let root = crate::pb::FlatUnixFs::try_from(&nodes[0].block[..]).unwrap();
+
+        let metadata = Metadata::from(&root.data);
+        assert_eq!(metadata.mode(), Some(0o755));
+        assert_eq!(metadata.mtime(), Some((1_600_000_000, 0)));
+    }
+
     fn verify_results(
         mut expected: Vec<(
             impl AsRef<str> + core::fmt::Debug,
@@ -0,0 +1,180 @@
+//! Rendering of HAMT sharded directories in the layout go-ipfs uses: each shard has 256 buckets,
+//! indexed on each level by the next byte of the murmur3 hash of the entry name.
+
+use super::iter::render_node;
+use super::{CustomFlatUnixFs, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions};
+use crate::pb::{UnixFs, UnixFsType};
+use alloc::borrow::Cow;
+use libipld::Cid;
+use std::collections::VecDeque;
+
+/// The number of buckets in each shard.
+const FANOUT: u64 = 256;
+
+/// The multicodec of murmur3-x64-64, which is the first half of the murmur3-x64-128 hash.
+const HASH_TYPE: u64 = 0x22;
+
+/// Returns the bytes of the hash of `name`, consumed one byte per level of the shards.
+fn hash(name: &str) -> [u8; 8] {
+    let hash = murmur3::murmur3_x64_128(&mut name.as_bytes(), 0)
+        .expect("reading from a slice cannot fail");
+    // go-ipfs uses the first of the two halves, in big endian
+    (hash as u64).to_be_bytes()
+}
+
+/// Renders the shards for the directory `links` as blocks pushed to `blocks`, the root shard
+/// being the last. Returns the link to the root shard.
+pub(super) fn render(
+    links: &[Option<NamedLeaf>],
+    blocks: &mut VecDeque<(Cid, Vec<u8>, u64)>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    let mut entries = links
+        .iter()
+        .map(|link| {
+            let link = link.as_ref().expect("all links have been rendered");
+            (hash(&link.0), link)
+        })
+        .collect::<Vec<_>>();
+
+    // sorting by the whole hash keeps the entries of each bucket, on every level, sorted
+    entries.sort_unstable_by_key(|entry| entry.0);
+
+    render_shard(&entries, 0, blocks, opts)
+}
+
+fn render_shard(
+    entries: &[([u8; 8], &NamedLeaf)],
+    depth: usize,
+    blocks: &mut VecDeque<(Cid, Vec<u8>, u64)>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    let mut links = Vec::new();
+    let mut bitfield = [0u8; FANOUT as usize / 8];
+
+    let mut remaining = entries;
+    while let Some(((hash, _), _)) = remaining.split_first() {
+        let index = hash[depth];
+        let len = remaining
+            .iter()
+            .take_while(|(other, _)| other[depth] == index)
+            .count();
+        let (bucket, rest) = remaining.split_at(len);
+        remaining = rest;
+
+        set_bit(&mut bitfield, index);
+
+        let link = match bucket {
+            [(_, NamedLeaf(name, cid, total_size))] => {
+                NamedLeaf(format!("{index:02X}{name}"), *cid, *total_size)
+            }
+            [(_, NamedLeaf(name, ..)), ..] if depth + 1 == hash.len() => {
+                return Err(TreeConstructionFailed::HashCollision(name.clone()));
+            }
+            _ => {
+                let shard = render_shard(bucket, depth + 1, blocks, opts)?;
+                NamedLeaf(format!("{index:02X}"), shard.link, shard.total_size)
+            }
+        };
+
+        links.push(Some(link));
+    }
+
+    push_shard(&links, &bitfield, blocks, opts)
+}
+
+fn set_bit(bitfield: &mut [u8], index: u8) {
+    let at = bitfield.len() - 1 - index as usize / 8;
+    bitfield[at] |= 1 << (index % 8);
+}
+
+fn push_shard(
+    links: &[Option<NamedLeaf>],
+    bitfield: &[u8],
+    blocks: &mut VecDeque<(Cid, Vec<u8>, u64)>,
+    opts: &TreeOptions,
+) -> Result<Leaf, TreeConstructionFailed> {
+    // go-ipfs serializes the bitfield like a big integer, without the leading zero bytes
+    let start = bitfield
+        .iter()
+        .position(|b| *b != 0)
+        .unwrap_or(bitfield.len());
+
+    let node = CustomFlatUnixFs {
+        links,
+        data: UnixFs {
+            Type: UnixFsType::HAMTShard,
+            Data: Some(Cow::Borrowed(&bitfield[start..])),
+            hashType: Some(HASH_TYPE),
+            fanout: Some(FANOUT),
+            ..Default::default()
+        },
+    };
+
+    let mut block = Vec::new();
+    let cid = render_node(&node, &mut block, opts)?;
+
+    let total_size = block.len() as u64
+        + links
+            .iter()
+            .map(|link| link.as_ref().map(|link| link.2).unwrap_or_default())
+            .sum::<u64>();
+
+    blocks.push_back((cid, block, total_size));
+
+    Ok(Leaf {
+        link: cid,
+        total_size,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hash, push_shard, set_bit, NamedLeaf, TreeOptions};
+    use core::convert::TryFrom;
+    use hex_literal::hex;
+    use libipld::Cid;
+    use std::collections::VecDeque;
+
+    // the root shard of linux-5.5-rc5/tools/testing/selftests/rcutorture/ imported by go-ipfs,
+    // also used in the sharded_lookup tests
+    const DIR: &[u8] = &hex!("122e0a2212204baf5104fe53d495223f8e2ba95375a31fda6b18e926cb54edd61f30b5f1de6512053641646f6318b535122c0a221220fd9f545068048e647d5d0b275ed171596e0c1c04b8fed09dc13bee7607e75bc7120242391883c00312330a2212208a4a68f6b88594ce373419586c12d24bde2d519ab636b1d2dcc986eb6265b7a3120a43444d616b6566696c65189601122f0a2212201ededc99d23a7ef43a8f17e6dd8b89934993245ef39e18936a37e412e536ed681205463562696e18c5ad030a280805121f200000000020000200000000000000000004000000000000000000000000002822308002");
+
+    #[test]
+    fn buckets_match_go_ipfs() {
+        // "formal" is in the "B9" shard along with other names
+        for (name, bucket) in [
+            ("doc", 0x6A),
+            ("formal", 0xB9),
+            ("Makefile", 0xCD),
+            ("bin", 0xF5),
+        ] {
+            assert_eq!(hash(name)[0], bucket, "{name}");
+        }
+    }
+
+    #[test]
+    fn shard_matches_go_ipfs() {
+        let parsed = crate::pb::FlatUnixFs::try_from(DIR).unwrap();
+
+        let mut bitfield = [0u8; 32];
+        let links = parsed
+            .links
+            .iter()
+            .map(|link| {
+                let name = link.Name.as_deref().unwrap().to_owned();
+                let index = u8::from_str_radix(&name[..2], 16).unwrap();
+                set_bit(&mut bitfield, index);
+
+                let cid = Cid::try_from(link.Hash.as_deref().unwrap()).unwrap();
+                Some(NamedLeaf(name, cid, link.Tsize.unwrap()))
+            })
+            .collect::<Vec<_>>();
+
+        let mut blocks = VecDeque::new();
+        push_shard(&links, &bitfield, &mut blocks, &TreeOptions::default()).unwrap();
+
+        let (_, block, _) = blocks.pop_front().unwrap();
+        assert_eq!(block, DIR);
+    }
+}
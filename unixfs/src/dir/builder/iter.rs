@@ -1,10 +1,10 @@
 use super::{
     CustomFlatUnixFs, DirBuilder, Entry, Leaf, NamedLeaf, TreeConstructionFailed, TreeOptions,
 };
+use crate::Metadata;
 use core::fmt;
-use libipld::multihash::{Code, Multihash};
 use libipld::Cid;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
 /// Constructs the directory nodes required for a tree.
 ///
@@ -22,6 +22,8 @@ pub struct PostOrderIterator {
     reused_children: Vec<Visited>,
     cid: Option<Cid>,
     total_size: u64,
+    // the rendered but not yet returned blocks of a sharded directory, root shard being the last
+    shard_blocks: VecDeque<(Cid, Vec<u8>, u64)>,
     // from TreeOptions
     opts: TreeOptions,
 }
@@ -56,9 +58,11 @@ enum Visited {
         /// Leaves will be stored directly in this field when there are no DirBuilder descendants,
         /// in the `PostOrderIterator::persisted_cids` otherwise.
         leaves: LeafStorage,
+        metadata: Metadata,
     },
     PostRoot {
         leaves: LeafStorage,
+        metadata: Metadata,
     },
 }
 
@@ -74,6 +78,7 @@ impl PostOrderIterator {
             reused_children: Vec::new(),
             cid: None,
             total_size: 0,
+            shard_blocks: Default::default(),
             opts,
         }
     }
@@ -81,69 +86,25 @@ impl PostOrderIterator {
     fn render_directory(
         links: &[Option<NamedLeaf>],
         buffer: &mut Vec<u8>,
-        block_size_limit: &Option<u64>,
+        shard_blocks: &mut VecDeque<(Cid, Vec<u8>, u64)>,
+        opts: &TreeOptions,
+        metadata: &Metadata,
     ) -> Result<Leaf, TreeConstructionFailed> {
         use crate::pb::{UnixFs, UnixFsType};
-        use quick_protobuf::{BytesWriter, MessageWrite, Writer};
-        use sha2::{Digest, Sha256};
-
-        // FIXME: ideas on how to turn this into a HAMT sharding on some heuristic. we probably
-        // need to introduce states in to the "iterator":
-        //
-        // 1. bucketization
-        // 2. another post order visit of the buckets?
-        //
-        // the nested post order visit should probably re-use the existing infra ("message
-        // passing") and new ids can be generated by giving this iterator the counter from
-        // BufferedTreeBuilder.
-        //
-        // could also be that the HAMT shard building should start earlier, since the same
-        // heuristic can be detected *at* bufferedtreewriter. there the split would be easier, and
-        // this would "just" be a single node rendering, and not need any additional states..
-
-        let node = CustomFlatUnixFs {
-            links,
-            data: UnixFs {
-                Type: UnixFsType::Directory,
-                ..Default::default()
-            },
-        };
-
-        let size = node.get_size();
-
-        if let Some(limit) = block_size_limit {
-            let size = size as u64;
-            if *limit < size {
-                // FIXME: this could probably be detected at builder
-                return Err(TreeConstructionFailed::TooLargeBlock(size));
-            }
-        }
-
-        let cap = buffer.capacity();
 
-        if let Some(additional) = size.checked_sub(cap) {
-            buffer.reserve(additional);
+        if needs_sharding(links, opts) {
+            return super::hamt::render(links, shard_blocks, opts);
         }
 
-        if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
-            let zeroes = [0; 8];
-
-            while needed_zeroes > 8 {
-                buffer.extend_from_slice(&zeroes[..]);
-                needed_zeroes -= zeroes.len();
-            }
-
-            buffer.extend(core::iter::repeat(0).take(needed_zeroes));
-        }
-
-        let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
-        node.write_message(&mut writer)
-            .map_err(TreeConstructionFailed::Protobuf)?;
+        let mut data = UnixFs {
+            Type: UnixFsType::Directory,
+            ..Default::default()
+        };
+        metadata.apply(&mut data);
 
-        buffer.truncate(size);
+        let node = CustomFlatUnixFs { links, data };
 
-        let mh = Multihash::wrap(Code::Sha2_256.into(), &Sha256::digest(&buffer)).unwrap();
-        let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
+        let cid = render_node(&node, buffer, opts)?;
 
         let combined_from_links = links
             .iter()
@@ -160,10 +121,30 @@ impl PostOrderIterator {
         })
     }
 
+    /// Returns the next of the blocks of a sharded directory.
+    fn next_shard_block(&mut self) -> Option<Result<TreeNode<'_>, TreeConstructionFailed>> {
+        let (cid, block, total_size) = self.shard_blocks.pop_front()?;
+
+        self.block_buffer = block;
+        self.cid = Some(cid);
+        self.total_size = total_size;
+
+        Some(Ok(TreeNode {
+            path: self.full_path.as_str(),
+            cid: self.cid.as_ref().unwrap(),
+            total_size: self.total_size,
+            block: &self.block_buffer,
+        }))
+    }
+
     /// Construct the next dag-pb node, if any.
     ///
     /// Returns a `TreeNode` of the latest constructed tree node.
     pub fn next_borrowed(&mut self) -> Option<Result<TreeNode<'_>, TreeConstructionFailed>> {
+        if !self.shard_blocks.is_empty() {
+            return self.next_shard_block();
+        }
+
         while let Some(visited) = self.pending.pop() {
             let (name, depth) = match &visited {
                 Visited::DescentRoot(_) => (None, 0),
@@ -187,7 +168,10 @@ impl PostOrderIterator {
                         leaves.into()
                     };
 
-                    self.pending.push(Visited::PostRoot { leaves });
+                    self.pending.push(Visited::PostRoot {
+                        leaves,
+                        metadata: node.metadata,
+                    });
                     self.pending.append(children);
                 }
                 Visited::Descent {
@@ -214,6 +198,7 @@ impl PostOrderIterator {
                         depth,
                         leaves,
                         index,
+                        metadata: node.metadata,
                     });
 
                     self.pending.append(children);
@@ -223,6 +208,7 @@ impl PostOrderIterator {
                     name,
                     leaves,
                     index,
+                    metadata,
                     ..
                 } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
@@ -231,7 +217,9 @@ impl PostOrderIterator {
                     let leaf = match Self::render_directory(
                         &leaves,
                         buffer,
-                        &self.opts.block_size_limit,
+                        &mut self.shard_blocks,
+                        &self.opts,
+                        &metadata,
                     ) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
@@ -258,6 +246,10 @@ impl PostOrderIterator {
                         }
                     }
 
+                    if !self.shard_blocks.is_empty() {
+                        return self.next_shard_block();
+                    }
+
                     return Some(Ok(TreeNode {
                         path: self.full_path.as_str(),
                         cid: self.cid.as_ref().unwrap(),
@@ -265,7 +257,7 @@ impl PostOrderIterator {
                         block: &self.block_buffer,
                     }));
                 }
-                Visited::PostRoot { leaves } => {
+                Visited::PostRoot { leaves, metadata } => {
                     let leaves = leaves.into_inner(&mut self.persisted_cids);
 
                     if !self.opts.wrap_with_directory {
@@ -277,7 +269,9 @@ impl PostOrderIterator {
                     let leaf = match Self::render_directory(
                         &leaves,
                         buffer,
-                        &self.opts.block_size_limit,
+                        &mut self.shard_blocks,
+                        &self.opts,
+                        &metadata,
                     ) {
                         Ok(leaf) => leaf,
                         Err(e) => return Some(Err(e)),
@@ -286,6 +280,10 @@ impl PostOrderIterator {
                     self.cid = Some(leaf.link);
                     self.total_size = leaf.total_size;
 
+                    if !self.shard_blocks.is_empty() {
+                        return self.next_shard_block();
+                    }
+
                     return Some(Ok(TreeNode {
                         path: self.full_path.as_str(),
                         cid: self.cid.as_ref().unwrap(),
@@ -355,6 +353,75 @@ pub struct OwnedTreeNode {
     pub block: Box<[u8]>,
 }
 
+/// Returns true if the directory of `links` should be rendered as a HAMT sharded directory.
+fn needs_sharding(links: &[Option<NamedLeaf>], opts: &TreeOptions) -> bool {
+    if opts
+        .shard_threshold
+        .is_some_and(|threshold| links.len() > threshold)
+    {
+        return true;
+    }
+
+    // the same estimate go-ipfs uses, which ignores the protobuf framing
+    opts.sharding_size.is_some_and(|size| {
+        links
+            .iter()
+            .flatten()
+            .map(|NamedLeaf(name, cid, _)| (name.len() + cid.encoded_len()) as u64)
+            .sum::<u64>()
+            >= size
+    })
+}
+
+/// Serializes the `node` into the `buffer`, returning the Cid of the block.
+pub(super) fn render_node(
+    node: &CustomFlatUnixFs<'_>,
+    buffer: &mut Vec<u8>,
+    opts: &TreeOptions,
+) -> Result<Cid, TreeConstructionFailed> {
+    use quick_protobuf::{BytesWriter, MessageWrite, Writer};
+
+    let size = node.get_size();
+
+    if let Some(limit) = opts.block_size_limit {
+        let size = size as u64;
+        if limit < size {
+            // FIXME: this could probably be detected at builder
+            return Err(TreeConstructionFailed::TooLargeBlock(size));
+        }
+    }
+
+    let cap = buffer.capacity();
+
+    if let Some(additional) = size.checked_sub(cap) {
+        buffer.reserve(additional);
+    }
+
+    if let Some(mut needed_zeroes) = size.checked_sub(buffer.len()) {
+        let zeroes = [0; 8];
+
+        while needed_zeroes > 8 {
+            buffer.extend_from_slice(&zeroes[..]);
+            needed_zeroes -= zeroes.len();
+        }
+
+        buffer.extend(core::iter::repeat(0).take(needed_zeroes));
+    }
+
+    let mut writer = Writer::new(BytesWriter::new(&mut buffer[..]));
+    node.write_message(&mut writer)
+        .map_err(TreeConstructionFailed::Protobuf)?;
+
+    buffer.truncate(size);
+
+    Ok(crate::dag_pb_cid(
+        opts.cid_version,
+        opts.hash,
+        buffer,
+        opts.inline_limit,
+    ))
+}
+
 fn update_full_path(
     (full_path, old_depth): (&mut String, &mut usize),
     name: Option<&str>,
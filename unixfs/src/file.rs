@@ -16,6 +16,12 @@ pub mod visit;
 /// File adder capable of constructing UnixFs v1 trees
 pub mod adder;
 
+/// Returns `true` if the Cid is of the raw codec, as the leaves of files added with raw leaves
+/// are. Such blocks are file content without any UnixFs wrapping.
+pub(crate) fn is_raw(cid: &libipld::Cid) -> bool {
+    cid.codec() == u64::from(libipld::IpldCodec::Raw)
+}
+
 /// Describes the errors which can happen during a visit or lower level block-by-block walking of
 /// the DAG.
 #[derive(Debug)]
@@ -1,25 +1,37 @@
-use libipld::multihash::{self, Multihash};
-use libipld::Cid;
+use libipld::cid::Version;
+use libipld::multihash::{Code, MultihashDigest};
+use libipld::{Cid, IpldCodec};
 
 use crate::pb::{FlatUnixFs, PBLink, UnixFs, UnixFsType};
+use crate::Metadata;
 use alloc::borrow::Cow;
 use core::fmt;
 use quick_protobuf::{MessageWrite, Writer};
 
-use sha2::{Digest, Sha256};
+mod buzhash;
+mod rabin;
 
 /// File tree builder. Implements [`core::default::Default`] which tracks the recent defaults.
 ///
 /// Custom file tree builder can be created with [`FileAdder::builder()`] and configuring the
-/// chunker and collector.
+/// chunker and collector, e.g. [`TrickleCollector`] for the trickle layout. Besides the fixed
+/// size chunking, content defined chunking following the go-ipfs `rabin` and `buzhash` chunkers
+/// is supported through [`Chunker`], which helps to reuse blocks between revisions of a file.
 ///
-/// Current implementation maintains an internal buffer for the block creation and uses a
-/// non-customizable hash function to produce Cid version 0 links. Currently does not support
-/// inline links.
+/// By default the blocks are hashed with sha2-256 and linked with Cid version 0 as go-ipfs does.
+/// The Cid version, hash function and raw leaves can be configured through the builder to match
+/// for example `ipfs add --cid-version=1 --raw-leaves`.
+///
+/// Blocks small enough can be inlined into their Cids with the identity hash, see
+/// [`FileAdderBuilder::with_inline_limit`].
+///
+/// Current implementation maintains an internal buffer for the block creation.
 #[derive(Default)]
 pub struct FileAdder {
     chunker: Chunker,
     collector: Collector,
+    format: BlockFormat,
+    metadata: Metadata,
     block_buffer: Vec<u8>,
     // all unflushed links as a flat vec; this is compacted as we grow and need to create a link
     // block for the last N blocks, as decided by the collector.
@@ -86,6 +98,7 @@ impl fmt::Display for LinkFormatter<'_> {
 
 /// Represents an intermediate structure which will be serialized into link blocks as both PBLink
 /// and UnixFs::blocksize. Also holds `depth`, which helps with compaction of the link blocks.
+#[derive(Clone)]
 struct Link {
     /// Depth of this link. Zero is leaf, and anything above it is, at least for
     /// [`BalancedCollector`], the compacted link blocks.
@@ -115,6 +128,8 @@ impl fmt::Debug for Link {
 pub struct FileAdderBuilder {
     chunker: Chunker,
     collector: Collector,
+    format: BlockFormat,
+    metadata: Metadata,
 }
 
 impl FileAdderBuilder {
@@ -131,13 +146,60 @@ impl FileAdderBuilder {
         }
     }
 
+    /// Configures the builder to store the leaves as blocks of the raw codec instead of wrapping
+    /// them in dag-pb UnixFs `File` nodes. The leaves are always linked with Cid version 1.
+    pub fn with_raw_leaves(mut self, raw_leaves: bool) -> Self {
+        self.format.raw_leaves = raw_leaves;
+        self
+    }
+
+    /// Configures the Cid version of the dag-pb blocks. Cid version 0 is only possible with
+    /// sha2-256, for other hash functions version 1 will be used.
+    pub fn with_cid_version(mut self, version: Version) -> Self {
+        self.format.version = version;
+        self
+    }
+
+    /// Configures the hash function, failing for others than the [`crate::SUPPORTED_HASHES`]:
+    /// sha2-256, blake2b-256 and blake3.
+    pub fn with_hash(mut self, hash: Code) -> Result<Self, crate::UnsupportedHash> {
+        self.format.hash = crate::check_hash(hash)?;
+        Ok(self)
+    }
+
+    /// Configures the builder to inline blocks of at most `limit` bytes into their Cids using the
+    /// identity hash, like `ipfs add --inline --inline-limit`. The limit cannot be larger than
+    /// [`crate::MAX_INLINE_LIMIT`]. Defaults to `None`, or no inlining.
+    pub fn with_inline_limit(mut self, limit: Option<usize>) -> Self {
+        self.format.inline_limit = limit;
+        self
+    }
+
+    /// Configures the mode and modification time stored in the root of the file, like
+    /// `ipfs add --preserve-mode --preserve-mtime`. A file consisting of a single raw leaf will get
+    /// a dag-pb root linking to the leaf.
+    pub fn with_metadata(self, metadata: Metadata) -> Self {
+        FileAdderBuilder { metadata, ..self }
+    }
+
     /// Returns a new FileAdder
     pub fn build(self) -> FileAdder {
-        let FileAdderBuilder { chunker, collector } = self;
+        let FileAdderBuilder {
+            chunker,
+            collector,
+            mut format,
+            metadata,
+        } = self;
+
+        if format.hash != Code::Sha2_256 {
+            format.version = Version::V1;
+        }
 
         FileAdder {
             chunker,
             collector,
+            format,
+            metadata,
             ..Default::default()
         }
     }
@@ -172,7 +234,13 @@ impl FileAdder {
             // blocks and user takes care of chunking (and buffering)?
             //
             // cat file | my_awesome_chunker | my_brilliant_collector
-            let leaf = Self::flush_buffered_leaf(accepted, &mut self.unflushed_links, false);
+            let leaf = Self::flush_buffered_leaf(
+                accepted,
+                &mut self.unflushed_links,
+                &self.collector,
+                &self.format,
+                false,
+            );
             assert!(leaf.is_some(), "chunk completed, must produce a new block");
             self.block_buffer.clear();
             let links = self.flush_buffered_links(false);
@@ -197,6 +265,8 @@ impl FileAdder {
                 let leaf = Self::flush_buffered_leaf(
                     self.block_buffer.as_slice(),
                     &mut self.unflushed_links,
+                    &self.collector,
+                    &self.format,
                     false,
                 );
                 assert!(leaf.is_some(), "chunk completed, must produce a new block");
@@ -216,24 +286,82 @@ impl FileAdder {
     /// Note: the API will hopefully evolve in a direction which will not allocate a new Vec for
     /// every block in the near-ish future.
     pub fn finish(mut self) -> impl Iterator<Item = (Cid, Vec<u8>)> {
-        let last_leaf =
-            Self::flush_buffered_leaf(&self.block_buffer, &mut self.unflushed_links, true);
+        let last_leaf = Self::flush_buffered_leaf(
+            &self.block_buffer,
+            &mut self.unflushed_links,
+            &self.collector,
+            &self.format,
+            true,
+        );
         let root_links = self.flush_buffered_links(true);
         // should probably error if there is neither?
-        last_leaf.into_iter().chain(root_links)
+        let mut blocks = last_leaf.into_iter().chain(root_links).collect::<Vec<_>>();
+
+        if !self.metadata.is_empty() {
+            if let Some(root) = blocks.pop() {
+                blocks.extend(self.root_with_metadata(root));
+            }
+        }
+
+        blocks.into_iter()
     }
 
-    /// Returns `None` when the input is empty but there are links, otherwise a new Cid and a
-    /// block.
+    /// Re-renders the root with the metadata; a raw root needs a new dag-pb root linking to it.
+    fn root_with_metadata(&self, (cid, block): (Cid, Vec<u8>)) -> Vec<(Cid, Vec<u8>)> {
+        if crate::file::is_raw(&cid) {
+            let size = block.len() as u64;
+            let mut flat = FlatUnixFs {
+                links: vec![PBLink {
+                    Hash: Some(cid.to_bytes().into()),
+                    Name: Some("".into()),
+                    Tsize: Some(size),
+                }],
+                data: UnixFs {
+                    Type: UnixFsType::File,
+                    filesize: Some(size),
+                    blocksizes: vec![size],
+                    ..Default::default()
+                },
+            };
+            self.metadata.apply(&mut flat.data);
+            let root = self.format.dag_pb(&flat);
+            vec![(cid, block), root]
+        } else {
+            let mut flat = FlatUnixFs::try_from(block.as_slice())
+                .expect("the root was just rendered as dag-pb");
+            self.metadata.apply(&mut flat.data);
+            vec![self.format.dag_pb(&flat)]
+        }
+    }
+
+    /// Returns `None` when the input is empty but there are links or the collector will create
+    /// the root for an empty file, otherwise a new Cid and a block.
     fn flush_buffered_leaf(
         input: &[u8],
         unflushed_links: &mut Vec<Link>,
+        collector: &Collector,
+        format: &BlockFormat,
         finishing: bool,
     ) -> Option<(Cid, Vec<u8>)> {
-        if input.is_empty() && (!finishing || !unflushed_links.is_empty()) {
+        if input.is_empty()
+            && (!finishing || !unflushed_links.is_empty() || !collector.needs_empty_leaf())
+        {
             return None;
         }
 
+        if format.raw_leaves {
+            let (cid, vec) = format.raw(input);
+
+            unflushed_links.push(Link {
+                depth: 0,
+                target: cid,
+                total_size: vec.len() as u64,
+                file_size: input.len() as u64,
+            });
+
+            return Some((cid, vec));
+        }
+
         // for empty unixfs file the bytes is missing but filesize is present.
 
         let data = if !input.is_empty() {
@@ -247,7 +375,7 @@ impl FileAdder {
         let inner = FlatUnixFs {
             links: Vec::new(),
             data: UnixFs {
-                Type: UnixFsType::File,
+                Type: collector.leaf_type(),
                 Data: data,
                 filesize,
                 // no blocksizes as there are no links
@@ -255,7 +383,7 @@ impl FileAdder {
             },
         };
 
-        let (cid, vec) = render_and_hash(&inner);
+        let (cid, vec) = format.dag_pb(&inner);
 
         let total_size = vec.len();
 
@@ -273,7 +401,7 @@ impl FileAdder {
 
     fn flush_buffered_links(&mut self, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
         self.collector
-            .flush_links(&mut self.unflushed_links, finishing)
+            .flush_links(&mut self.unflushed_links, &self.format, finishing)
     }
 
     /// Test helper for collecting all of the produced blocks; probably not a good idea outside
@@ -281,7 +409,11 @@ impl FileAdder {
     /// chunker, otherwise `all_content` is pushed at `amt` sized slices with the idea of catching
     /// bugs in chunkers.
     #[cfg(test)]
-    fn collect_blocks(mut self, all_content: &[u8], mut amt: usize) -> Vec<(Cid, Vec<u8>)> {
+    pub(crate) fn collect_blocks(
+        mut self,
+        all_content: &[u8],
+        mut amt: usize,
+    ) -> Vec<(Cid, Vec<u8>)> {
         let mut written = 0;
         let mut blocks_received = Vec::new();
 
@@ -305,24 +437,71 @@ impl FileAdder {
     }
 }
 
-fn render_and_hash(flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
-    // TODO: as shown in later dagger we don't really need to render the FlatUnixFs fully; we could
-    // either just render a fixed header and continue with the body OR links, though the links are
-    // a bit more complicated.
-    let mut out = Vec::with_capacity(flat.get_size());
-    let mut writer = Writer::new(&mut out);
-    flat.write_message(&mut writer)
-        .expect("unsure how this could fail");
-    let mh = Multihash::wrap(multihash::Code::Sha2_256.into(), &Sha256::digest(&out)).unwrap();
-    let cid = Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0");
-    (cid, out)
+/// Describes how the blocks are encoded and hashed into Cids.
+#[derive(Debug, Clone, Copy)]
+struct BlockFormat {
+    version: Version,
+    hash: Code,
+    raw_leaves: bool,
+    inline_limit: Option<usize>,
+}
+
+impl Default for BlockFormat {
+    fn default() -> Self {
+        BlockFormat {
+            version: Version::V0,
+            hash: Code::Sha2_256,
+            raw_leaves: false,
+            inline_limit: None,
+        }
+    }
+}
+
+impl BlockFormat {
+    fn dag_pb(&self, flat: &FlatUnixFs<'_>) -> (Cid, Vec<u8>) {
+        // TODO: as shown in later dagger we don't really need to render the FlatUnixFs fully; we
+        // could either just render a fixed header and continue with the body OR links, though the
+        // links are a bit more complicated.
+        let mut out = Vec::with_capacity(flat.get_size());
+        let mut writer = Writer::new(&mut out);
+        flat.write_message(&mut writer)
+            .expect("unsure how this could fail");
+        let cid = crate::dag_pb_cid(self.version, self.hash, &out, self.inline_limit);
+        (cid, out)
+    }
+
+    fn raw(&self, data: &[u8]) -> (Cid, Vec<u8>) {
+        if let Some(cid) = crate::inline_cid(IpldCodec::Raw.into(), data, self.inline_limit) {
+            return (cid, data.to_vec());
+        }
+        let mh = self.hash.digest(data);
+        (Cid::new_v1(IpldCodec::Raw.into(), mh), data.to_vec())
+    }
 }
 
 /// Chunker strategy
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Chunker {
     /// Size based chunking
     Size(usize),
+    /// Content defined chunking using Rabin fingerprints over a 16 byte window with the polynomial
+    /// and boundary rules of the `rabin-{min}-{avg}-{max}` chunker of go-ipfs. The average size is
+    /// rounded down to a power of two.
+    Rabin {
+        /// Minimum chunk size, unless the file ends earlier. Must be at least 16.
+        min: usize,
+        /// Targeted average chunk size.
+        avg: usize,
+        /// Maximum chunk size.
+        max: usize,
+    },
+    /// Content defined chunking using a cyclic polynomial (buzhash) over a 32 byte window with
+    /// the sizes and boundary rules of the `buzhash` chunker of go-ipfs: chunks are between 128KiB
+    /// and 512KiB.
+    ///
+    /// The table of per byte values differs from the one of go-ipfs, so the chunks and the Cids
+    /// do not match those of `ipfs add --chunker=buzhash`.
+    Buzhash,
 }
 
 impl Default for Chunker {
@@ -333,6 +512,15 @@ impl Default for Chunker {
 }
 
 impl Chunker {
+    /// Returns the Rabin chunker configured like go-ipfs `rabin-{avg}` would be.
+    pub fn rabin(avg: usize) -> Self {
+        Chunker::Rabin {
+            min: avg / 3,
+            avg,
+            max: avg + avg / 2,
+        }
+    }
+
     fn accept<'a>(&mut self, input: &'a [u8], buffered: &[u8]) -> (&'a [u8], bool) {
         use Chunker::*;
 
@@ -343,6 +531,27 @@ impl Chunker {
                 let ready = buffered.len() + l >= *max;
                 (accepted, ready)
             }
+            Rabin { min, avg, max } => {
+                let found = rabin::Rabin::new(*min, *avg, *max).find_boundary(buffered, input);
+                Self::accept_until(input, buffered, found)
+            }
+            Buzhash => {
+                let found = buzhash::find_boundary(buffered, input);
+                Self::accept_until(input, buffered, found)
+            }
+        }
+    }
+
+    /// Content defined chunkers report the boundary as the total length of the chunk; without a
+    /// boundary all of the input belongs to the current chunk.
+    fn accept_until<'a>(
+        input: &'a [u8],
+        buffered: &[u8],
+        boundary: Option<usize>,
+    ) -> (&'a [u8], bool) {
+        match boundary {
+            Some(len) => (&input[..len - buffered.len()], true),
+            None => (input, false),
         }
     }
 
@@ -351,18 +560,156 @@ impl Chunker {
 
         match self {
             Size(max) => *max,
+            Rabin { max, .. } => *max,
+            Buzhash => buzhash::MAX,
+        }
+    }
+}
+
+/// Largest chunk size accepted when parsing chunker strings, same as go-ipfs.
+const CHUNK_SIZE_LIMIT: usize = 1024 * 1024;
+
+impl fmt::Display for Chunker {
+    /// Formats the chunker in the go-ipfs `--chunker` format, which can be parsed back with
+    /// [`core::str::FromStr`].
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Chunker::*;
+
+        match self {
+            Size(size) => write!(fmt, "size-{size}"),
+            Rabin { min, avg, max } => write!(fmt, "rabin-{min}-{avg}-{max}"),
+            Buzhash => write!(fmt, "buzhash"),
+        }
+    }
+}
+
+impl core::str::FromStr for Chunker {
+    type Err = ParseChunkerError;
+
+    /// Parses the go-ipfs chunker strings: `default`, `size-{size}`, `rabin`, `rabin-{avg}`,
+    /// `rabin-{min}-{avg}-{max}` and `buzhash`. As with go-ipfs, the rabin parameters may also be
+    /// labeled as in `rabin-min:{min}-avg:{avg}-max:{max}`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use ParseChunkerError::*;
+
+        fn number(s: &str) -> Result<usize, ParseChunkerError> {
+            s.parse().map_err(|_| InvalidNumber(s.to_owned()))
+        }
+
+        fn labeled(part: &str, label: &str) -> Result<usize, ParseChunkerError> {
+            match part.split_once(':') {
+                Some((l, value)) if l == label => number(value),
+                Some(_) => Err(InvalidLabel(part.to_owned())),
+                None => number(part),
+            }
+        }
+
+        if s.is_empty() || s == "default" {
+            return Ok(Chunker::default());
+        }
+
+        if s == "buzhash" {
+            return Ok(Chunker::Buzhash);
+        }
+
+        if let Some(size) = s.strip_prefix("size-") {
+            let size = number(size)?;
+            return match size {
+                0 => Err(ZeroSize),
+                size if size > CHUNK_SIZE_LIMIT => Err(TooLarge),
+                size => Ok(Chunker::Size(size)),
+            };
+        }
+
+        if !s.starts_with("rabin") {
+            return Err(Unrecognized(s.to_owned()));
+        }
+
+        let parts = s.split('-').collect::<Vec<_>>();
+
+        match parts.as_slice() {
+            ["rabin"] => Ok(Chunker::rabin(256 * 1024)),
+            ["rabin", avg] => {
+                let avg = number(avg)?;
+                if avg / 3 < 16 {
+                    return Err(RabinMinTooSmall);
+                }
+                if avg + avg / 2 > CHUNK_SIZE_LIMIT {
+                    return Err(TooLarge);
+                }
+                Ok(Chunker::rabin(avg))
+            }
+            ["rabin", min, avg, max] => {
+                let min = labeled(min, "min")?;
+                let avg = labeled(avg, "avg")?;
+                let max = labeled(max, "max")?;
+
+                if min < 16 {
+                    Err(RabinMinTooSmall)
+                } else if min >= avg || avg >= max {
+                    Err(RabinUnordered)
+                } else if max > CHUNK_SIZE_LIMIT {
+                    Err(TooLarge)
+                } else {
+                    Ok(Chunker::Rabin { min, avg, max })
+                }
+            }
+            _ => Err(Unrecognized(s.to_owned())),
+        }
+    }
+}
+
+/// Failure cases for parsing a [`Chunker`] from the go-ipfs chunker string format.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseChunkerError {
+    /// The chunker name or format was not recognized.
+    Unrecognized(String),
+    /// A size could not be parsed as a number.
+    InvalidNumber(String),
+    /// Rabin parameters were labeled in wrong order or with unknown labels.
+    InvalidLabel(String),
+    /// Size based chunker cannot have zero size.
+    ZeroSize,
+    /// The chunks could become larger than the 1MiB limit.
+    TooLarge,
+    /// Rabin minimum size must be at least 16 bytes, the size of the window.
+    RabinMinTooSmall,
+    /// Rabin sizes must be given as increasing minimum, average and maximum.
+    RabinUnordered,
+}
+
+impl fmt::Display for ParseChunkerError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use ParseChunkerError::*;
+
+        match self {
+            Unrecognized(s) => write!(fmt, "unrecognized chunker: {s:?}"),
+            InvalidNumber(s) => write!(fmt, "invalid chunker size: {s:?}"),
+            InvalidLabel(s) => write!(fmt, "expected min, avg and max labels in order: {s:?}"),
+            ZeroSize => write!(fmt, "chunker size must be greater than 0"),
+            TooLarge => write!(
+                fmt,
+                "chunker parameters may not exceed the maximum chunk size of {CHUNK_SIZE_LIMIT}"
+            ),
+            RabinMinTooSmall => write!(fmt, "rabin min must be at least 16"),
+            RabinUnordered => write!(fmt, "rabin sizes must be ordered as min < avg < max"),
         }
     }
 }
 
+impl std::error::Error for ParseChunkerError {}
+
 /// Collector or layout strategy. For more information, see the [Layout section of the spec].
-/// Currently only the default balanced collector/layout has been implemented.
+/// Both of the layouts go-ipfs supports have been implemented: the default balanced and the
+/// trickle layout.
 ///
 /// [Layout section of the spec]: https://github.com/ipfs/specs/blob/master/UNIXFS.md#layout
 #[derive(Debug, Clone)]
 pub enum Collector {
     /// Balanced trees.
     Balanced(BalancedCollector),
+    /// Trickle trees.
+    Trickle(TrickleCollector),
 }
 
 impl Default for Collector {
@@ -372,13 +719,36 @@ impl Default for Collector {
 }
 
 impl Collector {
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        format: &BlockFormat,
+        finishing: bool,
+    ) -> Vec<(Cid, Vec<u8>)> {
         use Collector::*;
 
         match self {
-            Balanced(bc) => bc.flush_links(pending, finishing),
+            Balanced(bc) => bc.flush_links(pending, format, finishing),
+            Trickle(tc) => tc.flush_links(pending, format, finishing),
         }
     }
+
+    /// The UnixFs type of the leaf blocks: go-ipfs uses `File` for balanced and `Raw` for trickle
+    /// layouts.
+    fn leaf_type(&self) -> UnixFsType {
+        use Collector::*;
+
+        match self {
+            Balanced(_) => UnixFsType::File,
+            Trickle(_) => UnixFsType::Raw,
+        }
+    }
+
+    /// Empty file is an empty leaf for the balanced layout, but the trickle layout creates an
+    /// empty root instead.
+    fn needs_empty_leaf(&self) -> bool {
+        matches!(self, Collector::Balanced(_))
+    }
 }
 
 /// BalancedCollector creates balanced UnixFs trees, most optimized for random access to different
@@ -436,7 +806,12 @@ impl BalancedCollector {
     /// In-place compression of the `pending` links to a balanced hierarchy. When `finishing`, the
     /// links will be compressed iteratively from the lowest level to produce a single root link
     /// block.
-    fn flush_links(&mut self, pending: &mut Vec<Link>, finishing: bool) -> Vec<(Cid, Vec<u8>)> {
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        format: &BlockFormat,
+        finishing: bool,
+    ) -> Vec<(Cid, Vec<u8>)> {
         /*
 
         file    |- - - - - - - - - - - - - - - - - - - - - - - - - - - - - - -|
@@ -555,7 +930,7 @@ impl BalancedCollector {
                         index + first_at
                     );
 
-                    Self::partition_link(
+                    partition_link(
                         link,
                         &mut reused_links,
                         &mut reused_blocksizes,
@@ -576,7 +951,7 @@ impl BalancedCollector {
                     },
                 };
 
-                let (cid, vec) = render_and_hash(&inner);
+                let (cid, vec) = format.dag_pb(&inner);
 
                 // start overwriting at the first index of this level, then continue forward on
                 // next iterations.
@@ -617,32 +992,208 @@ impl BalancedCollector {
 
         ret
     }
+}
+
+/// Each link needs to be partitioned into the four mut arguments received by this function in
+/// order to produce the expected UnixFs output.
+fn partition_link(
+    link: &Link,
+    links: &mut Vec<PBLink<'static>>,
+    blocksizes: &mut Vec<u64>,
+    nested_size: &mut u64,
+    nested_total_size: &mut u64,
+) {
+    links.push(PBLink {
+        Hash: Some(link.target.to_bytes().into()),
+        Name: Some("".into()),
+        Tsize: Some(link.total_size),
+    });
+    blocksizes.push(link.file_size);
+    *nested_size += link.file_size;
+    *nested_total_size += link.total_size;
+}
+
+/// TrickleCollector creates trickle UnixFs trees matching go-ipfs `--trickle`, which are most
+/// optimized for sequential reading and appending. Each node is first filled with leaves up to
+/// the branching factor, after which `layer_repeat` subtrees of each increasing depth are added.
+#[derive(Clone)]
+pub struct TrickleCollector {
+    branching_factor: usize,
+    layer_repeat: usize,
+    // the nodes from the root to the currently filled node
+    open: Vec<TrickleNode>,
+}
+
+/// A trickle node which is still being filled.
+#[derive(Clone)]
+struct TrickleNode {
+    /// Depth of the subtrees this node can still have; `None` for the unlimited root.
+    max_depth: Option<usize>,
+    links: Vec<Link>,
+    /// Depth of the subtrees being currently added.
+    depth: usize,
+    /// How many subtrees of the current depth have already been added.
+    repeated: usize,
+}
+
+impl TrickleNode {
+    fn new(max_depth: Option<usize>) -> Self {
+        TrickleNode {
+            max_depth,
+            links: Vec::new(),
+            depth: 1,
+            repeated: 0,
+        }
+    }
+}
+
+impl fmt::Debug for TrickleCollector {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "TrickleCollector {{ branching_factor: {}, layer_repeat: {}, open: {} }}",
+            self.branching_factor,
+            self.layer_repeat,
+            self.open.len()
+        )
+    }
+}
+
+impl Default for TrickleCollector {
+    /// Returns a default collector which matches go-ipfs: 174 leaves per node and 4 repeats of
+    /// each layer.
+    fn default() -> Self {
+        Self::with_branching_factor(174, 4)
+    }
+}
+
+impl From<TrickleCollector> for Collector {
+    fn from(t: TrickleCollector) -> Self {
+        Collector::Trickle(t)
+    }
+}
+
+impl TrickleCollector {
+    /// Configure Trickle collector with the given branching factor and the amount of subtrees of
+    /// the same depth.
+    pub fn with_branching_factor(branching_factor: usize, layer_repeat: usize) -> Self {
+        assert!(branching_factor > 0);
+        assert!(layer_repeat > 0);
+
+        Self {
+            branching_factor,
+            layer_repeat,
+            open: vec![TrickleNode::new(None)],
+        }
+    }
+
+    /// Moves the `pending` leaves into the tree, creating the blocks for any completed subtrees.
+    /// When `finishing`, all of the open nodes are completed and the root link is left in
+    /// `pending`.
+    fn flush_links(
+        &mut self,
+        pending: &mut Vec<Link>,
+        format: &BlockFormat,
+        finishing: bool,
+    ) -> Vec<(Cid, Vec<u8>)> {
+        let mut ret = Vec::new();
+
+        for leaf in pending.drain(..) {
+            debug_assert_eq!(leaf.depth, 0);
+            self.place_leaf(leaf, format, &mut ret);
+        }
+
+        if finishing {
+            while let Some(node) = self.open.pop() {
+                let (link, block) = self.render(node, format);
+                ret.push(block);
+                match self.open.last_mut() {
+                    Some(parent) => parent.links.push(link),
+                    None => pending.push(link),
+                }
+            }
+            self.open.push(TrickleNode::new(None));
+        }
+
+        ret
+    }
+
+    fn place_leaf(&mut self, leaf: Link, format: &BlockFormat, ret: &mut Vec<(Cid, Vec<u8>)>) {
+        loop {
+            let node = self.open.last_mut().expect("root is never completed");
+
+            if node.links.len() < self.branching_factor {
+                node.links.push(leaf);
+                return;
+            }
+
+            if node.repeated == self.layer_repeat {
+                node.depth += 1;
+                node.repeated = 0;
+            }
+
+            if node.max_depth.map(|max| node.depth >= max).unwrap_or(false) {
+                // all of the subtrees have been added, the leaf goes to one of the ancestors
+                let node = self.open.pop().expect("just had it");
+                let (link, block) = self.render(node, format);
+                ret.push(block);
+
+                let parent = self.open.last_mut().expect("root is never completed");
+                parent.links.push(link);
+                parent.repeated += 1;
+                continue;
+            }
+
+            let depth = node.depth;
+            self.open.push(TrickleNode::new(Some(depth)));
+        }
+    }
+
+    fn render(&self, node: TrickleNode, format: &BlockFormat) -> (Link, (Cid, Vec<u8>)) {
+        let mut links = Vec::with_capacity(node.links.len());
+        let mut blocksizes = Vec::with_capacity(node.links.len());
+        let mut nested_size = 0;
+        let mut nested_total_size = 0;
+
+        for link in &node.links {
+            partition_link(
+                link,
+                &mut links,
+                &mut blocksizes,
+                &mut nested_size,
+                &mut nested_total_size,
+            );
+        }
 
-    /// Each link needs to be partitioned into the four mut arguments received by this function in
-    /// order to produce the expected UnixFs output.
-    fn partition_link(
-        link: &Link,
-        links: &mut Vec<PBLink<'static>>,
-        blocksizes: &mut Vec<u64>,
-        nested_size: &mut u64,
-        nested_total_size: &mut u64,
-    ) {
-        links.push(PBLink {
-            Hash: Some(link.target.to_bytes().into()),
-            Name: Some("".into()),
-            Tsize: Some(link.total_size),
-        });
-        blocksizes.push(link.file_size);
-        *nested_size += link.file_size;
-        *nested_total_size += link.total_size;
+        let inner = FlatUnixFs {
+            links,
+            data: UnixFs {
+                Type: UnixFsType::File,
+                filesize: Some(nested_size),
+                blocksizes,
+                ..Default::default()
+            },
+        };
+
+        let (cid, vec) = format.dag_pb(&inner);
+
+        let link = Link {
+            depth: node.max_depth.unwrap_or(self.open.len()) + 1,
+            target: cid,
+            total_size: nested_total_size + vec.len() as u64,
+            file_size: nested_size,
+        };
+
+        (link, (cid, vec))
     }
 }
 
 #[cfg(test)]
 mod tests {
 
-    use super::{BalancedCollector, Chunker, FileAdder};
+    use super::{BalancedCollector, Chunker, FileAdder, ParseChunkerError, TrickleCollector};
     use crate::test_support::FakeBlockstore;
+    use crate::Metadata;
     use core::convert::TryFrom;
     use hex_literal::hex;
     use libipld::Cid;
@@ -866,4 +1417,420 @@ mod tests {
 
         assert_eq!(blocks_count, 175);
     }
+
+    /// Deterministic noise for the content defined chunkers to find boundaries in.
+    fn pseudorandom_content(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    fn leaf_lengths(chunker: Chunker, content: &[u8], amt: usize) -> Vec<usize> {
+        FileAdder::builder()
+            .with_chunker(chunker)
+            .build()
+            .collect_blocks(content, amt)
+            .iter()
+            .filter_map(|(_, block)| {
+                let flat = crate::pb::FlatUnixFs::try_from(block.as_slice()).unwrap();
+                // leaves are produced in order, link blocks have no data
+                flat.data.Data.map(|data| data.len())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rabin_chunks_within_bounds() {
+        let content = pseudorandom_content(64 * 1024, 0x5eed);
+        let chunker = Chunker::Rabin {
+            min: 256,
+            avg: 1024,
+            max: 2048,
+        };
+
+        let lengths = leaf_lengths(chunker, &content, 0);
+
+        assert_eq!(lengths.iter().sum::<usize>(), content.len());
+        assert!(lengths.len() > 16, "too few chunks: {lengths:?}");
+        let (last, rest) = lengths.split_last().unwrap();
+        assert!(
+            rest.iter().all(|&l| (256..=2048).contains(&l)),
+            "{lengths:?}"
+        );
+        assert!(*last <= 2048);
+        // some boundaries must had been found by content, not only at max
+        assert!(rest.iter().any(|&l| l < 2048), "{lengths:?}");
+    }
+
+    #[test]
+    fn content_defined_chunks_independent_of_push_sizes() {
+        let content = pseudorandom_content(24 * 1024, 0xfeed);
+        let chunker = Chunker::Rabin {
+            min: 128,
+            avg: 512,
+            max: 1024,
+        };
+
+        let expected = leaf_lengths(chunker, &content, 0);
+
+        for amt in [1, 7, 16, 100, 513, 4096] {
+            assert_eq!(leaf_lengths(chunker, &content, amt), expected, "amt: {amt}");
+        }
+    }
+
+    #[test]
+    fn rabin_reuses_blocks_after_edit() {
+        let original = pseudorandom_content(256 * 1024, 0xc0ffee);
+        let mut edited = original.clone();
+        // insert a few bytes in the middle, which would shift every following fixed size chunk
+        edited.splice(100_000..100_000, b"hello world".iter().copied());
+
+        let chunker = Chunker::rabin(4096);
+
+        let cids = |content: &[u8]| {
+            FileAdder::builder()
+                .with_chunker(chunker)
+                .build()
+                .collect_blocks(content, 0)
+                .into_iter()
+                .map(|(cid, _)| cid)
+                .collect::<std::collections::HashSet<_>>()
+        };
+
+        let before = cids(&original);
+        let after = cids(&edited);
+
+        let shared = before.intersection(&after).count();
+        assert!(
+            shared * 10 > before.len() * 9,
+            "only {shared} of {} blocks reused",
+            before.len()
+        );
+    }
+
+    #[test]
+    fn buzhash_chunks_within_bounds() {
+        let content = pseudorandom_content(2 * 1024 * 1024, 0xbadc0de);
+
+        let lengths = leaf_lengths(Chunker::Buzhash, &content, 64 * 1024);
+
+        assert_eq!(lengths.iter().sum::<usize>(), content.len());
+        let (_, rest) = lengths.split_last().unwrap();
+        assert!(
+            rest.iter().all(|&l| (128 * 1024..=512 * 1024).contains(&l)),
+            "{lengths:?}"
+        );
+    }
+
+    #[test]
+    fn small_file_is_single_content_defined_chunk() {
+        for chunker in [Chunker::rabin(256 * 1024), Chunker::Buzhash] {
+            assert_eq!(leaf_lengths(chunker, b"foobar\n", 0), [7]);
+        }
+    }
+
+    #[test]
+    fn parse_go_ipfs_chunker_strings() {
+        let parsed = [
+            ("default", Chunker::Size(256 * 1024)),
+            ("size-1234", Chunker::Size(1234)),
+            ("buzhash", Chunker::Buzhash),
+            (
+                "rabin",
+                Chunker::Rabin {
+                    min: 87381,
+                    avg: 262144,
+                    max: 393216,
+                },
+            ),
+            (
+                "rabin-1000",
+                Chunker::Rabin {
+                    min: 333,
+                    avg: 1000,
+                    max: 1500,
+                },
+            ),
+            (
+                "rabin-16-32-64",
+                Chunker::Rabin {
+                    min: 16,
+                    avg: 32,
+                    max: 64,
+                },
+            ),
+            (
+                "rabin-min:16-avg:32-max:64",
+                Chunker::Rabin {
+                    min: 16,
+                    avg: 32,
+                    max: 64,
+                },
+            ),
+        ];
+
+        for (s, expected) in parsed {
+            let chunker = s.parse::<Chunker>().unwrap();
+            assert_eq!(chunker, expected, "{s}");
+            assert_eq!(chunker.to_string().parse::<Chunker>().unwrap(), expected);
+        }
+
+        let failing = [
+            ("size-0", ParseChunkerError::ZeroSize),
+            ("size-1048577", ParseChunkerError::TooLarge),
+            ("rabin-8-32-64", ParseChunkerError::RabinMinTooSmall),
+            ("rabin-47", ParseChunkerError::RabinMinTooSmall),
+            ("rabin-64-32-128", ParseChunkerError::RabinUnordered),
+            ("rabin-16-32-2000000", ParseChunkerError::TooLarge),
+            (
+                "rabin-avg:16-min:32-max:64",
+                ParseChunkerError::InvalidLabel("avg:16".into()),
+            ),
+            (
+                "rabin-1-2",
+                ParseChunkerError::Unrecognized("rabin-1-2".into()),
+            ),
+            ("fastcdc", ParseChunkerError::Unrecognized("fastcdc".into())),
+        ];
+
+        for (s, expected) in failing {
+            assert_eq!(s.parse::<Chunker>().unwrap_err(), expected, "{s}");
+        }
+    }
+
+    #[test]
+    fn trickle_empty_file_matches_balanced() {
+        let blocks = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .build()
+            .collect_blocks(b"", 0);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH"
+        );
+    }
+
+    #[test]
+    fn trickle_single_chunk_has_raw_leaf_and_root() {
+        let blocks = FileAdder::builder()
+            .with_collector(TrickleCollector::default())
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 2);
+
+        let leaf = crate::pb::FlatUnixFs::try_from(blocks[0].1.as_slice()).unwrap();
+        assert_eq!(leaf.data.Type, crate::pb::UnixFsType::Raw);
+        assert_eq!(leaf.data.Data.as_deref(), Some(&b"foobar\n"[..]));
+
+        let root = crate::pb::FlatUnixFs::try_from(blocks[1].1.as_slice()).unwrap();
+        assert_eq!(root.data.Type, crate::pb::UnixFsType::File);
+        assert_eq!(root.data.filesize, Some(7));
+        assert_eq!(root.links.len(), 1);
+    }
+
+    #[test]
+    fn trickle_layout() {
+        // with two leaves per node and two repeats per layer the ten leaves are laid out as:
+        //
+        // root: leaf, leaf, d1, d1, d2
+        // d1: leaf, leaf
+        // d2: leaf, leaf, d1
+        let content = b"0123456789";
+
+        let build = |amt| {
+            FileAdder::builder()
+                .with_chunker(Chunker::Size(1))
+                .with_collector(TrickleCollector::with_branching_factor(2, 2))
+                .build()
+                .collect_blocks(content, amt)
+        };
+
+        let blocks = build(0);
+
+        // 10 leaves, three d1 nodes, one d2 node and the root
+        assert_eq!(blocks.len(), 10 + 3 + 1 + 1);
+
+        let (root_cid, root) = blocks.last().unwrap();
+        let root = crate::pb::FlatUnixFs::try_from(root.as_slice()).unwrap();
+        assert_eq!(root.data.blocksizes, [1, 1, 2, 2, 4]);
+        assert_eq!(root.data.filesize, Some(10));
+
+        let last_child = Cid::try_from(root.links[4].Hash.as_deref().unwrap()).unwrap();
+        let (_, last_child) = blocks.iter().find(|(cid, _)| cid == &last_child).unwrap();
+        let last_child = crate::pb::FlatUnixFs::try_from(last_child.as_slice()).unwrap();
+        assert_eq!(last_child.data.blocksizes, [1, 1, 2]);
+
+        for amt in [3, 4] {
+            assert_eq!(&build(amt).last().unwrap().0, root_cid);
+        }
+    }
+
+    #[test]
+    fn raw_leaves_single_block_file() {
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        // the single raw leaf is the root, the same as go-ipfs 0.7 `add --raw-leaves`
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].1.as_slice(), b"foobar\n");
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafkreifoybygix7fh3r3g5rqle3wcnhqldgdg4shzf4k3ulyw3gn7mabt4"
+        );
+    }
+
+    #[test]
+    fn raw_leaves_multi_block_file() {
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .with_cid_version(libipld::cid::Version::V1)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 5);
+
+        let (leaves, root) = blocks.split_at(4);
+        for ((cid, block), expected) in leaves.iter().zip([&b"fo"[..], b"ob", b"ar", b"\n"]) {
+            assert_eq!(cid.codec(), 0x55);
+            assert_eq!(block.as_slice(), expected);
+        }
+
+        let (root_cid, root) = &root[0];
+        assert_eq!(root_cid.version(), libipld::cid::Version::V1);
+        assert_eq!(root_cid.codec(), 0x70);
+
+        let root = crate::pb::FlatUnixFs::try_from(root.as_slice()).unwrap();
+        assert_eq!(root.data.blocksizes, [2, 2, 2, 1]);
+        assert_eq!(root.links[3].Tsize, Some(1));
+    }
+
+    #[test]
+    fn raw_leaves_empty_file() {
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(b"", 0);
+
+        assert_eq!(blocks.len(), 1);
+        assert!(blocks[0].1.is_empty());
+        assert_eq!(blocks[0].0.codec(), 0x55);
+    }
+
+    #[test]
+    fn non_sha2_256_hash_uses_cidv1() {
+        let blocks = FileAdder::builder()
+            .with_hash(libipld::multihash::Code::Blake2b256)
+            .unwrap()
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(
+            blocks[0].0.to_string(),
+            "bafk2bzaceb56efkimsqv2mg32634lt4ych2nzrjpwpazbxol6pphiwgzgdr7g"
+        );
+
+        let blocks = FileAdder::builder()
+            .with_hash(libipld::multihash::Code::Blake3_256)
+            .unwrap()
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks[0].0.version(), libipld::cid::Version::V1);
+        assert_eq!(blocks[0].0.codec(), 0x70);
+        assert_eq!(blocks[0].0.hash().code(), 0x1e);
+    }
+
+    #[test]
+    fn inline_small_blocks() {
+        let blocks = FileAdder::builder()
+            .with_inline_limit(Some(32))
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 1);
+        let (cid, block) = &blocks[0];
+        assert_eq!(cid.version(), libipld::cid::Version::V1);
+        assert_eq!(cid.codec(), 0x70);
+        assert_eq!(cid.hash().code(), crate::IDENTITY_HASH_CODE);
+        assert_eq!(cid.hash().digest(), block.as_slice());
+
+        // only the raw leaves fit the limit; the root is hashed as usual
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .with_inline_limit(Some(2))
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 5);
+        for (cid, block) in &blocks[..4] {
+            assert_eq!(cid.codec(), 0x55);
+            assert_eq!(cid.hash().code(), crate::IDENTITY_HASH_CODE);
+            assert_eq!(cid.hash().digest(), block.as_slice());
+        }
+        let (root, _) = blocks.last().unwrap();
+        assert_eq!(root.version(), libipld::cid::Version::V0);
+    }
+
+    #[test]
+    fn inline_limit_is_capped() {
+        let content = [0u8; crate::MAX_INLINE_LIMIT + 1];
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .with_inline_limit(Some(1024))
+            .build()
+            .collect_blocks(&content, 0);
+
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].0.hash().code(), 0x12);
+    }
+
+    #[test]
+    fn metadata_in_root() {
+        let metadata = Metadata::default()
+            .with_mode(0o100644)
+            .with_mtime(1_600_000_000, 500);
+
+        let blocks = FileAdder::builder()
+            .with_metadata(metadata.clone())
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 1);
+        let root = crate::pb::FlatUnixFs::try_from(blocks[0].1.as_slice()).unwrap();
+        assert_eq!(root.data.Data.as_deref(), Some(&b"foobar\n"[..]));
+        assert_eq!(
+            Metadata::from(&root.data),
+            Metadata::default()
+                .with_mode(0o644)
+                .with_mtime(1_600_000_000, 500)
+        );
+
+        // a raw root gets wrapped
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .with_metadata(metadata)
+            .build()
+            .collect_blocks(b"foobar\n", 0);
+
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].0.codec(), 0x55);
+        let root = crate::pb::FlatUnixFs::try_from(blocks[1].1.as_slice()).unwrap();
+        assert_eq!(root.data.filesize, Some(7));
+        assert_eq!(root.data.blocksizes, [7]);
+        assert_eq!(root.data.mode, Some(0o644));
+        assert_eq!(root.links[0].Tsize, Some(7));
+    }
 }
@@ -0,0 +1,80 @@
+//! Buzhash (cyclic polynomial) based content defined chunking following the parameters and the
+//! boundary rules of the `buzhash` chunker of go-ipfs (`github.com/ipfs/go-ipfs-chunker`).
+//!
+//! The per byte values are not the ones go-ipfs uses, so the boundaries differ from go-ipfs.
+
+/// Smallest chunk produced, unless the file ends earlier.
+pub(super) const MIN: usize = 128 * 1024;
+
+/// Largest chunk produced.
+pub(super) const MAX: usize = 512 * 1024;
+
+/// A boundary is found when these bits of the state are zero, making the average chunk size
+/// `MIN` + 128KiB.
+const MASK: u32 = (1 << 17) - 1;
+
+/// The size of the rolling window in bytes. Being equal to the width of the state means a byte
+/// leaving the window has been rotated a full cycle, so it can be removed without rotating.
+const WINDOW_SIZE: usize = 32;
+
+/// Fixed pseudo-random values for each byte value.
+// FIXME: go-ipfs uses a hardcoded table of random values which needs to be copied here for the
+// boundaries (and so the Cids) to match; until then this table is derived with splitmix64.
+static BYTEHASH: [u32; 256] = bytehash();
+
+const fn bytehash() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut state = 0u64;
+    let mut i = 0;
+    while i < table.len() {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        table[i] = (z >> 32) as u32;
+        i += 1;
+    }
+    table
+}
+
+/// Returns the length of the chunk when a boundary is found within the bytes of the current chunk,
+/// which are the already `buffered` and the new `input` bytes.
+pub(super) fn find_boundary(buffered: &[u8], input: &[u8]) -> Option<usize> {
+    let end = (buffered.len() + input.len()).min(MAX);
+    // positions up to and including buffered.len() have been searched on earlier calls.
+    let start = MIN.max(buffered.len() + 1);
+
+    if start > end {
+        return (end == MAX).then_some(MAX);
+    }
+
+    let byte_at = |index: usize| -> u8 {
+        if index < buffered.len() {
+            buffered[index]
+        } else {
+            input[index - buffered.len()]
+        }
+    };
+
+    let mut state = 0u32;
+    for index in start - WINDOW_SIZE..start {
+        state = state.rotate_left(1) ^ BYTEHASH[byte_at(index) as usize];
+    }
+
+    let mut at = start;
+    loop {
+        if state & MASK == 0 {
+            return Some(at);
+        }
+        if at == end {
+            break;
+        }
+        state = state.rotate_left(1)
+            ^ BYTEHASH[byte_at(at - WINDOW_SIZE) as usize]
+            ^ BYTEHASH[byte_at(at) as usize];
+        at += 1;
+    }
+
+    (end == MAX).then_some(MAX)
+}
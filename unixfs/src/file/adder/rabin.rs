@@ -0,0 +1,155 @@
+//! Rabin fingerprint based content defined chunking, compatible with the `rabin` chunker of
+//! go-ipfs (`github.com/ipfs/go-ipfs-chunker`) which in turn builds on
+//! `github.com/whyrusleeping/chunker`, a fork of the restic chunker.
+
+/// The irreducible polynomial used by go-ipfs, `IpfsRabinPoly`.
+const POLYNOMIAL: u64 = 0x3DF305DFB2A805;
+
+/// The size of the rolling window in bytes.
+const WINDOW_SIZE: usize = 16;
+
+/// How much the state needs to be shifted to get the index into [`Tables::modulo`].
+const POLYNOMIAL_SHIFT: u32 = degree(POLYNOMIAL) - 8;
+
+static TABLES: Tables = Tables::new();
+
+struct Tables {
+    /// `out[b]` is the hash of `b` followed by `WINDOW_SIZE - 1` zero bytes; xor'ing it to the
+    /// state slides out the byte `b`.
+    out: [u64; 256],
+    /// `modulo[b]` reduces the state after shifting a new byte in, when the top 8 bits of the
+    /// state before shifting were `b`.
+    modulo: [u64; 256],
+}
+
+impl Tables {
+    const fn new() -> Self {
+        let mut out = [0u64; 256];
+        let mut modulo = [0u64; 256];
+
+        let k = degree(POLYNOMIAL);
+
+        let mut b = 0;
+        while b < 256 {
+            let mut h = append_byte(0, b as u8);
+            let mut i = 0;
+            while i < WINDOW_SIZE - 1 {
+                h = append_byte(h, 0);
+                i += 1;
+            }
+            out[b] = h;
+
+            modulo[b] = reduce((b as u64) << k) | ((b as u64) << k);
+
+            b += 1;
+        }
+
+        Tables { out, modulo }
+    }
+}
+
+/// Returns the degree of the polynomial `x`, which must not be zero.
+const fn degree(x: u64) -> u32 {
+    63 - x.leading_zeros()
+}
+
+/// Returns `x` modulo [`POLYNOMIAL`].
+const fn reduce(mut x: u64) -> u64 {
+    while x != 0 && degree(x) >= degree(POLYNOMIAL) {
+        x ^= POLYNOMIAL << (degree(x) - degree(POLYNOMIAL));
+    }
+    x
+}
+
+const fn append_byte(hash: u64, b: u8) -> u64 {
+    reduce((hash << 8) | b as u64)
+}
+
+/// Shifts in the byte `b` to the `state` from which the outgoing byte has already been removed.
+#[inline]
+fn shift_in(state: u64, b: u8) -> u64 {
+    let index = (state >> POLYNOMIAL_SHIFT) as usize;
+    ((state << 8) | b as u64) ^ TABLES.modulo[index]
+}
+
+/// Configured rabin chunker. The chunk boundary is found at the first position after `min` bytes
+/// where the lowest bits of the fingerprint of the last [`WINDOW_SIZE`] bytes are all zero, or at
+/// `max` bytes.
+pub(super) struct Rabin {
+    min: usize,
+    max: usize,
+    mask: u64,
+}
+
+impl Rabin {
+    /// Creates the chunker as go-ipfs would: the mask has `floor(log2(avg))` bits set.
+    pub(super) fn new(min: usize, avg: usize, max: usize) -> Self {
+        let bits = usize::BITS - 1 - avg.max(1).leading_zeros();
+        Rabin {
+            // the window needs to fit within the minimum
+            min: min.max(WINDOW_SIZE),
+            max,
+            mask: (1u64 << bits) - 1,
+        }
+    }
+
+    /// Returns the length of the chunk when a boundary is found within the bytes of the current
+    /// chunk, which are the already `buffered` and the new `input` bytes.
+    pub(super) fn find_boundary(&self, buffered: &[u8], input: &[u8]) -> Option<usize> {
+        let end = (buffered.len() + input.len()).min(self.max);
+        // positions up to and including buffered.len() have been searched on earlier calls.
+        let start = self.min.max(buffered.len() + 1);
+
+        if start > end {
+            return (end == self.max).then_some(self.max);
+        }
+
+        let byte_at = |index: usize| -> u8 {
+            if index < buffered.len() {
+                buffered[index]
+            } else {
+                input[index - buffered.len()]
+            }
+        };
+
+        // go-ipfs starts each chunk with a state where the window is primed with a single 1 byte,
+        // which gets slided out as the last byte before the first possible boundary is reached.
+        let mut state = 1;
+        for (nth, index) in (start - WINDOW_SIZE..start).enumerate() {
+            if nth == WINDOW_SIZE - 1 {
+                state ^= TABLES.out[1];
+            }
+            state = shift_in(state, byte_at(index));
+        }
+
+        let mut at = start;
+        loop {
+            if state & self.mask == 0 {
+                return Some(at);
+            }
+            if at == end {
+                break;
+            }
+            state ^= TABLES.out[byte_at(at - WINDOW_SIZE) as usize];
+            state = shift_in(state, byte_at(at));
+            at += 1;
+        }
+
+        (end == self.max).then_some(self.max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TABLES;
+
+    #[test]
+    fn tables_match_go_ipfs() {
+        // spot checks from the tables generated by whyrusleeping/chunker for IpfsRabinPoly
+        assert_eq!(TABLES.out[1], 0x17fa63217c2ad7);
+        assert_eq!(TABLES.out[2], 0x1207c39d4afdab);
+        assert_eq!(TABLES.out[255], 0x171e3aded86a75);
+        assert_eq!(TABLES.modulo[1], 0x3df305dfb2a805);
+        assert_eq!(TABLES.modulo[255], 0x1fe1d6d65a0be2bc);
+    }
+}
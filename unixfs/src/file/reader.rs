@@ -84,6 +84,20 @@ impl<'a> FileReader<'a> {
         Self::from_parts(inner, 0, metadata)
     }
 
+    /// Called by Traversal to continue the traversal with a leaf of the raw codec, which is all
+    /// content.
+    fn from_raw(offset: u64, data: &'a [u8], metadata: Metadata) -> Self {
+        Self {
+            offset,
+            end: Ending::Chunk(offset + data.len() as u64),
+            links: Vec::new(),
+            data,
+            blocksizes: Vec::new(),
+            metadata,
+            file_size: data.len() as u64,
+        }
+    }
+
     /// Called by Traversal to continue traversing a file tree traversal.
     fn from_continued(
         traversal: Traversal,
@@ -212,6 +226,22 @@ impl Traversal {
         FileReader::from_continued(self, tree_range.start, next_block)
     }
 
+    /// Continues the walk with a leaf block of the raw codec, as created when adding files with
+    /// raw leaves. Same as with [`Traversal::continue_walk`] the block contents are not validated.
+    pub fn continue_walk_raw<'a>(
+        self,
+        next_block: &'a [u8],
+        tree_range: &Range<u64>,
+    ) -> Result<FileReader<'a>, FileReadFailed> {
+        self.last_ending
+            .check_is_suitable_next(self.last_offset, tree_range)?;
+        Ok(FileReader::from_raw(
+            tree_range.start,
+            next_block,
+            self.metadata,
+        ))
+    }
+
     /// Returns the total size of the file.
     pub fn file_size(&self) -> u64 {
         self.file_size
@@ -34,6 +34,17 @@ impl IdleFileVisit {
         self.start_from_reader(fr, &mut None)
     }
 
+    /// Begins the visitation from a block of the raw codec, which is a single block file without
+    /// any metadata.
+    ///
+    /// Returns the same tuple as [`IdleFileVisit::start`], though there is never anything more to
+    /// visit.
+    pub fn start_raw(self, block: &'_ [u8]) -> FileVisitResult<'_> {
+        let range = 0..block.len() as u64;
+        let content = maybe_target_slice(block, &range, self.range.as_ref());
+        (content, block.len() as u64, Metadata::default(), None)
+    }
+
     pub(crate) fn start_from_parsed<'a>(
         self,
         block: FlatUnixFs<'a>,
@@ -155,13 +166,17 @@ impl FileVisit {
         cache: &mut Option<Cache>,
     ) -> Result<(&'a [u8], Option<Self>), FileReadFailed> {
         let traversal = self.state;
-        let (_, range) = self
+        let (cid, range) = self
             .pending
             .pop()
             .expect("User called continue_walk there must have been a next link");
 
         // interesting, validation doesn't trigger if the range is the same?
-        let fr = traversal.continue_walk(next, &range)?;
+        let fr = if crate::file::is_raw(&cid) {
+            traversal.continue_walk_raw(next, &range)?
+        } else {
+            traversal.continue_walk(next, &range)?
+        };
         let (content, traversal) = fr.content();
         match content {
             FileContent::Bytes(content) => {
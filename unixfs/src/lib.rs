@@ -35,6 +35,80 @@ pub mod walk;
 #[cfg(test)]
 pub(crate) mod test_support;
 
+/// The multihash code of the identity hash, which stores the data as the digest.
+pub const IDENTITY_HASH_CODE: u64 = 0x00;
+
+/// The largest block which can be inlined into a Cid with the identity hash, limited by the size
+/// of the multihash digest in `libipld::Cid`.
+pub const MAX_INLINE_LIMIT: usize = 64;
+
+/// Returns a Cid version 1 inlining the `data` with the identity hash when the data is at most
+/// `limit` bytes.
+pub(crate) fn inline_cid(codec: u64, data: &[u8], limit: Option<usize>) -> Option<libipld::Cid> {
+    let limit = limit?.min(MAX_INLINE_LIMIT);
+    if data.len() > limit {
+        return None;
+    }
+    let mh = libipld::multihash::Multihash::wrap(IDENTITY_HASH_CODE, data).ok()?;
+    Some(libipld::Cid::new_v1(codec, mh))
+}
+
+/// Returns the Cid of a dag-pb `block` hashed with `hash`, unless the block is small enough to be
+/// inlined. Cid version 0 is only possible with sha2-256, for other hash functions version 1 will
+/// be used.
+pub fn dag_pb_cid(
+    version: libipld::cid::Version,
+    hash: libipld::multihash::Code,
+    block: &[u8],
+    inline_limit: Option<usize>,
+) -> libipld::Cid {
+    use libipld::cid::Version;
+    use libipld::multihash::{Code, MultihashDigest};
+
+    let codec = libipld::IpldCodec::DagPb.into();
+    if let Some(cid) = inline_cid(codec, block, inline_limit) {
+        return cid;
+    }
+
+    let mh = hash.digest(block);
+    match version {
+        Version::V0 if hash == Code::Sha2_256 => {
+            libipld::Cid::new_v0(mh).expect("sha2_256 is the correct multihash for cidv0")
+        }
+        _ => libipld::Cid::new_v1(codec, mh),
+    }
+}
+
+/// The hash functions supported for the created blocks: sha2-256, blake2b-256 and blake3.
+pub const SUPPORTED_HASHES: [libipld::multihash::Code; 3] = [
+    libipld::multihash::Code::Sha2_256,
+    libipld::multihash::Code::Blake2b256,
+    libipld::multihash::Code::Blake3_256,
+];
+
+/// The hash function is not one of the [`SUPPORTED_HASHES`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedHash(pub libipld::multihash::Code);
+
+impl fmt::Display for UnsupportedHash {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(fmt, "unsupported hash function {:?}", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedHash {}
+
+/// Returns an error unless the hash function is one of the [`SUPPORTED_HASHES`].
+pub(crate) fn check_hash(
+    hash: libipld::multihash::Code,
+) -> Result<libipld::multihash::Code, UnsupportedHash> {
+    if SUPPORTED_HASHES.contains(&hash) {
+        Ok(hash)
+    } else {
+        Err(UnsupportedHash(hash))
+    }
+}
+
 /// A link could not be transformed into a Cid.
 #[derive(Debug)]
 #[non_exhaustive]
@@ -134,6 +208,33 @@ pub struct Metadata {
 }
 
 impl Metadata {
+    /// Sets the file mode, of which only the permission, sticky, set user id and set group id bits
+    /// are kept like go-ipfs does.
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode & 0o7777);
+        self
+    }
+
+    /// Sets the modification time as seconds and nanoseconds since the unix epoch.
+    pub fn with_mtime(mut self, seconds: i64, nanos: u32) -> Self {
+        self.mtime = Some((seconds, nanos));
+        self
+    }
+
+    /// Returns `true` when neither the mode nor the mtime has been specified.
+    pub fn is_empty(&self) -> bool {
+        self.mode.is_none() && self.mtime.is_none()
+    }
+
+    /// Writes the metadata into the node being serialized.
+    pub(crate) fn apply(&self, data: &mut UnixFs<'_>) {
+        data.mode = self.mode;
+        data.mtime = self.mtime.map(|(seconds, nanos)| pb::unixfs::UnixTime {
+            Seconds: seconds,
+            FractionalNanoseconds: (nanos != 0).then_some(nanos),
+        });
+    }
+
     /// Returns the full file mode, if one has been specified.
     ///
     /// The full file mode is originally read through `st_mode` field of `stat` struct defined in
@@ -150,6 +150,23 @@ impl Walker {
             return Ok(ContinuedWalk::File(segment, cid, path, metadata, *sz));
         }
 
+        let next_is_raw = next
+            .as_ref()
+            .map(|(cid, ..)| crate::file::is_raw(cid))
+            .unwrap_or(false);
+
+        if next_is_raw {
+            // raw leaves are added as single block files without the UnixFs wrapping
+            let (bytes, file_size, metadata, step) = IdleFileVisit::default().start_raw(bytes);
+            return Ok(Self::file_started(
+                current,
+                next,
+                pending,
+                should_continue,
+                (bytes, file_size, metadata, step),
+            ));
+        }
+
         let flat = FlatUnixFs::try_from(bytes)?;
         let metadata = Metadata::from(&flat.data);
 
@@ -236,37 +253,13 @@ impl Walker {
                 })
             }
             UnixFsType::Raw | UnixFsType::File => {
-                let (bytes, file_size, metadata, step) =
-                    IdleFileVisit::default().start_from_parsed(flat, cache)?;
-                let (cid, name, depth) = next.take().expect("validated at new and earlier");
-                let file_continues = step.is_some();
-
-                match current {
-                    None => {
-                        let ie =
-                            InnerEntry::new_root_file(cid, metadata, &name, step, file_size, depth);
-                        *current = Some(ie);
-                    }
-                    Some(ie) => {
-                        ie.as_file(cid, &name, depth, metadata, step, file_size);
-                    }
-                };
-
-                let next_local = pending.pop();
-                if file_continues || next_local.is_some() {
-                    *next = next_local;
-                    *should_continue = true;
-                }
-
-                let segment = FileSegment::first(bytes, !file_continues);
-
-                let ie = current.as_ref().unwrap();
-                Ok(ContinuedWalk::File(
-                    segment,
-                    &ie.cid,
-                    &ie.path,
-                    &ie.metadata,
-                    file_size,
+                let started = IdleFileVisit::default().start_from_parsed(flat, cache)?;
+                Ok(Self::file_started(
+                    current,
+                    next,
+                    pending,
+                    should_continue,
+                    started,
                 ))
             }
             UnixFsType::Metadata => Err(Error::UnsupportedType(flat.data.Type.into())),
@@ -304,6 +297,39 @@ impl Walker {
         }
     }
 
+    /// Moves the walk to the file which was started from the `next` block.
+    fn file_started<'c>(
+        current: &'c mut Option<InnerEntry>,
+        next: &mut Option<(Cid, String, usize)>,
+        pending: &mut Vec<(Cid, String, usize)>,
+        should_continue: &mut bool,
+        (bytes, file_size, metadata, step): (&'c [u8], u64, Metadata, Option<FileVisit>),
+    ) -> ContinuedWalk<'c> {
+        let (cid, name, depth) = next.take().expect("validated at new and earlier");
+        let file_continues = step.is_some();
+
+        match current {
+            None => {
+                let ie = InnerEntry::new_root_file(cid, metadata, &name, step, file_size, depth);
+                *current = Some(ie);
+            }
+            Some(ie) => {
+                ie.as_file(cid, &name, depth, metadata, step, file_size);
+            }
+        };
+
+        let next_local = pending.pop();
+        if file_continues || next_local.is_some() {
+            *next = next_local;
+            *should_continue = true;
+        }
+
+        let segment = FileSegment::first(bytes, !file_continues);
+
+        let ie = current.as_ref().unwrap();
+        ContinuedWalk::File(segment, &ie.cid, &ie.path, &ie.metadata, file_size)
+    }
+
     /// Returns `true` if there are more links to walk over.
     pub fn should_continue(&self) -> bool {
         self.should_continue
@@ -921,6 +947,73 @@ mod tests {
         }
     }
 
+    #[test]
+    fn raw_leaves_file() {
+        use crate::file::adder::{Chunker, FileAdder};
+
+        let content = b"foobar\n";
+        let blocks = FileAdder::builder()
+            .with_chunker(Chunker::Size(2))
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(content, 0);
+        let root = blocks.last().unwrap().0;
+        let blocks = blocks.into_iter().collect::<HashMap<_, _>>();
+
+        for range in [0..7, 1..6, 3..4] {
+            let mut walker = Walker::new(root, String::new());
+            let mut cache = None;
+            let mut read = Vec::new();
+
+            while walker.should_continue() {
+                let (next, _) = walker.pending_links();
+                let block = &blocks[next];
+                match walker.next(block, &mut cache).unwrap() {
+                    ContinuedWalk::File(segment, _, _, _, size) => {
+                        assert_eq!(size, 7);
+                        read.extend_from_slice(segment.as_ref());
+                    }
+                    x => unreachable!("{:?}", x),
+                }
+            }
+
+            assert_eq!(read, content);
+
+            // the file visit should only load the leaves within the range
+            let mut visit = crate::file::visit::IdleFileVisit::default()
+                .with_target_range(range.start as u64..range.end as u64)
+                .start(&blocks[&root])
+                .unwrap()
+                .3;
+            let mut read = Vec::new();
+            while let Some(v) = visit {
+                let next = *v.pending_links().0;
+                let (bytes, next) = v.continue_walk(&blocks[&next], &mut None).unwrap();
+                read.extend_from_slice(bytes);
+                visit = next;
+            }
+
+            assert_eq!(read, &content[range]);
+        }
+
+        // the single block raw file is still a file
+        let blocks = FileAdder::builder()
+            .with_raw_leaves(true)
+            .build()
+            .collect_blocks(content, 0);
+        let mut walker = Walker::new(blocks[0].0, String::from("foobar.txt"));
+        match walker.next(&blocks[0].1, &mut None).unwrap() {
+            ContinuedWalk::File(segment, _, path, _, size) => {
+                assert_eq!(segment.as_ref(), content);
+                assert!(segment.is_last());
+                assert_eq!(path, Path::new("foobar.txt"));
+                assert_eq!(size, 7);
+            }
+            x => unreachable!("{:?}", x),
+        }
+        assert!(!walker.should_continue());
+    }
+
     fn walk_everything(root_name: &str, cid: &str) -> HashMap<PathBuf, usize> {
         let mut ret = HashMap::new();
 